@@ -0,0 +1,35 @@
+// Validates MS-Wasm binaries, printing errors as wabt does:
+//
+//     mswasm-validate boxes.wasm [more.wasm ...]
+
+use std::process::exit;
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: mswasm-validate <file.wasm>...");
+        exit(2);
+    }
+    let mut failed = false;
+    for path in &paths {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        let errors = match mswasm::parse(&bytes) {
+            Ok(module) => mswasm::validate(&module).err().unwrap_or_default(),
+            Err(e) => vec![e],
+        };
+        for e in &errors {
+            eprintln!("{}:{}", path, e);
+        }
+        failed |= !errors.is_empty();
+    }
+    if failed {
+        exit(1);
+    }
+}
@@ -0,0 +1,36 @@
+// Prints an MS-Wasm binary in the text format, as mswasm-wabt's wasm2wat
+// does:
+//
+//     mswasm-wasm2wat hello.wasm [-o hello.wat]
+
+use std::process::exit;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, output) = match &args[..] {
+        [input] => (input, None),
+        [input, o, output] if o == "-o" => (input, Some(output)),
+        _ => {
+            eprintln!("usage: mswasm-wasm2wat <file.wasm> [-o <file.wat>]");
+            exit(2);
+        }
+    };
+    let bytes = std::fs::read(input).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        exit(1);
+    });
+    let module = mswasm::parse(&bytes).unwrap_or_else(|e| {
+        eprintln!("{}:{}", input, e);
+        exit(1);
+    });
+    let text = mswasm::print_wat(&module);
+    match output {
+        Some(output) => {
+            if let Err(e) = std::fs::write(output, text) {
+                eprintln!("{}: {}", output, e);
+                exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+}
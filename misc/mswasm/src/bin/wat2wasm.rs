@@ -0,0 +1,31 @@
+// Assembles a module in the MS-Wasm text format, writing it next to the
+// input with a .wasm extension unless told otherwise:
+//
+//     mswasm-wat2wasm hello.wat [-o hello.wasm]
+
+use std::path::Path;
+use std::process::exit;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, output) = match &args[..] {
+        [input] => (input, Path::new(input).with_extension("wasm")),
+        [input, o, output] if o == "-o" => (input, output.into()),
+        _ => {
+            eprintln!("usage: mswasm-wat2wasm <file.wat> [-o <file.wasm>]");
+            exit(2);
+        }
+    };
+    let text = std::fs::read_to_string(input).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        exit(1);
+    });
+    let module = mswasm::parse_wat(&text).unwrap_or_else(|e| {
+        eprintln!("{}:{}", input, e);
+        exit(1);
+    });
+    if let Err(e) = std::fs::write(&output, mswasm::encode(&module)) {
+        eprintln!("{}: {}", output.display(), e);
+        exit(1);
+    }
+}
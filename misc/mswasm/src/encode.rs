@@ -0,0 +1,473 @@
+use crate::instr::*;
+use crate::module::*;
+use crate::types::*;
+
+// Writes a module as a binary, with the shortest encoding of every integer
+// and custom sections back after the sections they followed. Binaries that
+// `parse` reads back as the same module come out byte for byte as they went
+// in, unless their integers were padded (as wasm-ld leaves the relocated
+// ones).
+//
+// Data segments get their pointers when the module uses handles, which is
+// how `parse` tells MS-Wasm binaries.
+pub fn encode(m: &Module) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.bytes.extend_from_slice(b"\0asm\x01\0\0\0");
+    w.customs(m, None);
+    for id in SectionId::ORDER.iter().copied() {
+        let mut s = Writer::default();
+        if s.section_body(m, id) {
+            w.byte(id.byte());
+            w.u32(s.bytes.len() as u32);
+            w.bytes.extend_from_slice(&s.bytes);
+        }
+        w.customs(m, Some(id));
+    }
+    w.bytes
+}
+
+// The contents of a `name` section with the given names, or None if there
+// are none
+pub(crate) fn name_section(names: &Names) -> Option<Vec<u8>> {
+    let mut w = Writer::default();
+    for (id, map) in &[(1, &names.funcs), (7, &names.globals), (9, &names.datas)] {
+        if map.is_empty() {
+            continue;
+        }
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort();
+        let mut s = Writer::default();
+        s.len(entries.len());
+        for (&index, name) in entries {
+            s.u32(index);
+            s.name(name);
+        }
+        w.byte(*id);
+        w.len(s.bytes.len());
+        w.bytes.extend_from_slice(&s.bytes);
+    }
+    if w.bytes.is_empty() {
+        None
+    } else {
+        Some(w.bytes)
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, b: u8) {
+        self.bytes.push(b);
+    }
+
+    fn u32(&mut self, mut v: u32) {
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                return self.byte(b);
+            }
+            self.byte(b | 0x80);
+        }
+    }
+
+    fn s64(&mut self, mut v: i64) {
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+                return self.byte(b);
+            }
+            self.byte(b | 0x80);
+        }
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn name(&mut self, name: &str) {
+        self.len(name.len());
+        self.bytes.extend_from_slice(name.as_bytes());
+    }
+
+    fn val_types(&mut self, types: &[ValType]) {
+        self.len(types.len());
+        for t in types {
+            self.byte(t.byte());
+        }
+    }
+
+    fn limits(&mut self, limits: Limits) {
+        match limits.max {
+            None => {
+                self.byte(0);
+                self.u32(limits.min);
+            }
+            Some(max) => {
+                self.byte(1);
+                self.u32(limits.min);
+                self.u32(max);
+            }
+        }
+    }
+
+    fn table_type(&mut self, t: TableType) {
+        self.byte(t.elem.byte());
+        self.limits(t.limits);
+    }
+
+    fn global_type(&mut self, t: GlobalType) {
+        self.byte(t.ty.byte());
+        self.byte(t.mutable as u8);
+    }
+
+    fn const_expr(&mut self, expr: &ConstExpr) {
+        self.instr(&match *expr {
+            ConstExpr::I32(v) => Instr::I32Const(v),
+            ConstExpr::I64(v) => Instr::I64Const(v),
+            ConstExpr::F32(v) => Instr::F32Const(v),
+            ConstExpr::F64(v) => Instr::F64Const(v),
+            ConstExpr::GlobalGet(g) => Instr::GlobalGet(g),
+            ConstExpr::HandleNull => Instr::HandleNull,
+        });
+        self.instr(&Instr::End);
+    }
+
+    fn mem_arg(&mut self, arg: MemArg) {
+        self.u32(arg.align);
+        self.u32(arg.offset);
+    }
+
+    fn misc(&mut self, sub: u32) {
+        self.byte(MISC_PREFIX);
+        self.u32(sub);
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match instr {
+            Instr::Unreachable => self.byte(0x00),
+            Instr::Nop => self.byte(0x01),
+            Instr::Block(bt) | Instr::Loop(bt) | Instr::If(bt) => {
+                self.byte(match instr {
+                    Instr::Block(_) => 0x02,
+                    Instr::Loop(_) => 0x03,
+                    _ => 0x04,
+                });
+                match *bt {
+                    BlockType::Empty => self.byte(0x40),
+                    BlockType::Value(t) => self.byte(t.byte()),
+                    BlockType::Func(index) => self.s64(index as i64),
+                }
+            }
+            Instr::Else => self.byte(0x05),
+            Instr::End => self.byte(0x0b),
+            Instr::Br(l) => {
+                self.byte(0x0c);
+                self.u32(*l);
+            }
+            Instr::BrIf(l) => {
+                self.byte(0x0d);
+                self.u32(*l);
+            }
+            Instr::BrTable(labels, default) => {
+                self.byte(0x0e);
+                self.len(labels.len());
+                for &l in labels {
+                    self.u32(l);
+                }
+                self.u32(*default);
+            }
+            Instr::Return => self.byte(0x0f),
+            Instr::Call(f) => {
+                self.byte(0x10);
+                self.u32(*f);
+            }
+            Instr::CallIndirect { ty, table } => {
+                self.byte(0x11);
+                self.u32(*ty);
+                self.u32(*table);
+            }
+            Instr::Drop => self.byte(0x1a),
+            Instr::Select => self.byte(0x1b),
+            Instr::LocalGet(i)
+            | Instr::LocalSet(i)
+            | Instr::LocalTee(i)
+            | Instr::GlobalGet(i)
+            | Instr::GlobalSet(i) => {
+                self.byte(match instr {
+                    Instr::LocalGet(_) => 0x20,
+                    Instr::LocalSet(_) => 0x21,
+                    Instr::LocalTee(_) => 0x22,
+                    Instr::GlobalGet(_) => 0x23,
+                    _ => 0x24,
+                });
+                self.u32(*i);
+            }
+            Instr::Memory(op, arg) => {
+                self.byte(op.opcode());
+                self.mem_arg(*arg);
+            }
+            Instr::MemorySize => self.bytes.extend_from_slice(&[0x3f, 0]),
+            Instr::MemoryGrow => self.bytes.extend_from_slice(&[0x40, 0]),
+            Instr::I32Const(v) => {
+                self.byte(0x41);
+                self.s64(*v as i64);
+            }
+            Instr::I64Const(v) => {
+                self.byte(0x42);
+                self.s64(*v);
+            }
+            Instr::F32Const(bits) => {
+                self.byte(0x43);
+                self.bytes.extend_from_slice(&bits.to_le_bytes());
+            }
+            Instr::F64Const(bits) => {
+                self.byte(0x44);
+                self.bytes.extend_from_slice(&bits.to_le_bytes());
+            }
+            Instr::Numeric(op) => match op.opcode() {
+                op if op >= 0xfc00 => self.misc(op - 0xfc00),
+                op => self.byte(op as u8),
+            },
+            Instr::MemoryInit(data) => {
+                self.misc(8);
+                self.u32(*data);
+                self.byte(0);
+            }
+            Instr::DataDrop(data) => {
+                self.misc(9);
+                self.u32(*data);
+            }
+            Instr::MemoryCopy => {
+                self.misc(10);
+                self.bytes.extend_from_slice(&[0, 0]);
+            }
+            Instr::MemoryFill => {
+                self.misc(11);
+                self.byte(0);
+            }
+            Instr::NewSegment => self.byte(NEW_SEGMENT),
+            Instr::FreeSegment => self.byte(FREE_SEGMENT),
+            Instr::HandleLoad(arg) => {
+                self.byte(HANDLE_LOAD);
+                self.mem_arg(*arg);
+            }
+            Instr::HandleStore(arg) => {
+                self.byte(HANDLE_STORE);
+                self.mem_arg(*arg);
+            }
+            Instr::HandleAdd => self.byte(HANDLE_ADD),
+            Instr::HandleNull => self.byte(HANDLE_NULL),
+            Instr::HandleEq => self.byte(HANDLE_EQ),
+            Instr::HandleLt => self.byte(HANDLE_LT),
+            Instr::HandleGetOffset => self.byte(HANDLE_GET_OFFSET),
+        }
+    }
+
+    fn body(&mut self, body: &Body) {
+        let mut b = Writer::default();
+        b.len(body.locals.len());
+        for &(n, t) in &body.locals {
+            b.u32(n);
+            b.byte(t.byte());
+        }
+        for instr in &body.instrs {
+            b.instr(instr);
+        }
+        self.len(b.bytes.len());
+        self.bytes.extend_from_slice(&b.bytes);
+    }
+
+    fn elem(&mut self, elem: &Elem) {
+        match &elem.mode {
+            ElemMode::Active { table: 0, offset } => {
+                self.byte(0);
+                self.const_expr(offset);
+            }
+            ElemMode::Active { table, offset } => {
+                self.byte(2);
+                self.u32(*table);
+                self.const_expr(offset);
+                self.byte(0);
+            }
+            ElemMode::Passive => self.bytes.extend_from_slice(&[1, 0]),
+            ElemMode::Declarative => self.bytes.extend_from_slice(&[3, 0]),
+        }
+        self.len(elem.funcs.len());
+        for &f in &elem.funcs {
+            self.u32(f);
+        }
+    }
+
+    fn data(&mut self, data: &Data, mswasm: bool) {
+        match &data.mode {
+            DataMode::Active { memory: 0, offset } => {
+                self.byte(0);
+                self.const_expr(offset);
+            }
+            DataMode::Active { memory, offset } => {
+                self.byte(2);
+                self.u32(*memory);
+                self.const_expr(offset);
+            }
+            DataMode::Passive => self.byte(1),
+        }
+        if mswasm {
+            let pointers = data.pointers.as_deref().unwrap_or_default();
+            self.len(2 * pointers.len());
+            for p in pointers {
+                self.u32(p.offset);
+                self.u32(p.size);
+            }
+        }
+        self.len(data.bytes.len());
+        self.bytes.extend_from_slice(&data.bytes);
+    }
+
+    fn customs(&mut self, m: &Module, after: Option<SectionId>) {
+        for c in m.customs.iter().filter(|c| c.after == after) {
+            let mut s = Writer::default();
+            s.name(&c.name);
+            s.bytes.extend_from_slice(&c.data);
+            self.byte(0);
+            self.len(s.bytes.len());
+            self.bytes.extend_from_slice(&s.bytes);
+        }
+    }
+
+    // Writes the contents of a section, returning whether the module has
+    // anything to put in it
+    fn section_body(&mut self, m: &Module, id: SectionId) -> bool {
+        match id {
+            SectionId::Custom => return false,
+            SectionId::Type if !m.types.is_empty() => {
+                self.len(m.types.len());
+                for t in &m.types {
+                    self.byte(0x60);
+                    self.val_types(&t.params);
+                    self.val_types(&t.results);
+                }
+            }
+            SectionId::Import if !m.imports.is_empty() => {
+                self.len(m.imports.len());
+                for i in &m.imports {
+                    self.name(&i.module);
+                    self.name(&i.field);
+                    match i.desc {
+                        ImportDesc::Func(ty) => {
+                            self.byte(0);
+                            self.u32(ty);
+                        }
+                        ImportDesc::Table(t) => {
+                            self.byte(1);
+                            self.table_type(t);
+                        }
+                        ImportDesc::Memory(t) => {
+                            self.byte(2);
+                            self.limits(t.limits);
+                        }
+                        ImportDesc::Global(t) => {
+                            self.byte(3);
+                            self.global_type(t);
+                        }
+                    }
+                }
+            }
+            SectionId::Function if !m.funcs.is_empty() => {
+                self.len(m.funcs.len());
+                for &ty in &m.funcs {
+                    self.u32(ty);
+                }
+            }
+            SectionId::Table if !m.tables.is_empty() => {
+                self.len(m.tables.len());
+                for &t in &m.tables {
+                    self.table_type(t);
+                }
+            }
+            SectionId::Memory if !m.memories.is_empty() => {
+                self.len(m.memories.len());
+                for t in &m.memories {
+                    self.limits(t.limits);
+                }
+            }
+            SectionId::Global if !m.globals.is_empty() => {
+                self.len(m.globals.len());
+                for g in &m.globals {
+                    self.global_type(g.ty);
+                    self.const_expr(&g.init);
+                }
+            }
+            SectionId::Export if !m.exports.is_empty() => {
+                self.len(m.exports.len());
+                for e in &m.exports {
+                    self.name(&e.name);
+                    self.byte(match e.kind {
+                        ExternKind::Func => 0,
+                        ExternKind::Table => 1,
+                        ExternKind::Memory => 2,
+                        ExternKind::Global => 3,
+                    });
+                    self.u32(e.index);
+                }
+            }
+            SectionId::Start => match m.start {
+                Some(start) => self.u32(start),
+                None => return false,
+            },
+            SectionId::Element if !m.elems.is_empty() => {
+                self.len(m.elems.len());
+                for e in &m.elems {
+                    self.elem(e);
+                }
+            }
+            SectionId::DataCount => match m.data_count {
+                Some(count) => self.u32(count),
+                None => return false,
+            },
+            SectionId::Code if !m.code.is_empty() => {
+                self.len(m.code.len());
+                for body in &m.code {
+                    self.body(body);
+                }
+            }
+            SectionId::Data if !m.datas.is_empty() => {
+                let mswasm = m.uses_handles();
+                self.len(m.datas.len());
+                for d in &m.datas {
+                    self.data(d, mswasm);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn round_trips() {
+        let bytes = include_bytes!("../../rWasm_files/examples/0002-call_and_tables.wasm");
+        assert_eq!(encode(&parse(bytes).unwrap()), &bytes[..]);
+        for path in crate::repo_files("wasm") {
+            let bytes = std::fs::read(&path).unwrap();
+            let module = parse(&bytes).unwrap();
+            let encoded = encode(&module);
+            if encoded != bytes {
+                // wasm-ld pads its LEBs, so only the module comes back the same
+                assert!(encoded.len() < bytes.len(), "{}", path.display());
+                let mut again = parse(&encoded).unwrap();
+                again.offsets = module.offsets.clone();
+                assert!(again == module, "{}", path.display());
+            }
+        }
+    }
+}
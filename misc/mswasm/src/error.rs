@@ -0,0 +1,222 @@
+use crate::module::SectionId;
+use crate::types::ValType;
+use std::fmt;
+
+// An error in a binary or a module, with where it is. Offsets are into the
+// binary; they are 0 for modules not parsed from one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    pub offset: usize,
+    pub section: Option<SectionId>,
+    // The function the error is in, as an index that counts imports
+    pub func: Option<u32>,
+    pub func_name: Option<String>,
+    pub kind: ErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedEof,
+    BadMagic,
+    BadVersion(u32),
+    IntegerTooLong,
+    IntegerTooLarge,
+    InvalidUtf8,
+    UnknownSection(u8),
+    UnexpectedSection(SectionId),
+    SectionSizeMismatch,
+    // Misc-prefixed opcodes are `0xfc00 | n`
+    UnknownOpcode(u32),
+    UnknownValType(u8),
+    // Anything else wrong with the encoding
+    Malformed(String),
+    // The operands of an instruction are not of the types it takes.
+    // Operands of any type, under unreachable code, are `None`.
+    TypeMismatch {
+        instr: &'static str,
+        expected: Vec<ValType>,
+        got: Vec<Option<ValType>>,
+    },
+    // What a block leaves on the stack is not what its type says
+    EndMismatch {
+        block: &'static str,
+        expected: Vec<ValType>,
+        got: Vec<Option<ValType>>,
+    },
+    Unknown {
+        what: &'static str,
+        index: u32,
+    },
+    ImmutableGlobal(u32),
+    // Anything else against the typing rules
+    Invalid(String),
+}
+
+// An error in a module in the text format, at a line and column (both
+// counted from 1) of it
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl TextError {
+    pub(crate) fn at(text: &str, offset: usize, message: String) -> Self {
+        let before = &text[..offset.min(text.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        TextError {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn new(offset: usize, section: Option<SectionId>, kind: ErrorKind) -> Self {
+        Error {
+            offset,
+            section,
+            func: None,
+            func_name: None,
+            kind,
+        }
+    }
+}
+
+struct Got<'a>(&'a [Option<ValType>]);
+
+impl fmt::Display for Got<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, t) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match t {
+                Some(t) => write!(f, "{}", t)?,
+                None => f.write_str("any")?,
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+impl ErrorKind {
+    // What usually causes the error, for the mistakes in handle use that
+    // compilers targeting MS-Wasm are known to make
+    pub fn hint(&self) -> Option<&'static str> {
+        let (instr, expected, got) = match self {
+            ErrorKind::TypeMismatch {
+                instr,
+                expected,
+                got,
+            } => (*instr, expected, got),
+            _ => return None,
+        };
+        let pairs = || {
+            expected
+                .iter()
+                .zip(got)
+                .filter_map(|(&e, &g)| Some((e, g?)))
+        };
+        if pairs().any(|p| p == (ValType::I32, ValType::Handle)) {
+            if instr == "call_indirect" {
+                return Some(
+                    "call_indirect takes a table index; function pointers are i32 indices \
+                     into the table in MS-Wasm, not handles",
+                );
+            }
+            return Some(
+                "a handle is not an integer; use handle.get_offset for the offset it \
+                 points at, or handle.eq/handle.lt to compare it",
+            );
+        }
+        if pairs().any(|(e, g)| e == ValType::Handle && (g == ValType::I32 || g == ValType::I64)) {
+            return Some(
+                "integers cannot be turned into handles; derive the address from a \
+                 handle to the segment with handle.add",
+            );
+        }
+        if instr == "handle.add" && pairs().any(|p| p == (ValType::I32, ValType::I64)) {
+            return Some("handle.add takes an i32 offset; wrap 64-bit offsets with i32.wrap_i64");
+        }
+        None
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedEof => f.write_str("unexpected end of input"),
+            ErrorKind::BadMagic => f.write_str("bad magic value"),
+            ErrorKind::BadVersion(v) => write!(f, "bad version {}", v),
+            ErrorKind::IntegerTooLong => f.write_str("integer representation too long"),
+            ErrorKind::IntegerTooLarge => f.write_str("integer too large"),
+            ErrorKind::InvalidUtf8 => f.write_str("invalid UTF-8 encoding"),
+            ErrorKind::UnknownSection(id) => write!(f, "unknown section id {}", id),
+            ErrorKind::UnexpectedSection(id) => write!(f, "unexpected {} section", id),
+            ErrorKind::SectionSizeMismatch => f.write_str("section size mismatch"),
+            ErrorKind::UnknownOpcode(op) if *op >= 0xfc00 => {
+                write!(f, "unexpected opcode 0xfc 0x{:x}", op - 0xfc00)
+            }
+            ErrorKind::UnknownOpcode(op) => write!(f, "unexpected opcode 0x{:x}", op),
+            ErrorKind::UnknownValType(b) => write!(f, "invalid value type 0x{:x}", b),
+            ErrorKind::Malformed(msg) | ErrorKind::Invalid(msg) => f.write_str(msg),
+            ErrorKind::TypeMismatch {
+                instr,
+                expected,
+                got,
+            } => write!(
+                f,
+                "type mismatch in {}, expected {} but got {}",
+                instr,
+                crate::types::Types(expected),
+                Got(got)
+            ),
+            ErrorKind::EndMismatch {
+                block,
+                expected,
+                got,
+            } => write!(
+                f,
+                "type mismatch at end of {}, expected {} but got {}",
+                block,
+                crate::types::Types(expected),
+                Got(got)
+            ),
+            ErrorKind::Unknown { what, index } => write!(f, "unknown {} {}", what, index),
+            ErrorKind::ImmutableGlobal(index) => write!(f, "global {} is immutable", index),
+        }
+    }
+}
+
+// As wabt prints them, `000025d: error: ...`, with the function and the
+// hint on a line of their own
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:07x}: error: {}", self.offset, self.kind)?;
+        match (self.func, &self.func_name, self.section) {
+            (Some(func), Some(name), _) => write!(f, "\n  in function {} ({})", func, name)?,
+            (Some(func), None, _) => write!(f, "\n  in function {}", func)?,
+            (None, _, Some(section)) => write!(f, "\n  in the {} section", section)?,
+            (None, _, None) => {}
+        }
+        if let Some(hint) = self.kind.hint() {
+            write!(f, "\n  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+// As wabt prints them, `3:5: error: ...`
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for TextError {}
@@ -0,0 +1,269 @@
+// Float literals of the text format, as wabt writes and reads them: exact
+// hexadecimal for what is printed, decimal or hexadecimal for what is read.
+
+#[derive(Copy, Clone)]
+pub(crate) struct Format {
+    sig_bits: u32,
+    exp_bits: u32,
+}
+
+pub(crate) const F32: Format = Format {
+    sig_bits: 23,
+    exp_bits: 8,
+};
+
+pub(crate) const F64: Format = Format {
+    sig_bits: 52,
+    exp_bits: 11,
+};
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn max_exp(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn sig_mask(self) -> u64 {
+        (1 << self.sig_bits) - 1
+    }
+
+    fn quiet_nan(self) -> u64 {
+        1 << (self.sig_bits - 1)
+    }
+}
+
+// `0x1.8p+0`, `-0x1p-149`, `inf`, `nan`, `nan:0x200000`
+pub(crate) fn to_hex(bits: u64, f: Format) -> String {
+    let mut s = String::new();
+    let total = f.sig_bits + f.exp_bits;
+    if bits >> total & 1 == 1 {
+        s.push('-');
+    }
+    let exp_field = bits >> f.sig_bits & f.max_exp();
+    let mut sig = bits & f.sig_mask();
+    if exp_field == f.max_exp() {
+        if sig == 0 {
+            s.push_str("inf");
+        } else {
+            s.push_str("nan");
+            if sig != f.quiet_nan() {
+                s.push_str(&format!(":0x{:x}", sig));
+            }
+        }
+        return s;
+    }
+    if exp_field == 0 && sig == 0 {
+        s.push_str("0x0p+0");
+        return s;
+    }
+    let mut exp = exp_field as i32 - f.bias();
+    // The fraction, left aligned, with the leading 1 of subnormals shifted
+    // out into the exponent
+    sig <<= 64 - f.sig_bits;
+    if exp_field == 0 {
+        let zeros = sig.leading_zeros();
+        sig = sig.checked_shl(zeros + 1).unwrap_or(0);
+        exp -= zeros as i32;
+    }
+    s.push_str("0x1");
+    if sig != 0 {
+        s.push('.');
+        while sig != 0 {
+            s.push(std::char::from_digit((sig >> 60) as u32, 16).unwrap());
+            sig <<= 4;
+        }
+    }
+    s.push_str(&format!(
+        "p{}{}",
+        if exp < 0 { '-' } else { '+' },
+        exp.abs()
+    ));
+    s
+}
+
+// The value as C's `%g` prints it, for the comments wabt adds to constants
+pub(crate) fn to_g(v: f64) -> String {
+    if v.is_nan() {
+        return if v.is_sign_negative() { "-nan" } else { "nan" }.into();
+    }
+    if v.is_infinite() {
+        return if v < 0.0 { "-inf" } else { "inf" }.into();
+    }
+    if v == 0.0 {
+        return if v.is_sign_negative() { "-0" } else { "0" }.into();
+    }
+    let sci = format!("{:.5e}", v);
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    let trim = |s: &str| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    if (-4..6).contains(&exp) {
+        trim(&format!("{:.*}", (5 - exp) as usize, v))
+    } else {
+        format!(
+            "{}e{}{:02}",
+            trim(mantissa),
+            if exp < 0 { '-' } else { '+' },
+            exp.abs()
+        )
+    }
+}
+
+// Reads a float literal (with any `_` separators already removed) as the
+// bits of the format, or None if it is malformed or out of range
+pub(crate) fn parse(text: &str, f: Format) -> Option<u64> {
+    let (negative, body) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let sign = (negative as u64) << (f.sig_bits + f.exp_bits);
+    let inf = f.max_exp() << f.sig_bits;
+    let magnitude = if body == "inf" {
+        inf
+    } else if body == "nan" {
+        inf | f.quiet_nan()
+    } else if let Some(payload) = body.strip_prefix("nan:0x") {
+        let payload = u64::from_str_radix(payload, 16).ok()?;
+        if payload == 0 || payload > f.sig_mask() {
+            return None;
+        }
+        inf | payload
+    } else if let Some(hex) = body.strip_prefix("0x") {
+        parse_hex(hex, f)?
+    } else {
+        if !body.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let bits = if f.sig_bits == F32.sig_bits {
+            body.parse::<f32>().ok()?.to_bits() as u64
+        } else {
+            body.parse::<f64>().ok()?.to_bits()
+        };
+        if bits == inf {
+            return None;
+        }
+        bits
+    };
+    Some(sign | magnitude)
+}
+
+fn parse_hex(hex: &str, f: Format) -> Option<u64> {
+    let (digits, exp) = match hex.find(['p', 'P']) {
+        Some(p) => (&hex[..p], hex[p + 1..].parse::<i64>().ok()?),
+        None => (hex, 0),
+    };
+    let (int, frac) = match digits.find('.') {
+        Some(dot) => (&digits[..dot], &digits[dot + 1..]),
+        None => (digits, ""),
+    };
+    if int.is_empty() {
+        return None;
+    }
+    // The digits as sig * 2^exp, with the ones that do not fit only
+    // remembered as nonzero or not
+    let mut sig = 0u64;
+    let mut exp = exp.clamp(-100_000, 100_000) as i32;
+    let mut sticky = false;
+    for (i, c) in int.chars().chain(frac.chars()).enumerate() {
+        let d = c.to_digit(16)? as u64;
+        let fractional = i >= int.len();
+        if sig >> 60 == 0 {
+            sig = sig << 4 | d;
+            if fractional {
+                exp -= 4;
+            }
+        } else {
+            sticky |= d != 0;
+            if !fractional {
+                exp += 4;
+            }
+        }
+    }
+    if sig == 0 {
+        return Some(0);
+    }
+    let top = 63 - sig.leading_zeros() as i32;
+    let bias = f.bias();
+    let sig_bits = f.sig_bits as i32;
+    // Exponent of the lowest bit the format keeps for this value
+    let lsb = (top + exp).max(1 - bias) - sig_bits;
+    let drop = lsb - exp;
+    let mut mant = if drop <= 0 {
+        sig << -drop
+    } else if drop > 64 {
+        // Less than half of the lowest bit
+        0
+    } else {
+        let kept = sig.checked_shr(drop as u32).unwrap_or(0);
+        let rest = sig & (u64::MAX >> (64 - drop));
+        let half = 1 << (drop - 1);
+        let round_up = rest > half || (rest == half && (sticky || kept & 1 == 1));
+        kept + round_up as u64
+    };
+    let mut e = lsb + sig_bits;
+    if mant >> (sig_bits + 1) != 0 {
+        mant >>= 1;
+        e += 1;
+    }
+    if mant >> sig_bits == 0 {
+        // Subnormal, whose exponent field is 0
+        return Some(mant);
+    }
+    let field = (e + bias) as u64;
+    if field >= f.max_exp() {
+        return None;
+    }
+    Some(field << f.sig_bits | (mant & f.sig_mask()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips() {
+        for &v in &[
+            0.0,
+            -0.0,
+            1.0,
+            1.5,
+            1e9,
+            4294967296.0,
+            9007199254740993.0,
+            5e-324,
+        ] {
+            let bits = f64::to_bits(v);
+            let hex = to_hex(bits, F64);
+            assert_eq!(parse(&hex, F64), Some(bits), "{}", hex);
+        }
+        assert_eq!(to_hex(f64::to_bits(1e9), F64), "0x1.dcd65p+29");
+        assert_eq!(
+            to_hex(f32::to_bits(f32::MIN_POSITIVE / 4.0) as u64, F32),
+            "0x1p-128"
+        );
+        assert_eq!(to_hex(0x7fc0_0000, F32), "nan");
+        assert_eq!(to_hex(0xffa0_0000, F32), "-nan:0x200000");
+        assert_eq!(parse("0x1.000001p+0", F32), Some(0x3f80_0000));
+        assert_eq!(parse("0x1.000003p+0", F32), Some(0x3f80_0002));
+        assert_eq!(parse("0x1p+128", F32), None);
+    }
+
+    #[test]
+    fn g_format() {
+        assert_eq!(to_g(4294967296.0), "4.29497e+09");
+        assert_eq!(to_g(9007199254740992.0), "9.0072e+15");
+        assert_eq!(to_g(1e9), "1e+09");
+        assert_eq!(to_g(0.5), "0.5");
+        assert_eq!(to_g(16.0), "16");
+        assert_eq!(to_g(0.0001), "0.0001");
+    }
+}
@@ -0,0 +1,422 @@
+use crate::types::{BlockType, ValType};
+
+// Opcodes MS-Wasm adds to Wasm, as mswasm-llvm emits them and mswasm-wabt
+// prints them. 0xfc stays the prefix of the Wasm "misc" instructions.
+pub const NEW_SEGMENT: u8 = 0xf4;
+pub const FREE_SEGMENT: u8 = 0xf5;
+pub const HANDLE_LOAD: u8 = 0xf7;
+pub const HANDLE_STORE: u8 = 0xf8;
+pub const HANDLE_ADD: u8 = 0xf9;
+pub const HANDLE_NULL: u8 = 0xfb;
+pub const MISC_PREFIX: u8 = 0xfc;
+pub const HANDLE_EQ: u8 = 0xfd;
+pub const HANDLE_LT: u8 = 0xfe;
+pub const HANDLE_GET_OFFSET: u8 = 0xff;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemArg {
+    // log2 of the alignment
+    pub align: u32,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Nop,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    CallIndirect { ty: u32, table: u32 },
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    Memory(MemOp, MemArg),
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    // Floats are kept as their bits, so that NaN payloads survive
+    F32Const(u32),
+    F64Const(u64),
+    Numeric(NumOp),
+    MemoryInit(u32),
+    DataDrop(u32),
+    MemoryCopy,
+    MemoryFill,
+    NewSegment,
+    FreeSegment,
+    HandleLoad(MemArg),
+    HandleStore(MemArg),
+    HandleAdd,
+    HandleNull,
+    HandleEq,
+    HandleLt,
+    HandleGetOffset,
+}
+
+impl Instr {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instr::Unreachable => "unreachable",
+            Instr::Nop => "nop",
+            Instr::Block(_) => "block",
+            Instr::Loop(_) => "loop",
+            Instr::If(_) => "if",
+            Instr::Else => "else",
+            Instr::End => "end",
+            Instr::Br(_) => "br",
+            Instr::BrIf(_) => "br_if",
+            Instr::BrTable(..) => "br_table",
+            Instr::Return => "return",
+            Instr::Call(_) => "call",
+            Instr::CallIndirect { .. } => "call_indirect",
+            Instr::Drop => "drop",
+            Instr::Select => "select",
+            Instr::LocalGet(_) => "local.get",
+            Instr::LocalSet(_) => "local.set",
+            Instr::LocalTee(_) => "local.tee",
+            Instr::GlobalGet(_) => "global.get",
+            Instr::GlobalSet(_) => "global.set",
+            Instr::Memory(op, _) => op.name(),
+            Instr::MemorySize => "memory.size",
+            Instr::MemoryGrow => "memory.grow",
+            Instr::I32Const(_) => "i32.const",
+            Instr::I64Const(_) => "i64.const",
+            Instr::F32Const(_) => "f32.const",
+            Instr::F64Const(_) => "f64.const",
+            Instr::Numeric(op) => op.name(),
+            Instr::MemoryInit(_) => "memory.init",
+            Instr::DataDrop(_) => "data.drop",
+            Instr::MemoryCopy => "memory.copy",
+            Instr::MemoryFill => "memory.fill",
+            Instr::NewSegment => "new_segment",
+            Instr::FreeSegment => "free_segment",
+            Instr::HandleLoad(_) => "handle.load",
+            Instr::HandleStore(_) => "handle.store",
+            Instr::HandleAdd => "handle.add",
+            Instr::HandleNull => "handle.null",
+            Instr::HandleEq => "handle.eq",
+            Instr::HandleLt => "handle.lt",
+            Instr::HandleGetOffset => "handle.get_offset",
+        }
+    }
+
+    // Operand and result types of the instructions whose typing depends on
+    // nothing but the instruction itself
+    pub fn signature(&self) -> Option<(&'static [ValType], &'static [ValType])> {
+        use ValType::*;
+        Some(match self {
+            Instr::Nop => (&[], &[]),
+            Instr::I32Const(_) => (&[], &[I32]),
+            Instr::I64Const(_) => (&[], &[I64]),
+            Instr::F32Const(_) => (&[], &[F32]),
+            Instr::F64Const(_) => (&[], &[F64]),
+            Instr::Numeric(op) => op.signature(),
+            Instr::NewSegment => (&[I32], &[Handle]),
+            Instr::FreeSegment => (&[Handle], &[]),
+            Instr::HandleLoad(_) => (&[Handle], &[Handle]),
+            Instr::HandleStore(_) => (&[Handle, Handle], &[]),
+            Instr::HandleAdd => (&[Handle, I32], &[Handle]),
+            Instr::HandleNull => (&[], &[Handle]),
+            Instr::HandleEq | Instr::HandleLt => (&[Handle, Handle], &[I32]),
+            Instr::HandleGetOffset => (&[Handle], &[I32]),
+            _ => return None,
+        })
+    }
+
+    pub fn is_mswasm(&self) -> bool {
+        matches!(
+            self,
+            Instr::NewSegment
+                | Instr::FreeSegment
+                | Instr::HandleLoad(_)
+                | Instr::HandleStore(_)
+                | Instr::HandleAdd
+                | Instr::HandleNull
+                | Instr::HandleEq
+                | Instr::HandleLt
+                | Instr::HandleGetOffset
+        )
+    }
+}
+
+macro_rules! memory_ops {
+    ($($opcode:literal $op:ident $name:literal $ty:ident $bytes:literal $store:literal,)*) => {
+        // Loads and stores through linear memory (through handles in MS-Wasm)
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub enum MemOp {
+            $($op,)*
+        }
+
+        impl MemOp {
+            pub fn from_opcode(opcode: u8) -> Option<Self> {
+                match opcode {
+                    $($opcode => Some(MemOp::$op),)*
+                    _ => None,
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(MemOp::$op),)*
+                    _ => None,
+                }
+            }
+
+            pub fn opcode(self) -> u8 {
+                match self {
+                    $(MemOp::$op => $opcode,)*
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(MemOp::$op => $name,)*
+                }
+            }
+
+            // Type of the value loaded or stored
+            pub fn ty(self) -> ValType {
+                match self {
+                    $(MemOp::$op => ValType::$ty,)*
+                }
+            }
+
+            // Number of bytes accessed, which is also the largest alignment
+            pub fn bytes(self) -> u32 {
+                match self {
+                    $(MemOp::$op => $bytes,)*
+                }
+            }
+
+            pub fn is_store(self) -> bool {
+                match self {
+                    $(MemOp::$op => $store,)*
+                }
+            }
+        }
+    };
+}
+
+memory_ops! {
+    0x28 I32Load "i32.load" I32 4 false,
+    0x29 I64Load "i64.load" I64 8 false,
+    0x2a F32Load "f32.load" F32 4 false,
+    0x2b F64Load "f64.load" F64 8 false,
+    0x2c I32Load8S "i32.load8_s" I32 1 false,
+    0x2d I32Load8U "i32.load8_u" I32 1 false,
+    0x2e I32Load16S "i32.load16_s" I32 2 false,
+    0x2f I32Load16U "i32.load16_u" I32 2 false,
+    0x30 I64Load8S "i64.load8_s" I64 1 false,
+    0x31 I64Load8U "i64.load8_u" I64 1 false,
+    0x32 I64Load16S "i64.load16_s" I64 2 false,
+    0x33 I64Load16U "i64.load16_u" I64 2 false,
+    0x34 I64Load32S "i64.load32_s" I64 4 false,
+    0x35 I64Load32U "i64.load32_u" I64 4 false,
+    0x36 I32Store "i32.store" I32 4 true,
+    0x37 I64Store "i64.store" I64 8 true,
+    0x38 F32Store "f32.store" F32 4 true,
+    0x39 F64Store "f64.store" F64 8 true,
+    0x3a I32Store8 "i32.store8" I32 1 true,
+    0x3b I32Store16 "i32.store16" I32 2 true,
+    0x3c I64Store8 "i64.store8" I64 1 true,
+    0x3d I64Store16 "i64.store16" I64 2 true,
+    0x3e I64Store32 "i64.store32" I64 4 true,
+}
+
+macro_rules! numeric_ops {
+    ($($opcode:literal $op:ident $name:literal [$($param:ident)*] [$($result:ident)*],)*) => {
+        // Instructions without immediates that only compute on their
+        // operands. The saturating truncations are encoded behind the misc
+        // prefix and have `0xfc00 | n` as their opcode here.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub enum NumOp {
+            $($op,)*
+        }
+
+        impl NumOp {
+            pub fn from_opcode(opcode: u32) -> Option<Self> {
+                match opcode {
+                    $($opcode => Some(NumOp::$op),)*
+                    _ => None,
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(NumOp::$op),)*
+                    _ => None,
+                }
+            }
+
+            pub fn opcode(self) -> u32 {
+                match self {
+                    $(NumOp::$op => $opcode,)*
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(NumOp::$op => $name,)*
+                }
+            }
+
+            pub fn signature(self) -> (&'static [ValType], &'static [ValType]) {
+                match self {
+                    $(NumOp::$op => (&[$(ValType::$param),*], &[$(ValType::$result),*]),)*
+                }
+            }
+        }
+    };
+}
+
+numeric_ops! {
+    0x45 I32Eqz "i32.eqz" [I32] [I32],
+    0x46 I32Eq "i32.eq" [I32 I32] [I32],
+    0x47 I32Ne "i32.ne" [I32 I32] [I32],
+    0x48 I32LtS "i32.lt_s" [I32 I32] [I32],
+    0x49 I32LtU "i32.lt_u" [I32 I32] [I32],
+    0x4a I32GtS "i32.gt_s" [I32 I32] [I32],
+    0x4b I32GtU "i32.gt_u" [I32 I32] [I32],
+    0x4c I32LeS "i32.le_s" [I32 I32] [I32],
+    0x4d I32LeU "i32.le_u" [I32 I32] [I32],
+    0x4e I32GeS "i32.ge_s" [I32 I32] [I32],
+    0x4f I32GeU "i32.ge_u" [I32 I32] [I32],
+    0x50 I64Eqz "i64.eqz" [I64] [I32],
+    0x51 I64Eq "i64.eq" [I64 I64] [I32],
+    0x52 I64Ne "i64.ne" [I64 I64] [I32],
+    0x53 I64LtS "i64.lt_s" [I64 I64] [I32],
+    0x54 I64LtU "i64.lt_u" [I64 I64] [I32],
+    0x55 I64GtS "i64.gt_s" [I64 I64] [I32],
+    0x56 I64GtU "i64.gt_u" [I64 I64] [I32],
+    0x57 I64LeS "i64.le_s" [I64 I64] [I32],
+    0x58 I64LeU "i64.le_u" [I64 I64] [I32],
+    0x59 I64GeS "i64.ge_s" [I64 I64] [I32],
+    0x5a I64GeU "i64.ge_u" [I64 I64] [I32],
+    0x5b F32Eq "f32.eq" [F32 F32] [I32],
+    0x5c F32Ne "f32.ne" [F32 F32] [I32],
+    0x5d F32Lt "f32.lt" [F32 F32] [I32],
+    0x5e F32Gt "f32.gt" [F32 F32] [I32],
+    0x5f F32Le "f32.le" [F32 F32] [I32],
+    0x60 F32Ge "f32.ge" [F32 F32] [I32],
+    0x61 F64Eq "f64.eq" [F64 F64] [I32],
+    0x62 F64Ne "f64.ne" [F64 F64] [I32],
+    0x63 F64Lt "f64.lt" [F64 F64] [I32],
+    0x64 F64Gt "f64.gt" [F64 F64] [I32],
+    0x65 F64Le "f64.le" [F64 F64] [I32],
+    0x66 F64Ge "f64.ge" [F64 F64] [I32],
+    0x67 I32Clz "i32.clz" [I32] [I32],
+    0x68 I32Ctz "i32.ctz" [I32] [I32],
+    0x69 I32Popcnt "i32.popcnt" [I32] [I32],
+    0x6a I32Add "i32.add" [I32 I32] [I32],
+    0x6b I32Sub "i32.sub" [I32 I32] [I32],
+    0x6c I32Mul "i32.mul" [I32 I32] [I32],
+    0x6d I32DivS "i32.div_s" [I32 I32] [I32],
+    0x6e I32DivU "i32.div_u" [I32 I32] [I32],
+    0x6f I32RemS "i32.rem_s" [I32 I32] [I32],
+    0x70 I32RemU "i32.rem_u" [I32 I32] [I32],
+    0x71 I32And "i32.and" [I32 I32] [I32],
+    0x72 I32Or "i32.or" [I32 I32] [I32],
+    0x73 I32Xor "i32.xor" [I32 I32] [I32],
+    0x74 I32Shl "i32.shl" [I32 I32] [I32],
+    0x75 I32ShrS "i32.shr_s" [I32 I32] [I32],
+    0x76 I32ShrU "i32.shr_u" [I32 I32] [I32],
+    0x77 I32Rotl "i32.rotl" [I32 I32] [I32],
+    0x78 I32Rotr "i32.rotr" [I32 I32] [I32],
+    0x79 I64Clz "i64.clz" [I64] [I64],
+    0x7a I64Ctz "i64.ctz" [I64] [I64],
+    0x7b I64Popcnt "i64.popcnt" [I64] [I64],
+    0x7c I64Add "i64.add" [I64 I64] [I64],
+    0x7d I64Sub "i64.sub" [I64 I64] [I64],
+    0x7e I64Mul "i64.mul" [I64 I64] [I64],
+    0x7f I64DivS "i64.div_s" [I64 I64] [I64],
+    0x80 I64DivU "i64.div_u" [I64 I64] [I64],
+    0x81 I64RemS "i64.rem_s" [I64 I64] [I64],
+    0x82 I64RemU "i64.rem_u" [I64 I64] [I64],
+    0x83 I64And "i64.and" [I64 I64] [I64],
+    0x84 I64Or "i64.or" [I64 I64] [I64],
+    0x85 I64Xor "i64.xor" [I64 I64] [I64],
+    0x86 I64Shl "i64.shl" [I64 I64] [I64],
+    0x87 I64ShrS "i64.shr_s" [I64 I64] [I64],
+    0x88 I64ShrU "i64.shr_u" [I64 I64] [I64],
+    0x89 I64Rotl "i64.rotl" [I64 I64] [I64],
+    0x8a I64Rotr "i64.rotr" [I64 I64] [I64],
+    0x8b F32Abs "f32.abs" [F32] [F32],
+    0x8c F32Neg "f32.neg" [F32] [F32],
+    0x8d F32Ceil "f32.ceil" [F32] [F32],
+    0x8e F32Floor "f32.floor" [F32] [F32],
+    0x8f F32Trunc "f32.trunc" [F32] [F32],
+    0x90 F32Nearest "f32.nearest" [F32] [F32],
+    0x91 F32Sqrt "f32.sqrt" [F32] [F32],
+    0x92 F32Add "f32.add" [F32 F32] [F32],
+    0x93 F32Sub "f32.sub" [F32 F32] [F32],
+    0x94 F32Mul "f32.mul" [F32 F32] [F32],
+    0x95 F32Div "f32.div" [F32 F32] [F32],
+    0x96 F32Min "f32.min" [F32 F32] [F32],
+    0x97 F32Max "f32.max" [F32 F32] [F32],
+    0x98 F32Copysign "f32.copysign" [F32 F32] [F32],
+    0x99 F64Abs "f64.abs" [F64] [F64],
+    0x9a F64Neg "f64.neg" [F64] [F64],
+    0x9b F64Ceil "f64.ceil" [F64] [F64],
+    0x9c F64Floor "f64.floor" [F64] [F64],
+    0x9d F64Trunc "f64.trunc" [F64] [F64],
+    0x9e F64Nearest "f64.nearest" [F64] [F64],
+    0x9f F64Sqrt "f64.sqrt" [F64] [F64],
+    0xa0 F64Add "f64.add" [F64 F64] [F64],
+    0xa1 F64Sub "f64.sub" [F64 F64] [F64],
+    0xa2 F64Mul "f64.mul" [F64 F64] [F64],
+    0xa3 F64Div "f64.div" [F64 F64] [F64],
+    0xa4 F64Min "f64.min" [F64 F64] [F64],
+    0xa5 F64Max "f64.max" [F64 F64] [F64],
+    0xa6 F64Copysign "f64.copysign" [F64 F64] [F64],
+    0xa7 I32WrapI64 "i32.wrap_i64" [I64] [I32],
+    0xa8 I32TruncF32S "i32.trunc_f32_s" [F32] [I32],
+    0xa9 I32TruncF32U "i32.trunc_f32_u" [F32] [I32],
+    0xaa I32TruncF64S "i32.trunc_f64_s" [F64] [I32],
+    0xab I32TruncF64U "i32.trunc_f64_u" [F64] [I32],
+    0xac I64ExtendI32S "i64.extend_i32_s" [I32] [I64],
+    0xad I64ExtendI32U "i64.extend_i32_u" [I32] [I64],
+    0xae I64TruncF32S "i64.trunc_f32_s" [F32] [I64],
+    0xaf I64TruncF32U "i64.trunc_f32_u" [F32] [I64],
+    0xb0 I64TruncF64S "i64.trunc_f64_s" [F64] [I64],
+    0xb1 I64TruncF64U "i64.trunc_f64_u" [F64] [I64],
+    0xb2 F32ConvertI32S "f32.convert_i32_s" [I32] [F32],
+    0xb3 F32ConvertI32U "f32.convert_i32_u" [I32] [F32],
+    0xb4 F32ConvertI64S "f32.convert_i64_s" [I64] [F32],
+    0xb5 F32ConvertI64U "f32.convert_i64_u" [I64] [F32],
+    0xb6 F32DemoteF64 "f32.demote_f64" [F64] [F32],
+    0xb7 F64ConvertI32S "f64.convert_i32_s" [I32] [F64],
+    0xb8 F64ConvertI32U "f64.convert_i32_u" [I32] [F64],
+    0xb9 F64ConvertI64S "f64.convert_i64_s" [I64] [F64],
+    0xba F64ConvertI64U "f64.convert_i64_u" [I64] [F64],
+    0xbb F64PromoteF32 "f64.promote_f32" [F32] [F64],
+    0xbc I32ReinterpretF32 "i32.reinterpret_f32" [F32] [I32],
+    0xbd I64ReinterpretF64 "i64.reinterpret_f64" [F64] [I64],
+    0xbe F32ReinterpretI32 "f32.reinterpret_i32" [I32] [F32],
+    0xbf F64ReinterpretI64 "f64.reinterpret_i64" [I64] [F64],
+    0xc0 I32Extend8S "i32.extend8_s" [I32] [I32],
+    0xc1 I32Extend16S "i32.extend16_s" [I32] [I32],
+    0xc2 I64Extend8S "i64.extend8_s" [I64] [I64],
+    0xc3 I64Extend16S "i64.extend16_s" [I64] [I64],
+    0xc4 I64Extend32S "i64.extend32_s" [I64] [I64],
+    0xfc00 I32TruncSatF32S "i32.trunc_sat_f32_s" [F32] [I32],
+    0xfc01 I32TruncSatF32U "i32.trunc_sat_f32_u" [F32] [I32],
+    0xfc02 I32TruncSatF64S "i32.trunc_sat_f64_s" [F64] [I32],
+    0xfc03 I32TruncSatF64U "i32.trunc_sat_f64_u" [F64] [I32],
+    0xfc04 I64TruncSatF32S "i64.trunc_sat_f32_s" [F32] [I64],
+    0xfc05 I64TruncSatF32U "i64.trunc_sat_f32_u" [F32] [I64],
+    0xfc06 I64TruncSatF64S "i64.trunc_sat_f64_s" [F64] [I64],
+    0xfc07 I64TruncSatF64U "i64.trunc_sat_f64_u" [F64] [I64],
+}
@@ -0,0 +1,59 @@
+// Reading and checking MS-Wasm binaries: Wasm with handles to segments in
+// place of addresses into linear memory. `parse` decodes a binary into a
+// `Module` and `validate` checks it against the typing rules, with errors
+// that say where (file offset, section and function) and, for the usual
+// misuses of handles, what to do about them. `print_wat` and `parse_wat`
+// convert to and from the text format mswasm-wabt uses, and `encode` writes
+// a `Module` back out as a binary.
+//
+//     let module = mswasm::parse(&std::fs::read("boxes.wasm")?)?;
+//     if let Err(errors) = mswasm::validate(&module) { ... }
+
+mod encode;
+mod error;
+mod float;
+mod instr;
+mod module;
+mod parse;
+mod print;
+mod types;
+mod validate;
+mod wat;
+
+pub use crate::encode::encode;
+pub use crate::error::{Error, ErrorKind, TextError};
+pub use crate::instr::{Instr, MemArg, MemOp, NumOp};
+pub use crate::module::*;
+pub use crate::parse::parse;
+pub use crate::print::print_wat;
+pub use crate::types::*;
+pub use crate::validate::validate;
+pub use crate::wat::parse_wat;
+
+// The files of the repository with the given extension, for the tests to
+// round-trip
+#[cfg(test)]
+fn repo_files(extension: &str) -> Vec<std::path::PathBuf> {
+    fn walk(dir: &std::path::Path, extension: &str, files: &mut Vec<std::path::PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy();
+            if path.is_dir() {
+                if !name.starts_with('.') && name != "target" {
+                    walk(&path, extension, files);
+                }
+            } else if path.extension().is_some_and(|e| e == extension) {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    walk(
+        &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../.."),
+        extension,
+        &mut files,
+    );
+    assert!(!files.is_empty());
+    files.sort();
+    files
+}
@@ -0,0 +1,382 @@
+use crate::instr::Instr;
+use crate::types::{FuncType, GlobalType, MemoryType, TableType, ValType};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import>,
+    // Type indices of the functions defined by the module, whose bodies are
+    // in `code`
+    pub funcs: Vec<u32>,
+    pub tables: Vec<TableType>,
+    pub memories: Vec<MemoryType>,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    pub start: Option<u32>,
+    pub elems: Vec<Elem>,
+    pub data_count: Option<u32>,
+    pub code: Vec<Body>,
+    pub datas: Vec<Data>,
+    pub customs: Vec<Custom>,
+    // Where things were in the binary the module was parsed from, empty for
+    // modules built otherwise
+    pub offsets: Offsets,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Import {
+    pub module: String,
+    pub field: String,
+    pub desc: ImportDesc,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportDesc {
+    Func(u32),
+    Table(TableType),
+    Memory(MemoryType),
+    Global(GlobalType),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Global {
+    pub ty: GlobalType,
+    pub init: ConstExpr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExternKind,
+    pub index: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExternKind {
+    Func,
+    Table,
+    Memory,
+    Global,
+}
+
+impl ExternKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ExternKind::Func => "func",
+            ExternKind::Table => "table",
+            ExternKind::Memory => "memory",
+            ExternKind::Global => "global",
+        }
+    }
+}
+
+// An element segment, of function indices
+#[derive(Clone, Debug, PartialEq)]
+pub struct Elem {
+    pub mode: ElemMode,
+    pub funcs: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ElemMode {
+    Active { table: u32, offset: ConstExpr },
+    Passive,
+    Declarative,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Body {
+    // Run-length encoded, as in the binary
+    pub locals: Vec<(u32, ValType)>,
+    // Ends with the `end` of the function
+    pub instrs: Vec<Instr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Data {
+    pub mode: DataMode,
+    // The handles to store over the data once it is in place. MS-Wasm data
+    // segments always have them, plain Wasm ones never do.
+    pub pointers: Option<Vec<Pointer>>,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DataMode {
+    // In MS-Wasm the offset is into the segment the runtime sets up for
+    // the data
+    Active { memory: u32, offset: ConstExpr },
+    Passive,
+}
+
+// A handle to a fresh segment of `size` bytes (or a null handle if `size`
+// is 0), to be stored `offset` bytes into the data of its segment
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pointer {
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConstExpr {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+    GlobalGet(u32),
+    HandleNull,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Custom {
+    pub name: String,
+    pub data: Vec<u8>,
+    // The section it follows, so that it can be written back in place
+    pub after: Option<SectionId>,
+}
+
+// The names of the `name` section that the text format uses, by index
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Names {
+    pub funcs: HashMap<u32, String>,
+    pub globals: HashMap<u32, String>,
+    pub datas: HashMap<u32, String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SectionId {
+    Custom,
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    Code,
+    Data,
+    DataCount,
+}
+
+impl SectionId {
+    // The sections other than custom ones, in the order they come in
+    pub const ORDER: [SectionId; 12] = [
+        SectionId::Type,
+        SectionId::Import,
+        SectionId::Function,
+        SectionId::Table,
+        SectionId::Memory,
+        SectionId::Global,
+        SectionId::Export,
+        SectionId::Start,
+        SectionId::Element,
+        SectionId::DataCount,
+        SectionId::Code,
+        SectionId::Data,
+    ];
+
+    pub fn from_byte(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => SectionId::Custom,
+            1 => SectionId::Type,
+            2 => SectionId::Import,
+            3 => SectionId::Function,
+            4 => SectionId::Table,
+            5 => SectionId::Memory,
+            6 => SectionId::Global,
+            7 => SectionId::Export,
+            8 => SectionId::Start,
+            9 => SectionId::Element,
+            10 => SectionId::Code,
+            11 => SectionId::Data,
+            12 => SectionId::DataCount,
+            _ => return None,
+        })
+    }
+
+    pub fn byte(self) -> u8 {
+        match self {
+            SectionId::Custom => 0,
+            SectionId::Type => 1,
+            SectionId::Import => 2,
+            SectionId::Function => 3,
+            SectionId::Table => 4,
+            SectionId::Memory => 5,
+            SectionId::Global => 6,
+            SectionId::Export => 7,
+            SectionId::Start => 8,
+            SectionId::Element => 9,
+            SectionId::Code => 10,
+            SectionId::Data => 11,
+            SectionId::DataCount => 12,
+        }
+    }
+
+    // Position in the order the sections must come in, the data count
+    // section going between the element and code sections
+    pub fn rank(self) -> u8 {
+        match self {
+            SectionId::DataCount => 10,
+            SectionId::Code => 11,
+            SectionId::Data => 12,
+            id => id.byte(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SectionId::Custom => "custom",
+            SectionId::Type => "type",
+            SectionId::Import => "import",
+            SectionId::Function => "function",
+            SectionId::Table => "table",
+            SectionId::Memory => "memory",
+            SectionId::Global => "global",
+            SectionId::Export => "export",
+            SectionId::Start => "start",
+            SectionId::Element => "element",
+            SectionId::Code => "code",
+            SectionId::Data => "data",
+            SectionId::DataCount => "data count",
+        }
+    }
+}
+
+impl fmt::Display for SectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// File offsets of the sections and of what is in them. Each vector has one
+// entry per item of its section, in order; `instrs` has the offset of every
+// instruction of every body.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Offsets {
+    pub sections: Vec<(SectionId, usize)>,
+    pub types: Vec<usize>,
+    pub imports: Vec<usize>,
+    pub funcs: Vec<usize>,
+    pub tables: Vec<usize>,
+    pub memories: Vec<usize>,
+    pub globals: Vec<usize>,
+    pub exports: Vec<usize>,
+    pub start: usize,
+    pub elems: Vec<usize>,
+    pub code: Vec<usize>,
+    pub instrs: Vec<Vec<usize>>,
+    pub datas: Vec<usize>,
+}
+
+impl Offsets {
+    pub fn section(&self, id: SectionId) -> usize {
+        self.sections
+            .iter()
+            .find(|&&(s, _)| s == id)
+            .map_or(0, |&(_, offset)| offset)
+    }
+}
+
+impl Module {
+    pub fn num_imported_funcs(&self) -> u32 {
+        self.imports
+            .iter()
+            .filter(|i| matches!(i.desc, ImportDesc::Func(_)))
+            .count() as u32
+    }
+
+    // Type index of a function, imported or defined
+    pub fn func_type_index(&self, func: u32) -> Option<u32> {
+        let imported = self.imports.iter().filter_map(|i| match i.desc {
+            ImportDesc::Func(ty) => Some(ty),
+            _ => None,
+        });
+        imported
+            .chain(self.funcs.iter().copied())
+            .nth(func as usize)
+    }
+
+    pub fn func_type(&self, func: u32) -> Option<&FuncType> {
+        self.types.get(self.func_type_index(func)? as usize)
+    }
+
+    pub fn global_types(&self) -> Vec<GlobalType> {
+        let imported = self.imports.iter().filter_map(|i| match i.desc {
+            ImportDesc::Global(ty) => Some(ty),
+            _ => None,
+        });
+        imported.chain(self.globals.iter().map(|g| g.ty)).collect()
+    }
+
+    pub fn table_types(&self) -> Vec<TableType> {
+        let imported = self.imports.iter().filter_map(|i| match i.desc {
+            ImportDesc::Table(ty) => Some(ty),
+            _ => None,
+        });
+        imported.chain(self.tables.iter().copied()).collect()
+    }
+
+    pub fn memory_types(&self) -> Vec<MemoryType> {
+        let imported = self.imports.iter().filter_map(|i| match i.desc {
+            ImportDesc::Memory(ty) => Some(ty),
+            _ => None,
+        });
+        imported.chain(self.memories.iter().copied()).collect()
+    }
+
+    // Names from the `name` section, if there is a well-formed one
+    pub fn names(&self) -> Names {
+        self.customs
+            .iter()
+            .find(|c| c.name == "name")
+            .and_then(|c| crate::parse::names(&c.data))
+            .unwrap_or_default()
+    }
+
+    // The last section `encode` writes, other than custom ones, which are the
+    // only ones it leaves out when empty
+    pub fn last_section(&self) -> Option<SectionId> {
+        SectionId::ORDER.iter().rev().copied().find(|&id| match id {
+            SectionId::Type => !self.types.is_empty(),
+            SectionId::Import => !self.imports.is_empty(),
+            SectionId::Function => !self.funcs.is_empty(),
+            SectionId::Table => !self.tables.is_empty(),
+            SectionId::Memory => !self.memories.is_empty(),
+            SectionId::Global => !self.globals.is_empty(),
+            SectionId::Export => !self.exports.is_empty(),
+            SectionId::Start => self.start.is_some(),
+            SectionId::Element => !self.elems.is_empty(),
+            SectionId::DataCount => self.data_count.is_some(),
+            SectionId::Code => !self.code.is_empty(),
+            SectionId::Data => !self.datas.is_empty(),
+            SectionId::Custom => false,
+        })
+    }
+
+    pub fn func_names(&self) -> HashMap<u32, String> {
+        self.names().funcs
+    }
+
+    // Whether this is an MS-Wasm module rather than a plain Wasm one. The
+    // binary formats only differ in the data segments, so it is told by its
+    // use of handles, which every MS-Wasm module has at least for its stack
+    // pointer.
+    pub fn is_mswasm(&self) -> bool {
+        self.uses_handles() || self.datas.iter().any(|d| d.pointers.is_some())
+    }
+
+    pub(crate) fn uses_handles(&self) -> bool {
+        let handle = |t: &ValType| *t == ValType::Handle;
+        self.types
+            .iter()
+            .any(|t| t.params.iter().any(handle) || t.results.iter().any(handle))
+            || self.global_types().iter().any(|g| handle(&g.ty))
+            || self.code.iter().any(|b| {
+                b.locals.iter().any(|(_, t)| handle(t)) || b.instrs.iter().any(Instr::is_mswasm)
+            })
+    }
+}
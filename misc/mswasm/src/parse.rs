@@ -0,0 +1,744 @@
+use crate::error::{Error, ErrorKind};
+use crate::instr::*;
+use crate::module::*;
+use crate::types::*;
+use std::convert::TryInto;
+
+// Engines reject functions with more locals than this, so do we rather
+// than allocate for a count the body cannot back
+pub(crate) const MAX_LOCALS: u64 = 50_000;
+
+// Parses an MS-Wasm (or plain Wasm) binary. Only the encoding is checked
+// here; `validate` checks the typing rules.
+pub fn parse(bytes: &[u8]) -> Result<Module> {
+    let mut r = Reader {
+        bytes,
+        pos: 0,
+        section: None,
+    };
+    if r.bytes(4)? != b"\0asm" {
+        return Err(r.error_at(0, ErrorKind::BadMagic));
+    }
+    let version = u32::from_le_bytes([r.byte()?, r.byte()?, r.byte()?, r.byte()?]);
+    if version != 1 {
+        return Err(r.error_at(4, ErrorKind::BadVersion(version)));
+    }
+
+    let mut m = Module::default();
+    let mut last: Option<SectionId> = None;
+    while r.pos < bytes.len() {
+        let start = r.pos;
+        r.section = None;
+        let id = r.byte()?;
+        let id = SectionId::from_byte(id)
+            .ok_or_else(|| r.error_at(start, ErrorKind::UnknownSection(id)))?;
+        r.section = Some(id);
+        let size = r.u32()? as usize;
+        let end = r.pos.checked_add(size).filter(|&end| end <= bytes.len());
+        let end = end.ok_or_else(|| r.error(ErrorKind::UnexpectedEof))?;
+        if id != SectionId::Custom {
+            if last.is_some_and(|last| id.rank() <= last.rank()) {
+                return Err(r.error_at(start, ErrorKind::UnexpectedSection(id)));
+            }
+            last = Some(id);
+        }
+        m.offsets.sections.push((id, start));
+
+        let mut s = Reader {
+            bytes: &bytes[..end],
+            pos: r.pos,
+            section: Some(id),
+        };
+        s.section_body(id, last, &mut m)?;
+        if s.pos != end {
+            return Err(s.error(ErrorKind::SectionSizeMismatch));
+        }
+        r.pos = end;
+    }
+
+    if m.funcs.len() != m.code.len() {
+        let section = if m.code.is_empty() {
+            SectionId::Function
+        } else {
+            SectionId::Code
+        };
+        return Err(Error::new(
+            m.offsets.section(section),
+            Some(section),
+            ErrorKind::Malformed("function and code section have inconsistent lengths".into()),
+        ));
+    }
+    if let Some(count) = m.data_count {
+        if count as usize != m.datas.len() {
+            return Err(Error::new(
+                m.offsets.section(SectionId::DataCount),
+                Some(SectionId::DataCount),
+                ErrorKind::Malformed(
+                    "data count and data section have inconsistent lengths".into(),
+                ),
+            ));
+        }
+    }
+    Ok(m)
+}
+
+// Names from the contents of a `name` section: its function (1), global
+// (7) and data segment (9) subsections
+pub(crate) fn names(data: &[u8]) -> Option<Names> {
+    let mut r = Reader {
+        bytes: data,
+        pos: 0,
+        section: Some(SectionId::Custom),
+    };
+    let mut names = Names::default();
+    while r.pos < data.len() {
+        let id = r.byte().ok()?;
+        let size = r.u32().ok()? as usize;
+        let map = match id {
+            1 => &mut names.funcs,
+            7 => &mut names.globals,
+            9 => &mut names.datas,
+            _ => {
+                r.bytes(size).ok()?;
+                continue;
+            }
+        };
+        for _ in 0..r.u32().ok()? {
+            let index = r.u32().ok()?;
+            map.insert(index, r.name().ok()?);
+        }
+    }
+    Some(names)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    section: Option<SectionId>,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl<'a> Reader<'a> {
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(self.pos, self.section, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: ErrorKind) -> Error {
+        Error::new(offset, self.section, kind)
+    }
+
+    fn malformed(&self, offset: usize, msg: &str) -> Error {
+        self.error_at(offset, ErrorKind::Malformed(msg.into()))
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or_else(|| self.error(ErrorKind::UnexpectedEof))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.remaining() {
+            return Err(self.error_at(self.bytes.len(), ErrorKind::UnexpectedEof));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn zero_byte(&mut self) -> Result<()> {
+        let start = self.pos;
+        match self.byte()? {
+            0 => Ok(()),
+            _ => Err(self.malformed(start, "zero byte expected")),
+        }
+    }
+
+    // LEB128 integers of `bits` bits, which take at most `ceil(bits / 7)`
+    // bytes. The bits of the last byte past `bits` must be zero, or copies
+    // of the sign bit if signed.
+    fn leb(&mut self, bits: u32, signed: bool) -> Result<i64> {
+        let max_len = bits.div_ceil(7);
+        let mut result = 0u64;
+        for i in 0..max_len {
+            let b = self.byte()?;
+            let shift = 7 * i;
+            result |= ((b & 0x7f) as u64) << shift;
+            if i == max_len - 1 {
+                if b & 0x80 != 0 {
+                    return Err(self.error(ErrorKind::IntegerTooLong));
+                }
+                let used = bits - shift;
+                if used < 7 {
+                    let sign = signed && (b >> (used - 1)) & 1 == 1;
+                    let unused = (b & 0x7f) >> used;
+                    if unused != if sign { 0x7f >> used } else { 0 } {
+                        return Err(self.error_at(self.pos - 1, ErrorKind::IntegerTooLarge));
+                    }
+                }
+            } else if b & 0x80 != 0 {
+                continue;
+            }
+            if signed && shift + 7 < 64 && b & 0x40 != 0 {
+                result |= !0u64 << (shift + 7);
+            }
+            return Ok(result as i64);
+        }
+        unreachable!()
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.leb(32, false)? as u32)
+    }
+
+    fn s32(&mut self) -> Result<i32> {
+        Ok(self.leb(32, true)? as i32)
+    }
+
+    fn s64(&mut self) -> Result<i64> {
+        self.leb(64, true)
+    }
+
+    // Count of a vector whose items take at least a byte each
+    fn count(&mut self) -> Result<u32> {
+        let start = self.pos;
+        let count = self.u32()?;
+        if count as usize > self.remaining() {
+            return Err(self.error_at(start, ErrorKind::UnexpectedEof));
+        }
+        Ok(count)
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let start = self.pos;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error_at(start, ErrorKind::InvalidUtf8))
+    }
+
+    fn val_type(&mut self) -> Result<ValType> {
+        let b = self.byte()?;
+        ValType::from_byte(b)
+            .ok_or_else(|| self.error_at(self.pos - 1, ErrorKind::UnknownValType(b)))
+    }
+
+    fn val_types(&mut self) -> Result<Vec<ValType>> {
+        (0..self.count()?).map(|_| self.val_type()).collect()
+    }
+
+    fn ref_type(&mut self) -> Result<RefType> {
+        let b = self.byte()?;
+        RefType::from_byte(b)
+            .ok_or_else(|| self.error_at(self.pos - 1, ErrorKind::UnknownValType(b)))
+    }
+
+    fn limits(&mut self) -> Result<Limits> {
+        let start = self.pos;
+        match self.byte()? {
+            0 => Ok(Limits {
+                min: self.u32()?,
+                max: None,
+            }),
+            1 => Ok(Limits {
+                min: self.u32()?,
+                max: Some(self.u32()?),
+            }),
+            _ => Err(self.malformed(start, "malformed limits flags")),
+        }
+    }
+
+    fn table_type(&mut self) -> Result<TableType> {
+        Ok(TableType {
+            elem: self.ref_type()?,
+            limits: self.limits()?,
+        })
+    }
+
+    fn global_type(&mut self) -> Result<GlobalType> {
+        let ty = self.val_type()?;
+        let start = self.pos;
+        let mutable = match self.byte()? {
+            0 => false,
+            1 => true,
+            _ => return Err(self.malformed(start, "malformed mutability")),
+        };
+        Ok(GlobalType { ty, mutable })
+    }
+
+    fn block_type(&mut self) -> Result<BlockType> {
+        match self.bytes.get(self.pos) {
+            Some(0x40) => {
+                self.pos += 1;
+                Ok(BlockType::Empty)
+            }
+            Some(&b) if ValType::from_byte(b).is_some() => {
+                self.pos += 1;
+                Ok(BlockType::Value(ValType::from_byte(b).unwrap()))
+            }
+            _ => {
+                let start = self.pos;
+                let index = self.leb(33, true)?;
+                if index < 0 {
+                    return Err(self.malformed(start, "malformed block type"));
+                }
+                Ok(BlockType::Func(index as u32))
+            }
+        }
+    }
+
+    fn mem_arg(&mut self) -> Result<MemArg> {
+        Ok(MemArg {
+            align: self.u32()?,
+            offset: self.u32()?,
+        })
+    }
+
+    fn const_expr(&mut self) -> Result<ConstExpr> {
+        let start = self.pos;
+        let expr = match self.instr()? {
+            Instr::I32Const(v) => ConstExpr::I32(v),
+            Instr::I64Const(v) => ConstExpr::I64(v),
+            Instr::F32Const(v) => ConstExpr::F32(v),
+            Instr::F64Const(v) => ConstExpr::F64(v),
+            Instr::GlobalGet(g) => ConstExpr::GlobalGet(g),
+            Instr::HandleNull => ConstExpr::HandleNull,
+            _ => return Err(self.malformed(start, "constant expression required")),
+        };
+        let end = self.pos;
+        match self.instr()? {
+            Instr::End => Ok(expr),
+            _ => Err(self.malformed(end, "constant expression required")),
+        }
+    }
+
+    fn instr(&mut self) -> Result<Instr> {
+        let start = self.pos;
+        let op = self.byte()?;
+        if let Some(op) = MemOp::from_opcode(op) {
+            return Ok(Instr::Memory(op, self.mem_arg()?));
+        }
+        if let Some(op) = NumOp::from_opcode(op as u32) {
+            return Ok(Instr::Numeric(op));
+        }
+        Ok(match op {
+            0x00 => Instr::Unreachable,
+            0x01 => Instr::Nop,
+            0x02 => Instr::Block(self.block_type()?),
+            0x03 => Instr::Loop(self.block_type()?),
+            0x04 => Instr::If(self.block_type()?),
+            0x05 => Instr::Else,
+            0x0b => Instr::End,
+            0x0c => Instr::Br(self.u32()?),
+            0x0d => Instr::BrIf(self.u32()?),
+            0x0e => {
+                let labels = (0..self.count()?)
+                    .map(|_| self.u32())
+                    .collect::<Result<_>>()?;
+                Instr::BrTable(labels, self.u32()?)
+            }
+            0x0f => Instr::Return,
+            0x10 => Instr::Call(self.u32()?),
+            0x11 => Instr::CallIndirect {
+                ty: self.u32()?,
+                table: self.u32()?,
+            },
+            0x1a => Instr::Drop,
+            0x1b => Instr::Select,
+            0x20 => Instr::LocalGet(self.u32()?),
+            0x21 => Instr::LocalSet(self.u32()?),
+            0x22 => Instr::LocalTee(self.u32()?),
+            0x23 => Instr::GlobalGet(self.u32()?),
+            0x24 => Instr::GlobalSet(self.u32()?),
+            0x3f => {
+                self.zero_byte()?;
+                Instr::MemorySize
+            }
+            0x40 => {
+                self.zero_byte()?;
+                Instr::MemoryGrow
+            }
+            0x41 => Instr::I32Const(self.s32()?),
+            0x42 => Instr::I64Const(self.s64()?),
+            0x43 => Instr::F32Const(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap())),
+            0x44 => Instr::F64Const(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            NEW_SEGMENT => Instr::NewSegment,
+            FREE_SEGMENT => Instr::FreeSegment,
+            HANDLE_LOAD => Instr::HandleLoad(self.mem_arg()?),
+            HANDLE_STORE => Instr::HandleStore(self.mem_arg()?),
+            HANDLE_ADD => Instr::HandleAdd,
+            HANDLE_NULL => Instr::HandleNull,
+            HANDLE_EQ => Instr::HandleEq,
+            HANDLE_LT => Instr::HandleLt,
+            HANDLE_GET_OFFSET => Instr::HandleGetOffset,
+            MISC_PREFIX => {
+                let sub = self.u32()?;
+                let op = 0xfc00u32.saturating_add(sub);
+                if let Some(op) = NumOp::from_opcode(op) {
+                    return Ok(Instr::Numeric(op));
+                }
+                match sub {
+                    8 => {
+                        let data = self.u32()?;
+                        self.zero_byte()?;
+                        Instr::MemoryInit(data)
+                    }
+                    9 => Instr::DataDrop(self.u32()?),
+                    10 => {
+                        self.zero_byte()?;
+                        self.zero_byte()?;
+                        Instr::MemoryCopy
+                    }
+                    11 => {
+                        self.zero_byte()?;
+                        Instr::MemoryFill
+                    }
+                    _ => return Err(self.error_at(start, ErrorKind::UnknownOpcode(op))),
+                }
+            }
+            _ => return Err(self.error_at(start, ErrorKind::UnknownOpcode(op as u32))),
+        })
+    }
+
+    // The instructions of a body up to and including its final `end`, with
+    // their offsets
+    fn instrs(&mut self, end: usize) -> Result<(Vec<Instr>, Vec<usize>)> {
+        let mut instrs = Vec::new();
+        let mut offsets = Vec::new();
+        let mut depth = 0u32;
+        loop {
+            if self.pos >= end {
+                return Err(self.malformed(end, "function body must end with end"));
+            }
+            offsets.push(self.pos);
+            let instr = self.instr()?;
+            match instr {
+                Instr::Block(_) | Instr::Loop(_) | Instr::If(_) => depth += 1,
+                Instr::End if depth == 0 => {
+                    instrs.push(instr);
+                    return Ok((instrs, offsets));
+                }
+                Instr::End => depth -= 1,
+                _ => {}
+            }
+            instrs.push(instr);
+        }
+    }
+
+    fn body(&mut self) -> Result<(Body, Vec<usize>)> {
+        let start = self.pos;
+        let size = self.u32()? as usize;
+        let end = self.pos + size;
+        if end > self.bytes.len() {
+            return Err(self.error_at(start, ErrorKind::UnexpectedEof));
+        }
+        let mut locals = Vec::new();
+        let mut total = 0u64;
+        for _ in 0..self.count()? {
+            let count = self.u32()?;
+            total += count as u64;
+            if total > MAX_LOCALS {
+                return Err(self.malformed(start, "too many locals"));
+            }
+            locals.push((count, self.val_type()?));
+        }
+        let (instrs, offsets) = self.instrs(end)?;
+        if self.pos != end {
+            return Err(self.malformed(self.pos, "function body has code after its end"));
+        }
+        Ok((Body { locals, instrs }, offsets))
+    }
+
+    fn func_indices(&mut self) -> Result<Vec<u32>> {
+        (0..self.count()?).map(|_| self.u32()).collect()
+    }
+
+    fn elem(&mut self) -> Result<Elem> {
+        let start = self.pos;
+        let elem_kind = |r: &mut Self| {
+            let start = r.pos;
+            match r.byte()? {
+                0 => Ok(()),
+                _ => Err(r.malformed(start, "unsupported element kind")),
+            }
+        };
+        let mode = match self.u32()? {
+            0 => ElemMode::Active {
+                table: 0,
+                offset: self.const_expr()?,
+            },
+            1 => {
+                elem_kind(self)?;
+                ElemMode::Passive
+            }
+            2 => {
+                let table = self.u32()?;
+                let offset = self.const_expr()?;
+                elem_kind(self)?;
+                ElemMode::Active { table, offset }
+            }
+            3 => {
+                elem_kind(self)?;
+                ElemMode::Declarative
+            }
+            _ => return Err(self.malformed(start, "unsupported element segment with expressions")),
+        };
+        Ok(Elem {
+            mode,
+            funcs: self.func_indices()?,
+        })
+    }
+
+    fn data(&mut self, mswasm: bool) -> Result<Data> {
+        let start = self.pos;
+        let mode = match self.u32()? {
+            0 => DataMode::Active {
+                memory: 0,
+                offset: self.const_expr()?,
+            },
+            1 => DataMode::Passive,
+            2 => DataMode::Active {
+                memory: self.u32()?,
+                offset: self.const_expr()?,
+            },
+            _ => return Err(self.malformed(start, "malformed data segment flags")),
+        };
+        let pointers = if mswasm {
+            // A vector of integers, two per pointer
+            let start = self.pos;
+            let count = self.count()?;
+            if count % 2 != 0 {
+                return Err(self.malformed(start, "pointers come in offset and size pairs"));
+            }
+            let mut pointers = Vec::with_capacity(count as usize / 2);
+            for _ in 0..count / 2 {
+                pointers.push(Pointer {
+                    offset: self.u32()?,
+                    size: self.u32()?,
+                });
+            }
+            Some(pointers)
+        } else {
+            None
+        };
+        let len = self.u32()? as usize;
+        Ok(Data {
+            mode,
+            pointers,
+            bytes: self.bytes(len)?.to_vec(),
+        })
+    }
+
+    fn section_body(
+        &mut self,
+        id: SectionId,
+        last: Option<SectionId>,
+        m: &mut Module,
+    ) -> Result<()> {
+        let o = &mut m.offsets;
+        match id {
+            SectionId::Custom => {
+                let name = self.name()?;
+                let data = self.bytes(self.remaining())?.to_vec();
+                m.customs.push(Custom {
+                    name,
+                    data,
+                    // `last` is this section's id for custom sections
+                    after: last,
+                });
+            }
+            SectionId::Type => {
+                for _ in 0..self.count()? {
+                    o.types.push(self.pos);
+                    if self.byte()? != 0x60 {
+                        return Err(self.malformed(self.pos - 1, "malformed function type"));
+                    }
+                    m.types.push(FuncType {
+                        params: self.val_types()?,
+                        results: self.val_types()?,
+                    });
+                }
+            }
+            SectionId::Import => {
+                for _ in 0..self.count()? {
+                    o.imports.push(self.pos);
+                    let module = self.name()?;
+                    let field = self.name()?;
+                    let start = self.pos;
+                    let desc = match self.byte()? {
+                        0 => ImportDesc::Func(self.u32()?),
+                        1 => ImportDesc::Table(self.table_type()?),
+                        2 => ImportDesc::Memory(MemoryType {
+                            limits: self.limits()?,
+                        }),
+                        3 => ImportDesc::Global(self.global_type()?),
+                        _ => return Err(self.malformed(start, "malformed import kind")),
+                    };
+                    m.imports.push(Import {
+                        module,
+                        field,
+                        desc,
+                    });
+                }
+            }
+            SectionId::Function => {
+                for _ in 0..self.count()? {
+                    o.funcs.push(self.pos);
+                    m.funcs.push(self.u32()?);
+                }
+            }
+            SectionId::Table => {
+                for _ in 0..self.count()? {
+                    o.tables.push(self.pos);
+                    m.tables.push(self.table_type()?);
+                }
+            }
+            SectionId::Memory => {
+                for _ in 0..self.count()? {
+                    o.memories.push(self.pos);
+                    m.memories.push(MemoryType {
+                        limits: self.limits()?,
+                    });
+                }
+            }
+            SectionId::Global => {
+                for _ in 0..self.count()? {
+                    o.globals.push(self.pos);
+                    m.globals.push(Global {
+                        ty: self.global_type()?,
+                        init: self.const_expr()?,
+                    });
+                }
+            }
+            SectionId::Export => {
+                for _ in 0..self.count()? {
+                    o.exports.push(self.pos);
+                    let name = self.name()?;
+                    let start = self.pos;
+                    let kind = match self.byte()? {
+                        0 => ExternKind::Func,
+                        1 => ExternKind::Table,
+                        2 => ExternKind::Memory,
+                        3 => ExternKind::Global,
+                        _ => return Err(self.malformed(start, "malformed export kind")),
+                    };
+                    m.exports.push(Export {
+                        name,
+                        kind,
+                        index: self.u32()?,
+                    });
+                }
+            }
+            SectionId::Start => {
+                o.start = self.pos;
+                m.start = Some(self.u32()?);
+            }
+            SectionId::Element => {
+                for _ in 0..self.count()? {
+                    o.elems.push(self.pos);
+                    m.elems.push(self.elem()?);
+                }
+            }
+            SectionId::DataCount => m.data_count = Some(self.u32()?),
+            SectionId::Code => {
+                for _ in 0..self.count()? {
+                    o.code.push(self.pos);
+                    let (body, offsets) = self.body()?;
+                    m.code.push(body);
+                    o.instrs.push(offsets);
+                }
+            }
+            SectionId::Data => {
+                let mswasm = m.uses_handles();
+                for _ in 0..self.count()? {
+                    m.offsets.datas.push(self.pos);
+                    let data = self.data(mswasm)?;
+                    m.datas.push(data);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(sections: &[u8]) -> Result<Module> {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        bytes.extend_from_slice(sections);
+        parse(&bytes)
+    }
+
+    #[test]
+    fn leb_limits() {
+        let mut r = Reader {
+            bytes: &[
+                0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x80, 0x7f, 0xff, 0xff, 0xff, 0xff, 0x1f,
+            ],
+            pos: 0,
+            section: None,
+        };
+        assert_eq!(r.u32().unwrap(), u32::MAX);
+        assert_eq!(r.s32().unwrap(), -1);
+        assert_eq!(r.s32().unwrap(), -128);
+        assert_eq!(r.u32().unwrap_err().kind, ErrorKind::IntegerTooLarge);
+        let mut r = Reader {
+            bytes: &[0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+            pos: 0,
+            section: None,
+        };
+        assert_eq!(r.u32().unwrap_err().kind, ErrorKind::IntegerTooLong);
+    }
+
+    #[test]
+    fn mswasm_data_segments_have_pointers() {
+        // A handle global, so that this is MS-Wasm, then a segment of 16
+        // bytes with a null handle at 0 and one to a segment of 32 bytes
+        // at 8
+        let global = [6, 5, 1, 0x6e, 1, HANDLE_NULL, 0x0b];
+        let data = [11, 27, 1, 0, 0x41, 0, 0x0b, 4, 0, 0, 8, 32, 16];
+        let m = module(&[&global[..], &data, &[0; 16]].concat()).unwrap();
+        assert!(m.is_mswasm());
+        assert_eq!(
+            m.datas[0].pointers,
+            Some(vec![
+                Pointer { offset: 0, size: 0 },
+                Pointer {
+                    offset: 8,
+                    size: 32
+                }
+            ])
+        );
+        assert_eq!(m.datas[0].bytes.len(), 16);
+    }
+
+    #[test]
+    fn plain_data_segments_have_none() {
+        let m = module(&[11, 8, 1, 0, 0x41, 0, 0x0b, 2, 1, 2]).unwrap();
+        assert!(!m.is_mswasm());
+        assert_eq!(m.datas[0].pointers, None);
+        assert_eq!(m.datas[0].bytes, [1, 2]);
+    }
+
+    #[test]
+    fn errors_say_where() {
+        // An unknown opcode in the only function
+        let err =
+            module(&[1, 4, 1, 0x60, 0, 0, 3, 2, 1, 0, 10, 5, 1, 3, 0, 0xf6, 0x0b]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnknownOpcode(0xf6));
+        assert_eq!(err.section, Some(SectionId::Code));
+        assert_eq!(err.offset, 8 + 15);
+        let err = module(&[3, 1, 0, 1, 1, 0]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::UnexpectedSection(SectionId::Type));
+        assert_eq!(err.offset, 8 + 3);
+    }
+}
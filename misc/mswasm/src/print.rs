@@ -0,0 +1,469 @@
+use crate::encode::name_section;
+use crate::float;
+use crate::instr::{Instr, MemArg};
+use crate::module::*;
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+// Prints a module in the text format, laid out as mswasm-wabt's `wasm2wat`
+// does: flat instructions with numbered labels, and names from the `name`
+// section where there are some. Data segments of MS-Wasm modules get a
+// `(pointers "...")` field with each pointer as two little-endian u32s, its
+// offset and size.
+//
+// Custom sections are printed last, as `(@custom "name" (after data)
+// "...")` annotations that say where they go. The `name` section is left
+// out when `parse_wat` makes it back from the `$` names.
+pub fn print_wat(m: &Module) -> String {
+    let names = m.names();
+    let mut p = Printer {
+        m,
+        funcs: ids(&names.funcs),
+        globals: ids(&names.globals),
+        datas: ids(&names.datas),
+        lines: vec!["(module".into()],
+    };
+    p.module();
+    let mut out = p.lines.join("\n");
+    out.push_str(")\n");
+    out
+}
+
+// `$` identifiers for names, made unique the way wabt does, with `.1`,
+// `.2`, ... appended to repeats
+fn ids(names: &HashMap<u32, String>) -> HashMap<u32, String> {
+    let mut indices: Vec<u32> = names.keys().copied().collect();
+    indices.sort_unstable();
+    let mut taken = HashSet::new();
+    let mut ids = HashMap::new();
+    for index in indices {
+        let name = &names[&index];
+        if name.is_empty() {
+            continue;
+        }
+        let base: String = std::iter::once('$')
+            .chain(name.chars().map(|c| if is_id_char(c) { c } else { '_' }))
+            .collect();
+        let mut id = base.clone();
+        let mut n = 1;
+        while taken.contains(&id) {
+            id = format!("{}.{}", base, n);
+            n += 1;
+        }
+        taken.insert(id.clone());
+        ids.insert(index, id);
+    }
+    ids
+}
+
+// How the text format names a section to place a custom section after it
+pub(crate) fn section_keyword(id: SectionId) -> &'static str {
+    match id {
+        SectionId::Function => "func",
+        SectionId::Element => "elem",
+        SectionId::DataCount => "datacount",
+        id => id.name(),
+    }
+}
+
+pub(crate) fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c)
+}
+
+// A string of bytes, with those that are not printable ASCII escaped
+pub(crate) fn quoted(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() + 2);
+    s.push('"');
+    for &b in bytes {
+        if (0x20..0x7f).contains(&b) && b != b'"' && b != b'\\' {
+            s.push(b as char);
+        } else {
+            write!(s, "\\{:02x}", b).unwrap();
+        }
+    }
+    s.push('"');
+    s
+}
+
+struct Printer<'a> {
+    m: &'a Module,
+    funcs: HashMap<u32, String>,
+    globals: HashMap<u32, String>,
+    datas: HashMap<u32, String>,
+    lines: Vec<String>,
+}
+
+// The name of an item if it has one, or its index
+fn var(names: &HashMap<u32, String>, index: u32) -> String {
+    names
+        .get(&index)
+        .cloned()
+        .unwrap_or_else(|| index.to_string())
+}
+
+// The name of an item being defined if it has one, or its index in a comment
+fn decl(names: &HashMap<u32, String>, index: u32) -> String {
+    names
+        .get(&index)
+        .cloned()
+        .unwrap_or_else(|| format!("(;{};)", index))
+}
+
+fn limits(l: Limits) -> String {
+    match l.max {
+        Some(max) => format!("{} {}", l.min, max),
+        None => l.min.to_string(),
+    }
+}
+
+fn global_type(t: GlobalType) -> String {
+    if t.mutable {
+        format!("(mut {})", t.ty)
+    } else {
+        t.ty.to_string()
+    }
+}
+
+// ` (param i32 i32) (result i32)`, empty for `[] -> []`
+fn signature(ty: &FuncType) -> String {
+    let mut s = String::new();
+    for (what, types) in &[("param", &ty.params), ("result", &ty.results)] {
+        if !types.is_empty() {
+            write!(s, " ({}", what).unwrap();
+            for t in types.iter() {
+                write!(s, " {}", t).unwrap();
+            }
+            s.push(')');
+        }
+    }
+    s
+}
+
+fn float32(bits: u32) -> String {
+    format!(
+        "{} (;={};)",
+        float::to_hex(bits as u64, float::F32),
+        float::to_g(f32::from_bits(bits) as f64)
+    )
+}
+
+fn float64(bits: u64) -> String {
+    format!(
+        "{} (;={};)",
+        float::to_hex(bits, float::F64),
+        float::to_g(f64::from_bits(bits))
+    )
+}
+
+impl Printer<'_> {
+    fn line(&mut self, indent: usize, text: String) {
+        self.lines
+            .push(format!("{:indent$}{}", "", text, indent = indent));
+    }
+
+    fn const_expr(&self, expr: &ConstExpr) -> String {
+        match *expr {
+            ConstExpr::I32(v) => format!("(i32.const {})", v),
+            ConstExpr::I64(v) => format!("(i64.const {})", v),
+            ConstExpr::F32(bits) => format!("(f32.const {})", float32(bits)),
+            ConstExpr::F64(bits) => format!("(f64.const {})", float64(bits)),
+            ConstExpr::GlobalGet(g) => format!("(global.get {})", var(&self.globals, g)),
+            ConstExpr::HandleNull => "(handle.null)".into(),
+        }
+    }
+
+    fn module(&mut self) {
+        let m = self.m;
+        for (i, ty) in m.types.iter().enumerate() {
+            self.line(2, format!("(type (;{};) (func{}))", i, signature(ty)));
+        }
+
+        let (mut funcs, mut tables, mut memories, mut globals) = (0, 0, 0, 0);
+        for import in &m.imports {
+            let desc = match import.desc {
+                ImportDesc::Func(ty) => {
+                    funcs += 1;
+                    format!("(func {} (type {}))", decl(&self.funcs, funcs - 1), ty)
+                }
+                ImportDesc::Table(t) => {
+                    tables += 1;
+                    format!(
+                        "(table (;{};) {} {})",
+                        tables - 1,
+                        limits(t.limits),
+                        t.elem.name()
+                    )
+                }
+                ImportDesc::Memory(t) => {
+                    memories += 1;
+                    format!("(memory (;{};) {})", memories - 1, limits(t.limits))
+                }
+                ImportDesc::Global(t) => {
+                    globals += 1;
+                    format!(
+                        "(global {} {})",
+                        decl(&self.globals, globals - 1),
+                        global_type(t)
+                    )
+                }
+            };
+            let text = format!(
+                "(import {} {} {})",
+                quoted(import.module.as_bytes()),
+                quoted(import.field.as_bytes()),
+                desc
+            );
+            self.line(2, text);
+        }
+
+        for (i, body) in m.code.iter().enumerate() {
+            self.func(funcs + i as u32, body);
+        }
+        for (i, t) in m.tables.iter().enumerate() {
+            let text = format!(
+                "(table (;{};) {} {})",
+                tables + i as u32,
+                limits(t.limits),
+                t.elem.name()
+            );
+            self.line(2, text);
+        }
+        for (i, t) in m.memories.iter().enumerate() {
+            let text = format!("(memory (;{};) {})", memories + i as u32, limits(t.limits));
+            self.line(2, text);
+        }
+        for (i, g) in m.globals.iter().enumerate() {
+            let text = format!(
+                "(global {} {} {})",
+                decl(&self.globals, globals + i as u32),
+                global_type(g.ty),
+                self.const_expr(&g.init)
+            );
+            self.line(2, text);
+        }
+        for e in &m.exports {
+            let index = match e.kind {
+                ExternKind::Func => var(&self.funcs, e.index),
+                ExternKind::Global => var(&self.globals, e.index),
+                _ => e.index.to_string(),
+            };
+            let text = format!(
+                "(export {} ({} {}))",
+                quoted(e.name.as_bytes()),
+                e.kind.name(),
+                index
+            );
+            self.line(2, text);
+        }
+        if let Some(start) = m.start {
+            let text = format!("(start {})", var(&self.funcs, start));
+            self.line(2, text);
+        }
+        for (i, e) in m.elems.iter().enumerate() {
+            let mut text = format!("(elem (;{};)", i);
+            match &e.mode {
+                ElemMode::Active { table, offset } => {
+                    if *table != 0 {
+                        write!(text, " (table {})", table).unwrap();
+                    }
+                    write!(text, " {}", self.const_expr(offset)).unwrap();
+                }
+                ElemMode::Passive => {}
+                ElemMode::Declarative => text.push_str(" declare"),
+            }
+            text.push_str(" func");
+            for &f in &e.funcs {
+                write!(text, " {}", var(&self.funcs, f)).unwrap();
+            }
+            text.push(')');
+            self.line(2, text);
+        }
+        for (i, d) in m.datas.iter().enumerate() {
+            let mut text = format!("(data {}", decl(&self.datas, i as u32));
+            if let DataMode::Active { memory, offset } = &d.mode {
+                if *memory != 0 {
+                    write!(text, " (memory {})", memory).unwrap();
+                }
+                write!(text, " {}", self.const_expr(offset)).unwrap();
+            }
+            if let Some(pointers) = &d.pointers {
+                let mut bytes = Vec::with_capacity(8 * pointers.len());
+                for p in pointers {
+                    bytes.extend_from_slice(&p.offset.to_le_bytes());
+                    bytes.extend_from_slice(&p.size.to_le_bytes());
+                }
+                write!(text, " (pointers {})", quoted(&bytes)).unwrap();
+            }
+            write!(text, " {})", quoted(&d.bytes)).unwrap();
+            self.line(2, text);
+        }
+        let implicit = self.implicit_names();
+        for (i, c) in m.customs.iter().enumerate() {
+            if Some(i) == implicit {
+                continue;
+            }
+            let place = match c.after {
+                Some(id) => format!("(after {})", section_keyword(id)),
+                None => "(before first)".into(),
+            };
+            let text = format!(
+                "(@custom {} {} {})",
+                quoted(c.name.as_bytes()),
+                place,
+                quoted(&c.data)
+            );
+            self.line(2, text);
+        }
+    }
+
+    // The index of the `name` section if `parse_wat` makes the same one, in
+    // the same place, from the names printed, so that it goes without saying
+    fn implicit_names(&self) -> Option<usize> {
+        let m = self.m;
+        let i = m.customs.iter().position(|c| c.name == "name")?;
+        let after = m.last_section();
+        if m.customs.iter().position(|c| c.after == after) != Some(i) {
+            return None;
+        }
+        let unquoted = |ids: &HashMap<u32, String>| {
+            ids.iter()
+                .map(|(&index, id)| (index, id[1..].to_string()))
+                .collect()
+        };
+        let names = Names {
+            funcs: unquoted(&self.funcs),
+            globals: unquoted(&self.globals),
+            datas: unquoted(&self.datas),
+        };
+        if name_section(&names).as_ref() == Some(&m.customs[i].data) {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    fn func(&mut self, index: u32, body: &Body) {
+        let m = self.m;
+        let ty = m.func_type_index(index).unwrap_or(0);
+        let sig = m.types.get(ty as usize).map(signature).unwrap_or_default();
+        let header = format!("(func {} (type {}){}", decl(&self.funcs, index), ty, sig);
+        self.line(2, header);
+        if !body.locals.is_empty() {
+            let mut text = "(local".to_string();
+            for &(n, t) in &body.locals {
+                for _ in 0..n {
+                    write!(text, " {}", t).unwrap();
+                }
+            }
+            text.push(')');
+            self.line(4, text);
+        }
+        // Labels are numbered by depth, the function's own being @0
+        let mut depth = 0;
+        let instrs = body.instrs.split_last().map_or(&[][..], |(_, rest)| rest);
+        for instr in instrs {
+            match instr {
+                Instr::End | Instr::Else => {
+                    let indent = 2 + 2 * depth;
+                    if let Instr::End = instr {
+                        depth -= 1;
+                    }
+                    self.line(indent, instr.name().into());
+                }
+                Instr::Block(bt) | Instr::Loop(bt) | Instr::If(bt) => {
+                    let bt = match *bt {
+                        BlockType::Empty => String::new(),
+                        BlockType::Value(t) => format!(" (result {})", t),
+                        BlockType::Func(ty) => format!(" (type {})", ty),
+                    };
+                    let text = format!("{}{}  ;; label = @{}", instr.name(), bt, depth + 1);
+                    self.line(4 + 2 * depth, text);
+                    depth += 1;
+                }
+                _ => {
+                    let text = self.instr(instr, depth);
+                    self.line(4 + 2 * depth, text);
+                }
+            }
+        }
+        self.lines.last_mut().unwrap().push(')');
+    }
+
+    fn instr(&self, instr: &Instr, depth: usize) -> String {
+        let label = |l: u32| format!("{} (;@{};)", l, depth as i64 - l as i64);
+        let mem_arg = |arg: &MemArg, natural: u32| {
+            let mut s = String::new();
+            if arg.offset != 0 {
+                write!(s, " offset={}", arg.offset).unwrap();
+            }
+            if arg.align != natural {
+                write!(s, " align={}", 1u64 << arg.align.min(63)).unwrap();
+            }
+            s
+        };
+        let name = instr.name();
+        match instr {
+            Instr::Br(l) | Instr::BrIf(l) => format!("{} {}", name, label(*l)),
+            Instr::BrTable(labels, default) => {
+                let mut s = name.to_string();
+                for &l in labels.iter().chain(std::iter::once(default)) {
+                    write!(s, " {}", label(l)).unwrap();
+                }
+                s
+            }
+            Instr::Call(f) => format!("call {}", var(&self.funcs, *f)),
+            Instr::CallIndirect { ty, table: 0 } => format!("call_indirect (type {})", ty),
+            Instr::CallIndirect { ty, table } => format!("call_indirect {} (type {})", table, ty),
+            Instr::LocalGet(i) | Instr::LocalSet(i) | Instr::LocalTee(i) => {
+                format!("{} {}", name, i)
+            }
+            Instr::GlobalGet(g) | Instr::GlobalSet(g) => {
+                format!("{} {}", name, var(&self.globals, *g))
+            }
+            Instr::Memory(op, arg) => {
+                format!("{}{}", name, mem_arg(arg, op.bytes().trailing_zeros()))
+            }
+            Instr::HandleLoad(arg) | Instr::HandleStore(arg) => {
+                format!("{}{}", name, mem_arg(arg, 3))
+            }
+            Instr::I32Const(v) => format!("{} {}", name, v),
+            Instr::I64Const(v) => format!("{} {}", name, v),
+            Instr::F32Const(bits) => format!("{} {}", name, float32(*bits)),
+            Instr::F64Const(bits) => format!("{} {}", name, float64(*bits)),
+            Instr::MemoryInit(d) | Instr::DataDrop(d) => {
+                format!("{} {}", name, var(&self.datas, *d))
+            }
+            _ => name.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    // wasm2wat leaves out custom sections, which are printed last
+    fn without_customs(text: &str) -> String {
+        let end = text.find("\n  (@custom").unwrap_or(text.len() - 2);
+        format!("{})\n", &text[..end])
+    }
+
+    #[test]
+    fn matches_wasm2wat() {
+        let module = parse(include_bytes!("../../rWasm_files/examples/main.wasm")).unwrap();
+        assert_eq!(
+            without_customs(&print_wat(&module)),
+            include_str!("../../rWasm_files/examples/main.wat")
+        );
+        let module = parse(include_bytes!(
+            "../../../rust_mswasm/temporal-safety-unsafe/temporal-safety-unsafe.wasm"
+        ))
+        .unwrap();
+        assert_eq!(
+            without_customs(&print_wat(&module)),
+            include_str!("../../../rust_mswasm/temporal-safety-unsafe/temporal-safety-unsafe.wat")
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+    // A reference to (a position in) a segment, MS-Wasm's replacement for
+    // addresses into linear memory
+    Handle,
+}
+
+impl ValType {
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0x7f => Some(ValType::I32),
+            0x7e => Some(ValType::I64),
+            0x7d => Some(ValType::F32),
+            0x7c => Some(ValType::F64),
+            0x6e => Some(ValType::Handle),
+            _ => None,
+        }
+    }
+
+    pub fn byte(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F32 => 0x7d,
+            ValType::F64 => 0x7c,
+            ValType::Handle => 0x6e,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+            ValType::Handle => "handle",
+        }
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Element type of a table
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RefType {
+    FuncRef,
+    ExternRef,
+}
+
+impl RefType {
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0x70 => Some(RefType::FuncRef),
+            0x6f => Some(RefType::ExternRef),
+            _ => None,
+        }
+    }
+
+    pub fn byte(self) -> u8 {
+        match self {
+            RefType::FuncRef => 0x70,
+            RefType::ExternRef => 0x6f,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RefType::FuncRef => "funcref",
+            RefType::ExternRef => "externref",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl fmt::Display for FuncType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", Types(&self.params), Types(&self.results))
+    }
+}
+
+// Formats a list of types the way wabt does, as `[handle, i32]`
+pub struct Types<'a>(pub &'a [ValType]);
+
+impl fmt::Display for Types<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, t) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", t)?;
+        }
+        f.write_str("]")
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TableType {
+    pub elem: RefType,
+    pub limits: Limits,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryType {
+    pub limits: Limits,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlobalType {
+    pub ty: ValType,
+    pub mutable: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlockType {
+    Empty,
+    Value(ValType),
+    // Index into the type section, for blocks with parameters or several
+    // results
+    Func(u32),
+}
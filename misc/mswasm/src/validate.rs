@@ -0,0 +1,743 @@
+use crate::error::{Error, ErrorKind};
+use crate::instr::{Instr, MemArg};
+use crate::module::*;
+use crate::parse::MAX_LOCALS;
+use crate::types::*;
+use std::collections::HashSet;
+
+// Largest number of 64 KiB pages a memory can have
+const MAX_PAGES: u32 = 65536;
+
+// Checks a module against the typing rules of MS-Wasm: those of Wasm, with
+// handles as the addresses of loads and stores and as the operands of the
+// MS-Wasm instructions. Plain Wasm modules (see `Module::is_mswasm`) are
+// checked with i32 addresses.
+//
+// Errors in the sections are all reported; in code, the first error of
+// each function is.
+pub fn validate(m: &Module) -> std::result::Result<(), Vec<Error>> {
+    let mut cx = Context {
+        m,
+        globals: m.global_types(),
+        tables: m.table_types(),
+        memories: m.memory_types().len(),
+        num_funcs: m.num_imported_funcs() + m.funcs.len() as u32,
+        num_imported_globals: m.global_types().len() - m.globals.len(),
+        address: if m.is_mswasm() {
+            ValType::Handle
+        } else {
+            ValType::I32
+        },
+        errors: Vec::new(),
+    };
+    cx.sections();
+    cx.code();
+    if cx.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(cx.errors)
+    }
+}
+
+struct Context<'a> {
+    m: &'a Module,
+    globals: Vec<GlobalType>,
+    tables: Vec<TableType>,
+    memories: usize,
+    num_funcs: u32,
+    num_imported_globals: usize,
+    address: ValType,
+    errors: Vec<Error>,
+}
+
+fn at(offsets: &[usize], index: usize) -> usize {
+    offsets.get(index).copied().unwrap_or(0)
+}
+
+fn invalid(msg: String) -> ErrorKind {
+    ErrorKind::Invalid(msg)
+}
+
+impl Context<'_> {
+    fn error(&mut self, offset: usize, section: SectionId, kind: ErrorKind) {
+        self.errors.push(Error::new(offset, Some(section), kind));
+    }
+
+    fn limits(&self, limits: Limits, max: u32, what: &str) -> Result<()> {
+        if limits.min > max || limits.max.is_some_and(|m| m > max) {
+            return Err(invalid(format!("{} size must be at most {}", what, max)));
+        }
+        if limits.max.is_some_and(|m| m < limits.min) {
+            return Err(invalid(format!(
+                "{} size minimum must not be greater than maximum",
+                what
+            )));
+        }
+        Ok(())
+    }
+
+    fn const_expr(&self, expr: &ConstExpr, expected: ValType) -> Result<()> {
+        let ty = match *expr {
+            ConstExpr::I32(_) => ValType::I32,
+            ConstExpr::I64(_) => ValType::I64,
+            ConstExpr::F32(_) => ValType::F32,
+            ConstExpr::F64(_) => ValType::F64,
+            ConstExpr::HandleNull => ValType::Handle,
+            ConstExpr::GlobalGet(g) => {
+                if g as usize >= self.num_imported_globals {
+                    return Err(invalid(format!(
+                        "initializer expression can only reference an imported global, not global {}",
+                        g
+                    )));
+                }
+                let global = self.globals[g as usize];
+                if global.mutable {
+                    return Err(invalid(format!(
+                        "initializer expression cannot reference mutable global {}",
+                        g
+                    )));
+                }
+                global.ty
+            }
+        };
+        if ty != expected {
+            return Err(ErrorKind::TypeMismatch {
+                instr: "initializer expression",
+                expected: vec![expected],
+                got: vec![Some(ty)],
+            });
+        }
+        Ok(())
+    }
+
+    fn func(&self, index: u32) -> Result<()> {
+        if index >= self.num_funcs {
+            return Err(ErrorKind::Unknown {
+                what: "function",
+                index,
+            });
+        }
+        Ok(())
+    }
+
+    fn type_index(&self, index: u32) -> Result<&FuncType> {
+        self.m.types.get(index as usize).ok_or(ErrorKind::Unknown {
+            what: "type",
+            index,
+        })
+    }
+
+    fn sections(&mut self) {
+        let m = self.m;
+        let o = &m.offsets;
+
+        for (i, import) in m.imports.iter().enumerate() {
+            let res = match import.desc {
+                ImportDesc::Func(ty) => self.type_index(ty).map(|_| ()),
+                ImportDesc::Table(t) => self.limits(t.limits, u32::MAX, "table"),
+                ImportDesc::Memory(t) => self.limits(t.limits, MAX_PAGES, "memory"),
+                ImportDesc::Global(_) => Ok(()),
+            };
+            if let Err(kind) = res {
+                self.error(at(&o.imports, i), SectionId::Import, kind);
+            }
+        }
+        for (i, &ty) in m.funcs.iter().enumerate() {
+            if let Err(kind) = self.type_index(ty) {
+                self.error(at(&o.funcs, i), SectionId::Function, kind);
+            }
+        }
+        if m.funcs.len() != m.code.len() {
+            let kind = invalid("function and code section have inconsistent lengths".into());
+            self.error(o.section(SectionId::Code), SectionId::Code, kind);
+        }
+
+        for (i, t) in m.tables.iter().enumerate() {
+            if let Err(kind) = self.limits(t.limits, u32::MAX, "table") {
+                self.error(at(&o.tables, i), SectionId::Table, kind);
+            }
+        }
+        if self.tables.len() > 1 {
+            let kind = invalid("multiple tables".into());
+            self.error(o.section(SectionId::Table), SectionId::Table, kind);
+        }
+        for (i, t) in m.memories.iter().enumerate() {
+            if let Err(kind) = self.limits(t.limits, MAX_PAGES, "memory") {
+                self.error(at(&o.memories, i), SectionId::Memory, kind);
+            }
+        }
+        if self.memories > 1 {
+            let kind = invalid("multiple memories".into());
+            self.error(o.section(SectionId::Memory), SectionId::Memory, kind);
+        }
+
+        for (i, g) in m.globals.iter().enumerate() {
+            if let Err(kind) = self.const_expr(&g.init, g.ty.ty) {
+                self.error(at(&o.globals, i), SectionId::Global, kind);
+            }
+        }
+
+        let mut names = HashSet::new();
+        for (i, e) in m.exports.iter().enumerate() {
+            let count = match e.kind {
+                ExternKind::Func => self.num_funcs as usize,
+                ExternKind::Table => self.tables.len(),
+                ExternKind::Memory => self.memories,
+                ExternKind::Global => self.globals.len(),
+            };
+            let res = if !names.insert(&e.name) {
+                Err(invalid(format!("duplicate export \"{}\"", e.name)))
+            } else if e.index as usize >= count {
+                Err(ErrorKind::Unknown {
+                    what: e.kind.name(),
+                    index: e.index,
+                })
+            } else {
+                Ok(())
+            };
+            if let Err(kind) = res {
+                self.error(at(&o.exports, i), SectionId::Export, kind);
+            }
+        }
+
+        if let Some(start) = m.start {
+            let res = self.func(start).and_then(|_| match m.func_type(start) {
+                Some(ty) if ty.params.is_empty() && ty.results.is_empty() => Ok(()),
+                Some(ty) => Err(invalid(format!(
+                    "start function must have type [] -> [], not {}",
+                    ty
+                ))),
+                None => Ok(()),
+            });
+            if let Err(kind) = res {
+                self.error(o.start, SectionId::Start, kind);
+            }
+        }
+
+        for (i, elem) in m.elems.iter().enumerate() {
+            let mut res = Ok(());
+            if let ElemMode::Active { table, offset } = &elem.mode {
+                res = match self.tables.get(*table as usize) {
+                    None => Err(ErrorKind::Unknown {
+                        what: "table",
+                        index: *table,
+                    }),
+                    Some(t) if t.elem != RefType::FuncRef => Err(invalid(format!(
+                        "table {} holds {}, not functions",
+                        table,
+                        t.elem.name()
+                    ))),
+                    Some(_) => self.const_expr(offset, ValType::I32),
+                };
+            }
+            let res = res.and_then(|_| elem.funcs.iter().try_for_each(|&f| self.func(f)));
+            if let Err(kind) = res {
+                self.error(at(&o.elems, i), SectionId::Element, kind);
+            }
+        }
+
+        for (i, data) in m.datas.iter().enumerate() {
+            let mut res = Ok(());
+            if let DataMode::Active { memory, offset } = &data.mode {
+                res = if *memory as usize >= self.memories {
+                    Err(ErrorKind::Unknown {
+                        what: "memory",
+                        index: *memory,
+                    })
+                } else {
+                    self.const_expr(offset, ValType::I32)
+                };
+            }
+            // Pointers need not fall within the bytes of their segment:
+            // clang places them in the zeroed memory that follows it
+            if let Err(kind) = res {
+                self.error(at(&o.datas, i), SectionId::Data, kind);
+            }
+        }
+    }
+
+    fn code(&mut self) {
+        let m = self.m;
+        let imported = m.num_imported_funcs();
+        let mut names = None;
+        for (i, (body, &ty)) in m.code.iter().zip(&m.funcs).enumerate() {
+            let ty = match m.types.get(ty as usize) {
+                Some(ty) => ty,
+                // Reported with the function section
+                None => continue,
+            };
+            let offsets = m.offsets.instrs.get(i).map_or(&[][..], |o| &o[..]);
+            if let Err((offset, kind)) = self.body(ty, body, offsets) {
+                let offset = offset.unwrap_or_else(|| at(&m.offsets.code, i));
+                let func = imported + i as u32;
+                let names = names.get_or_insert_with(|| m.func_names());
+                self.errors.push(Error {
+                    offset,
+                    section: Some(SectionId::Code),
+                    func: Some(func),
+                    func_name: names.get(&func).cloned(),
+                    kind,
+                });
+            }
+        }
+    }
+
+    fn body(
+        &self,
+        ty: &FuncType,
+        body: &Body,
+        offsets: &[usize],
+    ) -> std::result::Result<(), (Option<usize>, ErrorKind)> {
+        let total: u64 = body.locals.iter().map(|&(n, _)| n as u64).sum();
+        if total > MAX_LOCALS {
+            return Err((None, invalid("too many locals".into())));
+        }
+        let mut locals = ty.params.clone();
+        for &(n, t) in &body.locals {
+            locals.extend(std::iter::repeat_n(t, n as usize));
+        }
+        let mut f = Func {
+            cx: self,
+            locals,
+            stack: Vec::new(),
+            frames: vec![Frame {
+                kind: "function",
+                params: Vec::new(),
+                results: ty.results.clone(),
+                height: 0,
+                unreachable: false,
+            }],
+        };
+        for (i, instr) in body.instrs.iter().enumerate() {
+            let offset = offsets.get(i).copied();
+            if f.frames.is_empty() {
+                let kind = invalid("instructions after the end of the function".into());
+                return Err((offset, kind));
+            }
+            f.instr(instr).map_err(|kind| (offset, kind))?;
+        }
+        if !f.frames.is_empty() {
+            return Err((None, invalid("function body must end with end".into())));
+        }
+        Ok(())
+    }
+}
+
+struct Frame {
+    kind: &'static str,
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+    height: usize,
+    unreachable: bool,
+}
+
+// The operand stack and the blocks open at a point of a function body, as
+// in the validation algorithm of the Wasm spec. Operands of unknown type
+// (under unreachable code) are `None`.
+struct Func<'a> {
+    cx: &'a Context<'a>,
+    locals: Vec<ValType>,
+    stack: Vec<Option<ValType>>,
+    frames: Vec<Frame>,
+}
+
+type Result<T> = std::result::Result<T, ErrorKind>;
+
+impl Func<'_> {
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn push(&mut self, types: &[ValType]) {
+        self.stack.extend(types.iter().map(|&t| Some(t)));
+    }
+
+    // Pops operands of the `expected` types, or fails with what is there
+    fn pop(&mut self, instr: &'static str, expected: &[ValType]) -> Result<()> {
+        let frame = self.frame();
+        let available = self.stack.len() - frame.height;
+        let n = expected.len().min(available);
+        let got = &self.stack[self.stack.len() - n..];
+        let matches = expected[expected.len() - n..]
+            .iter()
+            .zip(got)
+            .all(|(&e, &g)| g.is_none_or(|g| g == e));
+        if !matches || (n < expected.len() && !frame.unreachable) {
+            return Err(ErrorKind::TypeMismatch {
+                instr,
+                expected: expected.to_vec(),
+                got: got.to_vec(),
+            });
+        }
+        self.stack.truncate(self.stack.len() - n);
+        Ok(())
+    }
+
+    fn pop_any(&mut self, instr: &'static str) -> Result<Option<ValType>> {
+        let frame = self.frame();
+        if self.stack.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err(invalid(format!(
+                "type mismatch in {}, expected [any] but got []",
+                instr
+            )));
+        }
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn block_type(&self, bt: BlockType) -> Result<(Vec<ValType>, Vec<ValType>)> {
+        Ok(match bt {
+            BlockType::Empty => (vec![], vec![]),
+            BlockType::Value(t) => (vec![], vec![t]),
+            BlockType::Func(index) => {
+                let ty = self.cx.type_index(index)?;
+                (ty.params.clone(), ty.results.clone())
+            }
+        })
+    }
+
+    fn open(&mut self, kind: &'static str, bt: BlockType) -> Result<()> {
+        let (params, results) = self.block_type(bt)?;
+        self.pop(kind, &params)?;
+        self.frames.push(Frame {
+            kind,
+            params: params.clone(),
+            results,
+            height: self.stack.len(),
+            unreachable: false,
+        });
+        self.push(&params);
+        Ok(())
+    }
+
+    // Checks that the innermost block leaves its results, and closes it
+    fn close(&mut self) -> Result<Frame> {
+        let frame = self.frame();
+        let (kind, results) = (frame.kind, frame.results.clone());
+        let got = self.stack[frame.height..].to_vec();
+        if got.len() > results.len() || self.pop(kind, &results).is_err() {
+            return Err(ErrorKind::EndMismatch {
+                block: kind,
+                expected: results,
+                got,
+            });
+        }
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.height);
+        Ok(frame)
+    }
+
+    // Types a branch to the block `depth` levels out passes
+    fn label(&self, depth: u32) -> Result<Vec<ValType>> {
+        let frame = self
+            .frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .map(|i| &self.frames[i])
+            .ok_or(ErrorKind::Unknown {
+                what: "label",
+                index: depth,
+            })?;
+        Ok(if frame.kind == "loop" {
+            frame.params.clone()
+        } else {
+            frame.results.clone()
+        })
+    }
+
+    fn local(&self, index: u32) -> Result<ValType> {
+        self.locals
+            .get(index as usize)
+            .copied()
+            .ok_or(ErrorKind::Unknown {
+                what: "local",
+                index,
+            })
+    }
+
+    fn global(&self, index: u32) -> Result<GlobalType> {
+        self.cx
+            .globals
+            .get(index as usize)
+            .copied()
+            .ok_or(ErrorKind::Unknown {
+                what: "global",
+                index,
+            })
+    }
+
+    fn memory(&self) -> Result<()> {
+        if self.cx.memories == 0 {
+            return Err(ErrorKind::Unknown {
+                what: "memory",
+                index: 0,
+            });
+        }
+        Ok(())
+    }
+
+    fn data_count(&self, instr: &str, index: u32) -> Result<()> {
+        match self.cx.m.data_count {
+            None => Err(invalid(format!("{} requires a data count section", instr))),
+            Some(count) if index >= count => Err(ErrorKind::Unknown {
+                what: "data segment",
+                index,
+            }),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn align(&self, arg: MemArg, bytes: u32) -> Result<()> {
+        if arg.align >= 32 || 1u64 << arg.align > bytes as u64 {
+            return Err(invalid(format!(
+                "alignment must not be larger than natural ({} bytes)",
+                bytes
+            )));
+        }
+        Ok(())
+    }
+
+    fn instr(&mut self, instr: &Instr) -> Result<()> {
+        let name = instr.name();
+        let address = self.cx.address;
+        if let Instr::HandleLoad(arg) | Instr::HandleStore(arg) = instr {
+            self.align(*arg, 8)?;
+        }
+        if let Some((params, results)) = instr.signature() {
+            self.pop(name, params)?;
+            self.push(results);
+            return Ok(());
+        }
+        match instr {
+            Instr::Unreachable => self.unreachable(),
+            Instr::Block(bt) => self.open("block", *bt)?,
+            Instr::Loop(bt) => self.open("loop", *bt)?,
+            Instr::If(bt) => {
+                self.pop("if", &[ValType::I32])?;
+                self.open("if", *bt)?;
+            }
+            Instr::Else => {
+                if self.frame().kind != "if" {
+                    return Err(invalid("else without a matching if".into()));
+                }
+                let frame = self.close()?;
+                self.frames.push(Frame {
+                    kind: "else",
+                    height: self.stack.len(),
+                    unreachable: false,
+                    ..frame
+                });
+                let params = self.frame().params.clone();
+                self.push(&params);
+            }
+            Instr::End => {
+                let frame = self.frame();
+                if frame.kind == "if" && frame.params != frame.results {
+                    return Err(ErrorKind::TypeMismatch {
+                        instr: "if false branch",
+                        expected: frame.results.clone(),
+                        got: frame.params.iter().map(|&t| Some(t)).collect(),
+                    });
+                }
+                let frame = self.close()?;
+                self.push(&frame.results);
+            }
+            Instr::Br(depth) => {
+                let types = self.label(*depth)?;
+                self.pop(name, &types)?;
+                self.unreachable();
+            }
+            Instr::BrIf(depth) => {
+                self.pop(name, &[ValType::I32])?;
+                let types = self.label(*depth)?;
+                self.pop(name, &types)?;
+                self.push(&types);
+            }
+            Instr::BrTable(labels, default) => {
+                self.pop(name, &[ValType::I32])?;
+                let types = self.label(*default)?;
+                for &depth in labels {
+                    let label = self.label(depth)?;
+                    if label.len() != types.len() {
+                        return Err(invalid(format!(
+                            "br_table targets {} and {} take different numbers of values",
+                            depth, default
+                        )));
+                    }
+                    // Checked without popping
+                    let from = self.stack.len().saturating_sub(label.len());
+                    let top = self.stack[from.max(self.frame().height)..].to_vec();
+                    self.pop(name, &label)?;
+                    self.stack.extend(top);
+                }
+                self.pop(name, &types)?;
+                self.unreachable();
+            }
+            Instr::Return => {
+                let results = self.frames[0].results.clone();
+                self.pop(name, &results)?;
+                self.unreachable();
+            }
+            Instr::Call(func) => {
+                self.cx.func(*func)?;
+                let ty = self.cx.m.func_type(*func).ok_or(ErrorKind::Unknown {
+                    what: "function",
+                    index: *func,
+                })?;
+                self.pop(name, &ty.params)?;
+                self.push(&ty.results);
+            }
+            Instr::CallIndirect { ty, table } => {
+                match self.cx.tables.get(*table as usize) {
+                    Some(t) if t.elem == RefType::FuncRef => {}
+                    Some(_) => return Err(invalid(format!("table {} holds no functions", table))),
+                    None => {
+                        return Err(ErrorKind::Unknown {
+                            what: "table",
+                            index: *table,
+                        })
+                    }
+                }
+                let ty = self.cx.type_index(*ty)?;
+                self.pop(name, &[ValType::I32])?;
+                self.pop(name, &ty.params)?;
+                self.push(&ty.results);
+            }
+            Instr::Drop => {
+                self.pop_any(name)?;
+            }
+            Instr::Select => {
+                self.pop(name, &[ValType::I32])?;
+                let a = self.pop_any(name)?;
+                let b = self.pop_any(name)?;
+                if let (Some(a), Some(b)) = (a, b) {
+                    if a != b {
+                        return Err(ErrorKind::TypeMismatch {
+                            instr: name,
+                            expected: vec![a, a],
+                            got: vec![Some(b), Some(a)],
+                        });
+                    }
+                }
+                self.stack.push(a.or(b));
+            }
+            Instr::LocalGet(index) => {
+                let t = self.local(*index)?;
+                self.push(&[t]);
+            }
+            Instr::LocalSet(index) => {
+                let t = self.local(*index)?;
+                self.pop(name, &[t])?;
+            }
+            Instr::LocalTee(index) => {
+                let t = self.local(*index)?;
+                self.pop(name, &[t])?;
+                self.push(&[t]);
+            }
+            Instr::GlobalGet(index) => {
+                let g = self.global(*index)?;
+                self.push(&[g.ty]);
+            }
+            Instr::GlobalSet(index) => {
+                let g = self.global(*index)?;
+                if !g.mutable {
+                    return Err(ErrorKind::ImmutableGlobal(*index));
+                }
+                self.pop(name, &[g.ty])?;
+            }
+            Instr::Memory(op, arg) => {
+                // MS-Wasm loads and stores go through handles to segments,
+                // not through the memory
+                if address == ValType::I32 {
+                    self.memory()?;
+                }
+                self.align(*arg, op.bytes())?;
+                if op.is_store() {
+                    self.pop(name, &[address, op.ty()])?;
+                } else {
+                    self.pop(name, &[address])?;
+                    self.push(&[op.ty()]);
+                }
+            }
+            Instr::MemorySize => {
+                self.memory()?;
+                self.push(&[ValType::I32]);
+            }
+            Instr::MemoryGrow => {
+                self.memory()?;
+                self.pop(name, &[ValType::I32])?;
+                self.push(&[ValType::I32]);
+            }
+            Instr::MemoryInit(data) => {
+                self.memory()?;
+                self.data_count(name, *data)?;
+                self.pop(name, &[address, ValType::I32, ValType::I32])?;
+            }
+            Instr::DataDrop(data) => self.data_count(name, *data)?,
+            Instr::MemoryCopy => {
+                self.memory()?;
+                self.pop(name, &[address, address, ValType::I32])?;
+            }
+            Instr::MemoryFill => {
+                self.memory()?;
+                self.pop(name, &[address, ValType::I32, ValType::I32])?;
+            }
+            _ => unreachable!("{} has a signature", name),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    fn errors(bytes: &[u8]) -> Vec<Error> {
+        validate(&parse(bytes).unwrap()).err().unwrap_or_default()
+    }
+
+    // The errors wabt reports in rust_cheri/error_messsage.txt. wabt gives
+    // the offset just past the instruction (0x25d, 0x495); these are of the
+    // instruction itself.
+    #[test]
+    fn boxes_misuse_handles() {
+        let errors = errors(include_bytes!("../../../rust_cheri/boxes.wasm"));
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].offset, 0x256);
+        assert_eq!(errors[0].func, Some(13));
+        assert_eq!(
+            errors[0].kind.to_string(),
+            "type mismatch in call_indirect, expected [i32] but got [handle]"
+        );
+        assert!(errors[0].kind.hint().unwrap().contains("table index"));
+        assert_eq!(errors[1].offset, 0x494);
+        assert_eq!(
+            errors[1].kind.to_string(),
+            "type mismatch in handle.add, expected [handle, i32] but got [handle, i64]"
+        );
+        assert!(errors[1].kind.hint().unwrap().contains("i32.wrap_i64"));
+    }
+
+    #[test]
+    fn examples_validate() {
+        for bytes in [
+            &include_bytes!("../../rWasm_files/examples/hello.wasm")[..],
+            include_bytes!("../../rWasm_files/examples/malloc.mswasm"),
+            include_bytes!("../../rWasm_files/examples/hello-rust-mswasm.wasm"),
+            include_bytes!("../../rWasm_files/examples/boxes-rust.wasm"),
+        ]
+        .iter()
+        {
+            assert_eq!(errors(bytes), []);
+        }
+    }
+}
@@ -0,0 +1,1451 @@
+use crate::encode::name_section;
+use crate::error::TextError;
+use crate::float;
+use crate::instr::*;
+use crate::module::*;
+use crate::print::section_keyword;
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+
+// Reads a module in the text format, with the MS-Wasm extensions that
+// mswasm-wabt prints: the `handle` type, the handle instructions, a
+// `(handle.null)` initializer and `(pointers "...")` in data segments, of
+// two little-endian u32s (offset and size) per pointer.
+//
+// Both the flat instructions `wasm2wat` prints and folded ones are read.
+// The `$` names of functions, globals and data segments go into a `name`
+// section, so that `print_wat` gives them back, unless the text has a
+// `name` section of its own among its `(@custom ...)` annotations.
+pub fn parse_wat(text: &str) -> Result<Module> {
+    let items = Lexer { text, pos: 0 }.top()?;
+    let fields = match items.first() {
+        Some(Sexpr::List(list, _)) if items.len() == 1 && head(list) == Some("module") => {
+            let skip = if id(list.get(1)).is_some() { 2 } else { 1 };
+            &list[skip..]
+        }
+        _ => &items[..],
+    };
+    let mut p = Parser {
+        text,
+        m: Module::default(),
+        types: Space::default(),
+        funcs: Space::default(),
+        tables: Space::default(),
+        memories: Space::default(),
+        globals: Space::default(),
+        elems: Space::default(),
+        datas: Space::default(),
+        names: Names::default(),
+        after_last: Vec::new(),
+    };
+    p.declare(fields)?;
+    p.define(fields)?;
+    p.finish()
+}
+
+type Result<T> = std::result::Result<T, TextError>;
+
+enum Sexpr<'a> {
+    Atom(&'a str, usize),
+    Str(Vec<u8>, usize),
+    List(Vec<Sexpr<'a>>, usize),
+}
+
+impl<'a> Sexpr<'a> {
+    fn pos(&self) -> usize {
+        match *self {
+            Sexpr::Atom(_, pos) | Sexpr::Str(_, pos) | Sexpr::List(_, pos) => pos,
+        }
+    }
+}
+
+// The keyword a list starts with
+fn head<'a>(list: &[Sexpr<'a>]) -> Option<&'a str> {
+    match list.first() {
+        Some(Sexpr::Atom(a, _)) => Some(a),
+        _ => None,
+    }
+}
+
+fn id<'a>(item: Option<&Sexpr<'a>>) -> Option<&'a str> {
+    match item {
+        Some(Sexpr::Atom(a, _)) if a.starts_with('$') => Some(a),
+        _ => None,
+    }
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn error(&self, pos: usize, msg: &str) -> TextError {
+        TextError::at(self.text, pos, msg.into())
+    }
+
+    fn peek(&self, ahead: usize) -> Option<u8> {
+        self.text.as_bytes().get(self.pos + ahead).copied()
+    }
+
+    fn top(&mut self) -> Result<Vec<Sexpr<'a>>> {
+        let items = self.items()?;
+        if self.pos < self.text.len() {
+            return Err(self.error(self.pos, "unexpected \")\""));
+        }
+        Ok(items)
+    }
+
+    // Skips white space and comments, `;; ...` to the end of the line and
+    // `(; ... ;)`, which nest
+    fn skip(&mut self) -> Result<()> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(b' '), _) | (Some(b'\t'), _) | (Some(b'\n'), _) | (Some(b'\r'), _) => {
+                    self.pos += 1
+                }
+                (Some(b';'), Some(b';')) => {
+                    let rest = &self.text[self.pos..];
+                    self.pos += rest.find('\n').unwrap_or(rest.len());
+                }
+                (Some(b'('), Some(b';')) => {
+                    let start = self.pos;
+                    let mut depth = 0;
+                    loop {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some(b'('), Some(b';')) => {
+                                depth += 1;
+                                self.pos += 2;
+                            }
+                            (Some(b';'), Some(b')')) => {
+                                depth -= 1;
+                                self.pos += 2;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            (Some(_), _) => self.pos += 1,
+                            (None, _) => {
+                                return Err(self.error(start, "unterminated block comment"))
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Items up to the `)` closing the list they are in, or the end
+    fn items(&mut self) -> Result<Vec<Sexpr<'a>>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip()?;
+            let start = self.pos;
+            match self.peek(0) {
+                None | Some(b')') => return Ok(items),
+                Some(b'(') => {
+                    self.pos += 1;
+                    let list = self.items()?;
+                    if self.peek(0) != Some(b')') {
+                        return Err(self.error(start, "unclosed \"(\""));
+                    }
+                    self.pos += 1;
+                    items.push(Sexpr::List(list, start));
+                }
+                Some(b'"') => items.push(Sexpr::Str(self.string()?, start)),
+                Some(_) => {
+                    let rest = &self.text[self.pos..];
+                    let len = rest
+                        .find(|c: char| c.is_whitespace() || "()\";".contains(c))
+                        .unwrap_or(rest.len());
+                    if len == 0 {
+                        return Err(self.error(start, "unexpected \";\""));
+                    }
+                    self.pos += len;
+                    items.push(Sexpr::Atom(&rest[..len], start));
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let mut s = Vec::new();
+        self.pos += 1;
+        loop {
+            let b = *bytes
+                .get(self.pos)
+                .ok_or_else(|| self.error(start, "unterminated string"))?;
+            self.pos += 1;
+            match b {
+                b'"' => return Ok(s),
+                b'\\' => {
+                    let esc = self.pos - 1;
+                    let c = self
+                        .peek(0)
+                        .ok_or_else(|| self.error(start, "unterminated string"))?;
+                    self.pos += 1;
+                    match c {
+                        b'n' => s.push(b'\n'),
+                        b't' => s.push(b'\t'),
+                        b'r' => s.push(b'\r'),
+                        b'"' | b'\'' | b'\\' => s.push(c),
+                        b'u' => {
+                            let rest = &self.text[self.pos..];
+                            let c = rest
+                                .strip_prefix('{')
+                                .and_then(|r| r.find('}').map(|end| &r[..end]))
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(std::char::from_u32)
+                                .ok_or_else(|| self.error(esc, "invalid escape"))?;
+                            self.pos += rest.find('}').unwrap() + 1;
+                            let mut buf = [0; 4];
+                            s.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                        _ => {
+                            let hex = self
+                                .text
+                                .get(self.pos - 1..self.pos + 1)
+                                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                                .ok_or_else(|| self.error(esc, "invalid escape"))?;
+                            self.pos += 1;
+                            s.push(hex);
+                        }
+                    }
+                }
+                _ => s.push(b),
+            }
+        }
+    }
+}
+
+// The items of a list, read in order
+struct Items<'s, 'a> {
+    items: &'s [Sexpr<'a>],
+    next: usize,
+    // Where the list ends, for errors about what is missing from it
+    end: usize,
+}
+
+impl<'s, 'a> Items<'s, 'a> {
+    fn new(items: &'s [Sexpr<'a>], end: usize) -> Self {
+        Items {
+            items,
+            next: 0,
+            end,
+        }
+    }
+
+    fn peek(&self) -> Option<&'s Sexpr<'a>> {
+        self.items.get(self.next)
+    }
+
+    fn next(&mut self) -> Option<&'s Sexpr<'a>> {
+        let item = self.items.get(self.next);
+        self.next += 1;
+        item
+    }
+
+    fn pos(&self) -> usize {
+        self.peek().map_or(self.end, Sexpr::pos)
+    }
+
+    fn id(&mut self) -> Option<&'a str> {
+        let id = id(self.peek());
+        if id.is_some() {
+            self.next += 1;
+        }
+        id
+    }
+
+    fn peek_atom(&self) -> Option<&'a str> {
+        match self.peek() {
+            Some(Sexpr::Atom(a, _)) => Some(a),
+            _ => None,
+        }
+    }
+
+    // Whether the next item is a list starting with `keyword`
+    fn peek_list(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Sexpr::List(l, _)) if head(l) == Some(keyword))
+    }
+
+    // The items after the keyword of the list `peek_list` found
+    fn list(&mut self) -> Items<'s, 'a> {
+        match self.next() {
+            Some(Sexpr::List(l, pos)) => {
+                let mut items = Items::new(l, *pos);
+                items.next = 1;
+                items
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn is_var(atom: &str) -> bool {
+    atom.starts_with('$') || atom.starts_with(|c: char| c.is_ascii_digit())
+}
+
+// An unsigned or signed integer literal, with the sign apart
+fn int_literal(atom: &str) -> Option<(bool, u64)> {
+    let (negative, body) = match atom.as_bytes().first() {
+        Some(b'-') => (true, &atom[1..]),
+        Some(b'+') => (false, &atom[1..]),
+        _ => (false, atom),
+    };
+    let body = body.replace('_', "");
+    let value = match body.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None if body.bytes().all(|b| b.is_ascii_digit()) => body.parse().ok()?,
+        None => return None,
+    };
+    Some((negative, value))
+}
+
+// Items of one kind, which are numbered in order and may have `$` names
+#[derive(Default)]
+struct Space<'a> {
+    ids: HashMap<&'a str, u32>,
+    count: u32,
+}
+
+impl<'a> Space<'a> {
+    fn declare(&mut self, id: Option<&'a str>) -> Option<u32> {
+        let index = self.count;
+        self.count += 1;
+        match id {
+            Some(id) if self.ids.insert(id, index).is_some() => None,
+            _ => Some(index),
+        }
+    }
+}
+
+// What a function body sees besides the module: its locals and the labels
+// of the blocks it is in, innermost last
+#[derive(Default)]
+struct FuncScope<'a> {
+    locals: HashMap<&'a str, u32>,
+    labels: Vec<Option<&'a str>>,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    m: Module,
+    types: Space<'a>,
+    funcs: Space<'a>,
+    tables: Space<'a>,
+    memories: Space<'a>,
+    globals: Space<'a>,
+    elems: Space<'a>,
+    datas: Space<'a>,
+    names: Names,
+    // Custom sections that go after the last section, which is only known
+    // once all are read
+    after_last: Vec<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, pos: usize, msg: String) -> TextError {
+        TextError::at(self.text, pos, msg)
+    }
+
+    fn end(&self, items: &Items) -> Result<()> {
+        match items.peek() {
+            None => Ok(()),
+            Some(item) => Err(self.error(item.pos(), "unexpected token".into())),
+        }
+    }
+
+    fn string(&self, items: &mut Items<'_, 'a>) -> Result<Vec<u8>> {
+        match items.next() {
+            Some(Sexpr::Str(s, _)) => Ok(s.clone()),
+            _ => Err(self.error(items.pos(), "expected a string".into())),
+        }
+    }
+
+    fn name(&self, items: &mut Items<'_, 'a>) -> Result<String> {
+        let pos = items.pos();
+        String::from_utf8(self.string(items)?)
+            .map_err(|_| self.error(pos, "invalid UTF-8 encoding".into()))
+    }
+
+    fn atom(&self, items: &mut Items<'_, 'a>, what: &str) -> Result<(&'a str, usize)> {
+        match items.next() {
+            Some(&Sexpr::Atom(a, pos)) => Ok((a, pos)),
+            _ => Err(self.error(items.pos(), format!("expected {}", what))),
+        }
+    }
+
+    fn u32(&self, items: &mut Items<'_, 'a>) -> Result<u32> {
+        let (atom, pos) = self.atom(items, "a natural number")?;
+        match int_literal(atom) {
+            Some((false, v)) if v <= u32::MAX as u64 && !atom.starts_with('+') => Ok(v as u32),
+            _ => Err(self.error(pos, format!("invalid natural number \"{}\"", atom))),
+        }
+    }
+
+    // An integer of `bits` bits, which may be written signed or unsigned
+    fn int(&self, items: &mut Items<'_, 'a>, bits: u32) -> Result<i64> {
+        let (atom, pos) = self.atom(items, "an integer")?;
+        let max = u64::MAX >> (64 - bits);
+        match int_literal(atom) {
+            Some((false, v)) if v <= max => Ok(v as i64),
+            Some((true, v)) if v <= max / 2 + 1 => Ok((v as i64).wrapping_neg()),
+            _ => Err(self.error(pos, format!("invalid i{} literal \"{}\"", bits, atom))),
+        }
+    }
+
+    fn float(&self, items: &mut Items<'_, 'a>, format: float::Format, bits: u32) -> Result<u64> {
+        let (atom, pos) = self.atom(items, "a float")?;
+        float::parse(&atom.replace('_', ""), format)
+            .ok_or_else(|| self.error(pos, format!("invalid f{} literal \"{}\"", bits, atom)))
+    }
+
+    fn var(&self, items: &mut Items<'_, 'a>, space: &Space, what: &str) -> Result<u32> {
+        let (atom, pos) = self.atom(items, &format!("a {} index or name", what))?;
+        if atom.starts_with('$') {
+            return space
+                .ids
+                .get(atom)
+                .copied()
+                .ok_or_else(|| self.error(pos, format!("undefined {} \"{}\"", what, atom)));
+        }
+        match int_literal(atom) {
+            Some((false, v)) if v <= u32::MAX as u64 => Ok(v as u32),
+            _ => Err(self.error(pos, format!("invalid {} index \"{}\"", what, atom))),
+        }
+    }
+
+    fn val_type(&self, items: &mut Items<'_, 'a>) -> Result<ValType> {
+        let (atom, pos) = self.atom(items, "a value type")?;
+        Ok(match atom {
+            "i32" => ValType::I32,
+            "i64" => ValType::I64,
+            "f32" => ValType::F32,
+            "f64" => ValType::F64,
+            "handle" => ValType::Handle,
+            _ => return Err(self.error(pos, format!("unknown value type \"{}\"", atom))),
+        })
+    }
+
+    fn val_types(&self, items: &mut Items<'_, 'a>) -> Result<Vec<ValType>> {
+        let mut types = Vec::new();
+        while items.peek().is_some() {
+            types.push(self.val_type(items)?);
+        }
+        Ok(types)
+    }
+
+    fn ref_type(&self, items: &mut Items<'_, 'a>) -> Result<RefType> {
+        let (atom, pos) = self.atom(items, "a reference type")?;
+        match atom {
+            "funcref" | "anyfunc" => Ok(RefType::FuncRef),
+            "externref" => Ok(RefType::ExternRef),
+            _ => Err(self.error(pos, format!("unknown reference type \"{}\"", atom))),
+        }
+    }
+
+    fn limits(&self, items: &mut Items<'_, 'a>) -> Result<Limits> {
+        let min = self.u32(items)?;
+        let max = match items.peek_atom() {
+            Some(a) if a.starts_with(|c: char| c.is_ascii_digit()) => Some(self.u32(items)?),
+            _ => None,
+        };
+        Ok(Limits { min, max })
+    }
+
+    fn global_type(&self, items: &mut Items<'_, 'a>) -> Result<GlobalType> {
+        if items.peek_list("mut") {
+            let mut l = items.list();
+            let ty = self.val_type(&mut l)?;
+            self.end(&l)?;
+            return Ok(GlobalType { ty, mutable: true });
+        }
+        Ok(GlobalType {
+            ty: self.val_type(items)?,
+            mutable: false,
+        })
+    }
+
+    // `(param ...)* (result ...)*`, with the names of the parameters
+    fn signature(&self, items: &mut Items<'_, 'a>) -> Result<(FuncType, Vec<Option<&'a str>>)> {
+        let mut ty = FuncType::default();
+        let mut ids = Vec::new();
+        while items.peek_list("param") {
+            let mut l = items.list();
+            if let Some(id) = l.id() {
+                ty.params.push(self.val_type(&mut l)?);
+                ids.push(Some(id));
+                self.end(&l)?;
+            } else {
+                let types = self.val_types(&mut l)?;
+                ids.extend(types.iter().map(|_| None));
+                ty.params.extend(types);
+            }
+        }
+        while items.peek_list("result") {
+            let mut l = items.list();
+            ty.results.extend(self.val_types(&mut l)?);
+        }
+        Ok((ty, ids))
+    }
+
+    // The index of a type, added after the others if there is none like it
+    fn type_index(&mut self, ty: FuncType) -> u32 {
+        match self.m.types.iter().position(|t| *t == ty) {
+            Some(i) => i as u32,
+            None => {
+                self.m.types.push(ty);
+                self.m.types.len() as u32 - 1
+            }
+        }
+    }
+
+    // `(type x)?` and a signature, which must agree if both are there
+    fn type_use(&mut self, items: &mut Items<'_, 'a>) -> Result<(u32, Vec<Option<&'a str>>)> {
+        let explicit = if items.peek_list("type") {
+            let pos = items.pos();
+            let mut l = items.list();
+            let index = self.var(&mut l, &self.types, "type")?;
+            self.end(&l)?;
+            Some((index, pos))
+        } else {
+            None
+        };
+        let inline_pos = items.pos();
+        let (ty, ids) = self.signature(items)?;
+        let inline = !ids.is_empty() || !ty.results.is_empty();
+        match explicit {
+            Some((index, pos)) => {
+                let declared = self
+                    .m
+                    .types
+                    .get(index as usize)
+                    .ok_or_else(|| self.error(pos, format!("unknown type {}", index)))?;
+                if inline && *declared != ty {
+                    return Err(self.error(
+                        inline_pos,
+                        format!("signature does not match type {}: {}", index, declared),
+                    ));
+                }
+                let ids = if inline {
+                    ids
+                } else {
+                    vec![None; declared.params.len()]
+                };
+                Ok((index, ids))
+            }
+            None => Ok((self.type_index(ty), ids)),
+        }
+    }
+
+    fn block_type(&mut self, items: &mut Items<'_, 'a>) -> Result<BlockType> {
+        if !items.peek_list("type") {
+            let start = items.next;
+            let (ty, _) = self.signature(items)?;
+            if ty.params.is_empty() && ty.results.len() <= 1 {
+                return Ok(ty
+                    .results
+                    .first()
+                    .map_or(BlockType::Empty, |&t| BlockType::Value(t)));
+            }
+            items.next = start;
+        }
+        let (index, _) = self.type_use(items)?;
+        Ok(BlockType::Func(index))
+    }
+
+    fn mem_arg(&self, items: &mut Items<'_, 'a>, natural: u32) -> Result<MemArg> {
+        let mut arg = MemArg {
+            align: natural.trailing_zeros(),
+            offset: 0,
+        };
+        while let Some(atom) = items.peek_atom() {
+            let pos = items.pos();
+            let invalid = || self.error(pos, format!("invalid memory immediate \"{}\"", atom));
+            if let Some(v) = atom.strip_prefix("offset=") {
+                arg.offset = match int_literal(v) {
+                    Some((false, v)) if v <= u32::MAX as u64 => v as u32,
+                    _ => return Err(invalid()),
+                };
+            } else if let Some(v) = atom.strip_prefix("align=") {
+                arg.align = match int_literal(v) {
+                    Some((false, v)) if v.is_power_of_two() => v.trailing_zeros(),
+                    _ => return Err(invalid()),
+                };
+            } else {
+                break;
+            }
+            items.next += 1;
+        }
+        Ok(arg)
+    }
+
+    fn label(&self, items: &mut Items<'_, 'a>, f: &FuncScope) -> Result<u32> {
+        let (atom, pos) = self.atom(items, "a label")?;
+        if atom.starts_with('$') {
+            return f
+                .labels
+                .iter()
+                .rev()
+                .position(|&l| l == Some(atom))
+                .map(|depth| depth as u32)
+                .ok_or_else(|| self.error(pos, format!("undefined label \"{}\"", atom)));
+        }
+        match int_literal(atom) {
+            Some((false, v)) if v <= u32::MAX as u64 => Ok(v as u32),
+            _ => Err(self.error(pos, format!("invalid label \"{}\"", atom))),
+        }
+    }
+
+    fn local(&self, items: &mut Items<'_, 'a>, f: &FuncScope) -> Result<u32> {
+        let (atom, pos) = self.atom(items, "a local index or name")?;
+        if atom.starts_with('$') {
+            return f
+                .locals
+                .get(atom)
+                .copied()
+                .ok_or_else(|| self.error(pos, format!("undefined local \"{}\"", atom)));
+        }
+        match int_literal(atom) {
+            Some((false, v)) if v <= u32::MAX as u64 => Ok(v as u32),
+            _ => Err(self.error(pos, format!("invalid local index \"{}\"", atom))),
+        }
+    }
+
+    // An instruction other than the structured ones, with its immediates
+    fn op(
+        &mut self,
+        op: &'a str,
+        pos: usize,
+        items: &mut Items<'_, 'a>,
+        f: &FuncScope<'a>,
+    ) -> Result<Instr> {
+        if let Some(mem) = MemOp::from_name(op) {
+            return Ok(Instr::Memory(mem, self.mem_arg(items, mem.bytes())?));
+        }
+        if let Some(num) = NumOp::from_name(op) {
+            return Ok(Instr::Numeric(num));
+        }
+        Ok(match op {
+            "unreachable" => Instr::Unreachable,
+            "nop" => Instr::Nop,
+            "br" => Instr::Br(self.label(items, f)?),
+            "br_if" => Instr::BrIf(self.label(items, f)?),
+            "br_table" => {
+                let mut labels = vec![self.label(items, f)?];
+                while items.peek_atom().is_some_and(is_var) {
+                    labels.push(self.label(items, f)?);
+                }
+                let default = labels.pop().unwrap();
+                Instr::BrTable(labels, default)
+            }
+            "return" => Instr::Return,
+            "call" => Instr::Call(self.var(items, &self.funcs, "function")?),
+            "call_indirect" => {
+                let table = match items.peek_atom() {
+                    Some(a) if is_var(a) => self.var(items, &self.tables, "table")?,
+                    _ => 0,
+                };
+                let (ty, _) = self.type_use(items)?;
+                Instr::CallIndirect { ty, table }
+            }
+            "drop" => Instr::Drop,
+            "select" => Instr::Select,
+            "local.get" => Instr::LocalGet(self.local(items, f)?),
+            "local.set" => Instr::LocalSet(self.local(items, f)?),
+            "local.tee" => Instr::LocalTee(self.local(items, f)?),
+            "global.get" => Instr::GlobalGet(self.var(items, &self.globals, "global")?),
+            "global.set" => Instr::GlobalSet(self.var(items, &self.globals, "global")?),
+            "memory.size" => Instr::MemorySize,
+            "memory.grow" => Instr::MemoryGrow,
+            "i32.const" => Instr::I32Const(self.int(items, 32)? as i32),
+            "i64.const" => Instr::I64Const(self.int(items, 64)?),
+            "f32.const" => Instr::F32Const(self.float(items, float::F32, 32)? as u32),
+            "f64.const" => Instr::F64Const(self.float(items, float::F64, 64)?),
+            "memory.init" => Instr::MemoryInit(self.var(items, &self.datas, "data segment")?),
+            "data.drop" => Instr::DataDrop(self.var(items, &self.datas, "data segment")?),
+            "memory.copy" => Instr::MemoryCopy,
+            "memory.fill" => Instr::MemoryFill,
+            "new_segment" => Instr::NewSegment,
+            "free_segment" => Instr::FreeSegment,
+            "handle.load" => Instr::HandleLoad(self.mem_arg(items, 8)?),
+            "handle.store" => Instr::HandleStore(self.mem_arg(items, 8)?),
+            "handle.add" => Instr::HandleAdd,
+            "handle.null" => Instr::HandleNull,
+            "handle.eq" => Instr::HandleEq,
+            "handle.lt" => Instr::HandleLt,
+            "handle.get_offset" => Instr::HandleGetOffset,
+            _ => return Err(self.error(pos, format!("unexpected token \"{}\"", op))),
+        })
+    }
+
+    // Instructions, flat or folded, up to the end of the list they are in
+    fn instrs(
+        &mut self,
+        items: &mut Items<'_, 'a>,
+        f: &mut FuncScope<'a>,
+        out: &mut Vec<Instr>,
+    ) -> Result<()> {
+        while let Some(item) = items.next() {
+            match *item {
+                Sexpr::List(..) => self.folded(item, f, out)?,
+                Sexpr::Atom(op @ "block", _)
+                | Sexpr::Atom(op @ "loop", _)
+                | Sexpr::Atom(op @ "if", _) => {
+                    f.labels.push(items.id());
+                    let bt = self.block_type(items)?;
+                    out.push(match op {
+                        "block" => Instr::Block(bt),
+                        "loop" => Instr::Loop(bt),
+                        _ => Instr::If(bt),
+                    });
+                }
+                Sexpr::Atom(op @ "else", pos) | Sexpr::Atom(op @ "end", pos) => {
+                    let label = match f.labels.last() {
+                        Some(&label) => label,
+                        None => return Err(self.error(pos, format!("unexpected \"{}\"", op))),
+                    };
+                    if let Some(id) = items.id() {
+                        if Some(id) != label {
+                            return Err(self.error(pos, format!("mismatching label \"{}\"", id)));
+                        }
+                    }
+                    if op == "end" {
+                        f.labels.pop();
+                        out.push(Instr::End);
+                    } else {
+                        out.push(Instr::Else);
+                    }
+                }
+                Sexpr::Atom(op, pos) => {
+                    let instr = self.op(op, pos, items, f)?;
+                    out.push(instr);
+                }
+                Sexpr::Str(_, pos) => return Err(self.error(pos, "unexpected string".into())),
+            }
+        }
+        Ok(())
+    }
+
+    // `(op immediates operand*)`, `(block ...)`, `(loop ...)` and
+    // `(if ... (then ...) (else ...)?)`
+    fn folded(
+        &mut self,
+        list: &Sexpr<'a>,
+        f: &mut FuncScope<'a>,
+        out: &mut Vec<Instr>,
+    ) -> Result<()> {
+        let (list, pos) = match list {
+            Sexpr::List(list, pos) => (list, *pos),
+            _ => return Err(self.error(list.pos(), "expected a folded instruction".into())),
+        };
+        let mut items = Items::new(list, pos);
+        let (op, op_pos) = self.atom(&mut items, "an instruction")?;
+        match op {
+            "block" | "loop" => {
+                f.labels.push(items.id());
+                let bt = self.block_type(&mut items)?;
+                out.push(if op == "block" {
+                    Instr::Block(bt)
+                } else {
+                    Instr::Loop(bt)
+                });
+                self.instrs(&mut items, f, out)?;
+                f.labels.pop();
+                out.push(Instr::End);
+            }
+            "if" => {
+                let label = items.id();
+                let bt = self.block_type(&mut items)?;
+                while items.peek().is_some() && !items.peek_list("then") {
+                    self.folded(items.next().unwrap(), f, out)?;
+                }
+                if !items.peek_list("then") {
+                    return Err(self.error(items.pos(), "expected (then ...)".into()));
+                }
+                f.labels.push(label);
+                out.push(Instr::If(bt));
+                let mut then = items.list();
+                self.instrs(&mut then, f, out)?;
+                if items.peek_list("else") {
+                    out.push(Instr::Else);
+                    let mut els = items.list();
+                    self.instrs(&mut els, f, out)?;
+                }
+                self.end(&items)?;
+                f.labels.pop();
+                out.push(Instr::End);
+            }
+            _ => {
+                let instr = self.op(op, op_pos, &mut items, f)?;
+                while let Some(operand) = items.next() {
+                    self.folded(operand, f, out)?;
+                }
+                out.push(instr);
+            }
+        }
+        Ok(())
+    }
+
+    // A constant expression, from the rest of `items`
+    fn const_expr(&mut self, items: &mut Items<'_, 'a>) -> Result<ConstExpr> {
+        let pos = items.pos();
+        let mut instrs = Vec::new();
+        self.instrs(items, &mut FuncScope::default(), &mut instrs)?;
+        match instrs[..] {
+            [Instr::I32Const(v)] => Ok(ConstExpr::I32(v)),
+            [Instr::I64Const(v)] => Ok(ConstExpr::I64(v)),
+            [Instr::F32Const(v)] => Ok(ConstExpr::F32(v)),
+            [Instr::F64Const(v)] => Ok(ConstExpr::F64(v)),
+            [Instr::GlobalGet(g)] => Ok(ConstExpr::GlobalGet(g)),
+            [Instr::HandleNull] => Ok(ConstExpr::HandleNull),
+            _ => Err(self.error(pos, "constant expression required".into())),
+        }
+    }
+
+    // The offset of an active segment: `(offset ...)` or one folded
+    // instruction, if there is one
+    fn offset(&mut self, items: &mut Items<'_, 'a>) -> Result<Option<ConstExpr>> {
+        if items.peek_list("offset") {
+            let mut l = items.list();
+            return Ok(Some(self.const_expr(&mut l)?));
+        }
+        match items.peek() {
+            Some(Sexpr::List(l, pos)) if !matches!(head(l), Some("pointers") | Some("memory")) => {
+                items.next += 1;
+                let one = std::slice::from_ref(items.items.get(items.next - 1).unwrap());
+                Ok(Some(self.const_expr(&mut Items::new(one, *pos))?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // `(export "name")*` then `(import "module" "field")?` of an item
+    // defined in place, returning the import if there is one
+    fn inline_export_import(
+        &mut self,
+        items: &mut Items<'_, 'a>,
+        kind: ExternKind,
+        index: u32,
+    ) -> Result<Option<(String, String)>> {
+        while items.peek_list("export") {
+            let mut l = items.list();
+            let name = self.name(&mut l)?;
+            self.end(&l)?;
+            self.m.exports.push(Export { name, kind, index });
+        }
+        if items.peek_list("import") {
+            let mut l = items.list();
+            let module = self.name(&mut l)?;
+            let field = self.name(&mut l)?;
+            self.end(&l)?;
+            return Ok(Some((module, field)));
+        }
+        Ok(None)
+    }
+
+    // Numbers the items of the module and reads the types, so that they can
+    // be referred to before they are defined
+    fn declare(&mut self, fields: &[Sexpr<'a>]) -> Result<()> {
+        let mut defined = HashSet::new();
+        for field in fields {
+            let (list, pos) = match field {
+                Sexpr::List(list, pos) => (list, *pos),
+                _ => return Err(self.error(field.pos(), "expected a module field".into())),
+            };
+            let mut items = Items::new(list, pos);
+            let (keyword, _) = self.atom(&mut items, "a module field")?;
+            let (kind, id) = match keyword {
+                "type" => {
+                    let id = items.id();
+                    if !items.peek_list("func") {
+                        return Err(self.error(items.pos(), "expected (func ...)".into()));
+                    }
+                    let mut l = items.list();
+                    let (ty, _) = self.signature(&mut l)?;
+                    self.end(&l)?;
+                    self.m.types.push(ty);
+                    (keyword, id)
+                }
+                "import" => {
+                    items.next += 2;
+                    match items.peek() {
+                        Some(Sexpr::List(l, _))
+                            if matches!(head(l), Some("func" | "table" | "memory" | "global")) =>
+                        {
+                            let kind = head(l).unwrap();
+                            if defined.contains(kind) {
+                                return Err(self.error(
+                                    pos,
+                                    "imports must occur before all non-import definitions".into(),
+                                ));
+                            }
+                            (kind, id(l.get(1)))
+                        }
+                        _ => return Err(self.error(items.pos(), "expected an import kind".into())),
+                    }
+                }
+                "func" | "table" | "memory" | "global" => {
+                    let id = items.id();
+                    while items.peek_list("export") {
+                        items.next += 1;
+                    }
+                    if items.peek_list("import") {
+                        if defined.contains(keyword) {
+                            return Err(self.error(
+                                pos,
+                                "imports must occur before all non-import definitions".into(),
+                            ));
+                        }
+                    } else {
+                        defined.insert(keyword);
+                    }
+                    (keyword, id)
+                }
+                "elem" | "data" => (keyword, items.id()),
+                "export" | "start" | "@custom" => continue,
+                _ => {
+                    return Err(self.error(pos, format!("unexpected module field \"{}\"", keyword)))
+                }
+            };
+            let text = self.text;
+            let (space, names) = match kind {
+                "type" => (&mut self.types, None),
+                "func" => (&mut self.funcs, Some(&mut self.names.funcs)),
+                "table" => (&mut self.tables, None),
+                "memory" => (&mut self.memories, None),
+                "global" => (&mut self.globals, Some(&mut self.names.globals)),
+                "elem" => (&mut self.elems, None),
+                "data" => (&mut self.datas, Some(&mut self.names.datas)),
+                _ => return Err(self.error(pos, format!("unexpected import kind \"{}\"", kind))),
+            };
+            let index = space.declare(id).ok_or_else(|| {
+                TextError::at(text, pos, format!("redefinition of \"{}\"", id.unwrap()))
+            })?;
+            if let (Some(names), Some(id)) = (names, id) {
+                names.insert(index, id[1..].to_string());
+            }
+            if keyword == "table"
+                && list
+                    .iter()
+                    .any(|i| matches!(i, Sexpr::List(l, _) if head(l) == Some("elem")))
+            {
+                self.elems.declare(None);
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, fields: &[Sexpr<'a>]) -> Result<()> {
+        for field in fields {
+            let (list, pos) = match field {
+                Sexpr::List(list, pos) => (list, *pos),
+                _ => unreachable!(),
+            };
+            let mut items = Items::new(list, pos);
+            items.next = 1;
+            match head(list).unwrap() {
+                "type" => {}
+                "import" => self.import(&mut items)?,
+                "func" => self.func(&mut items)?,
+                "table" => self.table(&mut items)?,
+                "memory" => self.memory(&mut items)?,
+                "global" => self.global(&mut items)?,
+                "export" => self.export(&mut items)?,
+                "start" => {
+                    self.m.start = Some(self.var(&mut items, &self.funcs, "function")?);
+                    self.end(&items)?;
+                }
+                "elem" => self.elem(&mut items)?,
+                "data" => self.data(&mut items)?,
+                "@custom" => self.custom(&mut items)?,
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    fn import(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let module = self.name(items)?;
+        let field = self.name(items)?;
+        // `declare` has checked the kind
+        let kind = match items.peek() {
+            Some(Sexpr::List(l, _)) => head(l).unwrap(),
+            _ => unreachable!(),
+        };
+        let mut l = items.list();
+        l.id();
+        let desc = self.import_desc(kind, &mut l)?;
+        self.end(&l)?;
+        self.end(items)?;
+        self.m.imports.push(Import {
+            module,
+            field,
+            desc,
+        });
+        Ok(())
+    }
+
+    fn import_desc(&mut self, kind: &str, items: &mut Items<'_, 'a>) -> Result<ImportDesc> {
+        Ok(match kind {
+            "func" => ImportDesc::Func(self.type_use(items)?.0),
+            "table" => {
+                let limits = self.limits(items)?;
+                ImportDesc::Table(TableType {
+                    elem: self.ref_type(items)?,
+                    limits,
+                })
+            }
+            "memory" => ImportDesc::Memory(MemoryType {
+                limits: self.limits(items)?,
+            }),
+            _ => ImportDesc::Global(self.global_type(items)?),
+        })
+    }
+
+    // For items defined with an inline import
+    fn imported(
+        &mut self,
+        module: String,
+        field: String,
+        kind: &str,
+        items: &mut Items<'_, 'a>,
+    ) -> Result<()> {
+        let desc = self.import_desc(kind, items)?;
+        self.end(items)?;
+        self.m.imports.push(Import {
+            module,
+            field,
+            desc,
+        });
+        Ok(())
+    }
+
+    fn func(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let index = self.m.num_imported_funcs() + self.m.funcs.len() as u32;
+        items.id();
+        if let Some((module, field)) = self.inline_export_import(items, ExternKind::Func, index)? {
+            return self.imported(module, field, "func", items);
+        }
+        let (ty, param_ids) = self.type_use(items)?;
+        let mut f = FuncScope::default();
+        let mut num_locals = param_ids.len() as u32;
+        for (i, id) in param_ids.iter().enumerate() {
+            if let Some(id) = id {
+                f.locals.insert(id, i as u32);
+            }
+        }
+        let mut locals: Vec<(u32, ValType)> = Vec::new();
+        while items.peek_list("local") {
+            let mut l = items.list();
+            if let Some(id) = l.id() {
+                f.locals.insert(id, num_locals);
+                let t = self.val_type(&mut l)?;
+                self.end(&l)?;
+                push_local(&mut locals, t);
+                num_locals += 1;
+            } else {
+                for t in self.val_types(&mut l)? {
+                    push_local(&mut locals, t);
+                    num_locals += 1;
+                }
+            }
+        }
+        let mut instrs = Vec::new();
+        self.instrs(items, &mut f, &mut instrs)?;
+        if !f.labels.is_empty() {
+            return Err(self.error(items.end, "unclosed block".into()));
+        }
+        instrs.push(Instr::End);
+        self.m.funcs.push(ty);
+        self.m.code.push(Body { locals, instrs });
+        Ok(())
+    }
+
+    fn table(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let index = self.m.table_types().len() as u32;
+        items.id();
+        if let Some((module, field)) = self.inline_export_import(items, ExternKind::Table, index)? {
+            return self.imported(module, field, "table", items);
+        }
+        let table = if items.peek_atom().is_some_and(|a| !is_var(a)) {
+            // `reftype (elem var*)`, with the size of the elements
+            let elem = self.ref_type(items)?;
+            if !items.peek_list("elem") {
+                return Err(self.error(items.pos(), "expected (elem ...)".into()));
+            }
+            let mut l = items.list();
+            let mut funcs = Vec::new();
+            while l.peek().is_some() {
+                funcs.push(self.var(&mut l, &self.funcs, "function")?);
+            }
+            let n = funcs.len() as u32;
+            self.m.elems.push(Elem {
+                mode: ElemMode::Active {
+                    table: index,
+                    offset: ConstExpr::I32(0),
+                },
+                funcs,
+            });
+            TableType {
+                elem,
+                limits: Limits {
+                    min: n,
+                    max: Some(n),
+                },
+            }
+        } else {
+            let limits = self.limits(items)?;
+            TableType {
+                elem: self.ref_type(items)?,
+                limits,
+            }
+        };
+        self.end(items)?;
+        self.m.tables.push(table);
+        Ok(())
+    }
+
+    fn memory(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let index = self.m.memory_types().len() as u32;
+        items.id();
+        if let Some((module, field)) =
+            self.inline_export_import(items, ExternKind::Memory, index)?
+        {
+            return self.imported(module, field, "memory", items);
+        }
+        let limits = self.limits(items)?;
+        self.end(items)?;
+        self.m.memories.push(MemoryType { limits });
+        Ok(())
+    }
+
+    fn global(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let index = self.m.global_types().len() as u32;
+        items.id();
+        if let Some((module, field)) =
+            self.inline_export_import(items, ExternKind::Global, index)?
+        {
+            return self.imported(module, field, "global", items);
+        }
+        let ty = self.global_type(items)?;
+        let init = self.const_expr(items)?;
+        self.m.globals.push(Global { ty, init });
+        Ok(())
+    }
+
+    fn export(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let name = self.name(items)?;
+        let pos = items.pos();
+        let (kind, space, what) = match items.peek() {
+            Some(Sexpr::List(l, _)) => match head(l) {
+                Some("func") => (ExternKind::Func, &self.funcs, "function"),
+                Some("table") => (ExternKind::Table, &self.tables, "table"),
+                Some("memory") => (ExternKind::Memory, &self.memories, "memory"),
+                Some("global") => (ExternKind::Global, &self.globals, "global"),
+                _ => return Err(self.error(pos, "expected an export kind".into())),
+            },
+            _ => return Err(self.error(pos, "expected an export kind".into())),
+        };
+        let mut l = items.list();
+        let index = self.var(&mut l, space, what)?;
+        self.end(&l)?;
+        self.end(items)?;
+        self.m.exports.push(Export { name, kind, index });
+        Ok(())
+    }
+
+    fn elem(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        items.id();
+        let table = if items.peek_list("table") {
+            let mut l = items.list();
+            let table = self.var(&mut l, &self.tables, "table")?;
+            self.end(&l)?;
+            Some(table)
+        } else {
+            None
+        };
+        let declare = items.peek_atom() == Some("declare");
+        if declare {
+            items.next += 1;
+        }
+        let offset = self.offset(items)?;
+        if items.peek_atom() == Some("func") {
+            items.next += 1;
+        }
+        let mut funcs = Vec::new();
+        while items.peek().is_some() {
+            funcs.push(self.var(items, &self.funcs, "function")?);
+        }
+        let mode = match offset {
+            Some(offset) => ElemMode::Active {
+                table: table.unwrap_or(0),
+                offset,
+            },
+            None if declare => ElemMode::Declarative,
+            None => ElemMode::Passive,
+        };
+        self.m.elems.push(Elem { mode, funcs });
+        Ok(())
+    }
+
+    fn data(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        items.id();
+        let memory = if items.peek_list("memory") {
+            let mut l = items.list();
+            let memory = self.var(&mut l, &self.memories, "memory")?;
+            self.end(&l)?;
+            memory
+        } else {
+            0
+        };
+        let mode = match self.offset(items)? {
+            Some(offset) => DataMode::Active { memory, offset },
+            None => DataMode::Passive,
+        };
+        let pointers = if items.peek_list("pointers") {
+            let pos = items.pos();
+            let mut l = items.list();
+            let mut bytes = Vec::new();
+            while l.peek().is_some() {
+                bytes.extend(self.string(&mut l)?);
+            }
+            if bytes.len() % 8 != 0 {
+                return Err(self.error(
+                    pos,
+                    "pointers must be 8 bytes each, an offset and a size".into(),
+                ));
+            }
+            let u32_at = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            let pointers = bytes
+                .chunks(8)
+                .map(|p| Pointer {
+                    offset: u32_at(&p[..4]),
+                    size: u32_at(&p[4..]),
+                })
+                .collect();
+            Some(pointers)
+        } else {
+            None
+        };
+        let mut bytes = Vec::new();
+        while items.peek().is_some() {
+            bytes.extend(self.string(items)?);
+        }
+        self.m.datas.push(Data {
+            mode,
+            pointers,
+            bytes,
+        });
+        Ok(())
+    }
+
+    // A custom section, `(@custom "name" (after code) "...")`. It goes after
+    // the last section unless it says otherwise.
+    fn custom(&mut self, items: &mut Items<'_, 'a>) -> Result<()> {
+        let name = self.name(items)?;
+        let mut after = None;
+        for keyword in &["after", "before"] {
+            if !items.peek_list(keyword) {
+                continue;
+            }
+            let mut l = items.list();
+            let (place, pos) = self.atom(&mut l, "a section")?;
+            self.end(&l)?;
+            let index = SectionId::ORDER
+                .iter()
+                .position(|&id| section_keyword(id) == place);
+            after = match (*keyword, place, index) {
+                ("after", "last", _) => None,
+                ("after", _, Some(i)) => Some(Some(SectionId::ORDER[i])),
+                ("before", "first", _) => Some(None),
+                // What goes before a section goes after the one before it
+                ("before", _, Some(i)) => Some(i.checked_sub(1).map(|i| SectionId::ORDER[i])),
+                _ => return Err(self.error(pos, format!("unknown section \"{}\"", place))),
+            };
+            break;
+        }
+        let mut data = Vec::new();
+        while items.peek().is_some() {
+            data.extend(self.string(items)?);
+        }
+        if after.is_none() {
+            self.after_last.push(self.m.customs.len());
+        }
+        self.m.customs.push(Custom {
+            name,
+            data,
+            after: after.flatten(),
+        });
+        Ok(())
+    }
+
+    // Settles what the binary needs that the text leaves implicit
+    fn finish(mut self) -> Result<Module> {
+        let m = &mut self.m;
+        if m.uses_handles() {
+            for d in &mut m.datas {
+                d.pointers.get_or_insert_with(Vec::new);
+            }
+        } else if m
+            .datas
+            .iter()
+            .any(|d| d.pointers.as_ref().is_some_and(|p| !p.is_empty()))
+        {
+            return Err(TextError::at(
+                self.text,
+                0,
+                "data segments have pointers but the module uses no handles, which the binary \
+                 format needs to tell it is MS-Wasm"
+                    .into(),
+            ));
+        }
+        let uses_data_count = m.code.iter().any(|b| {
+            b.instrs
+                .iter()
+                .any(|i| matches!(i, Instr::MemoryInit(_) | Instr::DataDrop(_)))
+        });
+        if uses_data_count {
+            m.data_count = Some(m.datas.len() as u32);
+        }
+        let last = m.last_section();
+        for &i in &self.after_last {
+            m.customs[i].after = last;
+        }
+        // Made from the names unless given as is, and then first after the
+        // last section, where `print_wat` expects it
+        if !m.customs.iter().any(|c| c.name == "name") {
+            if let Some(data) = name_section(&self.names) {
+                let at = m.customs.iter().position(|c| c.after == last);
+                m.customs.insert(
+                    at.unwrap_or(m.customs.len()),
+                    Custom {
+                        name: "name".into(),
+                        data,
+                        after: last,
+                    },
+                );
+            }
+        }
+        Ok(self.m)
+    }
+}
+
+// Adds a local to the run-length encoded locals of a body
+fn push_local(locals: &mut Vec<(u32, ValType)>, t: ValType) {
+    match locals.last_mut() {
+        Some((n, last)) if *last == t => *n += 1,
+        _ => locals.push((1, t)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode, parse, print_wat};
+
+    #[test]
+    fn repo_wat_files_round_trip() {
+        for path in crate::repo_files("wat") {
+            let text = std::fs::read_to_string(&path).unwrap();
+            let module = parse_wat(&text).unwrap_or_else(|e| panic!("{}:{}", path.display(), e));
+            let again = print_wat(&parse(&encode(&module)).unwrap());
+            assert!(again == text, "{}", path.display());
+        }
+    }
+
+    // Custom sections and all, binaries come back through the text as
+    // `encode` writes them
+    #[test]
+    fn repo_wasm_files_round_trip_through_text() {
+        for path in crate::repo_files("wasm") {
+            let module = parse(&std::fs::read(&path).unwrap()).unwrap();
+            let again = parse_wat(&print_wat(&module))
+                .unwrap_or_else(|e| panic!("{}:{}", path.display(), e));
+            assert!(encode(&again) == encode(&module), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn custom_sections() {
+        let text = r#"(module
+              (@custom "a" (before first) "x")
+              (memory 1)
+              (@custom "b" "y" "z")
+              (@custom "c" (after memory) "")
+              (@custom "d" (before code) "w"))"#;
+        let module = parse_wat(text).unwrap();
+        let placed: Vec<_> = module
+            .customs
+            .iter()
+            .map(|c| (c.name.as_str(), &c.data[..], c.after))
+            .collect();
+        assert_eq!(
+            placed,
+            [
+                ("a", &b"x"[..], None),
+                ("b", &b"yz"[..], Some(SectionId::Memory)),
+                ("c", &b""[..], Some(SectionId::Memory)),
+                ("d", &b"w"[..], Some(SectionId::DataCount)),
+            ]
+        );
+        assert_eq!(
+            print_wat(&parse(&encode(&module)).unwrap()),
+            "(module\n  (memory (;0;) 1)\n  (@custom \"a\" (before first) \"x\")\n  \
+             (@custom \"b\" (after memory) \"yz\")\n  (@custom \"c\" (after memory) \"\")\n  \
+             (@custom \"d\" (after memory) \"w\"))\n"
+        );
+        let e = parse_wat("(module (@custom \"a\" (after nowhere) \"\"))").unwrap_err();
+        assert_eq!(e.to_string(), "1:29: error: unknown section \"nowhere\"");
+    }
+
+    #[test]
+    fn folded_instructions_and_pointers() {
+        let module = parse_wat(
+            r#"(module
+              (global $sp (mut handle) (handle.null))
+              (memory 1)
+              (func $f (param $n i32) (result i32)
+                (if (result i32) (local.get $n)
+                  (then (i32.add (local.get $n) (i32.const -1)))
+                  (else (i32.const 0xffff_ffff))))
+              (data (i32.const 8) (pointers "\10\00\00\00" "\04\00\00\00") "abc"))"#,
+        )
+        .unwrap();
+        assert_eq!(
+            module.code[0].instrs,
+            [
+                Instr::LocalGet(0),
+                Instr::If(BlockType::Value(ValType::I32)),
+                Instr::LocalGet(0),
+                Instr::I32Const(-1),
+                Instr::Numeric(NumOp::from_name("i32.add").unwrap()),
+                Instr::Else,
+                Instr::I32Const(-1),
+                Instr::End,
+                Instr::End,
+            ]
+        );
+        assert_eq!(
+            module.datas[0].pointers,
+            Some(vec![Pointer {
+                offset: 16,
+                size: 4
+            }])
+        );
+        assert_eq!(module.names().funcs[&0], "f");
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let e = parse_wat("(module\n  (func\n    local.get $x))").unwrap_err();
+        assert_eq!((e.line, e.column), (3, 15));
+        assert_eq!(e.to_string(), "3:15: error: undefined local \"$x\"");
+        let e = parse_wat("(module (data (i32.const 0) (pointers \"\\01\")))").unwrap_err();
+        assert!(e.message.contains("8 bytes"), "{}", e);
+    }
+}
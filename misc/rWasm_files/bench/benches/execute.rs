@@ -0,0 +1,123 @@
+// Time spent in generated code: the guest's own `memcpy` (in `output_malloc`
+// and `output-hello-c`) and `strlen` (in `output-hello-c`), called directly on
+// a live instance.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+#[path = "../../output_malloc/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod malloc {
+    include!("../../output_malloc/src/main.rs");
+
+    pub struct Memcpy {
+        module: WasmModule,
+        dst: Handle,
+        src: Handle,
+        len: i32,
+    }
+
+    impl Memcpy {
+        pub fn new(len: u32) -> Self {
+            let mut module = WasmModule::new();
+            let dst = module.alloc_segment(len).unwrap();
+            let src = module.alloc_segment(len).unwrap();
+            let len = len as i32;
+            Memcpy {
+                module,
+                dst,
+                src,
+                len,
+            }
+        }
+
+        pub fn run(&mut self) -> Handle {
+            self.module.func_28(self.dst, self.src, self.len).unwrap()
+        }
+    }
+}
+
+#[path = "../../output-hello-c/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod hello_c {
+    include!("../../output-hello-c/src/main.rs");
+
+    pub struct Memcpy {
+        module: WasmModule,
+        dst: Handle,
+        src: Handle,
+        len: i32,
+    }
+
+    impl Memcpy {
+        pub fn new(len: u32) -> Self {
+            let mut module = WasmModule::new();
+            let dst = module.alloc_segment(len).unwrap();
+            let src = module.alloc_segment(len).unwrap();
+            let len = len as i32;
+            Memcpy {
+                module,
+                dst,
+                src,
+                len,
+            }
+        }
+
+        pub fn run(&mut self) -> Handle {
+            self.module.func_31(self.dst, self.src, self.len).unwrap()
+        }
+    }
+
+    pub struct Strlen {
+        module: WasmModule,
+        s: Handle,
+    }
+
+    impl Strlen {
+        // A string of `len - 1` bytes and its terminator
+        pub fn new(len: u32) -> Self {
+            let mut module = WasmModule::new();
+            let s = module.alloc_segment(len).unwrap();
+            let data = module.segment_data_mut(s).unwrap();
+            data.iter_mut().for_each(|b| *b = b'a');
+            data[len as usize - 1] = 0;
+            Strlen { module, s }
+        }
+
+        pub fn run(&mut self) -> i32 {
+            self.module.func_32(self.s).unwrap()
+        }
+    }
+}
+
+const SIZES: [u32; 2] = [64, 4 << 10];
+
+fn memcpy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memcpy");
+    for &len in &SIZES {
+        group.throughput(Throughput::Bytes(len as u64));
+        let mut bench = malloc::Memcpy::new(len);
+        group.bench_with_input(BenchmarkId::new("output_malloc", len), &len, |b, _| {
+            b.iter(|| bench.run())
+        });
+        let mut bench = hello_c::Memcpy::new(len);
+        group.bench_with_input(BenchmarkId::new("output-hello-c", len), &len, |b, _| {
+            b.iter(|| bench.run())
+        });
+    }
+    group.finish();
+}
+
+fn strlen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("strlen");
+    for &len in &SIZES {
+        group.throughput(Throughput::Bytes(len as u64));
+        let mut bench = hello_c::Strlen::new(len);
+        group.bench_with_input(BenchmarkId::new("output-hello-c", len), &len, |b, _| {
+            b.iter(|| bench.run())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, memcpy, strlen);
+criterion_main!(benches);
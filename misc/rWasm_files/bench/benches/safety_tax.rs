@@ -0,0 +1,215 @@
+// What MS-Wasm costs over plain Wasm. `access` and `stores` do the same work
+// through the linear memory of the plain Wasm runtime (`output-hello`) and
+// through the segments of the MS-Wasm one, a piece of the MS-Wasm path at a
+// time. End-to-end overhead is not measured: no program here is built both
+// as plain Wasm and as MS-Wasm. Run it once per tag representation to see
+// what the tags cost:
+//
+//     cargo bench --bench safety_tax [--features notags|packedtags]
+
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
+
+#[path = "../../output-hello/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod plain {
+    include!("../../output-hello/src/lib.rs");
+
+    // `len` words of linear memory, past the first page as data usually is
+    pub struct Memory {
+        memory: Vec<u8>,
+        start: i32,
+        len: i32,
+    }
+
+    impl Memory {
+        pub fn new(len: u32) -> Self {
+            let start = 65536;
+            let mut memory = vec![0u8; start + 8 * len as usize];
+            for (i, word) in memory[start..].chunks_mut(4).enumerate() {
+                word.copy_from_slice(&(i as i32).to_le_bytes());
+            }
+            Memory {
+                memory,
+                start: start as i32,
+                len: len as i32,
+            }
+        }
+
+        pub fn sum(&self) -> i32 {
+            let mut sum = 0i32;
+            for i in 0..self.len {
+                let addr = self.start.wrapping_add(4 * i);
+                sum = sum.wrapping_add(read_mem_i32(&self.memory, addr as usize).unwrap());
+            }
+            sum
+        }
+
+        // The stores spell out what `write!` expands to, which can't be named
+        // here next to `std::write`
+        pub fn store(&mut self) {
+            for i in 0..self.len {
+                let addr = self.start.wrapping_add(8 * i);
+                write_mem_i64(&mut self.memory, addr as usize, i as i64).unwrap();
+            }
+        }
+    }
+}
+
+#[path = "../../output-rust-mswasm/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod mswasm {
+    include!("../../output-rust-mswasm/src/main.rs");
+
+    // A segment of `len` words (8 bytes each, room for a handle)
+    pub struct Memory {
+        segments: Segments,
+        segment: Handle,
+        len: i32,
+    }
+
+    impl Memory {
+        pub fn new(len: u32) -> Self {
+            let mut segments = Segments::new();
+            let segment = Handle::segment(segments.allocate(8 * len).unwrap());
+            let data = segments
+                .get_mut(segment)
+                .unwrap()
+                .get_mut_data_slice(0, 8 * len as usize)
+                .unwrap();
+            for (i, word) in data.chunks_mut(4).enumerate() {
+                word.copy_from_slice(&(i as i32).to_le_bytes());
+            }
+            Memory {
+                segments,
+                segment,
+                len: len as i32,
+            }
+        }
+
+        // Looks the segment up for every word, at offsets computed as
+        // integers
+        pub fn sum_lookup(&self) -> Result<i32, Trap> {
+            let mut sum = 0i32;
+            for i in 0..self.len {
+                let data = self
+                    .segments
+                    .get(self.segment)?
+                    .get_data_within(self.segment)?;
+                sum =
+                    sum.wrapping_add(read_mem_i32(data, 4 * i as usize).ok_or(Trap::OutOfBounds)?);
+            }
+            Ok(sum)
+        }
+
+        // Goes to every word with a handle, through a segment looked up once
+        pub fn sum_arithmetic(&self) -> Result<i32, Trap> {
+            let data = self.segments.get(self.segment)?.get_data()?;
+            let mut sum = 0i32;
+            for i in 0..self.len {
+                let h = self.segment.add(4 * i)?;
+                let offset = h.checked_offset(4)?;
+                sum = sum.wrapping_add(read_mem_i32(data, offset).ok_or(Trap::OutOfBounds)?);
+            }
+            Ok(sum)
+        }
+
+        // Both, as the generated code does
+        pub fn sum(&self) -> Result<i32, Trap> {
+            let mut sum = 0i32;
+            for i in 0..self.len {
+                sum =
+                    sum.wrapping_add(read!(read_mem_i32, self.segments, self.segment.add(4 * i)?));
+            }
+            Ok(sum)
+        }
+
+        // The stores spell out what `write!` expands to, which can't be named
+        // here next to `std::write`
+        pub fn store(&mut self) -> Result<(), Trap> {
+            for i in 0..self.len {
+                let h = self.segment.add(8 * i)?;
+                write_mem_i64(
+                    self.segments.get_mut(h)?.get_mut_data_within(h)?,
+                    h.segment_offset()?,
+                    i as i64,
+                )
+                .ok_or(Trap::OutOfBounds)?;
+            }
+            Ok(())
+        }
+
+        pub fn store_handles(&mut self) -> Result<(), Trap> {
+            for i in 0..self.len {
+                let h = self.segment.add(8 * i)?;
+                self.segments
+                    .get_mut(h)?
+                    .store_handle(h.checked_offset(8)?, self.segment)?;
+            }
+            Ok(())
+        }
+
+        pub fn load_handles(&self) -> Result<(), Trap> {
+            for i in 0..self.len {
+                read!(get_handle, self.segments, self.segment.add(8 * i)?);
+            }
+            Ok(())
+        }
+    }
+}
+
+// The tag representation the MS-Wasm runtime is built with, as part of the
+// group names so that each keeps its own history
+const TAGS: &str = if cfg!(feature = "notags") {
+    "notags"
+} else if cfg!(feature = "packedtags") {
+    "packedtags"
+} else {
+    "default tags"
+};
+
+const LEN: u32 = 4 << 10;
+
+fn group<'a>(
+    c: &'a mut Criterion,
+    name: &str,
+) -> BenchmarkGroup<'a, criterion::measurement::WallTime> {
+    c.benchmark_group(format!("{} ({})", name, TAGS))
+}
+
+// Summing an array of `i32`s
+fn access(c: &mut Criterion) {
+    let mut group = group(c, "access");
+    group.throughput(Throughput::Elements(LEN as u64));
+    let plain = plain::Memory::new(LEN);
+    group.bench_function("linear memory", |b| b.iter(|| plain.sum()));
+    let mswasm = mswasm::Memory::new(LEN);
+    group.bench_function("segment lookup", |b| {
+        b.iter(|| mswasm.sum_lookup().unwrap())
+    });
+    group.bench_function("handle arithmetic", |b| {
+        b.iter(|| mswasm.sum_arithmetic().unwrap())
+    });
+    group.bench_function("handles", |b| b.iter(|| mswasm.sum().unwrap()));
+    group.finish();
+}
+
+// Storing and loading 8-byte words, which is where tags come in
+fn stores(c: &mut Criterion) {
+    let mut group = group(c, "stores");
+    group.throughput(Throughput::Elements(LEN as u64));
+    let mut plain = plain::Memory::new(LEN);
+    group.bench_function("linear memory i64", |b| b.iter(|| plain.store()));
+    let mut mswasm = mswasm::Memory::new(LEN);
+    group.bench_function("segment i64", |b| b.iter(|| mswasm.store().unwrap()));
+    group.bench_function("segment handle", |b| {
+        b.iter(|| mswasm.store_handles().unwrap())
+    });
+    mswasm.store_handles().unwrap();
+    group.bench_function("segment handle load", |b| {
+        b.iter(|| mswasm.load_handles().unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, access, stores);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+// The `sum` loop of `output-for-loop-unsafe-c`, over an array of `i32`s in a
+// segment of its own. Each iteration makes five accesses to the stack frame
+// and one to the array, so this is mostly the cost of going from a handle to
+// the segment behind it.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+#[path = "../../output-for-loop-unsafe-c/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod for_loop {
+    include!("../../output-for-loop-unsafe-c/src/main.rs");
+
+    pub struct Sum {
+        module: WasmModule,
+        array: Handle,
+        len: i32,
+    }
+
+    impl Sum {
+        pub fn new(len: u32) -> Self {
+            let mut module = WasmModule::new();
+            // `__wasm_call_ctors`, which sets up the stack
+            module.func_1().unwrap();
+            let array = module.alloc_segment(4 * len).unwrap();
+            for (i, word) in module
+                .segment_data_mut(array)
+                .unwrap()
+                .chunks_mut(4)
+                .enumerate()
+            {
+                word.copy_from_slice(&(i as i32).to_le_bytes());
+            }
+            let len = len as i32;
+            Sum { module, array, len }
+        }
+
+        pub fn run(&mut self) -> i32 {
+            self.module.func_4(self.array, self.len).unwrap()
+        }
+    }
+}
+
+fn sum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sum");
+    for &len in &[16, 4 << 10] {
+        group.throughput(Throughput::Elements(len as u64));
+        let mut bench = for_loop::Sum::new(len);
+        group.bench_with_input(BenchmarkId::from_parameter(len), &len, |b, _| {
+            b.iter(|| bench.run())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sum);
+criterion_main!(benches);
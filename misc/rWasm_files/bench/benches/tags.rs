@@ -0,0 +1,97 @@
+// Cost of clearing, scanning and moving the tags of large ranges, in the tag
+// representation the benchmark is built with:
+//
+//     cargo bench --bench tags [--features notags|packedtags]
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+#[path = "../../output-add/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod add {
+    include!("../../output-add/src/main.rs");
+
+    pub struct Bench(Segment);
+
+    impl Bench {
+        // A segment of `len` bytes with a handle stored every `spacing` bytes
+        pub fn new(len: u32, spacing: Option<usize>) -> Self {
+            let mut segment = Segment::allocate(len);
+            if let Some(spacing) = spacing {
+                for offset in (0..len as usize).step_by(spacing) {
+                    segment.store_handle(offset, Handle::NULL).unwrap();
+                }
+            }
+            Bench(segment)
+        }
+
+        fn tags(&mut self) -> &mut Tags {
+            match &mut self.0 {
+                Segment::Allocated { tags, .. } => tags,
+                Segment::Freed => unreachable!(),
+            }
+        }
+
+        // What writing (or initializing) all the data in the segment costs
+        pub fn write_data(&mut self) {
+            let len = self.0.len().unwrap();
+            self.0.get_mut_data_slice(0, len).unwrap();
+        }
+
+        pub fn any_handle(&mut self) -> bool {
+            let len = self.0.len().unwrap() / 8;
+            self.tags().any_handle_in_range(0..len).unwrap()
+        }
+
+        // Moves the tags of the first half of the segment up by one tag
+        pub fn copy(&mut self) {
+            let len = self.0.len().unwrap() / 8;
+            self.tags().copy_range(None, 0..len / 2, 1).unwrap();
+        }
+    }
+}
+
+const SIZES: [u32; 2] = [4 << 10, 1 << 20];
+
+fn write_data(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_data");
+    for &len in &SIZES {
+        group.throughput(Throughput::Bytes(len as u64));
+        for &(name, spacing) in &[("no handles", None), ("handle every 64 bytes", Some(64))] {
+            group.bench_with_input(BenchmarkId::new(name, len), &len, |b, &len| {
+                b.iter_batched_ref(
+                    || add::Bench::new(len, spacing),
+                    |bench| bench.write_data(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn any_handle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("any_handle_in_range");
+    for &len in &SIZES {
+        group.throughput(Throughput::Bytes(len as u64));
+        let mut bench = add::Bench::new(len, None);
+        group.bench_with_input(BenchmarkId::from_parameter(len), &len, |b, _| {
+            b.iter(|| bench.any_handle())
+        });
+    }
+    group.finish();
+}
+
+fn copy_range(c: &mut Criterion) {
+    let mut group = c.benchmark_group("copy_range");
+    for &len in &SIZES {
+        group.throughput(Throughput::Bytes(len as u64 / 2));
+        let mut bench = add::Bench::new(len, Some(64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &len, |b, _| {
+            b.iter(|| bench.copy())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, write_data, any_handle, copy_range);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+// Throughput of `fd_write` through the WASI bridge, with the buffers of the
+// call in one segment (handed to wiggle in place) or spread over two (copied
+// through scratch memory).
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+#[path = "../../output-hello-c/src"]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod hello_c {
+    include!("../../output-hello-c/src/main.rs");
+
+    pub struct FdWrite {
+        ctx: wasi_common::WasiCtx,
+        segments: Segments,
+        iovs: Handle,
+        nwritten: Handle,
+    }
+
+    impl FdWrite {
+        // Two iovecs of `len / 2` bytes each, to a sink on stdout
+        pub fn new(len: u32, split: bool) -> Self {
+            let ctx = wasi_common::WasiCtxBuilder::new()
+                .stdout(wasi_common::virtfs::pipe::WritePipe::new(std::io::sink()))
+                .build()
+                .unwrap();
+            let mut segments = Segments::new();
+            let mut segment = |size| Handle::segment(segments.allocate(size).unwrap());
+            // Room is left past the buffers for the call's own iovec array
+            let first = segment(len + 32);
+            let second = if split {
+                segment(len / 2)
+            } else {
+                first.add((len / 2) as i32).unwrap()
+            };
+            let iovs = segment(2 * 16);
+            let nwritten = segment(4);
+            let mut bench = FdWrite {
+                ctx,
+                segments,
+                iovs,
+                nwritten,
+            };
+            bench.set_iovec(0, first, len / 2);
+            bench.set_iovec(1, second, len / 2);
+            bench
+        }
+
+        fn set_iovec(&mut self, i: i32, buf: Handle, len: u32) {
+            let entry = 16 * i as usize;
+            let segment = self.segments.get_mut(self.iovs).unwrap();
+            segment.store_handle(entry, buf).unwrap();
+            let data = segment.get_mut_data_slice(entry + 8, entry + 12).unwrap();
+            write_mem_u32(data, entry + 8, len).unwrap();
+        }
+
+        pub fn run(&mut self) {
+            let res = ms_wasm_wasi::fd_write(
+                &self.ctx,
+                &mut self.segments,
+                1,
+                self.iovs,
+                2,
+                self.nwritten,
+            )
+            .unwrap();
+            assert_eq!(res, 0);
+        }
+    }
+}
+
+fn fd_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fd_write");
+    for &len in &[64, 4 << 10, 256 << 10] {
+        group.throughput(Throughput::Bytes(len as u64));
+        for &(name, split) in &[("one segment", false), ("two segments", true)] {
+            let mut bench = hello_c::FdWrite::new(len, split);
+            group.bench_with_input(BenchmarkId::new(name, len), &len, |b, _| {
+                b.iter(|| bench.run())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fd_write);
+criterion_main!(benches);
@@ -1,5 +1,6 @@
 mod guest_mem_wrapper;
 
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::TryInto;
 
 #[derive(Copy, Clone, Debug)]
@@ -41,16 +42,316 @@ impl From<TaggedVal> for ValType {
     }
 }
 
+// The ways a module can trap. Every generated function returns
+// `Result<_, Trap>`, so a trap unwinds the whole guest call stack back
+// to the exported entry point that was called by the host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trap {
+    // Access through a handle to a segment that has been freed
+    SegmentFreed,
+    // Access outside of the bounds of a handle or of its segment
+    OutOfBounds,
+    // Dereference of a handle whose tag was cleared by a data write
+    CorruptedHandle,
+    // Dereference of `handle.null` (possibly with an offset)
+    NullDereference,
+    // A value did not have the type the instruction expected
+    TagMismatch,
+    // Handle load/store at an offset that is not 8-byte aligned
+    MisalignedHandleAccess,
+    // `free_segment` on a segment that was already freed
+    DoubleFree,
+    // `free_segment` on a handle that does not point at the start of its
+    // segment, or that was sliced to a part of it
+    InteriorFree,
+    // `free_segment` on `handle.null`
+    NullFree,
+    // `free_segment` on a corrupted handle or one with a segment ID that was
+    // never handed out
+    ForgedFree,
+    // No segment IDs left to hand out
+    SegmentsExhausted,
+    // An allocation would have gone over one of the `MemoryLimits`
+    MemoryLimitExceeded,
+    // `call_indirect` to an empty table slot
+    UndefinedTableElement,
+    // `call_indirect` to a function of the wrong type
+    IndirectCallSignatureMismatch,
+    IntegerDivideByZero,
+    InvalidConversionToInteger,
+    // Reached an `unreachable` instruction
+    Unreachable,
+    // The guest call stack grew too deep
+    StackOverflow,
+    // The guest used up the fuel it was given
+    OutOfFuel,
+    // Stopped through an `InterruptHandle`
+    Interrupted,
+    // Not a fault: the guest called `proc_exit` with this status
+    Exit(i32),
+    // Not a fault either: `WasmModule::call` was given the name of no export
+    UnknownExport,
+    // Nor this: the arguments given to `WasmModule::call`, or the results
+    // a host function returned, do not match the signature
+    ArgumentMismatch,
+    // Call to an import that no host function was linked for
+    UnresolvedImport,
+}
+
+impl std::fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Trap::SegmentFreed => "use of a freed segment",
+            Trap::OutOfBounds => "out of bounds segment access",
+            Trap::CorruptedHandle => "dereference of a corrupted handle",
+            Trap::NullDereference => "null handle dereference",
+            Trap::TagMismatch => "value of unexpected type",
+            Trap::MisalignedHandleAccess => "misaligned handle access",
+            Trap::DoubleFree => "double free of a segment",
+            Trap::InteriorFree => "free of a handle into the middle of a segment",
+            Trap::NullFree => "free of a null handle",
+            Trap::ForgedFree => "free of a forged handle",
+            Trap::SegmentsExhausted => "segment IDs exhausted",
+            Trap::MemoryLimitExceeded => "memory limit exceeded",
+            Trap::UndefinedTableElement => "undefined table element",
+            Trap::IndirectCallSignatureMismatch => "indirect call signature mismatch",
+            Trap::IntegerDivideByZero => "integer divide by zero",
+            Trap::InvalidConversionToInteger => "invalid conversion to integer",
+            Trap::Unreachable => "unreachable executed",
+            Trap::StackOverflow => "call stack exhausted",
+            Trap::OutOfFuel => "all fuel consumed",
+            Trap::Interrupted => "interrupted by the host",
+            Trap::UnknownExport => "no export of that name",
+            Trap::ArgumentMismatch => "values do not match the signature",
+            Trap::UnresolvedImport => "call to an unresolved import",
+            Trap::Exit(code) => return write!(f, "exit with status {}", code),
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for Trap {}
+
+// Why `WasmModuleBuilder::build` failed
+#[derive(Debug)]
+pub enum BuildError {
+    // A directory to preopen could not be opened on the host
+    Preopen(std::path::PathBuf, std::io::Error),
+    // The WASI context could not be built
+    Wasi(wasi_common::WasiCtxBuilderError),
+    // The module's own initialization trapped
+    Trap(Trap),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::Preopen(path, e) => write!(f, "cannot preopen {}: {}", path.display(), e),
+            BuildError::Wasi(e) => write!(f, "cannot build WASI context: {}", e),
+            BuildError::Trap(trap) => write!(f, "wasm trap during instantiation: {}", trap),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+// A value passed to or returned from an export, the public counterpart of
+// `TaggedVal`
+#[derive(Copy, Clone, Debug)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Handle(Handle),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+    Handle,
+}
+
+impl Value {
+    pub fn ty(&self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::Handle(_) => ValueType::Handle,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+            ValueType::Handle => "handle",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// An exported function and its signature
+#[derive(Copy, Clone, Debug)]
+pub struct Export {
+    pub name: &'static str,
+    pub params: &'static [ValueType],
+    pub results: &'static [ValueType],
+}
+
+// A function the module imports from the host, and its signature
+#[derive(Copy, Clone, Debug)]
+pub struct Import {
+    pub module: &'static str,
+    pub field: &'static str,
+    pub params: &'static [ValueType],
+    pub results: &'static [ValueType],
+}
+
+fn fmt_signature(
+    f: &mut std::fmt::Formatter,
+    params: &[ValueType],
+    results: &[ValueType],
+) -> std::fmt::Result {
+    let list = |types: &[ValueType]| {
+        types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    write!(f, "({})", list(params))?;
+    match results {
+        [] => Ok(()),
+        [result] => write!(f, " -> {}", result),
+        results => write!(f, " -> ({})", list(results)),
+    }
+}
+
+impl std::fmt::Display for Export {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        fmt_signature(f, self.params, self.results)
+    }
+}
+
+impl std::fmt::Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.module, self.field)?;
+        fmt_signature(f, self.params, self.results)
+    }
+}
+
+// A trap leaving a generated function body, together with the code offset of
+// the Wasm instruction that raised it. Generated code attaches the offset at
+// each call that can trap (see `At`); traps raised in the bodies of
+// imported functions have no Wasm code to point at.
+struct Fault {
+    trap: Trap,
+    code_offset: Option<u32>,
+}
+
+impl Fault {
+    #[inline]
+    fn new(trap: Trap, code_offset: u32) -> Self {
+        Fault {
+            trap,
+            code_offset: Some(code_offset),
+        }
+    }
+}
+
+impl From<Trap> for Fault {
+    #[inline]
+    fn from(trap: Trap) -> Self {
+        Fault {
+            trap,
+            code_offset: None,
+        }
+    }
+}
+
+// Attaches the code offset of the instruction being run to a trap raised by
+// generated code
+trait At<T> {
+    fn at(self, code_offset: u32) -> Result<T, Fault>;
+}
+
+impl<T> At<T> for Result<T, Trap> {
+    #[inline]
+    fn at(self, code_offset: u32) -> Result<T, Fault> {
+        self.map_err(|trap| Fault::new(trap, code_offset))
+    }
+}
+
+// A guest function that a trap unwound through
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WasmFrame {
+    pub func_index: u32,
+    // Offset from the start of the code section of the instruction that
+    // trapped (innermost frame) or made the call (outer frames). `None` for
+    // imported functions, which have no Wasm code.
+    pub code_offset: Option<u32>,
+}
+
+impl WasmFrame {
+    // Name from the module's `name` section
+    pub fn func_name(&self) -> &'static str {
+        FUNC_NAMES[self.func_index as usize]
+    }
+}
+
+impl std::fmt::Display for WasmFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "`{}` (func {})", self.func_name(), self.func_index)?;
+        if let Some(offset) = self.code_offset {
+            write!(f, " at code offset {:#x}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+// The guest call stack at the last trap, innermost frame first
+#[derive(Clone, Debug, Default)]
+pub struct WasmBacktrace {
+    frames: Vec<WasmFrame>,
+}
+
+impl WasmBacktrace {
+    pub fn frames(&self) -> &[WasmFrame] {
+        &self.frames
+    }
+}
+
+impl std::fmt::Display for WasmBacktrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            let prefix = if i == 0 { "in" } else { "called from" };
+            writeln!(f, "    {} {}", prefix, frame)?;
+        }
+        Ok(())
+    }
+}
+
 macro_rules! tagged_value_conversion {
     ($ty:ty, $try_as:ident, $e:tt) => {
         impl TaggedVal {
             #[inline]
             #[allow(dead_code)]
-            fn $try_as(&self) -> Option<$ty> {
+            fn $try_as(&self) -> Result<$ty, Trap> {
                 if let $e(v) = self {
-                    Some(*v)
+                    Ok(*v)
                 } else {
-                    None
+                    Err(Trap::TagMismatch)
                 }
             }
         }
@@ -87,16 +388,16 @@ impl From<u64> for TaggedVal {
 }
 
 trait SafeFloatConv<T> {
-    fn try_to_int(self) -> Option<T>;
+    fn try_to_int(self) -> Result<T, Trap>;
 }
 macro_rules! safe_float_conv {
     ($from:ty, $to:ty) => {
         impl SafeFloatConv<$to> for $from {
-            fn try_to_int(self) -> Option<$to> {
+            fn try_to_int(self) -> Result<$to, Trap> {
                 if self.is_finite() {
-                    Some(self as $to)
+                    Ok(self as $to)
                 } else {
-                    None
+                    Err(Trap::InvalidConversionToInteger)
                 }
             }
         }
@@ -122,18 +423,33 @@ impl TaggedVal {
     // keep this around
     #[inline(always)]
     #[allow(dead_code, non_snake_case)]
-    fn try_as_Handle(&self) -> Option<Handle> {
+    fn try_as_Handle(&self) -> Result<Handle, Trap> {
         self.try_as_handle()
     }
 }
 
+// A handle as the guest sees it. Only the runtime makes valid handles: the
+// host gets them from `alloc_segment`, `read_handle` and the like, and can
+// neither forge one nor widen its bounds.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct Handle {
+    repr: HandleRepr,
+}
+
 #[derive(Copy, Clone, Debug)]
-pub enum Handle {
+#[cfg_attr(test, derive(PartialEq))]
+enum HandleRepr {
     Valid {
         base_segment_id: u32, // Note: Using segment ID here, rather than a base into memory
         offset: u32,
-        // Note: Ignoring `bound: u32` for now, since we don't (yet)
-        // have handle.slice/segment_slice/etc.
+        // Note: `base` and `bound` are offsets into the segment. A
+        // handle fresh out of `new_segment` spans the whole segment
+        // (`bound == Handle::SEGMENT_END`); `handle.slice` narrows
+        // them down. Accesses are checked against both these and the
+        // segment's own length.
+        base: u32,
+        bound: u32,
     },
     Corrupted {
         bytes: [u8; 8],
@@ -144,110 +460,232 @@ pub enum Handle {
 }
 impl std::fmt::Display for Handle {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Handle::Valid {
+        match self.repr {
+            HandleRepr::Valid {
+                base_segment_id,
+                offset,
+                base: 0,
+                bound: Handle::SEGMENT_END,
+            } => {
+                let (index, generation) = Segments::split_id(base_segment_id);
+                write!(f, "<seg={} gen={} off={:#x?}>", index, generation, offset)
+            }
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
-            } => write!(f, "<seg={} off={:#x?}>", base_segment_id, offset),
-            Handle::Corrupted { bytes } => write!(f, "<corrupted {:?}>", bytes),
-            Handle::Null { offset } => write!(f, "<null off={:#x?}>", offset),
+                base,
+                bound,
+            } => {
+                let (index, generation) = Segments::split_id(base_segment_id);
+                write!(
+                    f,
+                    "<seg={} gen={} off={:#x?} bounds=[{:#x?}, {:#x?})>",
+                    index, generation, offset, base, bound
+                )
+            }
+            HandleRepr::Corrupted { bytes } => write!(f, "<corrupted {:?}>", bytes),
+            HandleRepr::Null { offset } => write!(f, "<null off={:#x?}>", offset),
         }
     }
 }
 
 impl Handle {
-    const NULL: Handle = Handle::Null { offset: 0 };
+    const NULL: Handle = Handle {
+        repr: HandleRepr::Null { offset: 0 },
+    };
+
+    // Upper bound of a handle that has not been sliced, i.e. one that
+    // may reach up to the end of its segment
+    const SEGMENT_END: u32 = u32::MAX;
+
+    // The whole of the segment `base_segment_id`, as `new_segment` hands out
+    fn segment(base_segment_id: u32) -> Self {
+        Handle {
+            repr: HandleRepr::Valid {
+                base_segment_id,
+                offset: 0,
+                base: 0,
+                bound: Handle::SEGMENT_END,
+            },
+        }
+    }
 
     #[allow(dead_code)]
-    fn add(self, amt: i32) -> Option<Self> {
-        match self {
-            Handle::Null { offset } => Some(Handle::Null {
-                offset: offset.checked_add(amt)?,
+    fn add(self, amt: i32) -> Result<Self, Trap> {
+        match self.repr {
+            HandleRepr::Null { offset } => Ok(Handle {
+                repr: HandleRepr::Null {
+                    offset: offset.checked_add(amt).ok_or(Trap::OutOfBounds)?,
+                },
             }),
-            Handle::Corrupted { .. } => None,
-            Handle::Valid {
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
+                base,
+                bound,
             } => {
                 let offset: i32 = offset as _;
                 let new_offset: i32 = offset.overflowing_add(amt).0;
-                Some(Handle::Valid {
-                    base_segment_id,
-                    offset: new_offset as _,
+                Ok(Handle {
+                    repr: HandleRepr::Valid {
+                        base_segment_id,
+                        offset: new_offset as _,
+                        base,
+                        bound,
+                    },
                 })
             }
         }
     }
 
     #[allow(dead_code)]
-    fn sub(self, amt: i32) -> Option<Self> {
+    fn sub(self, amt: i32) -> Result<Self, Trap> {
         self.add(-amt)
     }
 
+    // handle.slice: narrows the handle down to the `len` bytes
+    // starting `start` bytes away from its current offset. The new
+    // range must lie within the current bounds of the handle; the
+    // resulting handle points at the start of the new range.
     #[allow(dead_code)]
-    #[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
-    fn segment_index(self) -> Option<usize> {
-        match self {
-            Handle::Null { .. } | Handle::Corrupted { .. } => None,
-            Handle::Valid {
+    fn slice(self, start: i32, len: i32) -> Result<Self, Trap> {
+        match self.repr {
+            HandleRepr::Null { .. } => Err(Trap::NullDereference),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid {
                 base_segment_id,
-                offset: _,
-            } => Some(base_segment_id as _),
+                offset,
+                base,
+                bound,
+            } => {
+                let new_base: u32 = (offset as i64 + start as i64)
+                    .try_into()
+                    .or(Err(Trap::OutOfBounds))?;
+                let new_bound: u32 = (new_base as i64 + len as i64)
+                    .try_into()
+                    .or(Err(Trap::OutOfBounds))?;
+                if new_base < base || new_bound < new_base || new_bound > bound {
+                    return Err(Trap::OutOfBounds);
+                }
+                Ok(Handle {
+                    repr: HandleRepr::Valid {
+                        base_segment_id,
+                        offset: new_base,
+                        base: new_base,
+                        bound: new_bound,
+                    },
+                })
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    fn is_sliced(self) -> bool {
+        match self.repr {
+            HandleRepr::Valid { base, bound, .. } => base != 0 || bound != Handle::SEGMENT_END,
+            HandleRepr::Null { .. } | HandleRepr::Corrupted { .. } => false,
+        }
+    }
+
+    // Slot index and generation, see `Segments`
+    #[allow(dead_code)]
+    fn segment_id(self) -> Result<u32, Trap> {
+        match self.repr {
+            HandleRepr::Null { .. } => Err(Trap::NullDereference),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid {
+                base_segment_id, ..
+            } => Ok(base_segment_id),
         }
     }
 
     #[allow(dead_code)]
     #[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
-    fn segment_offset(self) -> Option<usize> {
-        match self {
-            Handle::Null { offset } => Some(offset as _),
-            Handle::Corrupted { .. } => None,
-            Handle::Valid {
-                base_segment_id: _,
-                offset,
-            } => Some(offset as _),
+    fn segment_offset(self) -> Result<usize, Trap> {
+        match self.repr {
+            HandleRepr::Null { offset } => Ok(offset as _),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid { offset, .. } => Ok(offset as _),
+        }
+    }
+
+    #[allow(dead_code)]
+    #[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
+    fn segment_bounds(self) -> Result<(usize, usize), Trap> {
+        match self.repr {
+            HandleRepr::Null { .. } => Err(Trap::NullDereference),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid { base, bound, .. } => Ok((base as _, bound as _)),
+        }
+    }
+
+    // Returns the offset of the handle if `len` bytes starting there
+    // are within its bounds
+    #[allow(dead_code)]
+    fn checked_offset(self, len: usize) -> Result<usize, Trap> {
+        let (base, bound) = self.segment_bounds()?;
+        let offset = self.segment_offset()?;
+        if offset < base || offset.checked_add(len).ok_or(Trap::OutOfBounds)? > bound {
+            Err(Trap::OutOfBounds)
+        } else {
+            Ok(offset)
         }
     }
 
     #[allow(dead_code)]
     fn to_bytes(self) -> ([u8; 8], Tag) {
-        match self {
-            Handle::Null { offset: 0 } => {
+        match self.repr {
+            HandleRepr::Null { offset } => {
+                // Null takes the one segment ID no segment gets (see
+                // `Segments::MAX_INDEX`). Its offset is stored inverted, so
+                // that plain null is all ones.
                 let mut res = [0u8; 8];
                 res[..4].copy_from_slice(&u32::MAX.to_ne_bytes());
-                res[4..].copy_from_slice(&u32::MAX.to_ne_bytes());
+                res[4..].copy_from_slice(&(!offset as u32).to_ne_bytes());
                 (res, Tag::Handle)
             }
-            Handle::Null { offset } => {
-                todo!("Trying to convert null with offset {} to bytes", offset)
-            }
-            Handle::Valid {
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
+                ..
             } => {
+                // Note: the bounds do not fit in the 8 bytes; the
+                // segment keeps them on the side (see
+                // `Segment::store_handle`)
                 let mut res = [0u8; 8];
                 res[..4].copy_from_slice(&base_segment_id.to_ne_bytes());
                 res[4..].copy_from_slice(&offset.to_ne_bytes());
                 (res, Tag::Handle)
             }
-            Handle::Corrupted { bytes } => (bytes, Tag::Data),
+            HandleRepr::Corrupted { bytes } => (bytes, Tag::Data),
         }
     }
 
     #[allow(dead_code)]
-    fn from_bytes(bytes: [u8; 8], tag: Tag) -> Self {
+    fn from_bytes(bytes: [u8; 8], tag: Tag, bounds: Option<(u32, u32)>) -> Self {
         if !tag.can_be_handle() {
-            Handle::Corrupted { bytes }
+            Handle {
+                repr: HandleRepr::Corrupted { bytes },
+            }
         } else {
             let base_segment_id = u32::from_ne_bytes(bytes[..4].try_into().unwrap());
             let offset = u32::from_ne_bytes(bytes[4..].try_into().unwrap());
             if base_segment_id == u32::MAX {
-                assert_eq!(offset, u32::MAX);
-                Handle::Null { offset: 0 }
+                Handle {
+                    repr: HandleRepr::Null {
+                        offset: !offset as i32,
+                    },
+                }
             } else {
-                Handle::Valid {
-                    base_segment_id,
-                    offset,
+                let (base, bound) = bounds.unwrap_or((0, Handle::SEGMENT_END));
+                Handle {
+                    repr: HandleRepr::Valid {
+                        base_segment_id,
+                        offset,
+                        base,
+                        bound,
+                    },
                 }
             }
         }
@@ -255,17 +693,19 @@ impl Handle {
 
     #[allow(dead_code)]
     fn is_eq(self, other: Self) -> bool {
-        match (self, other) {
-            (Handle::Null { offset: o1 }, Handle::Null { offset: o2 }) => o1 == o2,
-            (Handle::Corrupted { bytes: b1 }, Handle::Corrupted { bytes: b2 }) => b1 == b2,
+        match (self.repr, other.repr) {
+            (HandleRepr::Null { offset: o1 }, HandleRepr::Null { offset: o2 }) => o1 == o2,
+            (HandleRepr::Corrupted { bytes: b1 }, HandleRepr::Corrupted { bytes: b2 }) => b1 == b2,
             (
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i1,
                     offset: o1,
+                    ..
                 },
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i2,
                     offset: o2,
+                    ..
                 },
             ) => i1 == i2 && o1 == o2,
             _ => false,
@@ -273,68 +713,139 @@ impl Handle {
     }
 
     #[allow(dead_code)]
-    fn is_lt(self, other: Self) -> Option<bool> {
-        match (self, other) {
-            (Handle::Corrupted { .. }, _) | (_, Handle::Corrupted { .. }) => None,
-            (Handle::Null { offset: o1 }, Handle::Null { offset: o2 }) => Some(o1 < o2),
-            (Handle::Null { .. }, _) => Some(true),
-            (_, Handle::Null { .. }) => Some(false),
+    fn is_lt(self, other: Self) -> Result<bool, Trap> {
+        match (self.repr, other.repr) {
+            (HandleRepr::Corrupted { .. }, _) | (_, HandleRepr::Corrupted { .. }) => {
+                Err(Trap::CorruptedHandle)
+            }
+            (HandleRepr::Null { offset: o1 }, HandleRepr::Null { offset: o2 }) => Ok(o1 < o2),
+            (HandleRepr::Null { .. }, _) => Ok(true),
+            (_, HandleRepr::Null { .. }) => Ok(false),
             (
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i1,
                     offset: o1,
+                    ..
                 },
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i2,
                     offset: o2,
+                    ..
                 },
-            ) => Some(i1 < i2 || (i1 == i2 && o1 < o2)),
+            ) => Ok(i1 < i2 || (i1 == i2 && o1 < o2)),
         }
     }
 }
 
 impl WasmModule {
     #[allow(dead_code)]
-    fn new_segment(&mut self, size: u32) -> Option<Handle> {
-        if size == 0 {
-            panic!("Trying to allocate 0 size segment. \
-                    It is easy to \"support\" this, but likely indicates something unexpected is happening, thus the panic.")
-        }
-        if self.segments.is_empty() {
-            // Use up the "0" segment, to prevent it from being used for a real segment
-            self.segments.push(Segment::Freed);
-        }
-        let id: u32 = self.segments.len().try_into().ok()?;
-        if id == u32::MAX {
-            // Filled up entire segment space, no more segments left
-            // to allocate. `u32::MAX` is reserved for the
-            // representation of `Handle::Null`.
-            return None;
-        }
-        self.segments.push(Segment::allocate(size));
-        Some(Handle::Valid {
-            base_segment_id: id,
-            offset: 0,
-        })
+    // A zero-sized segment, as `malloc(0)` asks for, is a segment like any
+    // other: it has an ID of its own and must be freed, but every access
+    // through it is out of bounds.
+    fn new_segment(&mut self, size: u32) -> Result<Handle, Trap> {
+        let id = match self.segments.allocate(size) {
+            Err(Trap::MemoryLimitExceeded)
+                if self.segments.limits.on_exceeded == OnMemoryLimit::ReturnNull =>
+            {
+                return Ok(Handle::NULL)
+            }
+            id => id?,
+        };
+        Ok(Handle::segment(id))
     }
 
     #[allow(dead_code)]
-    fn free_segment(&mut self, h: Handle) -> Option<()> {
-        match h {
-            Handle::Valid {
+    fn free_segment(&mut self, h: Handle) -> Result<(), Trap> {
+        match h.repr {
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
+                ..
             } => {
-                if offset == 0 {
-                    self.segments.get_mut(base_segment_id as usize)?.free();
-                    Some(())
+                // A sliced handle only grants access to part of the
+                // segment, so it cannot be used to free all of it
+                if offset == 0 && !h.is_sliced() {
+                    self.segments.free(base_segment_id)
                 } else {
-                    None
+                    Err(Trap::InteriorFree)
                 }
             }
-            Handle::Corrupted { .. } => None,
-            Handle::Null { .. } => None,
+            HandleRepr::Corrupted { .. } => Err(Trap::ForgedFree),
+            HandleRepr::Null { .. } => Err(Trap::NullFree),
+        }
+    }
+
+    // A memcpy that keeps handles, as a runtime intrinsic: handles copied
+    // whole to an aligned offset stay handles, bounds and all, and the
+    // ranges may overlap, as for memmove. A guest's own compiled memcpy
+    // copies bytes, so the handles it copies become data; none of the
+    // generated code calls this in its place.
+    #[allow(dead_code)]
+    fn memcpy(&mut self, dst: Handle, src: Handle, len: i32) -> Result<Handle, Trap> {
+        self.segments.copy(dst, src, len as u32 as usize)?;
+        Ok(dst)
+    }
+
+    // Limits are only checked as segments are allocated, so lowering one
+    // below what is live already does not free anything
+    #[allow(dead_code)]
+    pub fn set_memory_limits(&mut self, limits: MemoryLimits) {
+        self.segments.limits = limits;
+    }
+
+    // How many freed segment IDs are held back before they can be reused
+    #[allow(dead_code)]
+    pub fn set_segment_quarantine(&mut self, len: usize) {
+        self.segments.set_quarantine_len(len);
+    }
+
+    // Every generated function charges one unit of fuel on entry, and every
+    // loop one per iteration (see `checkpoint`), so that a metered guest
+    // always runs out eventually. Unmetered (`None`) by default.
+    #[allow(dead_code)]
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    // Tops up the fuel of a metered module
+    #[allow(dead_code)]
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // A handle other threads can stop the guest with
+    #[allow(dead_code)]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            flag: self.interrupt.clone(),
+        }
+    }
+
+    // Polled on entry to every generated function and at the top of every
+    // loop
+    #[inline]
+    fn checkpoint(&mut self) -> Result<(), Trap> {
+        // Plain load first, to keep the common case free of atomic writes.
+        // The interrupt is consumed, so the module can be called into again
+        // afterwards.
+        if self.interrupt.load(std::sync::atomic::Ordering::Relaxed)
+            && self
+                .interrupt
+                .swap(false, std::sync::atomic::Ordering::Relaxed)
+        {
+            return Err(Trap::Interrupted);
         }
+        if let Some(left) = &mut self.fuel {
+            *left = left.checked_sub(1).ok_or(Trap::OutOfFuel)?;
+        }
+        Ok(())
     }
 }
 
@@ -387,17 +898,48 @@ impl Tags {
     fn get(&self, tag_offset: usize) -> Option<Tag> {
         self.tags.get(tag_offset).cloned()
     }
+    #[must_use]
+    fn clear_range(&mut self, range: std::ops::Range<usize>) -> Option<()> {
+        self.tags.get_mut(range)?.fill(Tag::Data);
+        Some(())
+    }
+    fn any_handle_in_range(&self, range: std::ops::Range<usize>) -> Option<bool> {
+        Some(self.tags.get(range)?.iter().any(Tag::can_be_handle))
+    }
+    // Like `slice::copy_within`, or a copy from `src` if there is one, but
+    // out of bounds ranges are refused
+    #[must_use]
+    fn copy_range(
+        &mut self,
+        src: Option<&Tags>,
+        from: std::ops::Range<usize>,
+        to: usize,
+    ) -> Option<()> {
+        let to = to..to.checked_add(from.len())?;
+        match src {
+            None => {
+                self.tags.get(from.clone())?;
+                self.tags.get(to.clone())?;
+                self.tags.copy_within(from, to.start);
+            }
+            Some(src) => self.tags.get_mut(to)?.copy_from_slice(src.tags.get(from)?),
+        }
+        Some(())
+    }
 }
 
 #[cfg(feature = "packedtags")]
 struct Tags {
     packed_tags: Vec<u64>,
+    // Number of tags, which the range operations are checked against
+    len: usize,
 }
 #[cfg(feature = "packedtags")]
 impl Tags {
     fn new(tags_size: usize) -> Self {
         Self {
             packed_tags: vec![0u64; (tags_size + 63) / 64],
+            len: tags_size,
         }
     }
     #[must_use]
@@ -416,6 +958,113 @@ impl Tags {
             Some(Tag::Handle)
         }
     }
+
+    // Bits `start..end` of a word, for `start < end <= 64`
+    fn mask(start: usize, end: usize) -> u64 {
+        (!0u64 >> (64 - (end - start))) << start
+    }
+
+    fn check(&self, range: &std::ops::Range<usize>) -> Option<()> {
+        if range.start <= range.end && range.end <= self.len {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    // Calls `f` with the index and mask of each word `range` covers
+    fn for_words(range: std::ops::Range<usize>, mut f: impl FnMut(usize, u64) -> bool) {
+        let mut i = range.start;
+        while i < range.end {
+            let word = i / 64;
+            let end = (range.end - word * 64).min(64);
+            if !f(word, Tags::mask(i % 64, end)) {
+                return;
+            }
+            i = word * 64 + end;
+        }
+    }
+
+    #[must_use]
+    fn clear_range(&mut self, range: std::ops::Range<usize>) -> Option<()> {
+        self.check(&range)?;
+        let packed_tags = &mut self.packed_tags;
+        Tags::for_words(range, |word, mask| {
+            packed_tags[word] &= !mask;
+            true
+        });
+        Some(())
+    }
+
+    fn any_handle_in_range(&self, range: std::ops::Range<usize>) -> Option<bool> {
+        self.check(&range)?;
+        let mut any = false;
+        Tags::for_words(range, |word, mask| {
+            any = self.packed_tags[word] & mask != 0;
+            !any
+        });
+        Some(any)
+    }
+
+    // The `n <= 64` tags from `start` on, in the low bits
+    fn bits(&self, start: usize, n: usize) -> u64 {
+        let (word, shift) = (start / 64, start % 64);
+        let mut bits = self.packed_tags[word] >> shift;
+        if shift + n > 64 {
+            bits |= self.packed_tags[word + 1] << (64 - shift);
+        }
+        if n < 64 {
+            bits &= (1 << n) - 1;
+        }
+        bits
+    }
+
+    fn set_bits(&mut self, start: usize, n: usize, bits: u64) {
+        let (word, shift) = (start / 64, start % 64);
+        let first = n.min(64 - shift);
+        let mask = Tags::mask(shift, shift + first);
+        self.packed_tags[word] = (self.packed_tags[word] & !mask) | ((bits << shift) & mask);
+        if first < n {
+            let mask = Tags::mask(0, n - first);
+            self.packed_tags[word + 1] =
+                (self.packed_tags[word + 1] & !mask) | ((bits >> first) & mask);
+        }
+    }
+
+    // Like `slice::copy_within`, or a copy from `src` if there is one, but
+    // out of bounds ranges are refused. Moves up to 64 tags at a time, back
+    // to front if the ranges overlap that way.
+    #[must_use]
+    fn copy_range(
+        &mut self,
+        src: Option<&Tags>,
+        from: std::ops::Range<usize>,
+        to: usize,
+    ) -> Option<()> {
+        src.unwrap_or(self).check(&from)?;
+        let len = from.len();
+        self.check(&(to..to.checked_add(len)?))?;
+        let chunks = (0..len).step_by(64);
+        match src {
+            Some(src) => chunks.for_each(|offset| {
+                let n = (len - offset).min(64);
+                self.set_bits(to + offset, n, src.bits(from.start + offset, n));
+            }),
+            None => {
+                let mut copy = |offset: usize| {
+                    let n = (len - offset).min(64);
+                    let bits = self.bits(from.start + offset, n);
+                    self.set_bits(to + offset, n, bits);
+                };
+                if to <= from.start {
+                    chunks.for_each(&mut copy);
+                } else {
+                    chunks.rev().for_each(&mut copy);
+                }
+            }
+        }
+        Some(())
+    }
 }
 
 #[cfg(feature = "notags")]
@@ -433,78 +1082,373 @@ impl Tags {
     fn get(&self, _tag_offset: usize) -> Option<Tag> {
         Some(Tag {})
     }
+    fn clear_range(&mut self, _range: std::ops::Range<usize>) -> Option<()> {
+        Some(())
+    }
+    // Without tags any bytes may be a handle
+    fn any_handle_in_range(&self, range: std::ops::Range<usize>) -> Option<bool> {
+        Some(!range.is_empty())
+    }
+    fn copy_range(
+        &mut self,
+        _src: Option<&Tags>,
+        _from: std::ops::Range<usize>,
+        _to: usize,
+    ) -> Option<()> {
+        Some(())
+    }
 }
 
 #[allow(dead_code)]
 enum Segment {
     Freed,
-    Allocated { data: Vec<u8>, tags: Tags },
+    Allocated {
+        data: Vec<u8>,
+        tags: Tags,
+        // Bounds of the sliced handles stored in this segment, keyed
+        // by tag offset. They do not fit in the 8 bytes a handle takes
+        // up in memory, so they are kept on the side, much like the
+        // tags themselves.
+        bounds: BTreeMap<usize, (u32, u32)>,
+    },
 }
-type Segments = Vec<Segment>;
 
-#[allow(dead_code)]
-impl Segment {
-    fn free(&mut self) {
-        *self = Segment::Freed;
+// Caps on the segments a module may have live at once. `None` is unlimited,
+// which is the default.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryLimits {
+    // Total size of all live segments, in bytes
+    pub total_bytes: Option<u64>,
+    pub segments: Option<u32>,
+    // Size of any one segment, in bytes
+    pub segment_size: Option<u32>,
+    pub on_exceeded: OnMemoryLimit,
+}
+
+// What `new_segment` does when it would go over a limit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnMemoryLimit {
+    // Trap with `Trap::MemoryLimitExceeded`
+    Trap,
+    // Hand the guest a null handle, as `malloc` returns null when out of
+    // memory
+    ReturnNull,
+}
+
+impl Default for OnMemoryLimit {
+    fn default() -> Self {
+        OnMemoryLimit::Trap
     }
+}
 
-    fn allocate(size: u32) -> Self {
-        let size = size as usize;
-        let tag_size = size.checked_add(7).unwrap() / 8; // ceiling-divide by 8
-        Segment::Allocated {
-            data: vec![0u8; size],
-            tags: Tags::new(tag_size),
+// The segment table. A segment ID holds the index of its slot in the low
+// `INDEX_BITS` bits and the generation of the slot in the remaining ones.
+// Freed slots are put in quarantine, and only once they have aged out of it
+// are they reused, with the next generation. A stale handle to a reused slot
+// thus still traps, since its generation no longer matches.
+//
+// IDs are 32 bits, as they share the 8 bytes of a handle in memory with its
+// offset, and so the generations are only 8 bits: a slot is retired once it
+// has been handed out `MAX_GENERATION + 1` = 256 times. Over its lifetime an
+// instance can therefore allocate at most 2^24 * 2^8 = 2^32 segments, after
+// which `new_segment` traps with `Trap::SegmentsExhausted`, however few of
+// them are live.
+struct Segments {
+    slots: Vec<Slot>,
+    // Freed slots, oldest first
+    quarantine: VecDeque<u32>,
+    quarantine_len: usize,
+    // Slots that aged out of the quarantine
+    reusable: Vec<u32>,
+    limits: MemoryLimits,
+    live_bytes: u64,
+    live_count: u32,
+}
+
+struct Slot {
+    generation: u32,
+    segment: Segment,
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Slot {
+            generation: 0,
+            segment: Segment::Freed,
         }
     }
+}
 
-    fn get_data(&self) -> Option<&[u8]> {
+#[allow(dead_code)]
+impl Segments {
+    const INDEX_BITS: u32 = 24;
+    const INDEX_MASK: u32 = (1 << Segments::INDEX_BITS) - 1;
+    // The last index is never handed out, so that no segment ID collides
+    // with the representation of a null handle (`u32::MAX`)
+    const MAX_INDEX: u32 = Segments::INDEX_MASK - 1;
+    // A slot whose generation runs out is retired rather than wrapped around
+    const MAX_GENERATION: u32 = u32::MAX >> Segments::INDEX_BITS;
+    const DEFAULT_QUARANTINE_LEN: usize = 1024;
+
+    fn new() -> Self {
+        Segments {
+            // Use up the "0" segment, to prevent it from being used for a real segment
+            slots: vec![Slot::default()],
+            quarantine: VecDeque::new(),
+            quarantine_len: Segments::DEFAULT_QUARANTINE_LEN,
+            reusable: vec![],
+            limits: MemoryLimits::default(),
+            live_bytes: 0,
+            live_count: 0,
+        }
+    }
+
+    fn split_id(id: u32) -> (usize, u32) {
+        (
+            (id & Segments::INDEX_MASK) as usize,
+            id >> Segments::INDEX_BITS,
+        )
+    }
+
+    // The index of the slot `h` refers to, provided it has not been reused
+    // since. This is on the path of every memory access, hence a single
+    // lookup for both the generation and the segment.
+    fn index(&self, h: Handle) -> Result<usize, Trap> {
+        let (index, generation) = Segments::split_id(h.segment_id()?);
+        match self.slots.get(index) {
+            Some(slot) if slot.generation == generation => Ok(index),
+            Some(_) => Err(Trap::SegmentFreed),
+            None => Err(Trap::CorruptedHandle),
+        }
+    }
+
+    fn get(&self, h: Handle) -> Result<&Segment, Trap> {
+        Ok(&self.slots[self.index(h)?].segment)
+    }
+
+    fn get_mut(&mut self, h: Handle) -> Result<&mut Segment, Trap> {
+        let index = self.index(h)?;
+        Ok(&mut self.slots[index].segment)
+    }
+
+    fn within_limits(&self, size: u32) -> bool {
+        let limits = &self.limits;
+        limits.segment_size.map_or(true, |max| size <= max)
+            && limits.segments.map_or(true, |max| self.live_count < max)
+            && limits
+                .total_bytes
+                .map_or(true, |max| self.live_bytes + size as u64 <= max)
+    }
+
+    fn allocate(&mut self, size: u32) -> Result<u32, Trap> {
+        if !self.within_limits(size) {
+            return Err(Trap::MemoryLimitExceeded);
+        }
+        let index = match self.reusable.pop() {
+            Some(index) => {
+                self.slots[index as usize].generation += 1;
+                index
+            }
+            None => {
+                let index = self.slots.len() as u32;
+                if index > Segments::MAX_INDEX {
+                    // Filled up entire segment space, no more segments
+                    // left to allocate
+                    return Err(Trap::SegmentsExhausted);
+                }
+                self.slots.push(Slot::default());
+                index
+            }
+        };
+        self.slots[index as usize].segment = Segment::allocate(size);
+        self.live_bytes += size as u64;
+        self.live_count += 1;
+        Ok(index | self.slots[index as usize].generation << Segments::INDEX_BITS)
+    }
+
+    fn free(&mut self, id: u32) -> Result<(), Trap> {
+        let (index, generation) = Segments::split_id(id);
+        match self.slots.get(index) {
+            Some(_) if index == 0 => return Err(Trap::ForgedFree),
+            None => return Err(Trap::ForgedFree),
+            // Freed, and the slot reused since
+            Some(slot) if slot.generation > generation => return Err(Trap::DoubleFree),
+            Some(slot) if slot.generation < generation => return Err(Trap::ForgedFree),
+            Some(slot) => {
+                if let Segment::Freed = slot.segment {
+                    return Err(Trap::DoubleFree);
+                }
+            }
+        }
+        if let Segment::Allocated { data, .. } = &self.slots[index].segment {
+            self.live_bytes -= data.len() as u64;
+            self.live_count -= 1;
+        }
+        self.slots[index].segment.free();
+        self.quarantine.push_back(index as u32);
+        self.age_quarantine();
+        Ok(())
+    }
+
+    // `memcpy` of `len` bytes from `src` to `dst`, see `Segment::copy_from`
+    fn copy(&mut self, dst: Handle, src: Handle, len: usize) -> Result<(), Trap> {
+        let from = src.checked_offset(len)?;
+        let to = dst.checked_offset(len)?;
+        let src_index = self.index(src)?;
+        let dst_index = self.index(dst)?;
+        if src_index == dst_index {
+            return self.get_mut(dst)?.copy_from(None, from, to, len);
+        }
+        // Taken out of its slot for the copy, to borrow both segments
+        let src_segment = std::mem::replace(&mut self.slots[src_index].segment, Segment::Freed);
+        let res = self
+            .get_mut(dst)
+            .and_then(|segment| segment.copy_from(Some(&src_segment), from, to, len));
+        self.slots[src_index].segment = src_segment;
+        res
+    }
+
+    // IDs of the allocated segments
+    fn live(&self) -> impl Iterator<Item = u32> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| matches!(slot.segment, Segment::Allocated { .. }))
+            .map(|(index, slot)| index as u32 | slot.generation << Segments::INDEX_BITS)
+    }
+
+    fn set_quarantine_len(&mut self, len: usize) {
+        self.quarantine_len = len;
+        self.age_quarantine();
+    }
+
+    fn age_quarantine(&mut self) {
+        while self.quarantine.len() > self.quarantine_len {
+            let index = self.quarantine.pop_front().unwrap();
+            if self.slots[index as usize].generation < Segments::MAX_GENERATION {
+                self.reusable.push(index);
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Segment {
+    fn free(&mut self) {
+        *self = Segment::Freed;
+    }
+
+    fn allocate(size: u32) -> Self {
+        let size = size as usize;
+        let tag_size = size.checked_add(7).unwrap() / 8; // ceiling-divide by 8
+        Segment::Allocated {
+            data: vec![0u8; size],
+            tags: Tags::new(tag_size),
+            bounds: BTreeMap::new(),
+        }
+    }
+
+    fn get_data(&self) -> Result<&[u8], Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, .. } => Some(data.as_ref()),
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, .. } => Ok(data.as_ref()),
         }
     }
 
-    fn len(&self) -> Option<usize> {
+    // Restricts the data to what is reachable through `h`. The
+    // returned slice is still indexed by segment offset, so it is cut
+    // at the bound of the handle, and the offset of the handle is
+    // checked against its base. Inlined into the loads and stores of the
+    // generated code, like the other accessors below.
+    #[inline(always)]
+    fn get_data_within(&self, h: Handle) -> Result<&[u8], Trap> {
+        let data = self.get_data()?;
+        let (base, bound) = h.segment_bounds()?;
+        if h.segment_offset()? < base {
+            Err(Trap::OutOfBounds)
+        } else {
+            Ok(&data[..bound.min(data.len())])
+        }
+    }
+
+    fn len(&self) -> Result<usize, Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, .. } => Some(data.len()),
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, .. } => Ok(data.len()),
         }
     }
 
     // Performs the necessary type conversion at write time as
     // described in the MS-Wasm position paper
-    fn get_mut_data(&mut self, update_offset: usize) -> Option<&mut [u8]> {
+    #[inline(always)]
+    fn get_mut_data(&mut self, update_offset: usize) -> Result<&mut [u8], Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
-                tags.update(update_offset / 8, Tag::Data)?;
-                Some(data.as_mut())
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                tags.update(update_offset / 8, Tag::Data)
+                    .ok_or(Trap::OutOfBounds)?;
+                // Bounds of a sliced handle overwritten. Most segments hold
+                // none, and this is on the path of every store.
+                if !bounds.is_empty() {
+                    bounds.remove(&(update_offset / 8));
+                }
+                Ok(data.as_mut())
             }
         }
     }
 
-    fn get_mut_data_slice(&mut self, start: usize, end: usize) -> Option<&mut [u8]> {
+    // Same as `get_data_within`, for writes through `h`
+    #[inline(always)]
+    fn get_mut_data_within(&mut self, h: Handle) -> Result<&mut [u8], Trap> {
+        let (base, bound) = h.segment_bounds()?;
+        let offset = h.segment_offset()?;
+        if offset < base {
+            return Err(Trap::OutOfBounds);
+        }
+        let data = self.get_mut_data(offset)?;
+        let bound = bound.min(data.len());
+        Ok(&mut data[..bound])
+    }
+
+    // The bytes `start..end` are about to be written as data, so their tags
+    // are cleared
+    fn get_mut_data_slice(&mut self, start: usize, end: usize) -> Result<&mut [u8], Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
-                for i in start / 8..end / 8 {
-                    tags.update(i, Tag::Data)?;
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                let range = start / 8..(end + 7) / 8;
+                // Only handles have tags to clear, or bounds
+                if tags
+                    .any_handle_in_range(range.clone())
+                    .ok_or(Trap::OutOfBounds)?
+                {
+                    tags.clear_range(range.clone()).ok_or(Trap::OutOfBounds)?;
+                    // Bounds of the sliced handles overwritten
+                    let stale: Vec<usize> = bounds.range(range).map(|(&k, _)| k).collect();
+                    for k in stale {
+                        bounds.remove(&k);
+                    }
                 }
-                Some(data.as_mut())
+                Ok(data.as_mut())
             }
         }
     }
 
-    fn get_handle(&self, offset: usize) -> Option<Handle> {
+    #[inline(always)]
+    fn get_handle(&self, offset: usize) -> Result<Handle, Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
                 if offset % 8 != 0 {
-                    None
+                    Err(Trap::MisalignedHandleAccess)
                 } else {
-                    Some(Handle::from_bytes(
-                        data.get(offset..offset + 8)?.try_into().ok()?,
-                        tags.get(offset / 8)?,
+                    Ok(Handle::from_bytes(
+                        data.get(offset..offset + 8)
+                            .ok_or(Trap::OutOfBounds)?
+                            .try_into()
+                            .unwrap(),
+                        tags.get(offset / 8).ok_or(Trap::OutOfBounds)?,
+                        bounds.get(&(offset / 8)).cloned(),
                     ))
                 }
             }
@@ -513,71 +1457,218 @@ impl Segment {
 
     // Performs the necessary type conversion at write time as
     // described in the MS-Wasm position paper
-    fn store_handle(&mut self, offset: usize, handle: Handle) -> Option<()> {
+    fn store_handle(&mut self, offset: usize, handle: Handle) -> Result<(), Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                if offset % 8 != 0 {
+                    return Err(Trap::MisalignedHandleAccess);
+                }
                 let (bytes, tag) = handle.to_bytes();
-                data.get_mut(offset..offset + 8)?.copy_from_slice(&bytes);
-                tags.update(offset / 8, tag)?;
-                Some(())
+                data.get_mut(offset..offset + 8)
+                    .ok_or(Trap::OutOfBounds)?
+                    .copy_from_slice(&bytes);
+                tags.update(offset / 8, tag).ok_or(Trap::OutOfBounds)?;
+                match handle.repr {
+                    HandleRepr::Valid { base, bound, .. } if handle.is_sliced() => {
+                        bounds.insert(offset / 8, (base, bound));
+                    }
+                    _ => {
+                        bounds.remove(&(offset / 8));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Copies `len` bytes from `from` in `src`, or in this segment if it is
+    // `None`, to `to` in this segment. Handles copied whole to an offset as
+    // aligned stay handles, bounds and all; everything else written becomes
+    // data, as it would through a copy byte by byte.
+    fn copy_from(
+        &mut self,
+        src: Option<&Segment>,
+        from: usize,
+        to: usize,
+        len: usize,
+    ) -> Result<(), Trap> {
+        // The granules copied whole, if any
+        let whole = if from % 8 == 0 && to % 8 == 0 {
+            from / 8..(from + len) / 8
+        } else {
+            from / 8..from / 8
+        };
+        let (handles, moved) = match src.unwrap_or(self) {
+            Segment::Freed => return Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                data.get(from..from + len).ok_or(Trap::OutOfBounds)?;
+                let handles = !whole.is_empty()
+                    && tags
+                        .any_handle_in_range(whole.clone())
+                        .ok_or(Trap::OutOfBounds)?;
+                let moved: Vec<(usize, (u32, u32))> = bounds
+                    .range(whole.clone())
+                    .map(|(&k, &v)| (k - whole.start + to / 8, v))
+                    .collect();
+                (handles, moved)
             }
+        };
+        let (data, tags, bounds) = match self {
+            Segment::Freed => return Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => (data, tags, bounds),
+        };
+        data.get(to..to + len).ok_or(Trap::OutOfBounds)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let written = to / 8..(to + len + 7) / 8;
+        let mut cleared = written.clone();
+        if handles {
+            let src_tags = src.map(Segment::tags).transpose()?;
+            tags.copy_range(src_tags, whole.clone(), to / 8)
+                .ok_or(Trap::OutOfBounds)?;
+            cleared.start += whole.len();
+        }
+        tags.clear_range(cleared).ok_or(Trap::OutOfBounds)?;
+        let stale: Vec<usize> = bounds.range(written).map(|(&k, _)| k).collect();
+        for k in stale {
+            bounds.remove(&k);
+        }
+        bounds.extend(moved);
+        match src {
+            None => data.copy_within(from..from + len, to),
+            Some(src) => data[to..to + len].copy_from_slice(&src.get_data()?[from..from + len]),
+        }
+        Ok(())
+    }
+
+    fn tags(&self) -> Result<&Tags, Trap> {
+        match self {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { tags, .. } => Ok(tags),
         }
     }
 }
 
-fn with_collected_memory_0<T, U: Into<Option<T>>>(
+fn with_collected_memory_0<T>(
     _segments: &mut Segments,
-    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper) -> U,
-) -> Option<T> {
-    f(&guest_mem_wrapper::GuestMemWrapper::from(&mut [])).into()
+    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper) -> T,
+) -> Result<T, Trap> {
+    Ok(f(&guest_mem_wrapper::GuestMemWrapper::from(&mut [])))
 }
 
-fn with_collected_memory_1<T, U: Into<Option<T>>>(
+// Exposes the segment `h0` points into as the callee's memory. The callee
+// may write the `written` bytes at `h0`, which are bounds checked and
+// turned into data up front.
+fn with_collected_memory_1<T>(
     segments: &mut Segments,
     h0: Handle,
-    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper, i32) -> U,
-) -> Option<T> {
-    let seg = segments.get_mut(h0.segment_index()?)?;
+    written: usize,
+    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper, i32) -> T,
+) -> Result<T, Trap> {
+    let (base, bound) = h0.segment_bounds()?;
+    let offset = h0.segment_offset()?;
+    if offset < base {
+        return Err(Trap::OutOfBounds);
+    }
+    if written > 0 {
+        h0.checked_offset(written)?;
+    }
+    let seg = segments.get_mut(h0)?;
+    let data = seg.get_mut_data_slice(offset, offset + written)?;
+    if offset + written > data.len() {
+        return Err(Trap::OutOfBounds);
+    }
+    let bound = bound.min(data.len());
     let res = f(
-        &guest_mem_wrapper::GuestMemWrapper::from(seg.get_mut_data_slice(0, 0)?),
-        h0.segment_offset()?.try_into().ok()?,
-    )
-    .into()?;
-    Some(res)
+        &guest_mem_wrapper::GuestMemWrapper::from(&mut data[..bound]),
+        offset.try_into().or(Err(Trap::OutOfBounds))?,
+    );
+    Ok(res)
 }
 
+// Loads and stores. Generated function bodies use the forms ending in
+// `at <code offset>`, which take the handle as the result of adding the
+// memarg offset to it and turn any trap into a `Fault` at that offset.
 macro_rules! write {
+    ($kind:ident, $segments:expr, $handle:expr, $val:expr, at $code_offset:expr) => {{
+        let handle = $handle.at($code_offset)?;
+        write!(@$kind, $segments, handle, $val, |trap| Fault::new(trap, $code_offset))
+    }};
     (store_handle, $segments:expr, $handle:expr, $val:expr) => {
-        $segments
-            .get_mut($handle.segment_index()?)?
-            .store_handle($handle.segment_offset()?, $val)?;
+        write!(@store_handle, $segments, $handle, $val, std::convert::identity)
     };
+    (bytes, $segments:expr, $handle:expr, $len:expr) => {{
+        let offset = $handle.checked_offset($len)?;
+        $segments
+            .get_mut($handle)?
+            .get_mut_data_slice(offset, offset + $len)?
+            .get_mut(offset..offset + $len)
+            .ok_or(Trap::OutOfBounds)?
+    }};
     ($writefn:ident, $segments:expr, $handle:expr, $val:expr) => {
+        write!(@$writefn, $segments, $handle, $val, std::convert::identity)
+    };
+    (@store_handle, $segments:expr, $handle:expr, $val:expr, $err:expr) => {
+        $segments
+            .get_mut($handle)
+            .map_err($err)?
+            .store_handle($handle.checked_offset(8).map_err($err)?, $val)
+            .map_err($err)?
+    };
+    (@$writefn:ident, $segments:expr, $handle:expr, $val:expr, $err:expr) => {
         $writefn(
             $segments
-                .get_mut($handle.segment_index()?)?
-                .get_mut_data($handle.segment_offset()?)?,
-            ($handle.segment_offset()?) as usize,
+                .get_mut($handle)
+                .map_err($err)?
+                .get_mut_data_within($handle)
+                .map_err($err)?,
+            $handle.segment_offset().map_err($err)?,
             $val,
-        )?;
+        )
+        .ok_or(Trap::OutOfBounds)
+        .map_err($err)?
     };
 }
 
 macro_rules! read {
+    ($kind:ident, $segments:expr, $handle:expr, at $code_offset:expr) => {{
+        let handle = $handle.at($code_offset)?;
+        read!(@$kind, $segments, handle, |trap| Fault::new(trap, $code_offset))
+    }};
     (get_handle, $segments:expr, $handle:expr) => {
+        read!(@get_handle, $segments, $handle, std::convert::identity)
+    };
+    (bytes, $segments:expr, $handle:expr, $len:expr) => {{
+        let offset = $handle.checked_offset($len)?;
         $segments
-            .get($handle.segment_index()?)?
-            .get_handle($handle.segment_offset()?)?
+            .get($handle)?
+            .get_data()?
+            .get(offset..offset + $len)
+            .ok_or(Trap::OutOfBounds)?
+    }};
+    ($readfn:ident, $segments:expr, $handle:expr) => {
+        read!(@$readfn, $segments, $handle, std::convert::identity)
     };
-    (bytes, $segments:expr, $handle:expr, $len:expr) => {
-        &$segments.get($handle.segment_index()?)?.get_data()?[$handle.segment_offset()?..][..$len]
+    (@get_handle, $segments:expr, $handle:expr, $err:expr) => {
+        $segments
+            .get($handle)
+            .map_err($err)?
+            .get_handle($handle.checked_offset(8).map_err($err)?)
+            .map_err($err)?
     };
-    ($readfn:ident, $segments:expr, $handle:expr) => {
+    (@$readfn:ident, $segments:expr, $handle:expr, $err:expr) => {
         $readfn(
-            $segments.get($handle.segment_index()?)?.get_data()?,
-            ($handle.segment_offset()?) as usize,
-        )?
+            $segments
+                .get($handle)
+                .map_err($err)?
+                .get_data_within($handle)
+                .map_err($err)?,
+            $handle.segment_offset().map_err($err)?,
+        )
+        .ok_or(Trap::OutOfBounds)
+        .map_err($err)?
     };
 }
 
@@ -586,6 +1677,234 @@ mod ms_wasm_wasi {
     use super::*;
     use wasi_common::wasi::wasi_snapshot_preview1;
 
+    // An iovec or ciovec in guest memory: the buffer handle, then its 32-bit
+    // length, padded to 16 bytes
+    const IOVEC_SIZE: i32 = 8 + 8;
+
+    // What wasi-common returns when wiggle cannot borrow guest memory
+    pub(super) const ERRNO_FAULT: i32 = 21;
+
+    // Whether any two non-empty buffers of the guest's iovec array overlap.
+    // wiggle borrows all the buffers of an `fd_read` at once, so it refuses
+    // them; copying them in to `Scratch` would hide that.
+    fn iovecs_overlap(segments: &Segments, iovs: Handle, iovs_len: i32) -> Result<bool, Trap> {
+        let mut bufs = vec![];
+        for i in 0..iovs_len {
+            let loc = read!(get_handle, segments, iovs.add(i * IOVEC_SIZE + 0)?);
+            let len = read!(read_mem_u32, segments, iovs.add(i * IOVEC_SIZE + 8)?) as usize;
+            if len > 0 {
+                bufs.push((loc.segment_id()?, loc.segment_offset()?, len));
+            }
+        }
+        bufs.sort();
+        Ok(bufs
+            .windows(2)
+            .any(|w| w[0].0 == w[1].0 && w[0].1 + w[0].2 > w[1].1))
+    }
+
+    // Stand-in linear memory for calls whose pointers may lead into several
+    // segments. Guest buffers are copied in, and whatever the callee wrote is
+    // copied back out with `copy_out`.
+    struct Scratch {
+        memory: Vec<u8>,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            Scratch { memory: vec![] }
+        }
+
+        // Reserves `len` zeroed bytes, 8-aligned so that any type wiggle
+        // reads or writes there is aligned
+        fn alloc(&mut self, len: usize) -> i32 {
+            let addr = (self.memory.len() + 7) & !7;
+            self.memory.resize(addr + len, 0);
+            addr as i32
+        }
+
+        // Copies `len` bytes at `h` in. Also used for output buffers, so
+        // that they are checked before the callee has any side effects.
+        fn copy_in(&mut self, segments: &Segments, h: Handle, len: usize) -> Result<i32, Trap> {
+            if len == 0 {
+                return Ok(self.alloc(0));
+            }
+            let bytes = read!(bytes, segments, h, len);
+            let addr = self.alloc(len);
+            self.memory[addr as usize..][..len].copy_from_slice(bytes);
+            Ok(addr)
+        }
+
+        // Copies the `len` bytes at `addr` back out to `h`, as data
+        fn copy_out(
+            &self,
+            segments: &mut Segments,
+            addr: i32,
+            h: Handle,
+            len: usize,
+        ) -> Result<(), Trap> {
+            if len == 0 {
+                return Ok(());
+            }
+            write!(bytes, segments, h, len).copy_from_slice(&self.memory[addr as usize..][..len]);
+            Ok(())
+        }
+
+        // Copies in the buffers of the guest's iovec array at `iovs` and lays
+        // out a wasm32 iovec array over the copies. Returns its address and
+        // the guest buffers along with the address of each copy.
+        fn copy_in_iovecs(
+            &mut self,
+            segments: &Segments,
+            iovs: Handle,
+            iovs_len: i32,
+        ) -> Result<(i32, Vec<(Handle, usize, i32)>), Trap> {
+            let mut bufs = vec![];
+            for i in 0..iovs_len {
+                let loc = read!(get_handle, segments, iovs.add(i * IOVEC_SIZE + 0)?);
+                let len = read!(read_mem_u32, segments, iovs.add(i * IOVEC_SIZE + 8)?) as usize;
+                bufs.push((loc, len, self.copy_in(segments, loc, len)?));
+            }
+            let array = self.alloc(8 * bufs.len());
+            for (i, &(_, len, addr)) in bufs.iter().enumerate() {
+                let entry = array as usize + 8 * i;
+                write_mem_i32(&mut self.memory, entry + 0, addr).ok_or(Trap::OutOfBounds)?;
+                write_mem_u32(&mut self.memory, entry + 4, len as u32).ok_or(Trap::OutOfBounds)?;
+            }
+            Ok((array, bufs))
+        }
+
+        // Copies the first `n` bytes the callee read into the iovec buffers
+        // back out to the guest
+        fn copy_out_iovecs(
+            &self,
+            segments: &mut Segments,
+            bufs: &[(Handle, usize, i32)],
+            mut n: usize,
+        ) -> Result<(), Trap> {
+            for &(loc, len, addr) in bufs {
+                let len = len.min(n);
+                self.copy_out(segments, addr, loc, len)?;
+                n -= len;
+            }
+            Ok(())
+        }
+
+        fn read_u32(&self, addr: i32) -> Result<u32, Trap> {
+            read_mem_u32(&self.memory, addr as usize).ok_or(Trap::OutOfBounds)
+        }
+
+        fn guest_memory(&mut self) -> guest_mem_wrapper::GuestMemWrapper<'_> {
+            guest_mem_wrapper::GuestMemWrapper::from(&mut self.memory)
+        }
+    }
+
+    // The buffers of a guest iovec array, when they all lie in one segment
+    // without overlapping: the segment, and the offset and length of each
+    // buffer in it. wiggle is then handed the segment's data itself instead
+    // of copies of the buffers. Calls with buffers spread over several
+    // segments, or filling theirs, go through `Scratch`.
+    struct SegmentIovecs {
+        segment: Handle,
+        bufs: Vec<(usize, usize)>,
+        // Offset of a gap around the buffers with room for a wasm32 iovec
+        // array over them and the result of the call
+        tail: usize,
+    }
+
+    impl SegmentIovecs {
+        fn find(segments: &Segments, iovs: Handle, iovs_len: i32) -> Result<Option<Self>, Trap> {
+            let mut segment: Option<(Handle, usize)> = None;
+            let mut bufs = vec![];
+            for i in 0..iovs_len {
+                let loc = read!(get_handle, segments, iovs.add(i * IOVEC_SIZE + 0)?);
+                let len = read!(read_mem_u32, segments, iovs.add(i * IOVEC_SIZE + 8)?) as usize;
+                if len == 0 {
+                    bufs.push((0, 0));
+                    continue;
+                }
+                // Traps wherever copying the buffer in would
+                read!(bytes, segments, loc, len);
+                match segment {
+                    None => segment = Some((loc, segments.get(loc)?.get_data()?.len())),
+                    Some((h, _)) if h.segment_id()? == loc.segment_id()? => {}
+                    Some(_) => return Ok(None),
+                }
+                bufs.push((loc.segment_offset()?, len));
+            }
+            let (segment, data_len) = match segment {
+                Some(segment) => segment,
+                None => return Ok(None),
+            };
+            let mut sorted: Vec<_> = bufs.iter().copied().filter(|&(_, len)| len > 0).collect();
+            sorted.sort();
+            if sorted.windows(2).any(|w| w[0].0 + w[0].1 > w[1].0) {
+                // wiggle would refuse to borrow them all at once
+                return Ok(None);
+            }
+            let tail_len = 8 * bufs.len() + 4;
+            let mut gap_start = 0;
+            for (offset, len) in sorted.into_iter().chain(Some((data_len, 0))) {
+                let tail = (gap_start + 3) & !3;
+                if tail + tail_len <= offset {
+                    return Ok(Some(SegmentIovecs {
+                        segment,
+                        bufs,
+                        tail,
+                    }));
+                }
+                gap_start = offset + len;
+            }
+            Ok(None)
+        }
+
+        // Calls `f` with the segment as guest memory, the address of a wasm32
+        // iovec array over the buffers and that of a `u32` for the result of
+        // the call. Both are laid out at `tail` for the duration of the call,
+        // with the guest's bytes there set aside and put back afterwards, so
+        // that the segment never grows. Returns what `f` returned and the
+        // result.
+        fn call(
+            &self,
+            segments: &mut Segments,
+            f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper, i32, i32) -> i32,
+        ) -> Result<(i32, u32), Trap> {
+            let data = match segments.get_mut(self.segment)? {
+                Segment::Allocated { data, .. } => data,
+                Segment::Freed => return Err(Trap::SegmentFreed),
+            };
+            let array = self.tail;
+            let result = array + 8 * self.bufs.len();
+            let tail = array..result + 4;
+            let saved = data.get(tail.clone()).ok_or(Trap::OutOfBounds)?.to_vec();
+            for (i, &(offset, len)) in self.bufs.iter().enumerate() {
+                write_mem_u32(data, array + 8 * i + 0, offset as u32).ok_or(Trap::OutOfBounds)?;
+                write_mem_u32(data, array + 8 * i + 4, len as u32).ok_or(Trap::OutOfBounds)?;
+            }
+            write_mem_u32(data, result, 0).ok_or(Trap::OutOfBounds)?;
+            let res = f(
+                &guest_mem_wrapper::GuestMemWrapper::from(&mut data[..]),
+                array as i32,
+                result as i32,
+            );
+            let n = read_mem_u32(data, result).ok_or(Trap::OutOfBounds)?;
+            data[tail].copy_from_slice(&saved);
+            Ok((res, n))
+        }
+
+        // The callee read `n` bytes into the buffers, which are data now
+        fn clear_tags(&self, segments: &mut Segments, mut n: usize) -> Result<(), Trap> {
+            let segment = segments.get_mut(self.segment)?;
+            for &(offset, len) in &self.bufs {
+                let len = len.min(n);
+                if len > 0 {
+                    segment.get_mut_data_slice(offset, offset + len)?;
+                }
+                n -= len;
+            }
+            Ok(())
+        }
+    }
+
     #[allow(dead_code)]
     // args_get(argv: Pointer<Pointer<u8>>, argv_buf: Pointer<u8>) -> Result<(), errno>
     pub(super) fn args_get(
@@ -593,7 +1912,7 @@ mod ms_wasm_wasi {
         segments: &mut Segments,
         arg0: Handle,
         arg1: Handle,
-    ) -> Option<i32> {
+    ) -> Result<i32, Trap> {
         let (argv_count, argv_buf_len) = {
             let mut memory = [0u8; 4 + 4];
             wasi_snapshot_preview1::args_sizes_get(
@@ -602,7 +1921,10 @@ mod ms_wasm_wasi {
                 0,
                 4,
             );
-            (read_mem_u32(&memory, 0)?, read_mem_u32(&memory, 4)?)
+            (
+                read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?,
+                read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?,
+            )
         };
 
         let argv_buf_start = (argv_count + 1) * 4;
@@ -619,19 +1941,19 @@ mod ms_wasm_wasi {
                 store_handle,
                 segments,
                 arg0.add(i as i32 * 8)?,
-                arg1.add(read_mem_i32(&memory, i as usize * 4)? - argv_buf_start as i32)?
+                arg1.add(
+                    read_mem_i32(&memory, i as usize * 4).ok_or(Trap::OutOfBounds)?
+                        - argv_buf_start as i32
+                )?
             );
         }
-        for i in 0..argv_buf_len {
-            write!(
-                write_mem_u8,
-                segments,
-                arg1.add(i as i32)?,
-                read_mem_u8(&memory, (argv_buf_start + i) as usize)?
-            );
+        if argv_buf_len > 0 {
+            let len = argv_buf_len as usize;
+            write!(bytes, segments, arg1, len)
+                .copy_from_slice(&memory[argv_buf_start as usize..][..len]);
         }
 
-        Some(res)
+        Ok(res)
     }
 
     #[allow(dead_code)]
@@ -641,7 +1963,7 @@ mod ms_wasm_wasi {
         segments: &mut Segments,
         arg0: Handle,
         arg1: Handle,
-    ) -> Option<i32> {
+    ) -> Result<i32, Trap> {
         let mut memory = [0u8; 4 + 4];
         let res = wasi_snapshot_preview1::args_sizes_get(
             ctx,
@@ -650,125 +1972,997 @@ mod ms_wasm_wasi {
             4,
         );
 
-        let arg0_res = read_mem_u32(&memory, 0)?;
-        let arg1_res = read_mem_u32(&memory, 4)?;
+        let arg0_res = read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?;
+        let arg1_res = read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?;
+
+        write!(write_mem_u32, segments, arg0, arg0_res);
+        write!(write_mem_u32, segments, arg1, arg1_res);
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // clock_res_get(id: clockid) -> Result<timestamp, errno>
+    pub(super) fn clock_res_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 8, |mem, arg1| {
+            wasi_snapshot_preview1::clock_res_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // clock_time_get(id: clockid, precision: timestamp) -> Result<timestamp, errno>
+    pub(super) fn clock_time_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg2, 8, |mem, arg2| {
+            wasi_snapshot_preview1::clock_time_get(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // environ_get(environ: Pointer<Pointer<u8>>, environ_buf: Pointer<u8>) -> Result<(), errno>
+    pub(super) fn environ_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: Handle,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        let (environ_count, environ_buf_len) = {
+            let mut memory = [0u8; 4 + 4];
+            wasi_snapshot_preview1::environ_sizes_get(
+                ctx,
+                &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+                0,
+                4,
+            );
+            (
+                read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?,
+                read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?,
+            )
+        };
+
+        let environ_buf_start = (environ_count + 1) * 4;
+        let mut memory: Vec<u8> = vec![0u8; (environ_buf_start + (environ_buf_len + 1)) as usize];
+        let res = wasi_snapshot_preview1::environ_get(
+            ctx,
+            &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+            0,
+            environ_buf_start as i32,
+        );
+
+        for i in 0..environ_count {
+            write!(
+                store_handle,
+                segments,
+                arg0.add(i as i32 * 8)?,
+                arg1.add(
+                    read_mem_i32(&memory, i as usize * 4).ok_or(Trap::OutOfBounds)?
+                        - environ_buf_start as i32
+                )?
+            );
+        }
+        if environ_buf_len > 0 {
+            let len = environ_buf_len as usize;
+            write!(bytes, segments, arg1, len)
+                .copy_from_slice(&memory[environ_buf_start as usize..][..len]);
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // environ_sizes_get() -> Result<(size, size), errno>
+    pub(super) fn environ_sizes_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: Handle,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        let mut memory = [0u8; 4 + 4];
+        let res = wasi_snapshot_preview1::environ_sizes_get(
+            ctx,
+            &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+            0,
+            4,
+        );
+
+        let arg0_res = read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?;
+        let arg1_res = read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?;
 
         write!(write_mem_u32, segments, arg0, arg0_res);
         write!(write_mem_u32, segments, arg1, arg1_res);
 
-        Some(res)
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_advise(fd: fd, offset: filesize, len: filesize, advice: advice) -> Result<(), errno>
+    pub(super) fn fd_advise(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i64,
+        arg3: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_advise(ctx, mem, arg0, arg1, arg2, arg3)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_allocate(fd: fd, offset: filesize, len: filesize) -> Result<(), errno>
+    pub(super) fn fd_allocate(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i64,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_allocate(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_close(fd: fd) -> Result<(), errno>
+    pub(super) fn fd_close(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_close(ctx, mem, arg0)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_datasync(fd: fd) -> Result<(), errno>
+    pub(super) fn fd_datasync(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_datasync(ctx, mem, arg0)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_fdstat_get(fd: fd) -> Result<fdstat, errno>
+    pub(super) fn fd_fdstat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 24, |mem, arg1| {
+            wasi_snapshot_preview1::fd_fdstat_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_fdstat_set_flags(fd: fd, flags: fdflags) -> Result<(), errno>
+    pub(super) fn fd_fdstat_set_flags(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_fdstat_set_flags(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_fdstat_set_rights(fd: fd, fs_rights_base: rights, fs_rights_inheriting: rights) -> Result<(), errno>
+    pub(super) fn fd_fdstat_set_rights(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i64,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_fdstat_set_rights(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_filestat_get(fd: fd) -> Result<filestat, errno>
+    pub(super) fn fd_filestat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 64, |mem, arg1| {
+            wasi_snapshot_preview1::fd_filestat_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_filestat_set_size(fd: fd, size: filesize) -> Result<(), errno>
+    pub(super) fn fd_filestat_set_size(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_filestat_set_size(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_filestat_set_times(fd: fd, atim: timestamp, mtim: timestamp, fst_flags: fstflags) -> Result<(), errno>
+    pub(super) fn fd_filestat_set_times(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i64,
+        arg3: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_filestat_set_times(ctx, mem, arg0, arg1, arg2, arg3)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_pread(fd: fd, iovs: iovec_array, offset: filesize) -> Result<size, errno>
+    pub(super) fn fd_pread(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        offset: i64,
+        nread: Handle,
+    ) -> Result<i32, Trap> {
+        if iovecs_overlap(segments, iovs_ptr, iovs_len)? {
+            return Ok(ERRNO_FAULT);
+        }
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nread);
+            let (res, n) = iovs.call(segments, |mem, iovs, nread| {
+                wasi_snapshot_preview1::fd_pread(ctx, mem, fd, iovs, iovs_len, offset, nread)
+            })?;
+            if res == 0 {
+                iovs.clear_tags(segments, n as usize)?;
+                write!(write_mem_u32, segments, nread, n);
+            }
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, bufs) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nread_addr = scratch.copy_in(segments, nread, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_pread(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            offset,
+            nread_addr,
+        );
+
+        if res == 0 {
+            let nread_res = scratch.read_u32(nread_addr)?;
+            scratch.copy_out_iovecs(segments, &bufs, nread_res as usize)?;
+            scratch.copy_out(segments, nread_addr, nread, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_prestat_dir_name(fd: fd, path: Pointer<u8>, path_len: size) -> Result<(), errno>
+    pub(super) fn fd_prestat_dir_name(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, arg2 as u32 as usize, |mem, arg1| {
+            wasi_snapshot_preview1::fd_prestat_dir_name(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_prestat_get(fd: fd) -> Result<prestat, errno>
+    pub(super) fn fd_prestat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 8, |mem, arg1| {
+            wasi_snapshot_preview1::fd_prestat_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_pwrite(fd: fd, iovs: ciovec_array, offset: filesize) -> Result<size, errno>
+    pub(super) fn fd_pwrite(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        offset: i64,
+        nwritten: Handle,
+    ) -> Result<i32, Trap> {
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nwritten);
+            let (res, n) = iovs.call(segments, |mem, iovs, nwritten| {
+                wasi_snapshot_preview1::fd_pwrite(ctx, mem, fd, iovs, iovs_len, offset, nwritten)
+            })?;
+            if res == 0 {
+                write!(write_mem_u32, segments, nwritten, n);
+            }
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, _) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nwritten_addr = scratch.copy_in(segments, nwritten, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_pwrite(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            offset,
+            nwritten_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, nwritten_addr, nwritten, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_read(fd: fd, iovs: iovec_array) -> Result<size, errno>
+    pub(super) fn fd_read(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        nread: Handle,
+    ) -> Result<i32, Trap> {
+        if iovecs_overlap(segments, iovs_ptr, iovs_len)? {
+            return Ok(ERRNO_FAULT);
+        }
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nread);
+            let (res, n) = iovs.call(segments, |mem, iovs, nread| {
+                wasi_snapshot_preview1::fd_read(ctx, mem, fd, iovs, iovs_len, nread)
+            })?;
+            if res == 0 {
+                iovs.clear_tags(segments, n as usize)?;
+                write!(write_mem_u32, segments, nread, n);
+            }
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, bufs) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nread_addr = scratch.copy_in(segments, nread, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_read(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            nread_addr,
+        );
+
+        if res == 0 {
+            let nread_res = scratch.read_u32(nread_addr)?;
+            scratch.copy_out_iovecs(segments, &bufs, nread_res as usize)?;
+            scratch.copy_out(segments, nread_addr, nread, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_readdir(fd: fd, buf: Pointer<u8>, buf_len: size, cookie: dircookie) -> Result<size, errno>
+    pub(super) fn fd_readdir(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        buf: Handle,
+        buf_len: i32,
+        cookie: i64,
+        bufused: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let buf_addr = scratch.copy_in(segments, buf, buf_len as u32 as usize)?;
+        let bufused_addr = scratch.copy_in(segments, bufused, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_readdir(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            buf_addr,
+            buf_len,
+            cookie,
+            bufused_addr,
+        );
+
+        if res == 0 {
+            let bufused_res = scratch.read_u32(bufused_addr)?;
+            scratch.copy_out(segments, buf_addr, buf, bufused_res as usize)?;
+            scratch.copy_out(segments, bufused_addr, bufused, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_renumber(fd: fd, to: fd) -> Result<(), errno>
+    pub(super) fn fd_renumber(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_renumber(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_seek(fd: fd, offset: filedelta, whence: whence) -> Result<filesize, errno>
+    pub(super) fn fd_seek(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i32,
+        arg3: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg3, 8, |mem, arg3| {
+            wasi_snapshot_preview1::fd_seek(ctx, mem, arg0, arg1, arg2, arg3)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_sync(fd: fd) -> Result<(), errno>
+    pub(super) fn fd_sync(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_sync(ctx, mem, arg0)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_tell(fd: fd) -> Result<filesize, errno>
+    pub(super) fn fd_tell(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 8, |mem, arg1| {
+            wasi_snapshot_preview1::fd_tell(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_write(fd: fd, iovs: ciovec_array) -> Result<size, errno>
+    pub(super) fn fd_write(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        nwritten: Handle,
+    ) -> Result<i32, Trap> {
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nwritten);
+            let (res, n) = iovs.call(segments, |mem, iovs, nwritten| {
+                wasi_snapshot_preview1::fd_write(ctx, mem, fd, iovs, iovs_len, nwritten)
+            })?;
+            if res == 0 {
+                write!(write_mem_u32, segments, nwritten, n);
+            }
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, _) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nwritten_addr = scratch.copy_in(segments, nwritten, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_write(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            nwritten_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, nwritten_addr, nwritten, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // path_create_directory(fd: fd, path: string) -> Result<(), errno>
+    pub(super) fn path_create_directory(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 0, |mem, arg1| {
+            wasi_snapshot_preview1::path_create_directory(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // path_filestat_get(fd: fd, flags: lookupflags, path: string) -> Result<filestat, errno>
+    pub(super) fn path_filestat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        flags: i32,
+        path: Handle,
+        path_len: i32,
+        buf: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let path_addr = scratch.copy_in(segments, path, path_len as u32 as usize)?;
+        let buf_addr = scratch.copy_in(segments, buf, 64)?;
+
+        let res = wasi_snapshot_preview1::path_filestat_get(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            flags,
+            path_addr,
+            path_len,
+            buf_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, buf_addr, buf, 64)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_filestat_set_times(fd: fd, flags: lookupflags, path: string, atim: timestamp, mtim: timestamp, fst_flags: fstflags) -> Result<(), errno>
+    pub(super) fn path_filestat_set_times(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+        arg2: Handle,
+        arg3: i32,
+        arg4: i64,
+        arg5: i64,
+        arg6: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg2, 0, |mem, arg2| {
+            wasi_snapshot_preview1::path_filestat_set_times(
+                ctx, mem, arg0, arg1, arg2, arg3, arg4, arg5, arg6,
+            )
+        })
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_link(old_fd: fd, old_flags: lookupflags, old_path: string, new_fd: fd, new_path: string) -> Result<(), errno>
+    pub(super) fn path_link(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        old_fd: i32,
+        old_flags: i32,
+        old_path: Handle,
+        old_path_len: i32,
+        new_fd: i32,
+        new_path: Handle,
+        new_path_len: i32,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let old_path_addr = scratch.copy_in(segments, old_path, old_path_len as u32 as usize)?;
+        let new_path_addr = scratch.copy_in(segments, new_path, new_path_len as u32 as usize)?;
+
+        Ok(wasi_snapshot_preview1::path_link(
+            ctx,
+            &scratch.guest_memory(),
+            old_fd,
+            old_flags,
+            old_path_addr,
+            old_path_len,
+            new_fd,
+            new_path_addr,
+            new_path_len,
+        ))
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_open(fd: fd, dirflags: lookupflags, path: string, oflags: oflags, fs_rights_base: rights, fs_rights_inheriting: rights, fdflags: fdflags) -> Result<fd, errno>
+    pub(super) fn path_open(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        dirflags: i32,
+        path: Handle,
+        path_len: i32,
+        oflags: i32,
+        fs_rights_base: i64,
+        fs_rights_inheriting: i64,
+        fdflags: i32,
+        opened_fd: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let path_addr = scratch.copy_in(segments, path, path_len as u32 as usize)?;
+        let opened_fd_addr = scratch.copy_in(segments, opened_fd, 4)?;
+
+        let res = wasi_snapshot_preview1::path_open(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            dirflags,
+            path_addr,
+            path_len,
+            oflags,
+            fs_rights_base,
+            fs_rights_inheriting,
+            fdflags,
+            opened_fd_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, opened_fd_addr, opened_fd, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_readlink(fd: fd, path: string, buf: Pointer<u8>, buf_len: size) -> Result<size, errno>
+    pub(super) fn path_readlink(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        path: Handle,
+        path_len: i32,
+        buf: Handle,
+        buf_len: i32,
+        bufused: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let path_addr = scratch.copy_in(segments, path, path_len as u32 as usize)?;
+        let buf_addr = scratch.copy_in(segments, buf, buf_len as u32 as usize)?;
+        let bufused_addr = scratch.copy_in(segments, bufused, 4)?;
+
+        let res = wasi_snapshot_preview1::path_readlink(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            path_addr,
+            path_len,
+            buf_addr,
+            buf_len,
+            bufused_addr,
+        );
+
+        if res == 0 {
+            let bufused_res = scratch.read_u32(bufused_addr)?;
+            scratch.copy_out(segments, buf_addr, buf, bufused_res as usize)?;
+            scratch.copy_out(segments, bufused_addr, bufused, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // path_remove_directory(fd: fd, path: string) -> Result<(), errno>
+    pub(super) fn path_remove_directory(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 0, |mem, arg1| {
+            wasi_snapshot_preview1::path_remove_directory(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_rename(fd: fd, old_path: string, new_fd: fd, new_path: string) -> Result<(), errno>
+    pub(super) fn path_rename(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        old_path: Handle,
+        old_path_len: i32,
+        new_fd: i32,
+        new_path: Handle,
+        new_path_len: i32,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let old_path_addr = scratch.copy_in(segments, old_path, old_path_len as u32 as usize)?;
+        let new_path_addr = scratch.copy_in(segments, new_path, new_path_len as u32 as usize)?;
+
+        Ok(wasi_snapshot_preview1::path_rename(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            old_path_addr,
+            old_path_len,
+            new_fd,
+            new_path_addr,
+            new_path_len,
+        ))
+    }
+
+    #[allow(dead_code)]
+    // path_symlink(old_path: string, fd: fd, new_path: string) -> Result<(), errno>
+    pub(super) fn path_symlink(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        old_path: Handle,
+        old_path_len: i32,
+        fd: i32,
+        new_path: Handle,
+        new_path_len: i32,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let old_path_addr = scratch.copy_in(segments, old_path, old_path_len as u32 as usize)?;
+        let new_path_addr = scratch.copy_in(segments, new_path, new_path_len as u32 as usize)?;
+
+        Ok(wasi_snapshot_preview1::path_symlink(
+            ctx,
+            &scratch.guest_memory(),
+            old_path_addr,
+            old_path_len,
+            fd,
+            new_path_addr,
+            new_path_len,
+        ))
     }
 
     #[allow(dead_code)]
-    // clock_time_get(id: clockid, precision: timestamp) -> Result<timestamp, errno>
-    pub(super) fn clock_time_get(
+    // path_unlink_file(fd: fd, path: string) -> Result<(), errno>
+    pub(super) fn path_unlink_file(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         arg0: i32,
-        arg1: i64,
-        arg2: Handle,
-    ) -> Option<i32> {
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
         // No internal pointers, just pass through directly
-        with_collected_memory_1(segments, arg2, |mem, arg2| {
-            wasi_snapshot_preview1::clock_time_get(ctx, mem, arg0, arg1, arg2)
+        with_collected_memory_1(segments, arg1, 0, |mem, arg1| {
+            wasi_snapshot_preview1::path_unlink_file(ctx, mem, arg0, arg1, arg2)
         })
     }
 
     #[allow(dead_code)]
-    // fd_close(fd: fd) -> Result<(), errno>
-    pub(super) fn fd_close(
+    // poll_oneoff(in: ConstPointer<subscription>, out: Pointer<event>, nsubscriptions: size) -> Result<size, errno>
+    pub(super) fn poll_oneoff(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        subscriptions: Handle,
+        events: Handle,
+        nsubscriptions: i32,
+        nevents: Handle,
+    ) -> Result<i32, Trap> {
+        // Neither struct holds a pointer, so they keep their wasm32 layout
+        const SUBSCRIPTION_SIZE: usize = 48;
+        const EVENT_SIZE: usize = 32;
+
+        let n = nsubscriptions as u32 as usize;
+        let mut scratch = Scratch::new();
+        let subscriptions_addr = scratch.copy_in(segments, subscriptions, n * SUBSCRIPTION_SIZE)?;
+        let events_addr = scratch.copy_in(segments, events, n * EVENT_SIZE)?;
+        let nevents_addr = scratch.copy_in(segments, nevents, 4)?;
+
+        let res = wasi_snapshot_preview1::poll_oneoff(
+            ctx,
+            &scratch.guest_memory(),
+            subscriptions_addr,
+            events_addr,
+            nsubscriptions,
+            nevents_addr,
+        );
+
+        if res == 0 {
+            let nevents_res = scratch.read_u32(nevents_addr)? as usize;
+            scratch.copy_out(
+                segments,
+                events_addr,
+                events,
+                nevents_res.min(n) * EVENT_SIZE,
+            )?;
+            scratch.copy_out(segments, nevents_addr, nevents, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // proc_raise(sig: signal) -> Result<(), errno>
+    pub(super) fn proc_raise(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         arg0: i32,
-    ) -> Option<i32> {
+    ) -> Result<i32, Trap> {
         // No pointers, just pass through directly
         with_collected_memory_0(segments, |mem| {
-            wasi_snapshot_preview1::fd_close(ctx, mem, arg0)
+            wasi_snapshot_preview1::proc_raise(ctx, mem, arg0)
         })
     }
 
     #[allow(dead_code)]
-    // fd_fdstat_get(fd: fd) -> Result<fdstat, errno>
-    pub(super) fn fd_fdstat_get(
+    // random_get(buf: Pointer<u8>, buf_len: size) -> Result<(), errno>
+    pub(super) fn random_get(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
-        arg0: i32,
-        arg1: Handle,
-    ) -> Option<i32> {
+        arg0: Handle,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
         // No internal pointers, just pass through directly
-        with_collected_memory_1(segments, arg1, |mem, arg1| {
-            wasi_snapshot_preview1::fd_fdstat_get(ctx, mem, arg0, arg1)
+        with_collected_memory_1(segments, arg0, arg1 as u32 as usize, |mem, arg0| {
+            wasi_snapshot_preview1::random_get(ctx, mem, arg0, arg1)
         })
     }
 
     #[allow(dead_code)]
-    // fd_seek(fd: fd, offset: filedelta, whence: whence) -> Result<filesize, errno>
-    pub(super) fn fd_seek(
+    // sched_yield() -> Result<(), errno>
+    pub(super) fn sched_yield(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
-        arg0: i32,
-        arg1: i64,
-        arg2: i32,
-        arg3: Handle,
-    ) -> Option<i32> {
-        // No internal pointers, just pass through directly
-        with_collected_memory_1(segments, arg3, |mem, arg3| {
-            wasi_snapshot_preview1::fd_seek(ctx, mem, arg0, arg1, arg2, arg3)
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::sched_yield(ctx, mem)
         })
     }
 
     #[allow(dead_code)]
-    // fd_write(fd: fd, iovs: ciovec_array) -> Result<size, errno>
-    pub(super) fn fd_write(
+    #[allow(clippy::too_many_arguments)]
+    // sock_recv(fd: fd, ri_data: iovec_array, ri_flags: riflags) -> Result<(size, roflags), errno>
+    pub(super) fn sock_recv(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         fd: i32,
-        iovs_ptr: Handle,
-        iovs_len: i32,
-        nwritten: Handle,
-    ) -> Option<i32> {
-        let mut iovs: Vec<&[u8]> = vec![];
-        for i in 0..iovs_len {
-            let loc = read!(get_handle, segments, iovs_ptr.add(i * (8 + 8) + 0)?);
-            let len = read!(read_mem_u32, segments, iovs_ptr.add(i * (8 + 8) + 8)?) as usize;
-            if len == 0 {
-                iovs.push(&[]);
-            } else {
-                iovs.push(read!(bytes, segments, loc, len));
-            }
-        }
-
-        let nwritten_start: usize = 8 * iovs.len();
-        let mem_iovs_data_start: usize = nwritten_start + 4;
-        let mem_iovs_data_len: usize = iovs.iter().map(|x| x.len()).sum();
+        ri_data: Handle,
+        ri_data_len: i32,
+        ri_flags: i32,
+        ro_datalen: Handle,
+        ro_flags: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let (iovs, bufs) = scratch.copy_in_iovecs(segments, ri_data, ri_data_len)?;
+        let ro_datalen_addr = scratch.copy_in(segments, ro_datalen, 4)?;
+        let ro_flags_addr = scratch.copy_in(segments, ro_flags, 2)?;
+
+        let res = wasi_snapshot_preview1::sock_recv(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            ri_data_len,
+            ri_flags,
+            ro_datalen_addr,
+            ro_flags_addr,
+        );
 
-        let mut memory = vec![0u8; mem_iovs_data_start + mem_iovs_data_len + 4];
-        {
-            let mut start = mem_iovs_data_start as u32;
-            for (i, iov) in iovs.into_iter().enumerate() {
-                write_mem_u32(&mut memory, 8 * i + 0, start)?;
-                write_mem_u32(&mut memory, 8 * i + 4, iov.len() as u32)?;
-                memory[start as usize..start as usize + iov.len()].copy_from_slice(iov);
-                start += iov.len() as u32;
-            }
-            assert_eq!(start as usize, mem_iovs_data_start + mem_iovs_data_len);
+        if res == 0 {
+            let ro_datalen_res = scratch.read_u32(ro_datalen_addr)?;
+            scratch.copy_out_iovecs(segments, &bufs, ro_datalen_res as usize)?;
+            scratch.copy_out(segments, ro_datalen_addr, ro_datalen, 4)?;
+            scratch.copy_out(segments, ro_flags_addr, ro_flags, 2)?;
         }
 
-        assert!(nwritten_start % 4 == 0, "nwritten_start must be 4-aligned");
+        Ok(res)
+    }
 
-        let res = wasi_snapshot_preview1::fd_write(
+    #[allow(dead_code)]
+    // sock_send(fd: fd, si_data: ciovec_array, si_flags: siflags) -> Result<size, errno>
+    pub(super) fn sock_send(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        si_data: Handle,
+        si_data_len: i32,
+        si_flags: i32,
+        so_datalen: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let (iovs, _) = scratch.copy_in_iovecs(segments, si_data, si_data_len)?;
+        let so_datalen_addr = scratch.copy_in(segments, so_datalen, 4)?;
+
+        let res = wasi_snapshot_preview1::sock_send(
             ctx,
-            &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+            &scratch.guest_memory(),
             fd,
-            0,
-            iovs_len,
-            nwritten_start as i32,
+            iovs,
+            si_data_len,
+            si_flags,
+            so_datalen_addr,
         );
 
-        let nwritten_res = read_mem_u32(&memory, nwritten_start)?;
-        write!(write_mem_u32, segments, nwritten, nwritten_res);
+        if res == 0 {
+            scratch.copy_out(segments, so_datalen_addr, so_datalen, 4)?;
+        }
+
+        Ok(res)
+    }
 
-        Some(res)
+    #[allow(dead_code)]
+    // sock_shutdown(fd: fd, how: sdflags) -> Result<(), errno>
+    pub(super) fn sock_shutdown(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::sock_shutdown(ctx, mem, arg0, arg1)
+        })
     }
 }
 
@@ -778,6 +2972,331 @@ pub struct WasmModule {
     globals: Vec<TaggedVal>,
     indirect_call_table: Vec<Option<usize>>,
     context: wasi_common::WasiCtx,
+    trap_backtrace: WasmBacktrace,
+    // By index into `HOST_IMPORTS`
+    host_funcs: Vec<Option<HostFunc>>,
+    // Fuel left, if metered
+    fuel: Option<u64>,
+    // Shared with every `InterruptHandle`
+    interrupt: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    // Generated functions currently on the host stack
+    call_depth: u32,
+    max_call_depth: u32,
+    // Lowest host stack address a guest call may start at, see `enter_call`
+    stack_limit: usize,
+}
+
+// Each guest call takes a native frame of the generated function (and one of
+// its wrapper). The host stack they take up is checked on its own (see
+// `WasmModule::enter_call`), since frames range from a few hundred bytes in
+// release builds to about 20 KiB for large functions in debug builds
+// (`hello-c`), so this is only a cap on the depth itself.
+const DEFAULT_MAX_CALL_DEPTH: u32 = 1000;
+
+// Stack size assumed for a host thread that `on_large_stack` did not start:
+// the 2 MiB that Rust gives spawned threads by default, such as those
+// `cargo test` runs tests on. The main thread usually has more.
+const DEFAULT_HOST_STACK: usize = 2 << 20;
+
+thread_local! {
+    // Stack size of the current host thread
+    static HOST_STACK: std::cell::Cell<usize> = std::cell::Cell::new(DEFAULT_HOST_STACK);
+}
+
+// Roughly where the host stack is at
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// Runs `f` on a fresh host thread with a stack of `stack_size` bytes, for
+// guests that recurse deeper than the default host stack allows. `WasmModule`
+// cannot move across threads, so it has to be created inside `f`.
+#[allow(dead_code)]
+pub fn on_large_stack<R: Send>(stack_size: usize, f: impl FnOnce() -> R + Send) -> R {
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn_scoped(scope, move || {
+                HOST_STACK.with(|size| size.set(stack_size));
+                f()
+            })
+            .expect("failed to spawn a thread for the guest");
+        thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+impl WasmModule {
+    // Called by the wrapper of each generated function when its body traps.
+    // Records the frame (unless the guest is just exiting) and hands the trap
+    // on to the caller.
+    #[cold]
+    fn unwind(&mut self, func_index: u32, fault: Fault) -> Trap {
+        if let Trap::Exit(_) = fault.trap {
+            return fault.trap;
+        }
+        self.trap_backtrace.frames.push(WasmFrame {
+            func_index,
+            code_offset: fault.code_offset,
+        });
+        fault.trap
+    }
+
+    // Called by the wrapper of each generated function before its body runs,
+    // which then runs through the returned guard. Besides the depth, the
+    // guest may take up half of the host thread's stack from where the host
+    // called into it, which leaves the other half to the host and to the
+    // host functions the guest calls.
+    #[inline]
+    fn enter_call(&mut self) -> Result<CallGuard<'_>, Trap> {
+        let sp = stack_pointer();
+        if self.call_depth == 0 {
+            // The stack grows down on every target the runtime builds for
+            self.stack_limit = sp.saturating_sub(HOST_STACK.with(|size| size.get()) / 2);
+        }
+        if self.call_depth >= self.max_call_depth || sp < self.stack_limit {
+            return Err(Trap::StackOverflow);
+        }
+        self.call_depth += 1;
+        Ok(CallGuard { module: self })
+    }
+
+    // How deeply guest calls may nest before they trap with
+    // `Trap::StackOverflow`. They trap sooner if they run short of host
+    // stack, see `on_large_stack`.
+    #[allow(dead_code)]
+    pub fn set_max_call_depth(&mut self, depth: u32) {
+        self.max_call_depth = depth;
+    }
+
+    // Guest call stack of the last trap returned from an export
+    #[allow(dead_code)]
+    pub fn trap_backtrace(&self) -> &WasmBacktrace {
+        &self.trap_backtrace
+    }
+}
+
+// The module for the body of a generated function to run on. Decrements
+// `call_depth` again when dropped, so that it is restored even if a host
+// function panics and the host catches the unwind.
+struct CallGuard<'a> {
+    module: &'a mut WasmModule,
+}
+
+impl std::ops::Deref for CallGuard<'_> {
+    type Target = WasmModule;
+
+    fn deref(&self) -> &WasmModule {
+        self.module
+    }
+}
+
+impl std::ops::DerefMut for CallGuard<'_> {
+    fn deref_mut(&mut self) -> &mut WasmModule {
+        self.module
+    }
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.module.call_depth -= 1;
+    }
+}
+
+// Stops a running module from another thread: the guest traps with
+// `Trap::Interrupted` at its next call or loop iteration.
+//
+// An interrupt raised while the module is idle is not dropped but stays
+// latched: the next `call` or `_start` traps as soon as it enters guest
+// code, so that an interrupt sent just before the guest starts running is
+// not lost. Each interrupt stops the guest once.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    flag: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[allow(dead_code)]
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+// A host function an import is linked to. It gets the module itself, to
+// reach guest memory through handles it was passed, and the arguments of
+// the call, which are checked against the import's signature like its
+// results.
+type HostFunc = std::rc::Rc<dyn Fn(&mut WasmModule, &[Value]) -> Result<Vec<Value>, Trap>>;
+
+// Host functions to satisfy imports with, by module and field name
+#[derive(Clone, Default)]
+pub struct Linker {
+    funcs: BTreeMap<(String, String), HostFunc>,
+}
+
+#[allow(dead_code)]
+impl Linker {
+    pub fn new() -> Self {
+        Linker::default()
+    }
+
+    pub fn func(
+        &mut self,
+        module: &str,
+        field: &str,
+        f: impl Fn(&mut WasmModule, &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+    ) -> &mut Self {
+        self.funcs
+            .insert((module.to_owned(), field.to_owned()), std::rc::Rc::new(f));
+        self
+    }
+
+    fn get(&self, module: &str, field: &str) -> Option<HostFunc> {
+        self.funcs
+            .get(&(module.to_owned(), field.to_owned()))
+            .cloned()
+    }
+}
+
+// Configures the WASI context a module runs in. Unless told otherwise the
+// guest gets no arguments, no environment, no preopened directories and
+// null stdio.
+pub struct WasmModuleBuilder {
+    wasi: wasi_common::WasiCtxBuilder,
+    preopens: Vec<(std::path::PathBuf, std::path::PathBuf)>,
+    linker: Linker,
+    fuel: Option<u64>,
+    max_call_depth: u32,
+    memory_limits: MemoryLimits,
+}
+
+#[allow(dead_code)]
+impl WasmModuleBuilder {
+    pub fn args<S: AsRef<[u8]>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.wasi.args(args);
+        self
+    }
+
+    pub fn inherit_args(mut self) -> Self {
+        self.wasi.inherit_args();
+        self
+    }
+
+    pub fn env<S: AsRef<[u8]>>(mut self, key: S, value: S) -> Self {
+        self.wasi.env(key, value);
+        self
+    }
+
+    pub fn inherit_env(mut self) -> Self {
+        self.wasi.inherit_env();
+        self
+    }
+
+    // Makes the host directory `host` visible to the guest as `guest`
+    pub fn preopen_dir(
+        mut self,
+        host: impl AsRef<std::path::Path>,
+        guest: impl AsRef<std::path::Path>,
+    ) -> Self {
+        self.preopens
+            .push((host.as_ref().to_owned(), guest.as_ref().to_owned()));
+        self
+    }
+
+    pub fn stdin(mut self, reader: impl std::io::Read + std::any::Any) -> Self {
+        self.wasi
+            .stdin(wasi_common::virtfs::pipe::ReadPipe::new(reader));
+        self
+    }
+
+    pub fn stdout(mut self, writer: impl std::io::Write + std::any::Any) -> Self {
+        self.wasi
+            .stdout(wasi_common::virtfs::pipe::WritePipe::new(writer));
+        self
+    }
+
+    pub fn stderr(mut self, writer: impl std::io::Write + std::any::Any) -> Self {
+        self.wasi
+            .stderr(wasi_common::virtfs::pipe::WritePipe::new(writer));
+        self
+    }
+
+    // Gives the guest the host's own stdin, stdout and stderr
+    pub fn inherit_stdio(mut self) -> Self {
+        self.wasi.inherit_stdio();
+        self
+    }
+
+    // Resolves the module's imports against `linker`, replacing any host
+    // functions added before
+    pub fn linker(mut self, linker: Linker) -> Self {
+        self.linker = linker;
+        self
+    }
+
+    pub fn func(
+        mut self,
+        module: &str,
+        field: &str,
+        f: impl Fn(&mut WasmModule, &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+    ) -> Self {
+        self.linker.func(module, field, f);
+        self
+    }
+
+    // Meters the module, see `WasmModule::set_fuel`
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // See `WasmModule::set_max_call_depth`
+    pub fn max_call_depth(mut self, depth: u32) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    // See `WasmModule::set_memory_limits`. The segments a module sets up for
+    // itself on creation count towards them, but are never refused.
+    pub fn memory_limits(mut self, limits: MemoryLimits) -> Self {
+        self.memory_limits = limits;
+        self
+    }
+
+    pub fn build(mut self) -> Result<WasmModule, BuildError> {
+        for (host, guest) in self.preopens {
+            let dir = std::fs::File::open(&host).map_err(|e| BuildError::Preopen(host, e))?;
+            self.wasi.preopened_dir(dir, guest);
+        }
+        let context = self.wasi.build().map_err(BuildError::Wasi)?;
+        let mut module = WasmModule::try_new(context).map_err(BuildError::Trap)?;
+        let linker = &self.linker;
+        module.host_funcs = HOST_IMPORTS
+            .iter()
+            .map(|import| linker.get(import.module, import.field))
+            .collect();
+        module.fuel = self.fuel;
+        module.max_call_depth = self.max_call_depth;
+        module.set_memory_limits(self.memory_limits);
+        Ok(module)
+    }
+}
+
+impl WasmModule {
+    #[allow(dead_code)]
+    pub fn builder() -> WasmModuleBuilder {
+        WasmModuleBuilder {
+            wasi: wasi_common::WasiCtxBuilder::new(),
+            preopens: vec![],
+            linker: Linker::new(),
+            fuel: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            memory_limits: MemoryLimits::default(),
+        }
+    }
 }
 
 macro_rules! memory_accessors {
@@ -817,15 +3336,183 @@ memory_accessors! {i64, read_mem_i64, write_mem_i64}
 memory_accessors! {f32, read_mem_f32, write_mem_f32}
 memory_accessors! {f64, read_mem_f64, write_mem_f64}
 
+// Plain values the host can read and write in guest memory, stored little
+// endian like the guest's own loads and stores
+pub trait GuestValue: Copy {
+    const SIZE: usize;
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    fn to_le_slice(self, bytes: &mut [u8]);
+}
+
+macro_rules! guest_values {
+    ($($ty:ty),*) => {
+        $(
+            impl GuestValue for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                fn from_le_slice(bytes: &[u8]) -> Self {
+                    <$ty>::from_le_bytes(bytes.try_into().unwrap())
+                }
+                fn to_le_slice(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+guest_values! {u8, u16, u32, u64, i8, i16, i32, i64, f32, f64}
+
+// Host access to guest memory. Reads and writes are checked against the
+// handle and its segment just like the guest's own, and writes clear tags
+// the same way, so the host cannot forge handles by accident.
+impl WasmModule {
+    // Handles spanning each segment that is currently allocated
+    #[allow(dead_code)]
+    pub fn live_segments(&self) -> Vec<Handle> {
+        self.segments.live().map(Handle::segment).collect()
+    }
+
+    // Bytes reachable through `h`, from its offset up to its bound
+    #[allow(dead_code)]
+    pub fn segment_data(&self, h: Handle) -> Result<&[u8], Trap> {
+        let offset = h.checked_offset(0)?;
+        self.segments
+            .get(h)?
+            .get_data_within(h)?
+            .get(offset..)
+            .ok_or(Trap::OutOfBounds)
+    }
+
+    // Same as `segment_data`, for the host to write to. All of it is
+    // turned into data up front.
+    #[allow(dead_code)]
+    pub fn segment_data_mut(&mut self, h: Handle) -> Result<&mut [u8], Trap> {
+        let len = self.segment_data(h)?.len();
+        Ok(write!(bytes, self.segments, h, len))
+    }
+
+    #[allow(dead_code)]
+    pub fn read_value<T: GuestValue>(&self, h: Handle) -> Result<T, Trap> {
+        Ok(T::from_le_slice(read!(bytes, self.segments, h, T::SIZE)))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_value<T: GuestValue>(&mut self, h: Handle, value: T) -> Result<(), Trap> {
+        value.to_le_slice(write!(bytes, self.segments, h, T::SIZE));
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn read_handle(&self, h: Handle) -> Result<Handle, Trap> {
+        Ok(read!(get_handle, self.segments, h))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_handle(&mut self, h: Handle, value: Handle) -> Result<(), Trap> {
+        write!(store_handle, self.segments, h, value);
+        Ok(())
+    }
+
+    // Allocates a zeroed segment of `size` bytes, e.g. to pass data to the
+    // guest. It is the guest's to free like any other. Subject to the
+    // module's `MemoryLimits`, so this may return a null handle.
+    #[allow(dead_code)]
+    pub fn alloc_segment(&mut self, size: u32) -> Result<Handle, Trap> {
+        self.new_segment(size)
+    }
+}
+
+impl From<Value> for TaggedVal {
+    fn from(v: Value) -> Self {
+        match v {
+            Value::I32(v) => I32(v),
+            Value::I64(v) => I64(v),
+            Value::F32(v) => F32(v),
+            Value::F64(v) => F64(v),
+            Value::Handle(v) => TaggedVal::Handle(v),
+        }
+    }
+}
+
+impl Value {
+    fn from_tagged(v: TaggedVal) -> Result<Self, Trap> {
+        match v {
+            I32(v) => Ok(Value::I32(v)),
+            I64(v) => Ok(Value::I64(v)),
+            F32(v) => Ok(Value::F32(v)),
+            F64(v) => Ok(Value::F64(v)),
+            TaggedVal::Handle(v) => Ok(Value::Handle(v)),
+            Undefined => Err(Trap::TagMismatch),
+        }
+    }
+}
+
+impl WasmModule {
+    #[allow(dead_code)]
+    pub fn exports(&self) -> &'static [Export] {
+        &EXPORTS
+    }
+
+    // Calls the export `name`. `args` must match its signature, or the
+    // call fails with `ArgumentMismatch` before entering the guest.
+    #[allow(dead_code)]
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let index = EXPORTS
+            .iter()
+            .position(|export| export.name == name)
+            .ok_or(Trap::UnknownExport)?;
+        let params = EXPORTS[index].params;
+        if args.len() != params.len() || args.iter().zip(params).any(|(arg, &ty)| arg.ty() != ty) {
+            return Err(Trap::ArgumentMismatch);
+        }
+        let args: Vec<TaggedVal> = args.iter().map(|&arg| arg.into()).collect();
+        self.call_export(index, &args)?
+            .into_iter()
+            .map(Value::from_tagged)
+            .collect()
+    }
+
+    // Imports that are resolved by host functions (see `Linker`)
+    #[allow(dead_code)]
+    pub fn imports(&self) -> &'static [Import] {
+        &HOST_IMPORTS
+    }
+
+    #[allow(dead_code)]
+    fn call_host(&mut self, import: usize, args: &[TaggedVal]) -> Result<Vec<TaggedVal>, Trap> {
+        let f = self.host_funcs[import]
+            .clone()
+            .ok_or(Trap::UnresolvedImport)?;
+        let args = args
+            .iter()
+            .map(|&arg| Value::from_tagged(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let results = f(self, &args)?;
+        let types = HOST_IMPORTS[import].results;
+        if results.len() != types.len()
+            || results.iter().zip(types).any(|(res, &ty)| res.ty() != ty)
+        {
+            return Err(Trap::ArgumentMismatch);
+        }
+        Ok(results.into_iter().map(TaggedVal::from).collect())
+    }
+}
+
 impl WasmModule {
     #[allow(unused_mut)]
-    fn try_new() -> Option<Self> {
+    fn try_new(context: wasi_common::WasiCtx) -> Result<Self, Trap> {
         let mut m = WasmModule {
             segments: Segments::new(),
             globals: vec![],
             indirect_call_table: vec![],
-            context: wasi_common::WasiCtx::new(std::env::args())
-                .expect("Unable to initialize WASI context"),
+            context,
+            trap_backtrace: WasmBacktrace::default(),
+            host_funcs: vec![],
+            fuel: None,
+            interrupt: Default::default(),
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack_limit: 0,
         };
         m.globals.resize_with(2, Default::default);
         m.globals[0] = TaggedVal::from(Handle::NULL);
@@ -834,14 +3521,24 @@ impl WasmModule {
         let init_handle = m.new_segment(1048576).unwrap();
         m.globals[1] = TaggedVal::from(init_handle); /* WORKAROUND for mswasm-llvm and data segment initialization */
 
-        Some(m)
+        Ok(m)
     }
     pub fn new() -> Self {
-        Self::try_new().unwrap()
+        Self::builder()
+            .inherit_args()
+            .inherit_stdio()
+            .build()
+            .unwrap()
     }
 }
 
 impl WasmModule {
+    fn func_0(&mut self, arg_0: i32) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_0_inner(arg_0)
+            .map_err(|fault| call.unwind(0, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -850,8 +3547,14 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_0(&mut self, arg_0: i32) -> Option<()> {
-        std::process::exit(arg_0)
+    fn func_0_inner(&mut self, arg_0: i32) -> Result<(), Fault> {
+        self.checkpoint()?;
+        Err(Trap::Exit(arg_0).into())
+    }
+
+    fn func_1(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_1_inner().map_err(|fault| call.unwind(1, fault))
     }
 
     #[allow(
@@ -862,15 +3565,22 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_1(&mut self) -> Option<()> {
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        v0 = TaggedVal::from(2097152i32);
-        v0 = TaggedVal::from(self.new_segment(v0.try_as_i32()? as u32)?);
-        v1 = TaggedVal::from(2097152i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        Some(())
+    fn func_1_inner(&mut self) -> Result<(), Fault> {
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        self.checkpoint().at(0x3)?;
+        v0_i32 = 2097152i32;
+        v0_handle = self.new_segment(v0_i32 as u32).at(0x8)?;
+        v1 = 2097152i32;
+        v0_handle = v0_handle.add(v1).at(0xe)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        Ok(())
+    }
+
+    fn func_2(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_2_inner().map_err(|fault| call.unwind(2, fault))
     }
 
     #[allow(
@@ -881,23 +3591,29 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_2(&mut self) -> Option<()> {
+    fn func_2_inner(&mut self) -> Result<(), Fault> {
         let mut local_0: i32 = 0i32;
-        let mut v0: TaggedVal;
+        let mut v0: i32;
+        self.checkpoint().at(0x16)?;
         'label_0: loop {
-            v0 = TaggedVal::from(self.func_3()?);
-            local_0 = v0.try_as_i32()?;
-            v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0 = self.func_3().at(0x18)?;
+            local_0 = v0;
+            v0 = (v0 == 0) as i32;
+            if v0 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(local_0);
-            self.func_4(v0.try_as_i32()?)?;
-            unreachable!("Reached a point explicitly marked unreachable in WASM module");
+            v0 = local_0;
+            self.func_4(v0).at(0x25)?;
+            return Err(Fault::new(Trap::Unreachable, 0x2b));
             break;
         }
-        Some(())
+        Ok(())
+    }
+
+    fn func_3(&mut self) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_3_inner().map_err(|fault| call.unwind(3, fault))
     }
 
     #[allow(
@@ -908,10 +3624,17 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_3(&mut self) -> Option<i32> {
-        let mut v0: TaggedVal;
-        v0 = TaggedVal::from(21i32);
-        Some(v0.try_as_i32()?)
+    fn func_3_inner(&mut self) -> Result<i32, Fault> {
+        let mut v0: i32;
+        self.checkpoint().at(0x30)?;
+        v0 = 21i32;
+        Ok(v0)
+    }
+
+    fn func_4(&mut self, arg_0: i32) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_4_inner(arg_0)
+            .map_err(|fault| call.unwind(4, fault))
     }
 
     #[allow(
@@ -922,83 +3645,123 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_4(&mut self, arg_0: i32) -> Option<()> {
+    fn func_4_inner(&mut self, arg_0: i32) -> Result<(), Fault> {
         let mut local_0: i32 = arg_0;
-        let mut v0: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        self.func_0(v0.try_as_i32()?)?;
+        let mut v0: i32;
+        self.checkpoint().at(0x35)?;
+        v0 = local_0;
+        self.func_0(v0).at(0x37)?;
         'label_0: loop {
+            self.checkpoint().at(0x3f)?;
             {}
             continue 'label_0;
             break;
         }
-        Some(())
+        Ok(())
     }
 }
 
 impl WasmModule {
     #[allow(dead_code)]
-    fn indirect_call(&mut self, idx: usize, args: &[TaggedVal]) -> Option<Vec<TaggedVal>> {
-        let call_target = (*self.indirect_call_table.get(idx)?)?;
+    fn indirect_call(&mut self, idx: usize, args: &[TaggedVal]) -> Result<Vec<TaggedVal>, Trap> {
+        let call_target = (*self
+            .indirect_call_table
+            .get(idx)
+            .ok_or(Trap::UndefinedTableElement)?)
+        .ok_or(Trap::UndefinedTableElement)?;
         match call_target {
             0 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 self.func_0(a0)?;
-                Some(vec![])
+                Ok(vec![])
             }
             1 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_1()?;
-                Some(vec![])
+                Ok(vec![])
             }
             2 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_2()?;
-                Some(vec![])
+                Ok(vec![])
             }
             3 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 let rets = self.func_3()?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             4 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 self.func_4(a0)?;
-                Some(vec![])
+                Ok(vec![])
             }
-            _ => None,
+            _ => Err(Trap::UndefinedTableElement),
         }
     }
 }
 
+// Names from the `name` section, by function index
+const FUNC_NAMES: [&str; 5] = [
+    "for_loop::proc_exit::h82b59493cf614b39",
+    "__mswasm_init_stack",
+    "_start",
+    "__original_main",
+    "exit",
+];
+
 impl WasmModule {
-    #[allow(dead_code)]
-    pub fn get_memory(&mut self) -> *mut u8 {
-        panic!("Memory export currently unimplemented for MS Wasm")
+    pub fn _start(&mut self) -> Result<(), Trap> {
+        self.trap_backtrace.frames.clear();
+        self.func_2()
     }
 }
 
+// Imports resolved through the `Linker`, indexed by `call_host`
+const HOST_IMPORTS: [Import; 0] = [];
+
+// Signatures of the exports above, in the order `call_export` dispatches on
+const EXPORTS: [Export; 1] = [Export {
+    name: "_start",
+    params: &[],
+    results: &[],
+}];
+
 impl WasmModule {
-    pub fn _start(&mut self) -> Option<()> {
-        self.func_2()
+    #[allow(unused_variables)]
+    fn call_export(&mut self, index: usize, args: &[TaggedVal]) -> Result<Vec<TaggedVal>, Trap> {
+        match index {
+            0 => {
+                self._start()?;
+                Ok(vec![])
+            }
+            _ => unreachable!(),
+        }
     }
 }
 fn main() {
     let mut wasm_module = WasmModule::new();
-    wasm_module._start().unwrap();
+    match wasm_module._start() {
+        Ok(()) => {}
+        Err(Trap::Exit(code)) => std::process::exit(code),
+        Err(trap) => {
+            eprintln!("wasm trap: {}", trap);
+            eprint!("{}", wasm_module.trap_backtrace());
+            std::process::exit(1);
+        }
+    }
 }
@@ -1,5 +1,6 @@
 mod guest_mem_wrapper;
 
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::TryInto;
 
 #[derive(Copy, Clone, Debug)]
@@ -41,16 +42,316 @@ impl From<TaggedVal> for ValType {
     }
 }
 
+// The ways a module can trap. Every generated function returns
+// `Result<_, Trap>`, so a trap unwinds the whole guest call stack back
+// to the exported entry point that was called by the host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trap {
+    // Access through a handle to a segment that has been freed
+    SegmentFreed,
+    // Access outside of the bounds of a handle or of its segment
+    OutOfBounds,
+    // Dereference of a handle whose tag was cleared by a data write
+    CorruptedHandle,
+    // Dereference of `handle.null` (possibly with an offset)
+    NullDereference,
+    // A value did not have the type the instruction expected
+    TagMismatch,
+    // Handle load/store at an offset that is not 8-byte aligned
+    MisalignedHandleAccess,
+    // `free_segment` on a segment that was already freed
+    DoubleFree,
+    // `free_segment` on a handle that does not point at the start of its
+    // segment, or that was sliced to a part of it
+    InteriorFree,
+    // `free_segment` on `handle.null`
+    NullFree,
+    // `free_segment` on a corrupted handle or one with a segment ID that was
+    // never handed out
+    ForgedFree,
+    // No segment IDs left to hand out
+    SegmentsExhausted,
+    // An allocation would have gone over one of the `MemoryLimits`
+    MemoryLimitExceeded,
+    // `call_indirect` to an empty table slot
+    UndefinedTableElement,
+    // `call_indirect` to a function of the wrong type
+    IndirectCallSignatureMismatch,
+    IntegerDivideByZero,
+    InvalidConversionToInteger,
+    // Reached an `unreachable` instruction
+    Unreachable,
+    // The guest call stack grew too deep
+    StackOverflow,
+    // The guest used up the fuel it was given
+    OutOfFuel,
+    // Stopped through an `InterruptHandle`
+    Interrupted,
+    // Not a fault: the guest called `proc_exit` with this status
+    Exit(i32),
+    // Not a fault either: `WasmModule::call` was given the name of no export
+    UnknownExport,
+    // Nor this: the arguments given to `WasmModule::call`, or the results
+    // a host function returned, do not match the signature
+    ArgumentMismatch,
+    // Call to an import that no host function was linked for
+    UnresolvedImport,
+}
+
+impl std::fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Trap::SegmentFreed => "use of a freed segment",
+            Trap::OutOfBounds => "out of bounds segment access",
+            Trap::CorruptedHandle => "dereference of a corrupted handle",
+            Trap::NullDereference => "null handle dereference",
+            Trap::TagMismatch => "value of unexpected type",
+            Trap::MisalignedHandleAccess => "misaligned handle access",
+            Trap::DoubleFree => "double free of a segment",
+            Trap::InteriorFree => "free of a handle into the middle of a segment",
+            Trap::NullFree => "free of a null handle",
+            Trap::ForgedFree => "free of a forged handle",
+            Trap::SegmentsExhausted => "segment IDs exhausted",
+            Trap::MemoryLimitExceeded => "memory limit exceeded",
+            Trap::UndefinedTableElement => "undefined table element",
+            Trap::IndirectCallSignatureMismatch => "indirect call signature mismatch",
+            Trap::IntegerDivideByZero => "integer divide by zero",
+            Trap::InvalidConversionToInteger => "invalid conversion to integer",
+            Trap::Unreachable => "unreachable executed",
+            Trap::StackOverflow => "call stack exhausted",
+            Trap::OutOfFuel => "all fuel consumed",
+            Trap::Interrupted => "interrupted by the host",
+            Trap::UnknownExport => "no export of that name",
+            Trap::ArgumentMismatch => "values do not match the signature",
+            Trap::UnresolvedImport => "call to an unresolved import",
+            Trap::Exit(code) => return write!(f, "exit with status {}", code),
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for Trap {}
+
+// Why `WasmModuleBuilder::build` failed
+#[derive(Debug)]
+pub enum BuildError {
+    // A directory to preopen could not be opened on the host
+    Preopen(std::path::PathBuf, std::io::Error),
+    // The WASI context could not be built
+    Wasi(wasi_common::WasiCtxBuilderError),
+    // The module's own initialization trapped
+    Trap(Trap),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::Preopen(path, e) => write!(f, "cannot preopen {}: {}", path.display(), e),
+            BuildError::Wasi(e) => write!(f, "cannot build WASI context: {}", e),
+            BuildError::Trap(trap) => write!(f, "wasm trap during instantiation: {}", trap),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+// A value passed to or returned from an export, the public counterpart of
+// `TaggedVal`
+#[derive(Copy, Clone, Debug)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Handle(Handle),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+    Handle,
+}
+
+impl Value {
+    pub fn ty(&self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::Handle(_) => ValueType::Handle,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+            ValueType::F32 => "f32",
+            ValueType::F64 => "f64",
+            ValueType::Handle => "handle",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// An exported function and its signature
+#[derive(Copy, Clone, Debug)]
+pub struct Export {
+    pub name: &'static str,
+    pub params: &'static [ValueType],
+    pub results: &'static [ValueType],
+}
+
+// A function the module imports from the host, and its signature
+#[derive(Copy, Clone, Debug)]
+pub struct Import {
+    pub module: &'static str,
+    pub field: &'static str,
+    pub params: &'static [ValueType],
+    pub results: &'static [ValueType],
+}
+
+fn fmt_signature(
+    f: &mut std::fmt::Formatter,
+    params: &[ValueType],
+    results: &[ValueType],
+) -> std::fmt::Result {
+    let list = |types: &[ValueType]| {
+        types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    write!(f, "({})", list(params))?;
+    match results {
+        [] => Ok(()),
+        [result] => write!(f, " -> {}", result),
+        results => write!(f, " -> ({})", list(results)),
+    }
+}
+
+impl std::fmt::Display for Export {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        fmt_signature(f, self.params, self.results)
+    }
+}
+
+impl std::fmt::Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.module, self.field)?;
+        fmt_signature(f, self.params, self.results)
+    }
+}
+
+// A trap leaving a generated function body, together with the code offset of
+// the Wasm instruction that raised it. Generated code attaches the offset at
+// each call that can trap (see `At`); traps raised in the bodies of
+// imported functions have no Wasm code to point at.
+struct Fault {
+    trap: Trap,
+    code_offset: Option<u32>,
+}
+
+impl Fault {
+    #[inline]
+    fn new(trap: Trap, code_offset: u32) -> Self {
+        Fault {
+            trap,
+            code_offset: Some(code_offset),
+        }
+    }
+}
+
+impl From<Trap> for Fault {
+    #[inline]
+    fn from(trap: Trap) -> Self {
+        Fault {
+            trap,
+            code_offset: None,
+        }
+    }
+}
+
+// Attaches the code offset of the instruction being run to a trap raised by
+// generated code
+trait At<T> {
+    fn at(self, code_offset: u32) -> Result<T, Fault>;
+}
+
+impl<T> At<T> for Result<T, Trap> {
+    #[inline]
+    fn at(self, code_offset: u32) -> Result<T, Fault> {
+        self.map_err(|trap| Fault::new(trap, code_offset))
+    }
+}
+
+// A guest function that a trap unwound through
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WasmFrame {
+    pub func_index: u32,
+    // Offset from the start of the code section of the instruction that
+    // trapped (innermost frame) or made the call (outer frames). `None` for
+    // imported functions, which have no Wasm code.
+    pub code_offset: Option<u32>,
+}
+
+impl WasmFrame {
+    // Name from the module's `name` section
+    pub fn func_name(&self) -> &'static str {
+        FUNC_NAMES[self.func_index as usize]
+    }
+}
+
+impl std::fmt::Display for WasmFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "`{}` (func {})", self.func_name(), self.func_index)?;
+        if let Some(offset) = self.code_offset {
+            write!(f, " at code offset {:#x}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+// The guest call stack at the last trap, innermost frame first
+#[derive(Clone, Debug, Default)]
+pub struct WasmBacktrace {
+    frames: Vec<WasmFrame>,
+}
+
+impl WasmBacktrace {
+    pub fn frames(&self) -> &[WasmFrame] {
+        &self.frames
+    }
+}
+
+impl std::fmt::Display for WasmBacktrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            let prefix = if i == 0 { "in" } else { "called from" };
+            writeln!(f, "    {} {}", prefix, frame)?;
+        }
+        Ok(())
+    }
+}
+
 macro_rules! tagged_value_conversion {
     ($ty:ty, $try_as:ident, $e:tt) => {
         impl TaggedVal {
             #[inline]
             #[allow(dead_code)]
-            fn $try_as(&self) -> Option<$ty> {
+            fn $try_as(&self) -> Result<$ty, Trap> {
                 if let $e(v) = self {
-                    Some(*v)
+                    Ok(*v)
                 } else {
-                    None
+                    Err(Trap::TagMismatch)
                 }
             }
         }
@@ -87,16 +388,16 @@ impl From<u64> for TaggedVal {
 }
 
 trait SafeFloatConv<T> {
-    fn try_to_int(self) -> Option<T>;
+    fn try_to_int(self) -> Result<T, Trap>;
 }
 macro_rules! safe_float_conv {
     ($from:ty, $to:ty) => {
         impl SafeFloatConv<$to> for $from {
-            fn try_to_int(self) -> Option<$to> {
+            fn try_to_int(self) -> Result<$to, Trap> {
                 if self.is_finite() {
-                    Some(self as $to)
+                    Ok(self as $to)
                 } else {
-                    None
+                    Err(Trap::InvalidConversionToInteger)
                 }
             }
         }
@@ -122,18 +423,33 @@ impl TaggedVal {
     // keep this around
     #[inline(always)]
     #[allow(dead_code, non_snake_case)]
-    fn try_as_Handle(&self) -> Option<Handle> {
+    fn try_as_Handle(&self) -> Result<Handle, Trap> {
         self.try_as_handle()
     }
 }
 
+// A handle as the guest sees it. Only the runtime makes valid handles: the
+// host gets them from `alloc_segment`, `read_handle` and the like, and can
+// neither forge one nor widen its bounds.
 #[derive(Copy, Clone, Debug)]
-pub enum Handle {
+#[cfg_attr(test, derive(PartialEq))]
+pub struct Handle {
+    repr: HandleRepr,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+enum HandleRepr {
     Valid {
         base_segment_id: u32, // Note: Using segment ID here, rather than a base into memory
         offset: u32,
-        // Note: Ignoring `bound: u32` for now, since we don't (yet)
-        // have handle.slice/segment_slice/etc.
+        // Note: `base` and `bound` are offsets into the segment. A
+        // handle fresh out of `new_segment` spans the whole segment
+        // (`bound == Handle::SEGMENT_END`); `handle.slice` narrows
+        // them down. Accesses are checked against both these and the
+        // segment's own length.
+        base: u32,
+        bound: u32,
     },
     Corrupted {
         bytes: [u8; 8],
@@ -144,110 +460,232 @@ pub enum Handle {
 }
 impl std::fmt::Display for Handle {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Handle::Valid {
+        match self.repr {
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
-            } => write!(f, "<seg={} off={:#x?}>", base_segment_id, offset),
-            Handle::Corrupted { bytes } => write!(f, "<corrupted {:?}>", bytes),
-            Handle::Null { offset } => write!(f, "<null off={:#x?}>", offset),
+                base: 0,
+                bound: Handle::SEGMENT_END,
+            } => {
+                let (index, generation) = Segments::split_id(base_segment_id);
+                write!(f, "<seg={} gen={} off={:#x?}>", index, generation, offset)
+            }
+            HandleRepr::Valid {
+                base_segment_id,
+                offset,
+                base,
+                bound,
+            } => {
+                let (index, generation) = Segments::split_id(base_segment_id);
+                write!(
+                    f,
+                    "<seg={} gen={} off={:#x?} bounds=[{:#x?}, {:#x?})>",
+                    index, generation, offset, base, bound
+                )
+            }
+            HandleRepr::Corrupted { bytes } => write!(f, "<corrupted {:?}>", bytes),
+            HandleRepr::Null { offset } => write!(f, "<null off={:#x?}>", offset),
         }
     }
 }
 
 impl Handle {
-    const NULL: Handle = Handle::Null { offset: 0 };
+    const NULL: Handle = Handle {
+        repr: HandleRepr::Null { offset: 0 },
+    };
+
+    // Upper bound of a handle that has not been sliced, i.e. one that
+    // may reach up to the end of its segment
+    const SEGMENT_END: u32 = u32::MAX;
+
+    // The whole of the segment `base_segment_id`, as `new_segment` hands out
+    fn segment(base_segment_id: u32) -> Self {
+        Handle {
+            repr: HandleRepr::Valid {
+                base_segment_id,
+                offset: 0,
+                base: 0,
+                bound: Handle::SEGMENT_END,
+            },
+        }
+    }
 
     #[allow(dead_code)]
-    fn add(self, amt: i32) -> Option<Self> {
-        match self {
-            Handle::Null { offset } => Some(Handle::Null {
-                offset: offset.checked_add(amt)?,
+    fn add(self, amt: i32) -> Result<Self, Trap> {
+        match self.repr {
+            HandleRepr::Null { offset } => Ok(Handle {
+                repr: HandleRepr::Null {
+                    offset: offset.checked_add(amt).ok_or(Trap::OutOfBounds)?,
+                },
             }),
-            Handle::Corrupted { .. } => None,
-            Handle::Valid {
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
+                base,
+                bound,
             } => {
                 let offset: i32 = offset as _;
                 let new_offset: i32 = offset.overflowing_add(amt).0;
-                Some(Handle::Valid {
-                    base_segment_id,
-                    offset: new_offset as _,
+                Ok(Handle {
+                    repr: HandleRepr::Valid {
+                        base_segment_id,
+                        offset: new_offset as _,
+                        base,
+                        bound,
+                    },
                 })
             }
         }
     }
 
     #[allow(dead_code)]
-    fn sub(self, amt: i32) -> Option<Self> {
+    fn sub(self, amt: i32) -> Result<Self, Trap> {
         self.add(-amt)
     }
 
+    // handle.slice: narrows the handle down to the `len` bytes
+    // starting `start` bytes away from its current offset. The new
+    // range must lie within the current bounds of the handle; the
+    // resulting handle points at the start of the new range.
     #[allow(dead_code)]
-    #[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
-    fn segment_index(self) -> Option<usize> {
-        match self {
-            Handle::Null { .. } | Handle::Corrupted { .. } => None,
-            Handle::Valid {
+    fn slice(self, start: i32, len: i32) -> Result<Self, Trap> {
+        match self.repr {
+            HandleRepr::Null { .. } => Err(Trap::NullDereference),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid {
                 base_segment_id,
-                offset: _,
-            } => Some(base_segment_id as _),
+                offset,
+                base,
+                bound,
+            } => {
+                let new_base: u32 = (offset as i64 + start as i64)
+                    .try_into()
+                    .or(Err(Trap::OutOfBounds))?;
+                let new_bound: u32 = (new_base as i64 + len as i64)
+                    .try_into()
+                    .or(Err(Trap::OutOfBounds))?;
+                if new_base < base || new_bound < new_base || new_bound > bound {
+                    return Err(Trap::OutOfBounds);
+                }
+                Ok(Handle {
+                    repr: HandleRepr::Valid {
+                        base_segment_id,
+                        offset: new_base,
+                        base: new_base,
+                        bound: new_bound,
+                    },
+                })
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    fn is_sliced(self) -> bool {
+        match self.repr {
+            HandleRepr::Valid { base, bound, .. } => base != 0 || bound != Handle::SEGMENT_END,
+            HandleRepr::Null { .. } | HandleRepr::Corrupted { .. } => false,
+        }
+    }
+
+    // Slot index and generation, see `Segments`
+    #[allow(dead_code)]
+    fn segment_id(self) -> Result<u32, Trap> {
+        match self.repr {
+            HandleRepr::Null { .. } => Err(Trap::NullDereference),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid {
+                base_segment_id, ..
+            } => Ok(base_segment_id),
         }
     }
 
     #[allow(dead_code)]
     #[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
-    fn segment_offset(self) -> Option<usize> {
-        match self {
-            Handle::Null { offset } => Some(offset as _),
-            Handle::Corrupted { .. } => None,
-            Handle::Valid {
-                base_segment_id: _,
-                offset,
-            } => Some(offset as _),
+    fn segment_offset(self) -> Result<usize, Trap> {
+        match self.repr {
+            HandleRepr::Null { offset } => Ok(offset as _),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid { offset, .. } => Ok(offset as _),
+        }
+    }
+
+    #[allow(dead_code)]
+    #[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
+    fn segment_bounds(self) -> Result<(usize, usize), Trap> {
+        match self.repr {
+            HandleRepr::Null { .. } => Err(Trap::NullDereference),
+            HandleRepr::Corrupted { .. } => Err(Trap::CorruptedHandle),
+            HandleRepr::Valid { base, bound, .. } => Ok((base as _, bound as _)),
+        }
+    }
+
+    // Returns the offset of the handle if `len` bytes starting there
+    // are within its bounds
+    #[allow(dead_code)]
+    fn checked_offset(self, len: usize) -> Result<usize, Trap> {
+        let (base, bound) = self.segment_bounds()?;
+        let offset = self.segment_offset()?;
+        if offset < base || offset.checked_add(len).ok_or(Trap::OutOfBounds)? > bound {
+            Err(Trap::OutOfBounds)
+        } else {
+            Ok(offset)
         }
     }
 
     #[allow(dead_code)]
     fn to_bytes(self) -> ([u8; 8], Tag) {
-        match self {
-            Handle::Null { offset: 0 } => {
+        match self.repr {
+            HandleRepr::Null { offset } => {
+                // Null takes the one segment ID no segment gets (see
+                // `Segments::MAX_INDEX`). Its offset is stored inverted, so
+                // that plain null is all ones.
                 let mut res = [0u8; 8];
                 res[..4].copy_from_slice(&u32::MAX.to_ne_bytes());
-                res[4..].copy_from_slice(&u32::MAX.to_ne_bytes());
+                res[4..].copy_from_slice(&(!offset as u32).to_ne_bytes());
                 (res, Tag::Handle)
             }
-            Handle::Null { offset } => {
-                todo!("Trying to convert null with offset {} to bytes", offset)
-            }
-            Handle::Valid {
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
+                ..
             } => {
+                // Note: the bounds do not fit in the 8 bytes; the
+                // segment keeps them on the side (see
+                // `Segment::store_handle`)
                 let mut res = [0u8; 8];
                 res[..4].copy_from_slice(&base_segment_id.to_ne_bytes());
                 res[4..].copy_from_slice(&offset.to_ne_bytes());
                 (res, Tag::Handle)
             }
-            Handle::Corrupted { bytes } => (bytes, Tag::Data),
+            HandleRepr::Corrupted { bytes } => (bytes, Tag::Data),
         }
     }
 
     #[allow(dead_code)]
-    fn from_bytes(bytes: [u8; 8], tag: Tag) -> Self {
+    fn from_bytes(bytes: [u8; 8], tag: Tag, bounds: Option<(u32, u32)>) -> Self {
         if !tag.can_be_handle() {
-            Handle::Corrupted { bytes }
+            Handle {
+                repr: HandleRepr::Corrupted { bytes },
+            }
         } else {
             let base_segment_id = u32::from_ne_bytes(bytes[..4].try_into().unwrap());
             let offset = u32::from_ne_bytes(bytes[4..].try_into().unwrap());
             if base_segment_id == u32::MAX {
-                assert_eq!(offset, u32::MAX);
-                Handle::Null { offset: 0 }
+                Handle {
+                    repr: HandleRepr::Null {
+                        offset: !offset as i32,
+                    },
+                }
             } else {
-                Handle::Valid {
-                    base_segment_id,
-                    offset,
+                let (base, bound) = bounds.unwrap_or((0, Handle::SEGMENT_END));
+                Handle {
+                    repr: HandleRepr::Valid {
+                        base_segment_id,
+                        offset,
+                        base,
+                        bound,
+                    },
                 }
             }
         }
@@ -255,17 +693,19 @@ impl Handle {
 
     #[allow(dead_code)]
     fn is_eq(self, other: Self) -> bool {
-        match (self, other) {
-            (Handle::Null { offset: o1 }, Handle::Null { offset: o2 }) => o1 == o2,
-            (Handle::Corrupted { bytes: b1 }, Handle::Corrupted { bytes: b2 }) => b1 == b2,
+        match (self.repr, other.repr) {
+            (HandleRepr::Null { offset: o1 }, HandleRepr::Null { offset: o2 }) => o1 == o2,
+            (HandleRepr::Corrupted { bytes: b1 }, HandleRepr::Corrupted { bytes: b2 }) => b1 == b2,
             (
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i1,
                     offset: o1,
+                    ..
                 },
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i2,
                     offset: o2,
+                    ..
                 },
             ) => i1 == i2 && o1 == o2,
             _ => false,
@@ -273,69 +713,140 @@ impl Handle {
     }
 
     #[allow(dead_code)]
-    fn is_lt(self, other: Self) -> Option<bool> {
-        match (self, other) {
-            (Handle::Corrupted { .. }, _) | (_, Handle::Corrupted { .. }) => None,
-            (Handle::Null { offset: o1 }, Handle::Null { offset: o2 }) => Some(o1 < o2),
-            (Handle::Null { .. }, _) => Some(true),
-            (_, Handle::Null { .. }) => Some(false),
+    fn is_lt(self, other: Self) -> Result<bool, Trap> {
+        match (self.repr, other.repr) {
+            (HandleRepr::Corrupted { .. }, _) | (_, HandleRepr::Corrupted { .. }) => {
+                Err(Trap::CorruptedHandle)
+            }
+            (HandleRepr::Null { offset: o1 }, HandleRepr::Null { offset: o2 }) => Ok(o1 < o2),
+            (HandleRepr::Null { .. }, _) => Ok(true),
+            (_, HandleRepr::Null { .. }) => Ok(false),
             (
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i1,
                     offset: o1,
+                    ..
                 },
-                Handle::Valid {
+                HandleRepr::Valid {
                     base_segment_id: i2,
                     offset: o2,
+                    ..
                 },
-            ) => Some(i1 < i2 || (i1 == i2 && o1 < o2)),
+            ) => Ok(i1 < i2 || (i1 == i2 && o1 < o2)),
         }
     }
 }
 
 impl WasmModule {
     #[allow(dead_code)]
-    fn new_segment(&mut self, size: u32) -> Option<Handle> {
-        if size == 0 {
-            panic!("Trying to allocate 0 size segment. \
-                    It is easy to \"support\" this, but likely indicates something unexpected is happening, thus the panic.")
-        }
-        if self.segments.is_empty() {
-            // Use up the "0" segment, to prevent it from being used for a real segment
-            self.segments.push(Segment::Freed);
-        }
-        let id: u32 = self.segments.len().try_into().ok()?;
-        if id == u32::MAX {
-            // Filled up entire segment space, no more segments left
-            // to allocate. `u32::MAX` is reserved for the
-            // representation of `Handle::Null`.
-            return None;
-        }
-        self.segments.push(Segment::allocate(size));
-        Some(Handle::Valid {
-            base_segment_id: id,
-            offset: 0,
-        })
+    // A zero-sized segment, as `malloc(0)` asks for, is a segment like any
+    // other: it has an ID of its own and must be freed, but every access
+    // through it is out of bounds.
+    fn new_segment(&mut self, size: u32) -> Result<Handle, Trap> {
+        let id = match self.segments.allocate(size) {
+            Err(Trap::MemoryLimitExceeded)
+                if self.segments.limits.on_exceeded == OnMemoryLimit::ReturnNull =>
+            {
+                return Ok(Handle::NULL)
+            }
+            id => id?,
+        };
+        Ok(Handle::segment(id))
     }
 
     #[allow(dead_code)]
-    fn free_segment(&mut self, h: Handle) -> Option<()> {
-        match h {
-            Handle::Valid {
+    fn free_segment(&mut self, h: Handle) -> Result<(), Trap> {
+        match h.repr {
+            HandleRepr::Valid {
                 base_segment_id,
                 offset,
+                ..
             } => {
-                if offset == 0 {
-                    self.segments.get_mut(base_segment_id as usize)?.free();
-                    Some(())
+                // A sliced handle only grants access to part of the
+                // segment, so it cannot be used to free all of it
+                if offset == 0 && !h.is_sliced() {
+                    self.segments.free(base_segment_id)
                 } else {
-                    None
+                    Err(Trap::InteriorFree)
                 }
             }
-            Handle::Corrupted { .. } => None,
-            Handle::Null { .. } => None,
+            HandleRepr::Corrupted { .. } => Err(Trap::ForgedFree),
+            HandleRepr::Null { .. } => Err(Trap::NullFree),
         }
     }
+
+    // A memcpy that keeps handles, as a runtime intrinsic: handles copied
+    // whole to an aligned offset stay handles, bounds and all, and the
+    // ranges may overlap, as for memmove. A guest's own compiled memcpy
+    // copies bytes, so the handles it copies become data; none of the
+    // generated code calls this in its place.
+    #[allow(dead_code)]
+    fn memcpy(&mut self, dst: Handle, src: Handle, len: i32) -> Result<Handle, Trap> {
+        self.segments.copy(dst, src, len as u32 as usize)?;
+        Ok(dst)
+    }
+
+    // Limits are only checked as segments are allocated, so lowering one
+    // below what is live already does not free anything
+    #[allow(dead_code)]
+    pub fn set_memory_limits(&mut self, limits: MemoryLimits) {
+        self.segments.limits = limits;
+    }
+
+    // How many freed segment IDs are held back before they can be reused
+    #[allow(dead_code)]
+    pub fn set_segment_quarantine(&mut self, len: usize) {
+        self.segments.set_quarantine_len(len);
+    }
+
+    // Every generated function charges one unit of fuel on entry, and every
+    // loop one per iteration (see `checkpoint`), so that a metered guest
+    // always runs out eventually. Unmetered (`None`) by default.
+    #[allow(dead_code)]
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    // Tops up the fuel of a metered module
+    #[allow(dead_code)]
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // A handle other threads can stop the guest with
+    #[allow(dead_code)]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            flag: self.interrupt.clone(),
+        }
+    }
+
+    // Polled on entry to every generated function and at the top of every
+    // loop
+    #[inline]
+    fn checkpoint(&mut self) -> Result<(), Trap> {
+        // Plain load first, to keep the common case free of atomic writes.
+        // The interrupt is consumed, so the module can be called into again
+        // afterwards.
+        if self.interrupt.load(std::sync::atomic::Ordering::Relaxed)
+            && self
+                .interrupt
+                .swap(false, std::sync::atomic::Ordering::Relaxed)
+        {
+            return Err(Trap::Interrupted);
+        }
+        if let Some(left) = &mut self.fuel {
+            *left = left.checked_sub(1).ok_or(Trap::OutOfFuel)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(not(feature = "notags"))]
@@ -387,17 +898,48 @@ impl Tags {
     fn get(&self, tag_offset: usize) -> Option<Tag> {
         self.tags.get(tag_offset).cloned()
     }
+    #[must_use]
+    fn clear_range(&mut self, range: std::ops::Range<usize>) -> Option<()> {
+        self.tags.get_mut(range)?.fill(Tag::Data);
+        Some(())
+    }
+    fn any_handle_in_range(&self, range: std::ops::Range<usize>) -> Option<bool> {
+        Some(self.tags.get(range)?.iter().any(Tag::can_be_handle))
+    }
+    // Like `slice::copy_within`, or a copy from `src` if there is one, but
+    // out of bounds ranges are refused
+    #[must_use]
+    fn copy_range(
+        &mut self,
+        src: Option<&Tags>,
+        from: std::ops::Range<usize>,
+        to: usize,
+    ) -> Option<()> {
+        let to = to..to.checked_add(from.len())?;
+        match src {
+            None => {
+                self.tags.get(from.clone())?;
+                self.tags.get(to.clone())?;
+                self.tags.copy_within(from, to.start);
+            }
+            Some(src) => self.tags.get_mut(to)?.copy_from_slice(src.tags.get(from)?),
+        }
+        Some(())
+    }
 }
 
 #[cfg(feature = "packedtags")]
 struct Tags {
     packed_tags: Vec<u64>,
+    // Number of tags, which the range operations are checked against
+    len: usize,
 }
 #[cfg(feature = "packedtags")]
 impl Tags {
     fn new(tags_size: usize) -> Self {
         Self {
             packed_tags: vec![0u64; (tags_size + 63) / 64],
+            len: tags_size,
         }
     }
     #[must_use]
@@ -416,6 +958,113 @@ impl Tags {
             Some(Tag::Handle)
         }
     }
+
+    // Bits `start..end` of a word, for `start < end <= 64`
+    fn mask(start: usize, end: usize) -> u64 {
+        (!0u64 >> (64 - (end - start))) << start
+    }
+
+    fn check(&self, range: &std::ops::Range<usize>) -> Option<()> {
+        if range.start <= range.end && range.end <= self.len {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    // Calls `f` with the index and mask of each word `range` covers
+    fn for_words(range: std::ops::Range<usize>, mut f: impl FnMut(usize, u64) -> bool) {
+        let mut i = range.start;
+        while i < range.end {
+            let word = i / 64;
+            let end = (range.end - word * 64).min(64);
+            if !f(word, Tags::mask(i % 64, end)) {
+                return;
+            }
+            i = word * 64 + end;
+        }
+    }
+
+    #[must_use]
+    fn clear_range(&mut self, range: std::ops::Range<usize>) -> Option<()> {
+        self.check(&range)?;
+        let packed_tags = &mut self.packed_tags;
+        Tags::for_words(range, |word, mask| {
+            packed_tags[word] &= !mask;
+            true
+        });
+        Some(())
+    }
+
+    fn any_handle_in_range(&self, range: std::ops::Range<usize>) -> Option<bool> {
+        self.check(&range)?;
+        let mut any = false;
+        Tags::for_words(range, |word, mask| {
+            any = self.packed_tags[word] & mask != 0;
+            !any
+        });
+        Some(any)
+    }
+
+    // The `n <= 64` tags from `start` on, in the low bits
+    fn bits(&self, start: usize, n: usize) -> u64 {
+        let (word, shift) = (start / 64, start % 64);
+        let mut bits = self.packed_tags[word] >> shift;
+        if shift + n > 64 {
+            bits |= self.packed_tags[word + 1] << (64 - shift);
+        }
+        if n < 64 {
+            bits &= (1 << n) - 1;
+        }
+        bits
+    }
+
+    fn set_bits(&mut self, start: usize, n: usize, bits: u64) {
+        let (word, shift) = (start / 64, start % 64);
+        let first = n.min(64 - shift);
+        let mask = Tags::mask(shift, shift + first);
+        self.packed_tags[word] = (self.packed_tags[word] & !mask) | ((bits << shift) & mask);
+        if first < n {
+            let mask = Tags::mask(0, n - first);
+            self.packed_tags[word + 1] =
+                (self.packed_tags[word + 1] & !mask) | ((bits >> first) & mask);
+        }
+    }
+
+    // Like `slice::copy_within`, or a copy from `src` if there is one, but
+    // out of bounds ranges are refused. Moves up to 64 tags at a time, back
+    // to front if the ranges overlap that way.
+    #[must_use]
+    fn copy_range(
+        &mut self,
+        src: Option<&Tags>,
+        from: std::ops::Range<usize>,
+        to: usize,
+    ) -> Option<()> {
+        src.unwrap_or(self).check(&from)?;
+        let len = from.len();
+        self.check(&(to..to.checked_add(len)?))?;
+        let chunks = (0..len).step_by(64);
+        match src {
+            Some(src) => chunks.for_each(|offset| {
+                let n = (len - offset).min(64);
+                self.set_bits(to + offset, n, src.bits(from.start + offset, n));
+            }),
+            None => {
+                let mut copy = |offset: usize| {
+                    let n = (len - offset).min(64);
+                    let bits = self.bits(from.start + offset, n);
+                    self.set_bits(to + offset, n, bits);
+                };
+                if to <= from.start {
+                    chunks.for_each(&mut copy);
+                } else {
+                    chunks.rev().for_each(&mut copy);
+                }
+            }
+        }
+        Some(())
+    }
 }
 
 #[cfg(feature = "notags")]
@@ -433,78 +1082,373 @@ impl Tags {
     fn get(&self, _tag_offset: usize) -> Option<Tag> {
         Some(Tag {})
     }
+    fn clear_range(&mut self, _range: std::ops::Range<usize>) -> Option<()> {
+        Some(())
+    }
+    // Without tags any bytes may be a handle
+    fn any_handle_in_range(&self, range: std::ops::Range<usize>) -> Option<bool> {
+        Some(!range.is_empty())
+    }
+    fn copy_range(
+        &mut self,
+        _src: Option<&Tags>,
+        _from: std::ops::Range<usize>,
+        _to: usize,
+    ) -> Option<()> {
+        Some(())
+    }
 }
 
 #[allow(dead_code)]
 enum Segment {
     Freed,
-    Allocated { data: Vec<u8>, tags: Tags },
+    Allocated {
+        data: Vec<u8>,
+        tags: Tags,
+        // Bounds of the sliced handles stored in this segment, keyed
+        // by tag offset. They do not fit in the 8 bytes a handle takes
+        // up in memory, so they are kept on the side, much like the
+        // tags themselves.
+        bounds: BTreeMap<usize, (u32, u32)>,
+    },
 }
-type Segments = Vec<Segment>;
 
-#[allow(dead_code)]
-impl Segment {
-    fn free(&mut self) {
-        *self = Segment::Freed;
-    }
+// Caps on the segments a module may have live at once. `None` is unlimited,
+// which is the default.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryLimits {
+    // Total size of all live segments, in bytes
+    pub total_bytes: Option<u64>,
+    pub segments: Option<u32>,
+    // Size of any one segment, in bytes
+    pub segment_size: Option<u32>,
+    pub on_exceeded: OnMemoryLimit,
+}
 
-    fn allocate(size: u32) -> Self {
-        let size = size as usize;
-        let tag_size = size.checked_add(7).unwrap() / 8; // ceiling-divide by 8
-        Segment::Allocated {
-            data: vec![0u8; size],
-            tags: Tags::new(tag_size),
-        }
-    }
+// What `new_segment` does when it would go over a limit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnMemoryLimit {
+    // Trap with `Trap::MemoryLimitExceeded`
+    Trap,
+    // Hand the guest a null handle, as `malloc` returns null when out of
+    // memory
+    ReturnNull,
+}
 
-    fn get_data(&self) -> Option<&[u8]> {
-        match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, .. } => Some(data.as_ref()),
-        }
+impl Default for OnMemoryLimit {
+    fn default() -> Self {
+        OnMemoryLimit::Trap
+    }
+}
+
+// The segment table. A segment ID holds the index of its slot in the low
+// `INDEX_BITS` bits and the generation of the slot in the remaining ones.
+// Freed slots are put in quarantine, and only once they have aged out of it
+// are they reused, with the next generation. A stale handle to a reused slot
+// thus still traps, since its generation no longer matches.
+//
+// IDs are 32 bits, as they share the 8 bytes of a handle in memory with its
+// offset, and so the generations are only 8 bits: a slot is retired once it
+// has been handed out `MAX_GENERATION + 1` = 256 times. Over its lifetime an
+// instance can therefore allocate at most 2^24 * 2^8 = 2^32 segments, after
+// which `new_segment` traps with `Trap::SegmentsExhausted`, however few of
+// them are live.
+struct Segments {
+    slots: Vec<Slot>,
+    // Freed slots, oldest first
+    quarantine: VecDeque<u32>,
+    quarantine_len: usize,
+    // Slots that aged out of the quarantine
+    reusable: Vec<u32>,
+    limits: MemoryLimits,
+    live_bytes: u64,
+    live_count: u32,
+}
+
+struct Slot {
+    generation: u32,
+    segment: Segment,
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Slot {
+            generation: 0,
+            segment: Segment::Freed,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Segments {
+    const INDEX_BITS: u32 = 24;
+    const INDEX_MASK: u32 = (1 << Segments::INDEX_BITS) - 1;
+    // The last index is never handed out, so that no segment ID collides
+    // with the representation of a null handle (`u32::MAX`)
+    const MAX_INDEX: u32 = Segments::INDEX_MASK - 1;
+    // A slot whose generation runs out is retired rather than wrapped around
+    const MAX_GENERATION: u32 = u32::MAX >> Segments::INDEX_BITS;
+    const DEFAULT_QUARANTINE_LEN: usize = 1024;
+
+    fn new() -> Self {
+        Segments {
+            // Use up the "0" segment, to prevent it from being used for a real segment
+            slots: vec![Slot::default()],
+            quarantine: VecDeque::new(),
+            quarantine_len: Segments::DEFAULT_QUARANTINE_LEN,
+            reusable: vec![],
+            limits: MemoryLimits::default(),
+            live_bytes: 0,
+            live_count: 0,
+        }
+    }
+
+    fn split_id(id: u32) -> (usize, u32) {
+        (
+            (id & Segments::INDEX_MASK) as usize,
+            id >> Segments::INDEX_BITS,
+        )
+    }
+
+    // The index of the slot `h` refers to, provided it has not been reused
+    // since. This is on the path of every memory access, hence a single
+    // lookup for both the generation and the segment.
+    fn index(&self, h: Handle) -> Result<usize, Trap> {
+        let (index, generation) = Segments::split_id(h.segment_id()?);
+        match self.slots.get(index) {
+            Some(slot) if slot.generation == generation => Ok(index),
+            Some(_) => Err(Trap::SegmentFreed),
+            None => Err(Trap::CorruptedHandle),
+        }
+    }
+
+    fn get(&self, h: Handle) -> Result<&Segment, Trap> {
+        Ok(&self.slots[self.index(h)?].segment)
+    }
+
+    fn get_mut(&mut self, h: Handle) -> Result<&mut Segment, Trap> {
+        let index = self.index(h)?;
+        Ok(&mut self.slots[index].segment)
+    }
+
+    fn within_limits(&self, size: u32) -> bool {
+        let limits = &self.limits;
+        limits.segment_size.map_or(true, |max| size <= max)
+            && limits.segments.map_or(true, |max| self.live_count < max)
+            && limits
+                .total_bytes
+                .map_or(true, |max| self.live_bytes + size as u64 <= max)
+    }
+
+    fn allocate(&mut self, size: u32) -> Result<u32, Trap> {
+        if !self.within_limits(size) {
+            return Err(Trap::MemoryLimitExceeded);
+        }
+        let index = match self.reusable.pop() {
+            Some(index) => {
+                self.slots[index as usize].generation += 1;
+                index
+            }
+            None => {
+                let index = self.slots.len() as u32;
+                if index > Segments::MAX_INDEX {
+                    // Filled up entire segment space, no more segments
+                    // left to allocate
+                    return Err(Trap::SegmentsExhausted);
+                }
+                self.slots.push(Slot::default());
+                index
+            }
+        };
+        self.slots[index as usize].segment = Segment::allocate(size);
+        self.live_bytes += size as u64;
+        self.live_count += 1;
+        Ok(index | self.slots[index as usize].generation << Segments::INDEX_BITS)
+    }
+
+    fn free(&mut self, id: u32) -> Result<(), Trap> {
+        let (index, generation) = Segments::split_id(id);
+        match self.slots.get(index) {
+            Some(_) if index == 0 => return Err(Trap::ForgedFree),
+            None => return Err(Trap::ForgedFree),
+            // Freed, and the slot reused since
+            Some(slot) if slot.generation > generation => return Err(Trap::DoubleFree),
+            Some(slot) if slot.generation < generation => return Err(Trap::ForgedFree),
+            Some(slot) => {
+                if let Segment::Freed = slot.segment {
+                    return Err(Trap::DoubleFree);
+                }
+            }
+        }
+        if let Segment::Allocated { data, .. } = &self.slots[index].segment {
+            self.live_bytes -= data.len() as u64;
+            self.live_count -= 1;
+        }
+        self.slots[index].segment.free();
+        self.quarantine.push_back(index as u32);
+        self.age_quarantine();
+        Ok(())
+    }
+
+    // `memcpy` of `len` bytes from `src` to `dst`, see `Segment::copy_from`
+    fn copy(&mut self, dst: Handle, src: Handle, len: usize) -> Result<(), Trap> {
+        let from = src.checked_offset(len)?;
+        let to = dst.checked_offset(len)?;
+        let src_index = self.index(src)?;
+        let dst_index = self.index(dst)?;
+        if src_index == dst_index {
+            return self.get_mut(dst)?.copy_from(None, from, to, len);
+        }
+        // Taken out of its slot for the copy, to borrow both segments
+        let src_segment = std::mem::replace(&mut self.slots[src_index].segment, Segment::Freed);
+        let res = self
+            .get_mut(dst)
+            .and_then(|segment| segment.copy_from(Some(&src_segment), from, to, len));
+        self.slots[src_index].segment = src_segment;
+        res
+    }
+
+    // IDs of the allocated segments
+    fn live(&self) -> impl Iterator<Item = u32> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| matches!(slot.segment, Segment::Allocated { .. }))
+            .map(|(index, slot)| index as u32 | slot.generation << Segments::INDEX_BITS)
+    }
+
+    fn set_quarantine_len(&mut self, len: usize) {
+        self.quarantine_len = len;
+        self.age_quarantine();
+    }
+
+    fn age_quarantine(&mut self) {
+        while self.quarantine.len() > self.quarantine_len {
+            let index = self.quarantine.pop_front().unwrap();
+            if self.slots[index as usize].generation < Segments::MAX_GENERATION {
+                self.reusable.push(index);
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Segment {
+    fn free(&mut self) {
+        *self = Segment::Freed;
+    }
+
+    fn allocate(size: u32) -> Self {
+        let size = size as usize;
+        let tag_size = size.checked_add(7).unwrap() / 8; // ceiling-divide by 8
+        Segment::Allocated {
+            data: vec![0u8; size],
+            tags: Tags::new(tag_size),
+            bounds: BTreeMap::new(),
+        }
+    }
+
+    fn get_data(&self) -> Result<&[u8], Trap> {
+        match self {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, .. } => Ok(data.as_ref()),
+        }
+    }
+
+    // Restricts the data to what is reachable through `h`. The
+    // returned slice is still indexed by segment offset, so it is cut
+    // at the bound of the handle, and the offset of the handle is
+    // checked against its base. Inlined into the loads and stores of the
+    // generated code, like the other accessors below.
+    #[inline(always)]
+    fn get_data_within(&self, h: Handle) -> Result<&[u8], Trap> {
+        let data = self.get_data()?;
+        let (base, bound) = h.segment_bounds()?;
+        if h.segment_offset()? < base {
+            Err(Trap::OutOfBounds)
+        } else {
+            Ok(&data[..bound.min(data.len())])
+        }
     }
 
-    fn len(&self) -> Option<usize> {
+    fn len(&self) -> Result<usize, Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, .. } => Some(data.len()),
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, .. } => Ok(data.len()),
         }
     }
 
     // Performs the necessary type conversion at write time as
     // described in the MS-Wasm position paper
-    fn get_mut_data(&mut self, update_offset: usize) -> Option<&mut [u8]> {
+    #[inline(always)]
+    fn get_mut_data(&mut self, update_offset: usize) -> Result<&mut [u8], Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
-                tags.update(update_offset / 8, Tag::Data)?;
-                Some(data.as_mut())
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                tags.update(update_offset / 8, Tag::Data)
+                    .ok_or(Trap::OutOfBounds)?;
+                // Bounds of a sliced handle overwritten. Most segments hold
+                // none, and this is on the path of every store.
+                if !bounds.is_empty() {
+                    bounds.remove(&(update_offset / 8));
+                }
+                Ok(data.as_mut())
             }
         }
     }
 
-    fn get_mut_data_slice(&mut self, start: usize, end: usize) -> Option<&mut [u8]> {
+    // Same as `get_data_within`, for writes through `h`
+    #[inline(always)]
+    fn get_mut_data_within(&mut self, h: Handle) -> Result<&mut [u8], Trap> {
+        let (base, bound) = h.segment_bounds()?;
+        let offset = h.segment_offset()?;
+        if offset < base {
+            return Err(Trap::OutOfBounds);
+        }
+        let data = self.get_mut_data(offset)?;
+        let bound = bound.min(data.len());
+        Ok(&mut data[..bound])
+    }
+
+    // The bytes `start..end` are about to be written as data, so their tags
+    // are cleared
+    fn get_mut_data_slice(&mut self, start: usize, end: usize) -> Result<&mut [u8], Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
-                for i in start / 8..end / 8 {
-                    tags.update(i, Tag::Data)?;
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                let range = start / 8..(end + 7) / 8;
+                // Only handles have tags to clear, or bounds
+                if tags
+                    .any_handle_in_range(range.clone())
+                    .ok_or(Trap::OutOfBounds)?
+                {
+                    tags.clear_range(range.clone()).ok_or(Trap::OutOfBounds)?;
+                    // Bounds of the sliced handles overwritten
+                    let stale: Vec<usize> = bounds.range(range).map(|(&k, _)| k).collect();
+                    for k in stale {
+                        bounds.remove(&k);
+                    }
                 }
-                Some(data.as_mut())
+                Ok(data.as_mut())
             }
         }
     }
 
-    fn get_handle(&self, offset: usize) -> Option<Handle> {
+    #[inline(always)]
+    fn get_handle(&self, offset: usize) -> Result<Handle, Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
                 if offset % 8 != 0 {
-                    None
+                    Err(Trap::MisalignedHandleAccess)
                 } else {
-                    Some(Handle::from_bytes(
-                        data.get(offset..offset + 8)?.try_into().ok()?,
-                        tags.get(offset / 8)?,
+                    Ok(Handle::from_bytes(
+                        data.get(offset..offset + 8)
+                            .ok_or(Trap::OutOfBounds)?
+                            .try_into()
+                            .unwrap(),
+                        tags.get(offset / 8).ok_or(Trap::OutOfBounds)?,
+                        bounds.get(&(offset / 8)).cloned(),
                     ))
                 }
             }
@@ -513,71 +1457,218 @@ impl Segment {
 
     // Performs the necessary type conversion at write time as
     // described in the MS-Wasm position paper
-    fn store_handle(&mut self, offset: usize, handle: Handle) -> Option<()> {
+    fn store_handle(&mut self, offset: usize, handle: Handle) -> Result<(), Trap> {
         match self {
-            Segment::Freed => None,
-            Segment::Allocated { data, tags } => {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                if offset % 8 != 0 {
+                    return Err(Trap::MisalignedHandleAccess);
+                }
                 let (bytes, tag) = handle.to_bytes();
-                data.get_mut(offset..offset + 8)?.copy_from_slice(&bytes);
-                tags.update(offset / 8, tag)?;
-                Some(())
+                data.get_mut(offset..offset + 8)
+                    .ok_or(Trap::OutOfBounds)?
+                    .copy_from_slice(&bytes);
+                tags.update(offset / 8, tag).ok_or(Trap::OutOfBounds)?;
+                match handle.repr {
+                    HandleRepr::Valid { base, bound, .. } if handle.is_sliced() => {
+                        bounds.insert(offset / 8, (base, bound));
+                    }
+                    _ => {
+                        bounds.remove(&(offset / 8));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Copies `len` bytes from `from` in `src`, or in this segment if it is
+    // `None`, to `to` in this segment. Handles copied whole to an offset as
+    // aligned stay handles, bounds and all; everything else written becomes
+    // data, as it would through a copy byte by byte.
+    fn copy_from(
+        &mut self,
+        src: Option<&Segment>,
+        from: usize,
+        to: usize,
+        len: usize,
+    ) -> Result<(), Trap> {
+        // The granules copied whole, if any
+        let whole = if from % 8 == 0 && to % 8 == 0 {
+            from / 8..(from + len) / 8
+        } else {
+            from / 8..from / 8
+        };
+        let (handles, moved) = match src.unwrap_or(self) {
+            Segment::Freed => return Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => {
+                data.get(from..from + len).ok_or(Trap::OutOfBounds)?;
+                let handles = !whole.is_empty()
+                    && tags
+                        .any_handle_in_range(whole.clone())
+                        .ok_or(Trap::OutOfBounds)?;
+                let moved: Vec<(usize, (u32, u32))> = bounds
+                    .range(whole.clone())
+                    .map(|(&k, &v)| (k - whole.start + to / 8, v))
+                    .collect();
+                (handles, moved)
             }
+        };
+        let (data, tags, bounds) = match self {
+            Segment::Freed => return Err(Trap::SegmentFreed),
+            Segment::Allocated { data, tags, bounds } => (data, tags, bounds),
+        };
+        data.get(to..to + len).ok_or(Trap::OutOfBounds)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let written = to / 8..(to + len + 7) / 8;
+        let mut cleared = written.clone();
+        if handles {
+            let src_tags = src.map(Segment::tags).transpose()?;
+            tags.copy_range(src_tags, whole.clone(), to / 8)
+                .ok_or(Trap::OutOfBounds)?;
+            cleared.start += whole.len();
+        }
+        tags.clear_range(cleared).ok_or(Trap::OutOfBounds)?;
+        let stale: Vec<usize> = bounds.range(written).map(|(&k, _)| k).collect();
+        for k in stale {
+            bounds.remove(&k);
+        }
+        bounds.extend(moved);
+        match src {
+            None => data.copy_within(from..from + len, to),
+            Some(src) => data[to..to + len].copy_from_slice(&src.get_data()?[from..from + len]),
+        }
+        Ok(())
+    }
+
+    fn tags(&self) -> Result<&Tags, Trap> {
+        match self {
+            Segment::Freed => Err(Trap::SegmentFreed),
+            Segment::Allocated { tags, .. } => Ok(tags),
         }
     }
 }
 
-fn with_collected_memory_0<T, U: Into<Option<T>>>(
+fn with_collected_memory_0<T>(
     _segments: &mut Segments,
-    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper) -> U,
-) -> Option<T> {
-    f(&guest_mem_wrapper::GuestMemWrapper::from(&mut [])).into()
+    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper) -> T,
+) -> Result<T, Trap> {
+    Ok(f(&guest_mem_wrapper::GuestMemWrapper::from(&mut [])))
 }
 
-fn with_collected_memory_1<T, U: Into<Option<T>>>(
+// Exposes the segment `h0` points into as the callee's memory. The callee
+// may write the `written` bytes at `h0`, which are bounds checked and
+// turned into data up front.
+fn with_collected_memory_1<T>(
     segments: &mut Segments,
     h0: Handle,
-    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper, i32) -> U,
-) -> Option<T> {
-    let seg = segments.get_mut(h0.segment_index()?)?;
+    written: usize,
+    f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper, i32) -> T,
+) -> Result<T, Trap> {
+    let (base, bound) = h0.segment_bounds()?;
+    let offset = h0.segment_offset()?;
+    if offset < base {
+        return Err(Trap::OutOfBounds);
+    }
+    if written > 0 {
+        h0.checked_offset(written)?;
+    }
+    let seg = segments.get_mut(h0)?;
+    let data = seg.get_mut_data_slice(offset, offset + written)?;
+    if offset + written > data.len() {
+        return Err(Trap::OutOfBounds);
+    }
+    let bound = bound.min(data.len());
     let res = f(
-        &guest_mem_wrapper::GuestMemWrapper::from(seg.get_mut_data_slice(0, 0)?),
-        h0.segment_offset()?.try_into().ok()?,
-    )
-    .into()?;
-    Some(res)
+        &guest_mem_wrapper::GuestMemWrapper::from(&mut data[..bound]),
+        offset.try_into().or(Err(Trap::OutOfBounds))?,
+    );
+    Ok(res)
 }
 
+// Loads and stores. Generated function bodies use the forms ending in
+// `at <code offset>`, which take the handle as the result of adding the
+// memarg offset to it and turn any trap into a `Fault` at that offset.
 macro_rules! write {
+    ($kind:ident, $segments:expr, $handle:expr, $val:expr, at $code_offset:expr) => {{
+        let handle = $handle.at($code_offset)?;
+        write!(@$kind, $segments, handle, $val, |trap| Fault::new(trap, $code_offset))
+    }};
     (store_handle, $segments:expr, $handle:expr, $val:expr) => {
-        $segments
-            .get_mut($handle.segment_index()?)?
-            .store_handle($handle.segment_offset()?, $val)?;
+        write!(@store_handle, $segments, $handle, $val, std::convert::identity)
     };
+    (bytes, $segments:expr, $handle:expr, $len:expr) => {{
+        let offset = $handle.checked_offset($len)?;
+        $segments
+            .get_mut($handle)?
+            .get_mut_data_slice(offset, offset + $len)?
+            .get_mut(offset..offset + $len)
+            .ok_or(Trap::OutOfBounds)?
+    }};
     ($writefn:ident, $segments:expr, $handle:expr, $val:expr) => {
+        write!(@$writefn, $segments, $handle, $val, std::convert::identity)
+    };
+    (@store_handle, $segments:expr, $handle:expr, $val:expr, $err:expr) => {
+        $segments
+            .get_mut($handle)
+            .map_err($err)?
+            .store_handle($handle.checked_offset(8).map_err($err)?, $val)
+            .map_err($err)?
+    };
+    (@$writefn:ident, $segments:expr, $handle:expr, $val:expr, $err:expr) => {
         $writefn(
             $segments
-                .get_mut($handle.segment_index()?)?
-                .get_mut_data($handle.segment_offset()?)?,
-            ($handle.segment_offset()?) as usize,
+                .get_mut($handle)
+                .map_err($err)?
+                .get_mut_data_within($handle)
+                .map_err($err)?,
+            $handle.segment_offset().map_err($err)?,
             $val,
-        )?;
+        )
+        .ok_or(Trap::OutOfBounds)
+        .map_err($err)?
     };
 }
 
 macro_rules! read {
+    ($kind:ident, $segments:expr, $handle:expr, at $code_offset:expr) => {{
+        let handle = $handle.at($code_offset)?;
+        read!(@$kind, $segments, handle, |trap| Fault::new(trap, $code_offset))
+    }};
     (get_handle, $segments:expr, $handle:expr) => {
+        read!(@get_handle, $segments, $handle, std::convert::identity)
+    };
+    (bytes, $segments:expr, $handle:expr, $len:expr) => {{
+        let offset = $handle.checked_offset($len)?;
         $segments
-            .get($handle.segment_index()?)?
-            .get_handle($handle.segment_offset()?)?
+            .get($handle)?
+            .get_data()?
+            .get(offset..offset + $len)
+            .ok_or(Trap::OutOfBounds)?
+    }};
+    ($readfn:ident, $segments:expr, $handle:expr) => {
+        read!(@$readfn, $segments, $handle, std::convert::identity)
     };
-    (bytes, $segments:expr, $handle:expr, $len:expr) => {
-        &$segments.get($handle.segment_index()?)?.get_data()?[$handle.segment_offset()?..][..$len]
+    (@get_handle, $segments:expr, $handle:expr, $err:expr) => {
+        $segments
+            .get($handle)
+            .map_err($err)?
+            .get_handle($handle.checked_offset(8).map_err($err)?)
+            .map_err($err)?
     };
-    ($readfn:ident, $segments:expr, $handle:expr) => {
+    (@$readfn:ident, $segments:expr, $handle:expr, $err:expr) => {
         $readfn(
-            $segments.get($handle.segment_index()?)?.get_data()?,
-            ($handle.segment_offset()?) as usize,
-        )?
+            $segments
+                .get($handle)
+                .map_err($err)?
+                .get_data_within($handle)
+                .map_err($err)?,
+            $handle.segment_offset().map_err($err)?,
+        )
+        .ok_or(Trap::OutOfBounds)
+        .map_err($err)?
     };
 }
 
@@ -586,6 +1677,234 @@ mod ms_wasm_wasi {
     use super::*;
     use wasi_common::wasi::wasi_snapshot_preview1;
 
+    // An iovec or ciovec in guest memory: the buffer handle, then its 32-bit
+    // length, padded to 16 bytes
+    const IOVEC_SIZE: i32 = 8 + 8;
+
+    // What wasi-common returns when wiggle cannot borrow guest memory
+    pub(super) const ERRNO_FAULT: i32 = 21;
+
+    // Whether any two non-empty buffers of the guest's iovec array overlap.
+    // wiggle borrows all the buffers of an `fd_read` at once, so it refuses
+    // them; copying them in to `Scratch` would hide that.
+    fn iovecs_overlap(segments: &Segments, iovs: Handle, iovs_len: i32) -> Result<bool, Trap> {
+        let mut bufs = vec![];
+        for i in 0..iovs_len {
+            let loc = read!(get_handle, segments, iovs.add(i * IOVEC_SIZE + 0)?);
+            let len = read!(read_mem_u32, segments, iovs.add(i * IOVEC_SIZE + 8)?) as usize;
+            if len > 0 {
+                bufs.push((loc.segment_id()?, loc.segment_offset()?, len));
+            }
+        }
+        bufs.sort();
+        Ok(bufs
+            .windows(2)
+            .any(|w| w[0].0 == w[1].0 && w[0].1 + w[0].2 > w[1].1))
+    }
+
+    // Stand-in linear memory for calls whose pointers may lead into several
+    // segments. Guest buffers are copied in, and whatever the callee wrote is
+    // copied back out with `copy_out`.
+    struct Scratch {
+        memory: Vec<u8>,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            Scratch { memory: vec![] }
+        }
+
+        // Reserves `len` zeroed bytes, 8-aligned so that any type wiggle
+        // reads or writes there is aligned
+        fn alloc(&mut self, len: usize) -> i32 {
+            let addr = (self.memory.len() + 7) & !7;
+            self.memory.resize(addr + len, 0);
+            addr as i32
+        }
+
+        // Copies `len` bytes at `h` in. Also used for output buffers, so
+        // that they are checked before the callee has any side effects.
+        fn copy_in(&mut self, segments: &Segments, h: Handle, len: usize) -> Result<i32, Trap> {
+            if len == 0 {
+                return Ok(self.alloc(0));
+            }
+            let bytes = read!(bytes, segments, h, len);
+            let addr = self.alloc(len);
+            self.memory[addr as usize..][..len].copy_from_slice(bytes);
+            Ok(addr)
+        }
+
+        // Copies the `len` bytes at `addr` back out to `h`, as data
+        fn copy_out(
+            &self,
+            segments: &mut Segments,
+            addr: i32,
+            h: Handle,
+            len: usize,
+        ) -> Result<(), Trap> {
+            if len == 0 {
+                return Ok(());
+            }
+            write!(bytes, segments, h, len).copy_from_slice(&self.memory[addr as usize..][..len]);
+            Ok(())
+        }
+
+        // Copies in the buffers of the guest's iovec array at `iovs` and lays
+        // out a wasm32 iovec array over the copies. Returns its address and
+        // the guest buffers along with the address of each copy.
+        fn copy_in_iovecs(
+            &mut self,
+            segments: &Segments,
+            iovs: Handle,
+            iovs_len: i32,
+        ) -> Result<(i32, Vec<(Handle, usize, i32)>), Trap> {
+            let mut bufs = vec![];
+            for i in 0..iovs_len {
+                let loc = read!(get_handle, segments, iovs.add(i * IOVEC_SIZE + 0)?);
+                let len = read!(read_mem_u32, segments, iovs.add(i * IOVEC_SIZE + 8)?) as usize;
+                bufs.push((loc, len, self.copy_in(segments, loc, len)?));
+            }
+            let array = self.alloc(8 * bufs.len());
+            for (i, &(_, len, addr)) in bufs.iter().enumerate() {
+                let entry = array as usize + 8 * i;
+                write_mem_i32(&mut self.memory, entry + 0, addr).ok_or(Trap::OutOfBounds)?;
+                write_mem_u32(&mut self.memory, entry + 4, len as u32).ok_or(Trap::OutOfBounds)?;
+            }
+            Ok((array, bufs))
+        }
+
+        // Copies the first `n` bytes the callee read into the iovec buffers
+        // back out to the guest
+        fn copy_out_iovecs(
+            &self,
+            segments: &mut Segments,
+            bufs: &[(Handle, usize, i32)],
+            mut n: usize,
+        ) -> Result<(), Trap> {
+            for &(loc, len, addr) in bufs {
+                let len = len.min(n);
+                self.copy_out(segments, addr, loc, len)?;
+                n -= len;
+            }
+            Ok(())
+        }
+
+        fn read_u32(&self, addr: i32) -> Result<u32, Trap> {
+            read_mem_u32(&self.memory, addr as usize).ok_or(Trap::OutOfBounds)
+        }
+
+        fn guest_memory(&mut self) -> guest_mem_wrapper::GuestMemWrapper<'_> {
+            guest_mem_wrapper::GuestMemWrapper::from(&mut self.memory)
+        }
+    }
+
+    // The buffers of a guest iovec array, when they all lie in one segment
+    // without overlapping: the segment, and the offset and length of each
+    // buffer in it. wiggle is then handed the segment's data itself instead
+    // of copies of the buffers. Calls with buffers spread over several
+    // segments, or filling theirs, go through `Scratch`.
+    struct SegmentIovecs {
+        segment: Handle,
+        bufs: Vec<(usize, usize)>,
+        // Offset of a gap around the buffers with room for a wasm32 iovec
+        // array over them and the result of the call
+        tail: usize,
+    }
+
+    impl SegmentIovecs {
+        fn find(segments: &Segments, iovs: Handle, iovs_len: i32) -> Result<Option<Self>, Trap> {
+            let mut segment: Option<(Handle, usize)> = None;
+            let mut bufs = vec![];
+            for i in 0..iovs_len {
+                let loc = read!(get_handle, segments, iovs.add(i * IOVEC_SIZE + 0)?);
+                let len = read!(read_mem_u32, segments, iovs.add(i * IOVEC_SIZE + 8)?) as usize;
+                if len == 0 {
+                    bufs.push((0, 0));
+                    continue;
+                }
+                // Traps wherever copying the buffer in would
+                read!(bytes, segments, loc, len);
+                match segment {
+                    None => segment = Some((loc, segments.get(loc)?.get_data()?.len())),
+                    Some((h, _)) if h.segment_id()? == loc.segment_id()? => {}
+                    Some(_) => return Ok(None),
+                }
+                bufs.push((loc.segment_offset()?, len));
+            }
+            let (segment, data_len) = match segment {
+                Some(segment) => segment,
+                None => return Ok(None),
+            };
+            let mut sorted: Vec<_> = bufs.iter().copied().filter(|&(_, len)| len > 0).collect();
+            sorted.sort();
+            if sorted.windows(2).any(|w| w[0].0 + w[0].1 > w[1].0) {
+                // wiggle would refuse to borrow them all at once
+                return Ok(None);
+            }
+            let tail_len = 8 * bufs.len() + 4;
+            let mut gap_start = 0;
+            for (offset, len) in sorted.into_iter().chain(Some((data_len, 0))) {
+                let tail = (gap_start + 3) & !3;
+                if tail + tail_len <= offset {
+                    return Ok(Some(SegmentIovecs {
+                        segment,
+                        bufs,
+                        tail,
+                    }));
+                }
+                gap_start = offset + len;
+            }
+            Ok(None)
+        }
+
+        // Calls `f` with the segment as guest memory, the address of a wasm32
+        // iovec array over the buffers and that of a `u32` for the result of
+        // the call. Both are laid out at `tail` for the duration of the call,
+        // with the guest's bytes there set aside and put back afterwards, so
+        // that the segment never grows. Returns what `f` returned and the
+        // result.
+        fn call(
+            &self,
+            segments: &mut Segments,
+            f: impl FnOnce(&guest_mem_wrapper::GuestMemWrapper, i32, i32) -> i32,
+        ) -> Result<(i32, u32), Trap> {
+            let data = match segments.get_mut(self.segment)? {
+                Segment::Allocated { data, .. } => data,
+                Segment::Freed => return Err(Trap::SegmentFreed),
+            };
+            let array = self.tail;
+            let result = array + 8 * self.bufs.len();
+            let tail = array..result + 4;
+            let saved = data.get(tail.clone()).ok_or(Trap::OutOfBounds)?.to_vec();
+            for (i, &(offset, len)) in self.bufs.iter().enumerate() {
+                write_mem_u32(data, array + 8 * i + 0, offset as u32).ok_or(Trap::OutOfBounds)?;
+                write_mem_u32(data, array + 8 * i + 4, len as u32).ok_or(Trap::OutOfBounds)?;
+            }
+            write_mem_u32(data, result, 0).ok_or(Trap::OutOfBounds)?;
+            let res = f(
+                &guest_mem_wrapper::GuestMemWrapper::from(&mut data[..]),
+                array as i32,
+                result as i32,
+            );
+            let n = read_mem_u32(data, result).ok_or(Trap::OutOfBounds)?;
+            data[tail].copy_from_slice(&saved);
+            Ok((res, n))
+        }
+
+        // The callee read `n` bytes into the buffers, which are data now
+        fn clear_tags(&self, segments: &mut Segments, mut n: usize) -> Result<(), Trap> {
+            let segment = segments.get_mut(self.segment)?;
+            for &(offset, len) in &self.bufs {
+                let len = len.min(n);
+                if len > 0 {
+                    segment.get_mut_data_slice(offset, offset + len)?;
+                }
+                n -= len;
+            }
+            Ok(())
+        }
+    }
+
     #[allow(dead_code)]
     // args_get(argv: Pointer<Pointer<u8>>, argv_buf: Pointer<u8>) -> Result<(), errno>
     pub(super) fn args_get(
@@ -593,7 +1912,7 @@ mod ms_wasm_wasi {
         segments: &mut Segments,
         arg0: Handle,
         arg1: Handle,
-    ) -> Option<i32> {
+    ) -> Result<i32, Trap> {
         let (argv_count, argv_buf_len) = {
             let mut memory = [0u8; 4 + 4];
             wasi_snapshot_preview1::args_sizes_get(
@@ -602,7 +1921,10 @@ mod ms_wasm_wasi {
                 0,
                 4,
             );
-            (read_mem_u32(&memory, 0)?, read_mem_u32(&memory, 4)?)
+            (
+                read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?,
+                read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?,
+            )
         };
 
         let argv_buf_start = (argv_count + 1) * 4;
@@ -619,19 +1941,19 @@ mod ms_wasm_wasi {
                 store_handle,
                 segments,
                 arg0.add(i as i32 * 8)?,
-                arg1.add(read_mem_i32(&memory, i as usize * 4)? - argv_buf_start as i32)?
+                arg1.add(
+                    read_mem_i32(&memory, i as usize * 4).ok_or(Trap::OutOfBounds)?
+                        - argv_buf_start as i32
+                )?
             );
         }
-        for i in 0..argv_buf_len {
-            write!(
-                write_mem_u8,
-                segments,
-                arg1.add(i as i32)?,
-                read_mem_u8(&memory, (argv_buf_start + i) as usize)?
-            );
+        if argv_buf_len > 0 {
+            let len = argv_buf_len as usize;
+            write!(bytes, segments, arg1, len)
+                .copy_from_slice(&memory[argv_buf_start as usize..][..len]);
         }
 
-        Some(res)
+        Ok(res)
     }
 
     #[allow(dead_code)]
@@ -641,7 +1963,7 @@ mod ms_wasm_wasi {
         segments: &mut Segments,
         arg0: Handle,
         arg1: Handle,
-    ) -> Option<i32> {
+    ) -> Result<i32, Trap> {
         let mut memory = [0u8; 4 + 4];
         let res = wasi_snapshot_preview1::args_sizes_get(
             ctx,
@@ -650,13 +1972,27 @@ mod ms_wasm_wasi {
             4,
         );
 
-        let arg0_res = read_mem_u32(&memory, 0)?;
-        let arg1_res = read_mem_u32(&memory, 4)?;
+        let arg0_res = read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?;
+        let arg1_res = read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?;
 
         write!(write_mem_u32, segments, arg0, arg0_res);
         write!(write_mem_u32, segments, arg1, arg1_res);
 
-        Some(res)
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // clock_res_get(id: clockid) -> Result<timestamp, errno>
+    pub(super) fn clock_res_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 8, |mem, arg1| {
+            wasi_snapshot_preview1::clock_res_get(ctx, mem, arg0, arg1)
+        })
     }
 
     #[allow(dead_code)]
@@ -667,117 +2003,1300 @@ mod ms_wasm_wasi {
         arg0: i32,
         arg1: i64,
         arg2: Handle,
-    ) -> Option<i32> {
+    ) -> Result<i32, Trap> {
         // No internal pointers, just pass through directly
-        with_collected_memory_1(segments, arg2, |mem, arg2| {
+        with_collected_memory_1(segments, arg2, 8, |mem, arg2| {
             wasi_snapshot_preview1::clock_time_get(ctx, mem, arg0, arg1, arg2)
         })
     }
 
     #[allow(dead_code)]
-    // fd_close(fd: fd) -> Result<(), errno>
-    pub(super) fn fd_close(
+    // environ_get(environ: Pointer<Pointer<u8>>, environ_buf: Pointer<u8>) -> Result<(), errno>
+    pub(super) fn environ_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: Handle,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        let (environ_count, environ_buf_len) = {
+            let mut memory = [0u8; 4 + 4];
+            wasi_snapshot_preview1::environ_sizes_get(
+                ctx,
+                &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+                0,
+                4,
+            );
+            (
+                read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?,
+                read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?,
+            )
+        };
+
+        let environ_buf_start = (environ_count + 1) * 4;
+        let mut memory: Vec<u8> = vec![0u8; (environ_buf_start + (environ_buf_len + 1)) as usize];
+        let res = wasi_snapshot_preview1::environ_get(
+            ctx,
+            &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+            0,
+            environ_buf_start as i32,
+        );
+
+        for i in 0..environ_count {
+            write!(
+                store_handle,
+                segments,
+                arg0.add(i as i32 * 8)?,
+                arg1.add(
+                    read_mem_i32(&memory, i as usize * 4).ok_or(Trap::OutOfBounds)?
+                        - environ_buf_start as i32
+                )?
+            );
+        }
+        if environ_buf_len > 0 {
+            let len = environ_buf_len as usize;
+            write!(bytes, segments, arg1, len)
+                .copy_from_slice(&memory[environ_buf_start as usize..][..len]);
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // environ_sizes_get() -> Result<(size, size), errno>
+    pub(super) fn environ_sizes_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: Handle,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        let mut memory = [0u8; 4 + 4];
+        let res = wasi_snapshot_preview1::environ_sizes_get(
+            ctx,
+            &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
+            0,
+            4,
+        );
+
+        let arg0_res = read_mem_u32(&memory, 0).ok_or(Trap::OutOfBounds)?;
+        let arg1_res = read_mem_u32(&memory, 4).ok_or(Trap::OutOfBounds)?;
+
+        write!(write_mem_u32, segments, arg0, arg0_res);
+        write!(write_mem_u32, segments, arg1, arg1_res);
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_advise(fd: fd, offset: filesize, len: filesize, advice: advice) -> Result<(), errno>
+    pub(super) fn fd_advise(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         arg0: i32,
-    ) -> Option<i32> {
+        arg1: i64,
+        arg2: i64,
+        arg3: i32,
+    ) -> Result<i32, Trap> {
         // No pointers, just pass through directly
         with_collected_memory_0(segments, |mem| {
-            wasi_snapshot_preview1::fd_close(ctx, mem, arg0)
+            wasi_snapshot_preview1::fd_advise(ctx, mem, arg0, arg1, arg2, arg3)
         })
     }
 
     #[allow(dead_code)]
-    // fd_fdstat_get(fd: fd) -> Result<fdstat, errno>
-    pub(super) fn fd_fdstat_get(
+    // fd_allocate(fd: fd, offset: filesize, len: filesize) -> Result<(), errno>
+    pub(super) fn fd_allocate(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         arg0: i32,
-        arg1: Handle,
-    ) -> Option<i32> {
-        // No internal pointers, just pass through directly
-        with_collected_memory_1(segments, arg1, |mem, arg1| {
-            wasi_snapshot_preview1::fd_fdstat_get(ctx, mem, arg0, arg1)
+        arg1: i64,
+        arg2: i64,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_allocate(ctx, mem, arg0, arg1, arg2)
         })
     }
 
     #[allow(dead_code)]
-    // fd_seek(fd: fd, offset: filedelta, whence: whence) -> Result<filesize, errno>
-    pub(super) fn fd_seek(
+    // fd_close(fd: fd) -> Result<(), errno>
+    pub(super) fn fd_close(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         arg0: i32,
-        arg1: i64,
-        arg2: i32,
-        arg3: Handle,
-    ) -> Option<i32> {
-        // No internal pointers, just pass through directly
-        with_collected_memory_1(segments, arg3, |mem, arg3| {
-            wasi_snapshot_preview1::fd_seek(ctx, mem, arg0, arg1, arg2, arg3)
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_close(ctx, mem, arg0)
         })
     }
 
     #[allow(dead_code)]
-    // fd_write(fd: fd, iovs: ciovec_array) -> Result<size, errno>
-    pub(super) fn fd_write(
+    // fd_datasync(fd: fd) -> Result<(), errno>
+    pub(super) fn fd_datasync(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_datasync(ctx, mem, arg0)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_fdstat_get(fd: fd) -> Result<fdstat, errno>
+    pub(super) fn fd_fdstat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 24, |mem, arg1| {
+            wasi_snapshot_preview1::fd_fdstat_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_fdstat_set_flags(fd: fd, flags: fdflags) -> Result<(), errno>
+    pub(super) fn fd_fdstat_set_flags(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_fdstat_set_flags(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_fdstat_set_rights(fd: fd, fs_rights_base: rights, fs_rights_inheriting: rights) -> Result<(), errno>
+    pub(super) fn fd_fdstat_set_rights(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i64,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_fdstat_set_rights(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_filestat_get(fd: fd) -> Result<filestat, errno>
+    pub(super) fn fd_filestat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 64, |mem, arg1| {
+            wasi_snapshot_preview1::fd_filestat_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_filestat_set_size(fd: fd, size: filesize) -> Result<(), errno>
+    pub(super) fn fd_filestat_set_size(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_filestat_set_size(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_filestat_set_times(fd: fd, atim: timestamp, mtim: timestamp, fst_flags: fstflags) -> Result<(), errno>
+    pub(super) fn fd_filestat_set_times(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i64,
+        arg3: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_filestat_set_times(ctx, mem, arg0, arg1, arg2, arg3)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_pread(fd: fd, iovs: iovec_array, offset: filesize) -> Result<size, errno>
+    pub(super) fn fd_pread(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        offset: i64,
+        nread: Handle,
+    ) -> Result<i32, Trap> {
+        if iovecs_overlap(segments, iovs_ptr, iovs_len)? {
+            return Ok(ERRNO_FAULT);
+        }
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nread);
+            let (res, n) = iovs.call(segments, |mem, iovs, nread| {
+                wasi_snapshot_preview1::fd_pread(ctx, mem, fd, iovs, iovs_len, offset, nread)
+            })?;
+            if res == 0 {
+                iovs.clear_tags(segments, n as usize)?;
+                write!(write_mem_u32, segments, nread, n);
+            }
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, bufs) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nread_addr = scratch.copy_in(segments, nread, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_pread(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            offset,
+            nread_addr,
+        );
+
+        if res == 0 {
+            let nread_res = scratch.read_u32(nread_addr)?;
+            scratch.copy_out_iovecs(segments, &bufs, nread_res as usize)?;
+            scratch.copy_out(segments, nread_addr, nread, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_prestat_dir_name(fd: fd, path: Pointer<u8>, path_len: size) -> Result<(), errno>
+    pub(super) fn fd_prestat_dir_name(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, arg2 as u32 as usize, |mem, arg1| {
+            wasi_snapshot_preview1::fd_prestat_dir_name(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_prestat_get(fd: fd) -> Result<prestat, errno>
+    pub(super) fn fd_prestat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 8, |mem, arg1| {
+            wasi_snapshot_preview1::fd_prestat_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_pwrite(fd: fd, iovs: ciovec_array, offset: filesize) -> Result<size, errno>
+    pub(super) fn fd_pwrite(
         ctx: &wasi_common::WasiCtx,
         segments: &mut Segments,
         fd: i32,
         iovs_ptr: Handle,
         iovs_len: i32,
+        offset: i64,
         nwritten: Handle,
-    ) -> Option<i32> {
-        let mut iovs: Vec<&[u8]> = vec![];
-        for i in 0..iovs_len {
-            let loc = read!(get_handle, segments, iovs_ptr.add(i * (8 + 8) + 0)?);
-            let len = read!(read_mem_u32, segments, iovs_ptr.add(i * (8 + 8) + 8)?) as usize;
-            if len == 0 {
-                iovs.push(&[]);
-            } else {
-                iovs.push(read!(bytes, segments, loc, len));
+    ) -> Result<i32, Trap> {
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nwritten);
+            let (res, n) = iovs.call(segments, |mem, iovs, nwritten| {
+                wasi_snapshot_preview1::fd_pwrite(ctx, mem, fd, iovs, iovs_len, offset, nwritten)
+            })?;
+            if res == 0 {
+                write!(write_mem_u32, segments, nwritten, n);
             }
+            return Ok(res);
         }
 
-        let nwritten_start: usize = 8 * iovs.len();
-        let mem_iovs_data_start: usize = nwritten_start + 4;
-        let mem_iovs_data_len: usize = iovs.iter().map(|x| x.len()).sum();
+        let mut scratch = Scratch::new();
+        let (iovs, _) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nwritten_addr = scratch.copy_in(segments, nwritten, 4)?;
 
-        let mut memory = vec![0u8; mem_iovs_data_start + mem_iovs_data_len + 4];
-        {
-            let mut start = mem_iovs_data_start as u32;
-            for (i, iov) in iovs.into_iter().enumerate() {
-                write_mem_u32(&mut memory, 8 * i + 0, start)?;
-                write_mem_u32(&mut memory, 8 * i + 4, iov.len() as u32)?;
-                memory[start as usize..start as usize + iov.len()].copy_from_slice(iov);
-                start += iov.len() as u32;
+        let res = wasi_snapshot_preview1::fd_pwrite(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            offset,
+            nwritten_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, nwritten_addr, nwritten, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_read(fd: fd, iovs: iovec_array) -> Result<size, errno>
+    pub(super) fn fd_read(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        nread: Handle,
+    ) -> Result<i32, Trap> {
+        if iovecs_overlap(segments, iovs_ptr, iovs_len)? {
+            return Ok(ERRNO_FAULT);
+        }
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nread);
+            let (res, n) = iovs.call(segments, |mem, iovs, nread| {
+                wasi_snapshot_preview1::fd_read(ctx, mem, fd, iovs, iovs_len, nread)
+            })?;
+            if res == 0 {
+                iovs.clear_tags(segments, n as usize)?;
+                write!(write_mem_u32, segments, nread, n);
+            }
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, bufs) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nread_addr = scratch.copy_in(segments, nread, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_read(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            nread_addr,
+        );
+
+        if res == 0 {
+            let nread_res = scratch.read_u32(nread_addr)?;
+            scratch.copy_out_iovecs(segments, &bufs, nread_res as usize)?;
+            scratch.copy_out(segments, nread_addr, nread, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_readdir(fd: fd, buf: Pointer<u8>, buf_len: size, cookie: dircookie) -> Result<size, errno>
+    pub(super) fn fd_readdir(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        buf: Handle,
+        buf_len: i32,
+        cookie: i64,
+        bufused: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let buf_addr = scratch.copy_in(segments, buf, buf_len as u32 as usize)?;
+        let bufused_addr = scratch.copy_in(segments, bufused, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_readdir(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            buf_addr,
+            buf_len,
+            cookie,
+            bufused_addr,
+        );
+
+        if res == 0 {
+            let bufused_res = scratch.read_u32(bufused_addr)?;
+            scratch.copy_out(segments, buf_addr, buf, bufused_res as usize)?;
+            scratch.copy_out(segments, bufused_addr, bufused, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // fd_renumber(fd: fd, to: fd) -> Result<(), errno>
+    pub(super) fn fd_renumber(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_renumber(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_seek(fd: fd, offset: filedelta, whence: whence) -> Result<filesize, errno>
+    pub(super) fn fd_seek(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i64,
+        arg2: i32,
+        arg3: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg3, 8, |mem, arg3| {
+            wasi_snapshot_preview1::fd_seek(ctx, mem, arg0, arg1, arg2, arg3)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_sync(fd: fd) -> Result<(), errno>
+    pub(super) fn fd_sync(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::fd_sync(ctx, mem, arg0)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_tell(fd: fd) -> Result<filesize, errno>
+    pub(super) fn fd_tell(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 8, |mem, arg1| {
+            wasi_snapshot_preview1::fd_tell(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // fd_write(fd: fd, iovs: ciovec_array) -> Result<size, errno>
+    pub(super) fn fd_write(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        iovs_ptr: Handle,
+        iovs_len: i32,
+        nwritten: Handle,
+    ) -> Result<i32, Trap> {
+        if let Some(iovs) = SegmentIovecs::find(segments, iovs_ptr, iovs_len)? {
+            // Checked before the callee has any side effects
+            read!(read_mem_u32, segments, nwritten);
+            let (res, n) = iovs.call(segments, |mem, iovs, nwritten| {
+                wasi_snapshot_preview1::fd_write(ctx, mem, fd, iovs, iovs_len, nwritten)
+            })?;
+            if res == 0 {
+                write!(write_mem_u32, segments, nwritten, n);
             }
-            assert_eq!(start as usize, mem_iovs_data_start + mem_iovs_data_len);
+            return Ok(res);
+        }
+
+        let mut scratch = Scratch::new();
+        let (iovs, _) = scratch.copy_in_iovecs(segments, iovs_ptr, iovs_len)?;
+        let nwritten_addr = scratch.copy_in(segments, nwritten, 4)?;
+
+        let res = wasi_snapshot_preview1::fd_write(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            iovs_len,
+            nwritten_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, nwritten_addr, nwritten, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // path_create_directory(fd: fd, path: string) -> Result<(), errno>
+    pub(super) fn path_create_directory(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 0, |mem, arg1| {
+            wasi_snapshot_preview1::path_create_directory(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // path_filestat_get(fd: fd, flags: lookupflags, path: string) -> Result<filestat, errno>
+    pub(super) fn path_filestat_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        flags: i32,
+        path: Handle,
+        path_len: i32,
+        buf: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let path_addr = scratch.copy_in(segments, path, path_len as u32 as usize)?;
+        let buf_addr = scratch.copy_in(segments, buf, 64)?;
+
+        let res = wasi_snapshot_preview1::path_filestat_get(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            flags,
+            path_addr,
+            path_len,
+            buf_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, buf_addr, buf, 64)?;
         }
 
-        assert!(nwritten_start % 4 == 0, "nwritten_start must be 4-aligned");
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_filestat_set_times(fd: fd, flags: lookupflags, path: string, atim: timestamp, mtim: timestamp, fst_flags: fstflags) -> Result<(), errno>
+    pub(super) fn path_filestat_set_times(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+        arg2: Handle,
+        arg3: i32,
+        arg4: i64,
+        arg5: i64,
+        arg6: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg2, 0, |mem, arg2| {
+            wasi_snapshot_preview1::path_filestat_set_times(
+                ctx, mem, arg0, arg1, arg2, arg3, arg4, arg5, arg6,
+            )
+        })
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_link(old_fd: fd, old_flags: lookupflags, old_path: string, new_fd: fd, new_path: string) -> Result<(), errno>
+    pub(super) fn path_link(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        old_fd: i32,
+        old_flags: i32,
+        old_path: Handle,
+        old_path_len: i32,
+        new_fd: i32,
+        new_path: Handle,
+        new_path_len: i32,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let old_path_addr = scratch.copy_in(segments, old_path, old_path_len as u32 as usize)?;
+        let new_path_addr = scratch.copy_in(segments, new_path, new_path_len as u32 as usize)?;
+
+        Ok(wasi_snapshot_preview1::path_link(
+            ctx,
+            &scratch.guest_memory(),
+            old_fd,
+            old_flags,
+            old_path_addr,
+            old_path_len,
+            new_fd,
+            new_path_addr,
+            new_path_len,
+        ))
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_open(fd: fd, dirflags: lookupflags, path: string, oflags: oflags, fs_rights_base: rights, fs_rights_inheriting: rights, fdflags: fdflags) -> Result<fd, errno>
+    pub(super) fn path_open(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        dirflags: i32,
+        path: Handle,
+        path_len: i32,
+        oflags: i32,
+        fs_rights_base: i64,
+        fs_rights_inheriting: i64,
+        fdflags: i32,
+        opened_fd: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let path_addr = scratch.copy_in(segments, path, path_len as u32 as usize)?;
+        let opened_fd_addr = scratch.copy_in(segments, opened_fd, 4)?;
+
+        let res = wasi_snapshot_preview1::path_open(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            dirflags,
+            path_addr,
+            path_len,
+            oflags,
+            fs_rights_base,
+            fs_rights_inheriting,
+            fdflags,
+            opened_fd_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, opened_fd_addr, opened_fd, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_readlink(fd: fd, path: string, buf: Pointer<u8>, buf_len: size) -> Result<size, errno>
+    pub(super) fn path_readlink(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        path: Handle,
+        path_len: i32,
+        buf: Handle,
+        buf_len: i32,
+        bufused: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let path_addr = scratch.copy_in(segments, path, path_len as u32 as usize)?;
+        let buf_addr = scratch.copy_in(segments, buf, buf_len as u32 as usize)?;
+        let bufused_addr = scratch.copy_in(segments, bufused, 4)?;
+
+        let res = wasi_snapshot_preview1::path_readlink(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            path_addr,
+            path_len,
+            buf_addr,
+            buf_len,
+            bufused_addr,
+        );
+
+        if res == 0 {
+            let bufused_res = scratch.read_u32(bufused_addr)?;
+            scratch.copy_out(segments, buf_addr, buf, bufused_res as usize)?;
+            scratch.copy_out(segments, bufused_addr, bufused, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // path_remove_directory(fd: fd, path: string) -> Result<(), errno>
+    pub(super) fn path_remove_directory(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 0, |mem, arg1| {
+            wasi_snapshot_preview1::path_remove_directory(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // path_rename(fd: fd, old_path: string, new_fd: fd, new_path: string) -> Result<(), errno>
+    pub(super) fn path_rename(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        old_path: Handle,
+        old_path_len: i32,
+        new_fd: i32,
+        new_path: Handle,
+        new_path_len: i32,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let old_path_addr = scratch.copy_in(segments, old_path, old_path_len as u32 as usize)?;
+        let new_path_addr = scratch.copy_in(segments, new_path, new_path_len as u32 as usize)?;
+
+        Ok(wasi_snapshot_preview1::path_rename(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            old_path_addr,
+            old_path_len,
+            new_fd,
+            new_path_addr,
+            new_path_len,
+        ))
+    }
+
+    #[allow(dead_code)]
+    // path_symlink(old_path: string, fd: fd, new_path: string) -> Result<(), errno>
+    pub(super) fn path_symlink(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        old_path: Handle,
+        old_path_len: i32,
+        fd: i32,
+        new_path: Handle,
+        new_path_len: i32,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let old_path_addr = scratch.copy_in(segments, old_path, old_path_len as u32 as usize)?;
+        let new_path_addr = scratch.copy_in(segments, new_path, new_path_len as u32 as usize)?;
+
+        Ok(wasi_snapshot_preview1::path_symlink(
+            ctx,
+            &scratch.guest_memory(),
+            old_path_addr,
+            old_path_len,
+            fd,
+            new_path_addr,
+            new_path_len,
+        ))
+    }
+
+    #[allow(dead_code)]
+    // path_unlink_file(fd: fd, path: string) -> Result<(), errno>
+    pub(super) fn path_unlink_file(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: Handle,
+        arg2: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg1, 0, |mem, arg1| {
+            wasi_snapshot_preview1::path_unlink_file(ctx, mem, arg0, arg1, arg2)
+        })
+    }
+
+    #[allow(dead_code)]
+    // poll_oneoff(in: ConstPointer<subscription>, out: Pointer<event>, nsubscriptions: size) -> Result<size, errno>
+    pub(super) fn poll_oneoff(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        subscriptions: Handle,
+        events: Handle,
+        nsubscriptions: i32,
+        nevents: Handle,
+    ) -> Result<i32, Trap> {
+        // Neither struct holds a pointer, so they keep their wasm32 layout
+        const SUBSCRIPTION_SIZE: usize = 48;
+        const EVENT_SIZE: usize = 32;
+
+        let n = nsubscriptions as u32 as usize;
+        let mut scratch = Scratch::new();
+        let subscriptions_addr = scratch.copy_in(segments, subscriptions, n * SUBSCRIPTION_SIZE)?;
+        let events_addr = scratch.copy_in(segments, events, n * EVENT_SIZE)?;
+        let nevents_addr = scratch.copy_in(segments, nevents, 4)?;
+
+        let res = wasi_snapshot_preview1::poll_oneoff(
+            ctx,
+            &scratch.guest_memory(),
+            subscriptions_addr,
+            events_addr,
+            nsubscriptions,
+            nevents_addr,
+        );
+
+        if res == 0 {
+            let nevents_res = scratch.read_u32(nevents_addr)? as usize;
+            scratch.copy_out(
+                segments,
+                events_addr,
+                events,
+                nevents_res.min(n) * EVENT_SIZE,
+            )?;
+            scratch.copy_out(segments, nevents_addr, nevents, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // proc_raise(sig: signal) -> Result<(), errno>
+    pub(super) fn proc_raise(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::proc_raise(ctx, mem, arg0)
+        })
+    }
+
+    #[allow(dead_code)]
+    // random_get(buf: Pointer<u8>, buf_len: size) -> Result<(), errno>
+    pub(super) fn random_get(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: Handle,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No internal pointers, just pass through directly
+        with_collected_memory_1(segments, arg0, arg1 as u32 as usize, |mem, arg0| {
+            wasi_snapshot_preview1::random_get(ctx, mem, arg0, arg1)
+        })
+    }
+
+    #[allow(dead_code)]
+    // sched_yield() -> Result<(), errno>
+    pub(super) fn sched_yield(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::sched_yield(ctx, mem)
+        })
+    }
+
+    #[allow(dead_code)]
+    #[allow(clippy::too_many_arguments)]
+    // sock_recv(fd: fd, ri_data: iovec_array, ri_flags: riflags) -> Result<(size, roflags), errno>
+    pub(super) fn sock_recv(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        ri_data: Handle,
+        ri_data_len: i32,
+        ri_flags: i32,
+        ro_datalen: Handle,
+        ro_flags: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let (iovs, bufs) = scratch.copy_in_iovecs(segments, ri_data, ri_data_len)?;
+        let ro_datalen_addr = scratch.copy_in(segments, ro_datalen, 4)?;
+        let ro_flags_addr = scratch.copy_in(segments, ro_flags, 2)?;
+
+        let res = wasi_snapshot_preview1::sock_recv(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            ri_data_len,
+            ri_flags,
+            ro_datalen_addr,
+            ro_flags_addr,
+        );
+
+        if res == 0 {
+            let ro_datalen_res = scratch.read_u32(ro_datalen_addr)?;
+            scratch.copy_out_iovecs(segments, &bufs, ro_datalen_res as usize)?;
+            scratch.copy_out(segments, ro_datalen_addr, ro_datalen, 4)?;
+            scratch.copy_out(segments, ro_flags_addr, ro_flags, 2)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // sock_send(fd: fd, si_data: ciovec_array, si_flags: siflags) -> Result<size, errno>
+    pub(super) fn sock_send(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        fd: i32,
+        si_data: Handle,
+        si_data_len: i32,
+        si_flags: i32,
+        so_datalen: Handle,
+    ) -> Result<i32, Trap> {
+        let mut scratch = Scratch::new();
+        let (iovs, _) = scratch.copy_in_iovecs(segments, si_data, si_data_len)?;
+        let so_datalen_addr = scratch.copy_in(segments, so_datalen, 4)?;
+
+        let res = wasi_snapshot_preview1::sock_send(
+            ctx,
+            &scratch.guest_memory(),
+            fd,
+            iovs,
+            si_data_len,
+            si_flags,
+            so_datalen_addr,
+        );
+
+        if res == 0 {
+            scratch.copy_out(segments, so_datalen_addr, so_datalen, 4)?;
+        }
+
+        Ok(res)
+    }
+
+    #[allow(dead_code)]
+    // sock_shutdown(fd: fd, how: sdflags) -> Result<(), errno>
+    pub(super) fn sock_shutdown(
+        ctx: &wasi_common::WasiCtx,
+        segments: &mut Segments,
+        arg0: i32,
+        arg1: i32,
+    ) -> Result<i32, Trap> {
+        // No pointers, just pass through directly
+        with_collected_memory_0(segments, |mem| {
+            wasi_snapshot_preview1::sock_shutdown(ctx, mem, arg0, arg1)
+        })
+    }
+}
+
+#[allow(dead_code)]
+pub struct WasmModule {
+    segments: Segments,
+    globals: Vec<TaggedVal>,
+    indirect_call_table: Vec<Option<usize>>,
+    context: wasi_common::WasiCtx,
+    trap_backtrace: WasmBacktrace,
+    // By index into `HOST_IMPORTS`
+    host_funcs: Vec<Option<HostFunc>>,
+    // Fuel left, if metered
+    fuel: Option<u64>,
+    // Shared with every `InterruptHandle`
+    interrupt: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    // Generated functions currently on the host stack
+    call_depth: u32,
+    max_call_depth: u32,
+    // Lowest host stack address a guest call may start at, see `enter_call`
+    stack_limit: usize,
+}
+
+// Each guest call takes a native frame of the generated function (and one of
+// its wrapper). The host stack they take up is checked on its own (see
+// `WasmModule::enter_call`), since frames range from a few hundred bytes in
+// release builds to about 20 KiB for large functions in debug builds
+// (`hello-c`), so this is only a cap on the depth itself.
+const DEFAULT_MAX_CALL_DEPTH: u32 = 1000;
+
+// Stack size assumed for a host thread that `on_large_stack` did not start:
+// the 2 MiB that Rust gives spawned threads by default, such as those
+// `cargo test` runs tests on. The main thread usually has more.
+const DEFAULT_HOST_STACK: usize = 2 << 20;
+
+thread_local! {
+    // Stack size of the current host thread
+    static HOST_STACK: std::cell::Cell<usize> = std::cell::Cell::new(DEFAULT_HOST_STACK);
+}
+
+// Roughly where the host stack is at
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// Runs `f` on a fresh host thread with a stack of `stack_size` bytes, for
+// guests that recurse deeper than the default host stack allows. `WasmModule`
+// cannot move across threads, so it has to be created inside `f`.
+#[allow(dead_code)]
+pub fn on_large_stack<R: Send>(stack_size: usize, f: impl FnOnce() -> R + Send) -> R {
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn_scoped(scope, move || {
+                HOST_STACK.with(|size| size.set(stack_size));
+                f()
+            })
+            .expect("failed to spawn a thread for the guest");
+        thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+impl WasmModule {
+    // Called by the wrapper of each generated function when its body traps.
+    // Records the frame (unless the guest is just exiting) and hands the trap
+    // on to the caller.
+    #[cold]
+    fn unwind(&mut self, func_index: u32, fault: Fault) -> Trap {
+        if let Trap::Exit(_) = fault.trap {
+            return fault.trap;
+        }
+        self.trap_backtrace.frames.push(WasmFrame {
+            func_index,
+            code_offset: fault.code_offset,
+        });
+        fault.trap
+    }
+
+    // Called by the wrapper of each generated function before its body runs,
+    // which then runs through the returned guard. Besides the depth, the
+    // guest may take up half of the host thread's stack from where the host
+    // called into it, which leaves the other half to the host and to the
+    // host functions the guest calls.
+    #[inline]
+    fn enter_call(&mut self) -> Result<CallGuard<'_>, Trap> {
+        let sp = stack_pointer();
+        if self.call_depth == 0 {
+            // The stack grows down on every target the runtime builds for
+            self.stack_limit = sp.saturating_sub(HOST_STACK.with(|size| size.get()) / 2);
+        }
+        if self.call_depth >= self.max_call_depth || sp < self.stack_limit {
+            return Err(Trap::StackOverflow);
+        }
+        self.call_depth += 1;
+        Ok(CallGuard { module: self })
+    }
+
+    // How deeply guest calls may nest before they trap with
+    // `Trap::StackOverflow`. They trap sooner if they run short of host
+    // stack, see `on_large_stack`.
+    #[allow(dead_code)]
+    pub fn set_max_call_depth(&mut self, depth: u32) {
+        self.max_call_depth = depth;
+    }
+
+    // Guest call stack of the last trap returned from an export
+    #[allow(dead_code)]
+    pub fn trap_backtrace(&self) -> &WasmBacktrace {
+        &self.trap_backtrace
+    }
+}
+
+// The module for the body of a generated function to run on. Decrements
+// `call_depth` again when dropped, so that it is restored even if a host
+// function panics and the host catches the unwind.
+struct CallGuard<'a> {
+    module: &'a mut WasmModule,
+}
+
+impl std::ops::Deref for CallGuard<'_> {
+    type Target = WasmModule;
+
+    fn deref(&self) -> &WasmModule {
+        self.module
+    }
+}
+
+impl std::ops::DerefMut for CallGuard<'_> {
+    fn deref_mut(&mut self) -> &mut WasmModule {
+        self.module
+    }
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.module.call_depth -= 1;
+    }
+}
+
+// Stops a running module from another thread: the guest traps with
+// `Trap::Interrupted` at its next call or loop iteration.
+//
+// An interrupt raised while the module is idle is not dropped but stays
+// latched: the next `call` or `_start` traps as soon as it enters guest
+// code, so that an interrupt sent just before the guest starts running is
+// not lost. Each interrupt stops the guest once.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    flag: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[allow(dead_code)]
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+// A host function an import is linked to. It gets the module itself, to
+// reach guest memory through handles it was passed, and the arguments of
+// the call, which are checked against the import's signature like its
+// results.
+type HostFunc = std::rc::Rc<dyn Fn(&mut WasmModule, &[Value]) -> Result<Vec<Value>, Trap>>;
+
+// Host functions to satisfy imports with, by module and field name
+#[derive(Clone, Default)]
+pub struct Linker {
+    funcs: BTreeMap<(String, String), HostFunc>,
+}
+
+#[allow(dead_code)]
+impl Linker {
+    pub fn new() -> Self {
+        Linker::default()
+    }
+
+    pub fn func(
+        &mut self,
+        module: &str,
+        field: &str,
+        f: impl Fn(&mut WasmModule, &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+    ) -> &mut Self {
+        self.funcs
+            .insert((module.to_owned(), field.to_owned()), std::rc::Rc::new(f));
+        self
+    }
+
+    fn get(&self, module: &str, field: &str) -> Option<HostFunc> {
+        self.funcs
+            .get(&(module.to_owned(), field.to_owned()))
+            .cloned()
+    }
+}
+
+// Configures the WASI context a module runs in. Unless told otherwise the
+// guest gets no arguments, no environment, no preopened directories and
+// null stdio.
+pub struct WasmModuleBuilder {
+    wasi: wasi_common::WasiCtxBuilder,
+    preopens: Vec<(std::path::PathBuf, std::path::PathBuf)>,
+    linker: Linker,
+    fuel: Option<u64>,
+    max_call_depth: u32,
+    memory_limits: MemoryLimits,
+}
+
+#[allow(dead_code)]
+impl WasmModuleBuilder {
+    pub fn args<S: AsRef<[u8]>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.wasi.args(args);
+        self
+    }
+
+    pub fn inherit_args(mut self) -> Self {
+        self.wasi.inherit_args();
+        self
+    }
+
+    pub fn env<S: AsRef<[u8]>>(mut self, key: S, value: S) -> Self {
+        self.wasi.env(key, value);
+        self
+    }
+
+    pub fn inherit_env(mut self) -> Self {
+        self.wasi.inherit_env();
+        self
+    }
+
+    // Makes the host directory `host` visible to the guest as `guest`
+    pub fn preopen_dir(
+        mut self,
+        host: impl AsRef<std::path::Path>,
+        guest: impl AsRef<std::path::Path>,
+    ) -> Self {
+        self.preopens
+            .push((host.as_ref().to_owned(), guest.as_ref().to_owned()));
+        self
+    }
+
+    pub fn stdin(mut self, reader: impl std::io::Read + std::any::Any) -> Self {
+        self.wasi
+            .stdin(wasi_common::virtfs::pipe::ReadPipe::new(reader));
+        self
+    }
+
+    pub fn stdout(mut self, writer: impl std::io::Write + std::any::Any) -> Self {
+        self.wasi
+            .stdout(wasi_common::virtfs::pipe::WritePipe::new(writer));
+        self
+    }
+
+    pub fn stderr(mut self, writer: impl std::io::Write + std::any::Any) -> Self {
+        self.wasi
+            .stderr(wasi_common::virtfs::pipe::WritePipe::new(writer));
+        self
+    }
+
+    // Gives the guest the host's own stdin, stdout and stderr
+    pub fn inherit_stdio(mut self) -> Self {
+        self.wasi.inherit_stdio();
+        self
+    }
+
+    // Resolves the module's imports against `linker`, replacing any host
+    // functions added before
+    pub fn linker(mut self, linker: Linker) -> Self {
+        self.linker = linker;
+        self
+    }
+
+    pub fn func(
+        mut self,
+        module: &str,
+        field: &str,
+        f: impl Fn(&mut WasmModule, &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+    ) -> Self {
+        self.linker.func(module, field, f);
+        self
+    }
+
+    // Meters the module, see `WasmModule::set_fuel`
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
 
-        let res = wasi_snapshot_preview1::fd_write(
-            ctx,
-            &guest_mem_wrapper::GuestMemWrapper::from(&mut memory),
-            fd,
-            0,
-            iovs_len,
-            nwritten_start as i32,
-        );
+    // See `WasmModule::set_max_call_depth`
+    pub fn max_call_depth(mut self, depth: u32) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
 
-        let nwritten_res = read_mem_u32(&memory, nwritten_start)?;
-        write!(write_mem_u32, segments, nwritten, nwritten_res);
+    // See `WasmModule::set_memory_limits`. The segments a module sets up for
+    // itself on creation count towards them, but are never refused.
+    pub fn memory_limits(mut self, limits: MemoryLimits) -> Self {
+        self.memory_limits = limits;
+        self
+    }
 
-        Some(res)
+    pub fn build(mut self) -> Result<WasmModule, BuildError> {
+        for (host, guest) in self.preopens {
+            let dir = std::fs::File::open(&host).map_err(|e| BuildError::Preopen(host, e))?;
+            self.wasi.preopened_dir(dir, guest);
+        }
+        let context = self.wasi.build().map_err(BuildError::Wasi)?;
+        let mut module = WasmModule::try_new(context).map_err(BuildError::Trap)?;
+        let linker = &self.linker;
+        module.host_funcs = HOST_IMPORTS
+            .iter()
+            .map(|import| linker.get(import.module, import.field))
+            .collect();
+        module.fuel = self.fuel;
+        module.max_call_depth = self.max_call_depth;
+        module.set_memory_limits(self.memory_limits);
+        Ok(module)
     }
 }
 
-#[allow(dead_code)]
-pub struct WasmModule {
-    segments: Segments,
-    globals: Vec<TaggedVal>,
-    indirect_call_table: Vec<Option<usize>>,
-    context: wasi_common::WasiCtx,
+impl WasmModule {
+    #[allow(dead_code)]
+    pub fn builder() -> WasmModuleBuilder {
+        WasmModuleBuilder {
+            wasi: wasi_common::WasiCtxBuilder::new(),
+            preopens: vec![],
+            linker: Linker::new(),
+            fuel: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            memory_limits: MemoryLimits::default(),
+        }
+    }
 }
 
 macro_rules! memory_accessors {
@@ -817,15 +3336,183 @@ memory_accessors! {i64, read_mem_i64, write_mem_i64}
 memory_accessors! {f32, read_mem_f32, write_mem_f32}
 memory_accessors! {f64, read_mem_f64, write_mem_f64}
 
+// Plain values the host can read and write in guest memory, stored little
+// endian like the guest's own loads and stores
+pub trait GuestValue: Copy {
+    const SIZE: usize;
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    fn to_le_slice(self, bytes: &mut [u8]);
+}
+
+macro_rules! guest_values {
+    ($($ty:ty),*) => {
+        $(
+            impl GuestValue for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                fn from_le_slice(bytes: &[u8]) -> Self {
+                    <$ty>::from_le_bytes(bytes.try_into().unwrap())
+                }
+                fn to_le_slice(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+guest_values! {u8, u16, u32, u64, i8, i16, i32, i64, f32, f64}
+
+// Host access to guest memory. Reads and writes are checked against the
+// handle and its segment just like the guest's own, and writes clear tags
+// the same way, so the host cannot forge handles by accident.
+impl WasmModule {
+    // Handles spanning each segment that is currently allocated
+    #[allow(dead_code)]
+    pub fn live_segments(&self) -> Vec<Handle> {
+        self.segments.live().map(Handle::segment).collect()
+    }
+
+    // Bytes reachable through `h`, from its offset up to its bound
+    #[allow(dead_code)]
+    pub fn segment_data(&self, h: Handle) -> Result<&[u8], Trap> {
+        let offset = h.checked_offset(0)?;
+        self.segments
+            .get(h)?
+            .get_data_within(h)?
+            .get(offset..)
+            .ok_or(Trap::OutOfBounds)
+    }
+
+    // Same as `segment_data`, for the host to write to. All of it is
+    // turned into data up front.
+    #[allow(dead_code)]
+    pub fn segment_data_mut(&mut self, h: Handle) -> Result<&mut [u8], Trap> {
+        let len = self.segment_data(h)?.len();
+        Ok(write!(bytes, self.segments, h, len))
+    }
+
+    #[allow(dead_code)]
+    pub fn read_value<T: GuestValue>(&self, h: Handle) -> Result<T, Trap> {
+        Ok(T::from_le_slice(read!(bytes, self.segments, h, T::SIZE)))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_value<T: GuestValue>(&mut self, h: Handle, value: T) -> Result<(), Trap> {
+        value.to_le_slice(write!(bytes, self.segments, h, T::SIZE));
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn read_handle(&self, h: Handle) -> Result<Handle, Trap> {
+        Ok(read!(get_handle, self.segments, h))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_handle(&mut self, h: Handle, value: Handle) -> Result<(), Trap> {
+        write!(store_handle, self.segments, h, value);
+        Ok(())
+    }
+
+    // Allocates a zeroed segment of `size` bytes, e.g. to pass data to the
+    // guest. It is the guest's to free like any other. Subject to the
+    // module's `MemoryLimits`, so this may return a null handle.
+    #[allow(dead_code)]
+    pub fn alloc_segment(&mut self, size: u32) -> Result<Handle, Trap> {
+        self.new_segment(size)
+    }
+}
+
+impl From<Value> for TaggedVal {
+    fn from(v: Value) -> Self {
+        match v {
+            Value::I32(v) => I32(v),
+            Value::I64(v) => I64(v),
+            Value::F32(v) => F32(v),
+            Value::F64(v) => F64(v),
+            Value::Handle(v) => TaggedVal::Handle(v),
+        }
+    }
+}
+
+impl Value {
+    fn from_tagged(v: TaggedVal) -> Result<Self, Trap> {
+        match v {
+            I32(v) => Ok(Value::I32(v)),
+            I64(v) => Ok(Value::I64(v)),
+            F32(v) => Ok(Value::F32(v)),
+            F64(v) => Ok(Value::F64(v)),
+            TaggedVal::Handle(v) => Ok(Value::Handle(v)),
+            Undefined => Err(Trap::TagMismatch),
+        }
+    }
+}
+
+impl WasmModule {
+    #[allow(dead_code)]
+    pub fn exports(&self) -> &'static [Export] {
+        &EXPORTS
+    }
+
+    // Calls the export `name`. `args` must match its signature, or the
+    // call fails with `ArgumentMismatch` before entering the guest.
+    #[allow(dead_code)]
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let index = EXPORTS
+            .iter()
+            .position(|export| export.name == name)
+            .ok_or(Trap::UnknownExport)?;
+        let params = EXPORTS[index].params;
+        if args.len() != params.len() || args.iter().zip(params).any(|(arg, &ty)| arg.ty() != ty) {
+            return Err(Trap::ArgumentMismatch);
+        }
+        let args: Vec<TaggedVal> = args.iter().map(|&arg| arg.into()).collect();
+        self.call_export(index, &args)?
+            .into_iter()
+            .map(Value::from_tagged)
+            .collect()
+    }
+
+    // Imports that are resolved by host functions (see `Linker`)
+    #[allow(dead_code)]
+    pub fn imports(&self) -> &'static [Import] {
+        &HOST_IMPORTS
+    }
+
+    #[allow(dead_code)]
+    fn call_host(&mut self, import: usize, args: &[TaggedVal]) -> Result<Vec<TaggedVal>, Trap> {
+        let f = self.host_funcs[import]
+            .clone()
+            .ok_or(Trap::UnresolvedImport)?;
+        let args = args
+            .iter()
+            .map(|&arg| Value::from_tagged(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let results = f(self, &args)?;
+        let types = HOST_IMPORTS[import].results;
+        if results.len() != types.len()
+            || results.iter().zip(types).any(|(res, &ty)| res.ty() != ty)
+        {
+            return Err(Trap::ArgumentMismatch);
+        }
+        Ok(results.into_iter().map(TaggedVal::from).collect())
+    }
+}
+
 impl WasmModule {
     #[allow(unused_mut)]
-    fn try_new() -> Option<Self> {
+    fn try_new(context: wasi_common::WasiCtx) -> Result<Self, Trap> {
         let mut m = WasmModule {
             segments: Segments::new(),
             globals: vec![],
             indirect_call_table: vec![],
-            context: wasi_common::WasiCtx::new(std::env::args())
-                .expect("Unable to initialize WASI context"),
+            context,
+            trap_backtrace: WasmBacktrace::default(),
+            host_funcs: vec![],
+            fuel: None,
+            interrupt: Default::default(),
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack_limit: 0,
         };
         m.globals.resize_with(2, Default::default);
         m.globals[0] = TaggedVal::from(Handle::NULL);
@@ -840,13 +3527,13 @@ impl WasmModule {
         let init_handle = m.new_segment(131072).unwrap();
         m.globals[1] = TaggedVal::from(init_handle); /* WORKAROUND for mswasm-llvm and data segment initialization */
         m.segments
-            .get_mut(init_handle.segment_index().unwrap())
+            .get_mut(init_handle)
             .unwrap()
             .get_mut_data_slice(1024, 1037)
             .unwrap()[1024..1037]
             .copy_from_slice(&[72, 101, 108, 108, 111, 32, 87, 111, 114, 108, 100, 33, 0]);
         m.segments
-            .get_mut(init_handle.segment_index().unwrap())
+            .get_mut(init_handle)
             .unwrap()
             .get_mut_data_slice(1040, 1224)
             .unwrap()[1040..1224]
@@ -883,14 +3570,24 @@ impl WasmModule {
             let newseg = { m.new_segment(1025)? };
             write!(store_handle, m.segments, init_handle.add(1312)?, newseg);
         }
-        Some(m)
+        Ok(m)
     }
     pub fn new() -> Self {
-        Self::try_new().unwrap()
+        Self::builder()
+            .inherit_args()
+            .inherit_stdio()
+            .build()
+            .unwrap()
     }
 }
 
 impl WasmModule {
+    fn func_0(&mut self, arg_0: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_0_inner(arg_0)
+            .map_err(|fault| call.unwind(0, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -899,14 +3596,21 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_0(&mut self, arg_0: i32) -> Option<i32> {
-        Some(ms_wasm_wasi::fd_close(
+    fn func_0_inner(&mut self, arg_0: i32) -> Result<i32, Fault> {
+        self.checkpoint()?;
+        Ok(ms_wasm_wasi::fd_close(
             &self.context,
             &mut self.segments,
             arg_0,
         )?)
     }
 
+    fn func_1(&mut self, arg_0: i32, arg_1: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_1_inner(arg_0, arg_1)
+            .map_err(|fault| call.unwind(1, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -915,8 +3619,9 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_1(&mut self, arg_0: i32, arg_1: Handle) -> Option<i32> {
-        Some(ms_wasm_wasi::fd_fdstat_get(
+    fn func_1_inner(&mut self, arg_0: i32, arg_1: Handle) -> Result<i32, Fault> {
+        self.checkpoint()?;
+        Ok(ms_wasm_wasi::fd_fdstat_get(
             &self.context,
             &mut self.segments,
             arg_0,
@@ -924,6 +3629,12 @@ impl WasmModule {
         )?)
     }
 
+    fn func_2(&mut self, arg_0: i32, arg_1: i64, arg_2: i32, arg_3: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_2_inner(arg_0, arg_1, arg_2, arg_3)
+            .map_err(|fault| call.unwind(2, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -932,8 +3643,15 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_2(&mut self, arg_0: i32, arg_1: i64, arg_2: i32, arg_3: Handle) -> Option<i32> {
-        Some(ms_wasm_wasi::fd_seek(
+    fn func_2_inner(
+        &mut self,
+        arg_0: i32,
+        arg_1: i64,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Fault> {
+        self.checkpoint()?;
+        Ok(ms_wasm_wasi::fd_seek(
             &self.context,
             &mut self.segments,
             arg_0,
@@ -943,6 +3661,18 @@ impl WasmModule {
         )?)
     }
 
+    fn func_3(
+        &mut self,
+        arg_0: i32,
+        arg_1: Handle,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_3_inner(arg_0, arg_1, arg_2, arg_3)
+            .map_err(|fault| call.unwind(3, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -951,8 +3681,15 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_3(&mut self, arg_0: i32, arg_1: Handle, arg_2: i32, arg_3: Handle) -> Option<i32> {
-        Some(ms_wasm_wasi::fd_write(
+    fn func_3_inner(
+        &mut self,
+        arg_0: i32,
+        arg_1: Handle,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Fault> {
+        self.checkpoint()?;
+        Ok(ms_wasm_wasi::fd_write(
             &self.context,
             &mut self.segments,
             arg_0,
@@ -962,6 +3699,12 @@ impl WasmModule {
         )?)
     }
 
+    fn func_4(&mut self, arg_0: i32) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_4_inner(arg_0)
+            .map_err(|fault| call.unwind(4, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -970,8 +3713,14 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_4(&mut self, arg_0: i32) -> Option<()> {
-        std::process::exit(arg_0)
+    fn func_4_inner(&mut self, arg_0: i32) -> Result<(), Fault> {
+        self.checkpoint()?;
+        Err(Trap::Exit(arg_0).into())
+    }
+
+    fn func_5(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_5_inner().map_err(|fault| call.unwind(5, fault))
     }
 
     #[allow(
@@ -982,9 +3731,15 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_5(&mut self) -> Option<()> {
-        self.func_6()?;
-        Some(())
+    fn func_5_inner(&mut self) -> Result<(), Fault> {
+        self.checkpoint().at(0x3)?;
+        self.func_6().at(0x3)?;
+        Ok(())
+    }
+
+    fn func_6(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_6_inner().map_err(|fault| call.unwind(6, fault))
     }
 
     #[allow(
@@ -995,15 +3750,22 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_6(&mut self) -> Option<()> {
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        v0 = TaggedVal::from(2097152i32);
-        v0 = TaggedVal::from(self.new_segment(v0.try_as_i32()? as u32)?);
-        v1 = TaggedVal::from(2097152i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        Some(())
+    fn func_6_inner(&mut self) -> Result<(), Fault> {
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        self.checkpoint().at(0x8)?;
+        v0_i32 = 2097152i32;
+        v0_handle = self.new_segment(v0_i32 as u32).at(0xd)?;
+        v1 = 2097152i32;
+        v0_handle = v0_handle.add(v1).at(0x13)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        Ok(())
+    }
+
+    fn func_7(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_7_inner().map_err(|fault| call.unwind(7, fault))
     }
 
     #[allow(
@@ -1014,26 +3776,32 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_7(&mut self) -> Option<()> {
+    fn func_7_inner(&mut self) -> Result<(), Fault> {
         let mut local_0: i32 = 0i32;
-        let mut v0: TaggedVal;
-        self.func_5()?;
-        v0 = TaggedVal::from(self.func_9()?);
-        local_0 = v0.try_as_i32()?;
-        self.func_16()?;
+        let mut v0: i32;
+        self.checkpoint().at(0x1b)?;
+        self.func_5().at(0x1b)?;
+        v0 = self.func_9().at(0x21)?;
+        local_0 = v0;
+        self.func_16().at(0x29)?;
         'label_0: loop {
-            v0 = TaggedVal::from(local_0);
-            v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0 = local_0;
+            v0 = (v0 == 0) as i32;
+            if v0 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(local_0);
-            self.func_14(v0.try_as_i32()?)?;
-            unreachable!("Reached a point explicitly marked unreachable in WASM module");
+            v0 = local_0;
+            self.func_14(v0).at(0x38)?;
+            return Err(Fault::new(Trap::Unreachable, 0x3e));
             break;
         }
-        Some(())
+        Ok(())
+    }
+
+    fn func_8(&mut self) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_8_inner().map_err(|fault| call.unwind(8, fault))
     }
 
     #[allow(
@@ -1044,16 +3812,23 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_8(&mut self) -> Option<i32> {
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        v0 = self.globals[1];
-        v1 = TaggedVal::from(1024i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v0 = TaggedVal::from(self.func_22(v0.try_as_Handle()?)?);
+    fn func_8_inner(&mut self) -> Result<i32, Fault> {
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        self.checkpoint().at(0x43)?;
+        v0_handle = self.globals[1].try_as_handle().at(0x43)?;
+        v1 = 1024i32;
+        v0_handle = v0_handle.add(v1).at(0x4f)?;
+        v0_i32 = self.func_22(v0_handle).at(0x50)?;
+
+        v0_i32 = 0i32;
+        Ok(v0_i32)
+    }
 
-        v0 = TaggedVal::from(0i32);
-        Some(v0.try_as_i32()?)
+    fn func_9(&mut self) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_9_inner().map_err(|fault| call.unwind(9, fault))
     }
 
     #[allow(
@@ -1064,10 +3839,17 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_9(&mut self) -> Option<i32> {
-        let mut v0: TaggedVal;
-        v0 = TaggedVal::from(self.func_8()?);
-        Some(v0.try_as_i32()?)
+    fn func_9_inner(&mut self) -> Result<i32, Fault> {
+        let mut v0: i32;
+        self.checkpoint().at(0x5c)?;
+        v0 = self.func_8().at(0x5c)?;
+        Ok(v0)
+    }
+
+    fn func_10(&mut self, arg_0: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_10_inner(arg_0)
+            .map_err(|fault| call.unwind(10, fault))
     }
 
     #[allow(
@@ -1078,15 +3860,22 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_10(&mut self, arg_0: i32) -> Option<i32> {
+    fn func_10_inner(&mut self, arg_0: i32) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v0 = TaggedVal::from(self.func_0(v0.try_as_i32()?)?);
-        v1 = TaggedVal::from(65535i32);
-        v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-        Some(v0.try_as_i32()?)
+        let mut v0: i32;
+        let mut v1: i32;
+        self.checkpoint().at(0x65)?;
+        v0 = local_0;
+        v0 = self.func_0(v0).at(0x67)?;
+        v1 = 65535i32;
+        v0 = v0 & v1;
+        Ok(v0)
+    }
+
+    fn func_11(&mut self, arg_0: i32, arg_1: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_11_inner(arg_0, arg_1)
+            .map_err(|fault| call.unwind(11, fault))
     }
 
     #[allow(
@@ -1097,17 +3886,25 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_11(&mut self, arg_0: i32, arg_1: Handle) -> Option<i32> {
+    fn func_11_inner(&mut self, arg_0: i32, arg_1: Handle) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
         let mut local_1: Handle = arg_1;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(local_1);
-        v0 = TaggedVal::from(self.func_1(v0.try_as_i32()?, v1.try_as_Handle()?)?);
-        v1 = TaggedVal::from(65535i32);
-        v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-        Some(v0.try_as_i32()?)
+        let mut v0: i32;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        self.checkpoint().at(0x75)?;
+        v0 = local_0;
+        v1_handle = local_1;
+        v0 = self.func_1(v0, v1_handle).at(0x79)?;
+        v1_i32 = 65535i32;
+        v0 = v0 & v1_i32;
+        Ok(v0)
+    }
+
+    fn func_12(&mut self, arg_0: i32, arg_1: i64, arg_2: i32, arg_3: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_12_inner(arg_0, arg_1, arg_2, arg_3)
+            .map_err(|fault| call.unwind(12, fault))
     }
 
     #[allow(
@@ -1118,28 +3915,43 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_12(&mut self, arg_0: i32, arg_1: i64, arg_2: i32, arg_3: Handle) -> Option<i32> {
+    fn func_12_inner(
+        &mut self,
+        arg_0: i32,
+        arg_1: i64,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
         let mut local_1: i64 = arg_1;
         let mut local_2: i32 = arg_2;
         let mut local_3: Handle = arg_3;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(local_1);
-        v2 = TaggedVal::from(local_2);
-        v3 = TaggedVal::from(local_3);
-        v0 = TaggedVal::from(self.func_2(
-            v0.try_as_i32()?,
-            v1.try_as_i64()?,
-            v2.try_as_i32()?,
-            v3.try_as_Handle()?,
-        )?);
-        v1 = TaggedVal::from(65535i32);
-        v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-        Some(v0.try_as_i32()?)
+        let mut v0: i32;
+        let mut v1_i32: i32;
+        let mut v1_i64: i64;
+        let mut v2: i32;
+        let mut v3: Handle;
+        self.checkpoint().at(0x87)?;
+        v0 = local_0;
+        v1_i64 = local_1;
+        v2 = local_2;
+        v3 = local_3;
+        v0 = self.func_2(v0, v1_i64, v2, v3).at(0x8f)?;
+        v1_i32 = 65535i32;
+        v0 = v0 & v1_i32;
+        Ok(v0)
+    }
+
+    fn func_13(
+        &mut self,
+        arg_0: i32,
+        arg_1: Handle,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_13_inner(arg_0, arg_1, arg_2, arg_3)
+            .map_err(|fault| call.unwind(13, fault))
     }
 
     #[allow(
@@ -1150,28 +3962,37 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_13(&mut self, arg_0: i32, arg_1: Handle, arg_2: i32, arg_3: Handle) -> Option<i32> {
+    fn func_13_inner(
+        &mut self,
+        arg_0: i32,
+        arg_1: Handle,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
         let mut local_1: Handle = arg_1;
         let mut local_2: i32 = arg_2;
         let mut local_3: Handle = arg_3;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(local_1);
-        v2 = TaggedVal::from(local_2);
-        v3 = TaggedVal::from(local_3);
-        v0 = TaggedVal::from(self.func_3(
-            v0.try_as_i32()?,
-            v1.try_as_Handle()?,
-            v2.try_as_i32()?,
-            v3.try_as_Handle()?,
-        )?);
-        v1 = TaggedVal::from(65535i32);
-        v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-        Some(v0.try_as_i32()?)
+        let mut v0: i32;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        let mut v3: Handle;
+        self.checkpoint().at(0x9d)?;
+        v0 = local_0;
+        v1_handle = local_1;
+        v2 = local_2;
+        v3 = local_3;
+        v0 = self.func_3(v0, v1_handle, v2, v3).at(0xa5)?;
+        v1_i32 = 65535i32;
+        v0 = v0 & v1_i32;
+        Ok(v0)
+    }
+
+    fn func_14(&mut self, arg_0: i32) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_14_inner(arg_0)
+            .map_err(|fault| call.unwind(14, fault))
     }
 
     #[allow(
@@ -1182,15 +4003,21 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_14(&mut self, arg_0: i32) -> Option<()> {
+    fn func_14_inner(&mut self, arg_0: i32) -> Result<(), Fault> {
         let mut local_0: i32 = arg_0;
-        let mut v0: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        self.func_4(v0.try_as_i32()?)?;
-        unreachable!("Reached a point explicitly marked unreachable in WASM module");
+        let mut v0: i32;
+        self.checkpoint().at(0xb3)?;
+        v0 = local_0;
+        self.func_4(v0).at(0xb5)?;
+        return Err(Fault::new(Trap::Unreachable, 0xbb));
         // no implicit return
     }
 
+    fn func_15(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_15_inner().map_err(|fault| call.unwind(15, fault))
+    }
+
     #[allow(
         unused_mut,
         unused_variables,
@@ -1199,8 +4026,14 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_15(&mut self) -> Option<()> {
-        Some(())
+    fn func_15_inner(&mut self) -> Result<(), Fault> {
+        self.checkpoint().at(0xbf)?;
+        Ok(())
+    }
+
+    fn func_16(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_16_inner().map_err(|fault| call.unwind(16, fault))
     }
 
     #[allow(
@@ -1211,10 +4044,16 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_16(&mut self) -> Option<()> {
-        self.func_15()?;
-        self.func_17()?;
-        Some(())
+    fn func_16_inner(&mut self) -> Result<(), Fault> {
+        self.checkpoint().at(0xc2)?;
+        self.func_15().at(0xc2)?;
+        self.func_17().at(0xc8)?;
+        Ok(())
+    }
+
+    fn func_17(&mut self) -> Result<(), Trap> {
+        let mut call = self.enter_call()?;
+        call.func_17_inner().map_err(|fault| call.unwind(17, fault))
     }
 
     #[allow(
@@ -1225,128 +4064,130 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_17(&mut self) -> Option<()> {
+    fn func_17_inner(&mut self) -> Result<(), Fault> {
         let mut local_0: Handle = Handle::NULL;
         let mut local_1: i32 = 0i32;
         let mut local_2: Handle = Handle::NULL;
         let mut local_3: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_i64: i64;
+        let mut v1_handle: Handle;
+        let mut v2_i32: i32;
+        let mut v2_handle: Handle;
+        let mut v3_i32: i32;
+        let mut v3_handle: Handle;
+        let mut v4: i32;
+        self.checkpoint().at(0xd8)?;
         'label_0: loop {
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            local_0 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(40i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v0 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?
-            ));
-            v1 = TaggedVal::from(local_0);
-            v2 = TaggedVal::from(48i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            v1 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v1.try_as_handle()?.add(0)?
-            ));
-            v0 = TaggedVal::from(v0.try_as_handle()?.is_eq(v1.try_as_handle()?) as u32);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = self.globals[1].try_as_handle().at(0xda)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0xe6)?;
+            local_0 = v0_handle;
+            v1_i32 = 40i32;
+            v0_handle = v0_handle.add(v1_i32).at(0xeb)?;
+            v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0xec);
+            v1_handle = local_0;
+            v2_i32 = 48i32;
+            v1_handle = v1_handle.add(v2_i32).at(0xf3)?;
+            v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0xf4);
+            v0_i32 = v0_handle.is_eq(v1_handle) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            local_0 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(64i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v0 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v0.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
-            local_1 = v0.try_as_i32()?;
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(Handle::NULL);
-            v2 = TaggedVal::from(0i32);
-            v3 = TaggedVal::from(local_1);
+            v0_handle = self.globals[1].try_as_handle().at(0xfa)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x106)?;
+            local_0 = v0_handle;
+            v1_i32 = 64i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x10c)?;
+            v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x10d);
+            local_1 = v0_i32;
+            v0_handle = local_0;
+            v1_handle = Handle::NULL;
+            v2_i32 = 0i32;
+            v3_i32 = local_1;
             {
-                let rets = self.indirect_call(v3.try_as_i32()? as usize, &[v0, v1, v2])?;
+                let rets = self
+                    .indirect_call(
+                        v3_i32 as usize,
+                        &[
+                            TaggedVal::from(v0_handle),
+                            TaggedVal::from(v1_handle),
+                            TaggedVal::from(v2_i32),
+                        ],
+                    )
+                    .at(0x119)?;
                 if rets.len() != 1 {
-                    return None;
+                    return Err(Fault::new(Trap::IndirectCallSignatureMismatch, 0x119));
                 }
-                v0 = rets[0];
+                let _ = rets[0];
             }
 
             break;
         }
         'label_1: loop {
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            local_0 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(8i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v0 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?
-            ));
-            local_2 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(local_0);
-            v2 = TaggedVal::from(16i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            v1 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v1.try_as_handle()?.add(0)?
-            ));
-            local_0 = v1.try_as_Handle()?;
-            v0 = TaggedVal::from(v0.try_as_handle()?.is_eq(v1.try_as_handle()?) as u32);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = self.globals[1].try_as_handle().at(0x124)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x130)?;
+            local_0 = v0_handle;
+            v1_i32 = 8i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x135)?;
+            v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x136);
+            local_2 = v0_handle;
+            v1_handle = local_0;
+            v2_i32 = 16i32;
+            v1_handle = v1_handle.add(v2_i32).at(0x13f)?;
+            v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x140);
+            local_0 = v1_handle;
+            v0_i32 = v0_handle.is_eq(v1_handle) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_1;
             }
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            local_3 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(local_2);
-            v1 = TaggedVal::from(v1.try_as_handle()?.segment_offset()? as u32);
-            v2 = TaggedVal::from(local_0);
-            v2 = TaggedVal::from(v2.try_as_handle()?.segment_offset()? as u32);
-            v1 = TaggedVal::from(v1.try_as_i32()?.wrapping_sub(v2.try_as_i32()?));
-            v1 = TaggedVal::from((v1.try_as_i32()? as i64));
-            v2 = TaggedVal::from(1i32);
-            v3 = TaggedVal::from(local_3);
-            v4 = TaggedVal::from(72i32);
-            v3 = TaggedVal::from(v3.try_as_handle()?.add(v4.try_as_i32()?)?);
-            v3 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v3.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v3.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
+            v0_handle = self.globals[1].try_as_handle().at(0x148)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x154)?;
+            local_3 = v0_handle;
+            v1_handle = local_2;
+            v1_i32 = v1_handle.segment_offset().at(0x159)? as i32;
+            v2_handle = local_0;
+            v2_i32 = v2_handle.segment_offset().at(0x15c)? as i32;
+            v1_i32 = v1_i32.wrapping_sub(v2_i32);
+            v1_i64 = (v1_i32 as i64);
+            v2_i32 = 1i32;
+            v3_handle = local_3;
+            v4 = 72i32;
+            v3_handle = v3_handle.add(v4).at(0x166)?;
+            v3_i32 = read!(read_mem_i32, self.segments, v3_handle.add(0), at 0x167);
             {
-                let rets = self.indirect_call(v3.try_as_i32()? as usize, &[v0, v1, v2])?;
+                let rets = self
+                    .indirect_call(
+                        v3_i32 as usize,
+                        &[
+                            TaggedVal::from(v0_handle),
+                            TaggedVal::from(v1_i64),
+                            TaggedVal::from(v2_i32),
+                        ],
+                    )
+                    .at(0x16a)?;
                 if rets.len() != 1 {
-                    return None;
+                    return Err(Fault::new(Trap::IndirectCallSignatureMismatch, 0x16a));
                 }
-                v0 = rets[0];
+                let _ = rets[0];
             }
 
             break;
         }
-        Some(())
+        Ok(())
+    }
+
+    fn func_18(&mut self, arg_0: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_18_inner(arg_0)
+            .map_err(|fault| call.unwind(18, fault))
     }
 
     #[allow(
@@ -1357,147 +4198,99 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_18(&mut self, arg_0: Handle) -> Option<i32> {
+    fn func_18_inner(&mut self, arg_0: Handle) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: Handle = Handle::NULL;
         let mut local_2: i32 = 0i32;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(116i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        local_1 = v0.try_as_Handle()?;
-        v1 = TaggedVal::from(local_1);
-        v1 = TaggedVal::from(read_mem_i32(
-            &self
-                .segments
-                .get(v1.try_as_Handle()?.segment_index()?)?
-                .get_data()?,
-            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-        )?);
-        local_2 = v1.try_as_i32()?;
-        v2 = TaggedVal::from(-1i32);
-        v1 = TaggedVal::from(v1.try_as_i32()?.wrapping_add(v2.try_as_i32()?));
-        v2 = TaggedVal::from(local_2);
-        v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-        write_mem_i32(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i32()?,
-        )?;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_i64: i64;
+        let mut v1_handle: Handle;
+        let mut v2_i32: i32;
+        let mut v2_handle: Handle;
+        let mut v3: i32;
+        self.checkpoint().at(0x17b)?;
+        v0_handle = local_0;
+        v1_i32 = 116i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x180)?;
+        local_1 = v0_handle;
+        v1_handle = local_1;
+        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x185);
+        local_2 = v1_i32;
+        v2_i32 = -1i32;
+        v1_i32 = v1_i32.wrapping_add(v2_i32);
+        v2_i32 = local_2;
+        v1_i32 = v1_i32 | v2_i32;
+        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x190);
         'label_0: loop {
-            v0 = TaggedVal::from(local_0);
-            v0 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v0.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
-            local_2 = v0.try_as_i32()?;
-            v1 = TaggedVal::from(8i32);
-            v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-            v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = local_0;
+            v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x197);
+            local_2 = v0_i32;
+            v1_i32 = 8i32;
+            v0_i32 = v0_i32 & v1_i32;
+            v0_i32 = (v0_i32 == 0) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(local_2);
-            v2 = TaggedVal::from(32i32);
-            v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-            write_mem_i32(
-                &mut self
-                    .segments
-                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                    .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                v1.try_as_i32()?,
-            )?;
-            v0 = TaggedVal::from(-1i32);
-            return Some(v0.try_as_i32()?);
+            v0_handle = local_0;
+            v1_i32 = local_2;
+            v2_i32 = 32i32;
+            v1_i32 = v1_i32 | v2_i32;
+            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x1a9);
+            v0_i32 = -1i32;
+            return Ok(v0_i32);
             break;
         }
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(0i64);
-        write_mem_i64(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i64()?,
-        )?;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(8i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(0i64);
-        write_mem_i64(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i64()?,
-        )?;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(48i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_0);
-        v2 = TaggedVal::from(80i32);
-        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-        v1 = TaggedVal::from(read!(
-            get_handle,
-            self.segments,
-            v1.try_as_handle()?.add(0)?
-        ));
-        local_1 = v1.try_as_Handle()?;
-        write!(
-            store_handle,
-            self.segments,
-            v0.try_as_handle()?.add(0)?,
-            v1.try_as_handle()?
-        );
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(40i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_1);
-        write!(
-            store_handle,
-            self.segments,
-            v0.try_as_handle()?.add(0)?,
-            v1.try_as_handle()?
-        );
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(32i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_1);
-        v2 = TaggedVal::from(local_0);
-        v3 = TaggedVal::from(88i32);
-        v2 = TaggedVal::from(v2.try_as_handle()?.add(v3.try_as_i32()?)?);
-        v2 = TaggedVal::from(read_mem_i32(
-            &self
-                .segments
-                .get(v2.try_as_Handle()?.segment_index()?)?
-                .get_data()?,
-            (v2.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-        )?);
-        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-        write!(
-            store_handle,
-            self.segments,
-            v0.try_as_handle()?.add(0)?,
-            v1.try_as_handle()?
-        );
-        v0 = TaggedVal::from(0i32);
-        Some(v0.try_as_i32()?)
+        v0_handle = local_0;
+        v1_i32 = 16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x1b4)?;
+        v1_i64 = 0i64;
+        write!(write_mem_i64, self.segments, v0_handle.add(0), v1_i64, at 0x1b7);
+        v0_handle = local_0;
+        v1_i32 = 8i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x1be)?;
+        v1_i64 = 0i64;
+        write!(write_mem_i64, self.segments, v0_handle.add(0), v1_i64, at 0x1c1);
+        v0_handle = local_0;
+        v1_i32 = 48i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x1c8)?;
+        v1_handle = local_0;
+        v2_i32 = 80i32;
+        v1_handle = v1_handle.add(v2_i32).at(0x1ce)?;
+        v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x1cf);
+        local_1 = v1_handle;
+        write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x1d4);
+        v0_handle = local_0;
+        v1_i32 = 40i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x1db)?;
+        v1_handle = local_1;
+        write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x1de);
+        v0_handle = local_0;
+        v1_i32 = 32i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x1e5)?;
+        v1_handle = local_1;
+        v2_handle = local_0;
+        v3 = 88i32;
+        v2_handle = v2_handle.add(v3).at(0x1ed)?;
+        v2_i32 = read!(read_mem_i32, self.segments, v2_handle.add(0), at 0x1ee);
+        v1_handle = v1_handle.add(v2_i32).at(0x1f1)?;
+        write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x1f2);
+        v0_i32 = 0i32;
+        Ok(v0_i32)
+    }
+
+    fn func_19(
+        &mut self,
+        arg_0: Handle,
+        arg_1: i32,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_19_inner(arg_0, arg_1, arg_2, arg_3)
+            .map_err(|fault| call.unwind(19, fault))
     }
 
     #[allow(
@@ -1508,7 +4301,13 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_19(&mut self, arg_0: Handle, arg_1: i32, arg_2: i32, arg_3: Handle) -> Option<i32> {
+    fn func_19_inner(
+        &mut self,
+        arg_0: Handle,
+        arg_1: i32,
+        arg_2: i32,
+        arg_3: Handle,
+    ) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: i32 = arg_1;
         let mut local_2: i32 = arg_2;
@@ -1518,285 +4317,248 @@ impl WasmModule {
         let mut local_6: i32 = 0i32;
         let mut local_7: i32 = 0i32;
         let mut local_8: i32 = 0i32;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
-        v0 = TaggedVal::from(local_2);
-        v1 = TaggedVal::from(local_1);
-        v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_mul(v1.try_as_i32()?));
-        local_4 = v0.try_as_i32()?;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        let mut v3_i32: i32;
+        let mut v3_handle: Handle;
+        let mut v4: i32;
+        self.checkpoint().at(0x201)?;
+        v0_i32 = local_2;
+        v1_i32 = local_1;
+        v0_i32 = v0_i32.wrapping_mul(v1_i32);
+        local_4 = v0_i32;
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(32i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_5 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(read!(
-                    get_handle,
-                    self.segments,
-                    v0.try_as_handle()?.add(0)?
-                ));
-                v1 = TaggedVal::from(Handle::NULL);
-                v0 = TaggedVal::from(v0.try_as_handle()?.is_eq(v1.try_as_handle()?) as u32);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? ^ v1.try_as_i32()?);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_3;
+                v1_i32 = 32i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x210)?;
+                local_5 = v0_handle;
+                v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x213);
+                v1_handle = Handle::NULL;
+                v0_i32 = v0_handle.is_eq(v1_handle) as i32;
+                v1_i32 = 1i32;
+                v0_i32 = v0_i32 ^ v1_i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(0i32);
-                local_6 = v0.try_as_i32()?;
-                v0 = TaggedVal::from(local_3);
-                v0 = TaggedVal::from(self.func_18(v0.try_as_Handle()?)?);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = 0i32;
+                local_6 = v0_i32;
+                v0_handle = local_3;
+                v0_i32 = self.func_18(v0_handle).at(0x223)?;
+                if v0_i32 != 0 {
                     {}
                     break 'label_0;
                 }
                 break;
             }
             'label_2: loop {
-                v0 = TaggedVal::from(local_5);
-                v0 = TaggedVal::from(read!(
-                    get_handle,
-                    self.segments,
-                    v0.try_as_handle()?.add(0)?
-                ));
-                v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-                v1 = TaggedVal::from(local_3);
-                v2 = TaggedVal::from(40i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(read!(
-                    get_handle,
-                    self.segments,
-                    v1.try_as_handle()?.add(0)?
-                ));
-                v1 = TaggedVal::from(v1.try_as_handle()?.segment_offset()? as u32);
-                v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_sub(v1.try_as_i32()?));
-                v1 = TaggedVal::from(local_4);
-                v0 = TaggedVal::from(
-                    ((v0.try_as_i32()? as u32) >= (v1.try_as_i32()? as u32)) as i32,
-                );
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_5;
+                v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x230);
+                v0_i32 = v0_handle.segment_offset().at(0x233)? as i32;
+                v1_handle = local_3;
+                v2 = 40i32;
+                v1_handle = v1_handle.add(v2).at(0x238)?;
+                v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x239);
+                v1_i32 = v1_handle.segment_offset().at(0x23c)? as i32;
+                v0_i32 = v0_i32.wrapping_sub(v1_i32);
+                v1_i32 = local_4;
+                v0_i32 = ((v0_i32 as u32) >= (v1_i32 as u32)) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_2;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_0);
-                v2 = TaggedVal::from(local_4);
-                v3 = TaggedVal::from(local_3);
-                v4 = TaggedVal::from(64i32);
-                v3 = TaggedVal::from(v3.try_as_handle()?.add(v4.try_as_i32()?)?);
-                v3 = TaggedVal::from(read_mem_i32(
-                    &self
-                        .segments
-                        .get(v3.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v3.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )?);
+                v0_handle = local_3;
+                v1_handle = local_0;
+                v2 = local_4;
+                v3_handle = local_3;
+                v4 = 64i32;
+                v3_handle = v3_handle.add(v4).at(0x24e)?;
+                v3_i32 = read!(read_mem_i32, self.segments, v3_handle.add(0), at 0x24f);
                 {
-                    let rets = self.indirect_call(v3.try_as_i32()? as usize, &[v0, v1, v2])?;
+                    let rets = self
+                        .indirect_call(
+                            v3_i32 as usize,
+                            &[
+                                TaggedVal::from(v0_handle),
+                                TaggedVal::from(v1_handle),
+                                TaggedVal::from(v2),
+                            ],
+                        )
+                        .at(0x252)?;
                     if rets.len() != 1 {
-                        return None;
+                        return Err(Fault::new(Trap::IndirectCallSignatureMismatch, 0x252));
                     }
-                    v0 = rets[0];
+                    v0_i32 = rets[0].try_as_i32().at(0x252)?;
                 }
-                local_6 = v0.try_as_i32()?;
+                local_6 = v0_i32;
                 {}
                 break 'label_0;
                 break;
             }
-            v0 = TaggedVal::from(0i32);
-            local_7 = v0.try_as_i32()?;
+            v0_i32 = 0i32;
+            local_7 = v0_i32;
             'label_3: loop {
                 'label_4: loop {
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(120i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v0 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v0.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    v1 = TaggedVal::from(0i32);
-                    v0 = TaggedVal::from((v0.try_as_i32()? >= v1.try_as_i32()?) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    v0_handle = local_3;
+                    v1_i32 = 120i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x26b)?;
+                    v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x26c);
+                    v1_i32 = 0i32;
+                    v0_i32 = (v0_i32 >= v1_i32) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_4;
                     }
-                    v0 = TaggedVal::from(local_4);
-                    local_6 = v0.try_as_i32()?;
+                    v0_i32 = local_4;
+                    local_6 = v0_i32;
                     {}
                     break 'label_3;
                     break;
                 }
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                local_6 = v0.try_as_i32()?;
+                v0_i32 = local_4;
+                v1_i32 = 1i32;
+                v0_i32 = v0_i32.wrapping_add(v1_i32);
+                local_6 = v0_i32;
                 'label_5: loop {
+                    self.checkpoint().at(0x284)?;
                     'label_6: loop {
-                        v0 = TaggedVal::from(local_6);
-                        v1 = TaggedVal::from(1i32);
-                        v0 = TaggedVal::from((v0.try_as_i32()? != v1.try_as_i32()?) as i32);
-                        if v0.try_as_i32()? != 0 {
+                        v0_i32 = local_6;
+                        v1_i32 = 1i32;
+                        v0_i32 = (v0_i32 != v1_i32) as i32;
+                        if v0_i32 != 0 {
                             {}
                             break 'label_6;
                         }
-                        v0 = TaggedVal::from(local_4);
-                        local_6 = v0.try_as_i32()?;
-                        v0 = TaggedVal::from(0i32);
-                        local_7 = v0.try_as_i32()?;
+                        v0_i32 = local_4;
+                        local_6 = v0_i32;
+                        v0_i32 = 0i32;
+                        local_7 = v0_i32;
                         {}
                         break 'label_3;
                         break;
                     }
-                    v0 = TaggedVal::from(local_6);
-                    v1 = TaggedVal::from(-2i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                    local_8 = v0.try_as_i32()?;
-                    v0 = TaggedVal::from(local_6);
-                    v1 = TaggedVal::from(-1i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                    local_7 = v0.try_as_i32()?;
-                    local_6 = v0.try_as_i32()?;
-                    v0 = TaggedVal::from(local_0);
-                    v1 = TaggedVal::from(local_8);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v0 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v0.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    v1 = TaggedVal::from(10i32);
-                    v0 = TaggedVal::from((v0.try_as_i32()? != v1.try_as_i32()?) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    v0_i32 = local_6;
+                    v1_i32 = -2i32;
+                    v0_i32 = v0_i32.wrapping_add(v1_i32);
+                    local_8 = v0_i32;
+                    v0_i32 = local_6;
+                    v1_i32 = -1i32;
+                    v0_i32 = v0_i32.wrapping_add(v1_i32);
+                    local_7 = v0_i32;
+                    local_6 = v0_i32;
+                    v0_handle = local_0;
+                    v1_i32 = local_8;
+                    v0_handle = v0_handle.add(v1_i32).at(0x2ac)?;
+                    v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(0), at 0x2ad) as i32;
+                    v1_i32 = 10i32;
+                    v0_i32 = (v0_i32 != v1_i32) as i32;
+                    if v0_i32 != 0 {
                         {}
                         continue 'label_5;
                     }
                     break;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_0);
-                v2 = TaggedVal::from(local_7);
-                v3 = TaggedVal::from(local_3);
-                v4 = TaggedVal::from(64i32);
-                v3 = TaggedVal::from(v3.try_as_handle()?.add(v4.try_as_i32()?)?);
-                v3 = TaggedVal::from(read_mem_i32(
-                    &self
-                        .segments
-                        .get(v3.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v3.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )?);
+                v0_handle = local_3;
+                v1_handle = local_0;
+                v2 = local_7;
+                v3_handle = local_3;
+                v4 = 64i32;
+                v3_handle = v3_handle.add(v4).at(0x2c1)?;
+                v3_i32 = read!(read_mem_i32, self.segments, v3_handle.add(0), at 0x2c2);
                 {
-                    let rets = self.indirect_call(v3.try_as_i32()? as usize, &[v0, v1, v2])?;
+                    let rets = self
+                        .indirect_call(
+                            v3_i32 as usize,
+                            &[
+                                TaggedVal::from(v0_handle),
+                                TaggedVal::from(v1_handle),
+                                TaggedVal::from(v2),
+                            ],
+                        )
+                        .at(0x2c5)?;
                     if rets.len() != 1 {
-                        return None;
+                        return Err(Fault::new(Trap::IndirectCallSignatureMismatch, 0x2c5));
                     }
-                    v0 = rets[0];
+                    v0_i32 = rets[0].try_as_i32().at(0x2c5)?;
                 }
-                local_6 = v0.try_as_i32()?;
-                v1 = TaggedVal::from(local_7);
-                v0 =
-                    TaggedVal::from(((v0.try_as_i32()? as u32) < (v1.try_as_i32()? as u32)) as i32);
-                local_8 = v0.try_as_i32()?;
-                if v0.try_as_i32()? != 0 {
+                local_6 = v0_i32;
+                v1_i32 = local_7;
+                v0_i32 = ((v0_i32 as u32) < (v1_i32 as u32)) as i32;
+                local_8 = v0_i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_0;
                 }
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(local_7);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_0 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(0i32);
-                v2 = TaggedVal::from(local_7);
-                v3 = TaggedVal::from(local_8);
-                if ValType::from(v1) != ValType::from(v2) {
-                    return None;
-                }
-                if v3.try_as_i32()? != 0 {
-                    v1 = v1;
-                } else {
-                    v1 = v2;
+                v0_handle = local_0;
+                v1_i32 = local_7;
+                v0_handle = v0_handle.add(v1_i32).at(0x2d9)?;
+                local_0 = v0_handle;
+                v0_i32 = local_4;
+                v1_i32 = 0i32;
+                v2 = local_7;
+                v3_i32 = local_8;
+                if v3_i32 == 0 {
+                    v1_i32 = v2;
                 }
-                v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_sub(v1.try_as_i32()?));
-                local_6 = v0.try_as_i32()?;
+                v0_i32 = v0_i32.wrapping_sub(v1_i32);
+                local_6 = v0_i32;
                 break;
             }
-            v0 = TaggedVal::from(local_3);
-            v1 = TaggedVal::from(40i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            local_3 = v0.try_as_Handle()?;
-            v0 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?
-            ));
-            v1 = TaggedVal::from(local_0);
-            v2 = TaggedVal::from(local_6);
-            v0 = TaggedVal::from(self.func_31(
-                v0.try_as_Handle()?,
-                v1.try_as_Handle()?,
-                v2.try_as_i32()?,
-            )?);
-
-            v0 = TaggedVal::from(local_3);
-            v1 = TaggedVal::from(local_3);
-            v1 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v1.try_as_handle()?.add(0)?
-            ));
-            v2 = TaggedVal::from(local_6);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            write!(
-                store_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?,
-                v1.try_as_handle()?
-            );
-            v0 = TaggedVal::from(local_7);
-            v1 = TaggedVal::from(local_6);
-            v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-            local_6 = v0.try_as_i32()?;
+            v0_handle = local_3;
+            v1_i32 = 40i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x2ed)?;
+            local_3 = v0_handle;
+            v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x2f0);
+            v1_handle = local_0;
+            v2 = local_6;
+            v0_handle = self.func_31(v0_handle, v1_handle, v2).at(0x2f7)?;
+
+            v0_handle = local_3;
+            v1_handle = local_3;
+            v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x302);
+            v2 = local_6;
+            v1_handle = v1_handle.add(v2).at(0x307)?;
+            write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x308);
+            v0_i32 = local_7;
+            v1_i32 = local_6;
+            v0_i32 = v0_i32.wrapping_add(v1_i32);
+            local_6 = v0_i32;
             break;
         }
         'label_7: loop {
-            v0 = TaggedVal::from(local_6);
-            v1 = TaggedVal::from(local_4);
-            v0 = TaggedVal::from((v0.try_as_i32()? != v1.try_as_i32()?) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0_i32 = local_6;
+            v1_i32 = local_4;
+            v0_i32 = (v0_i32 != v1_i32) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_7;
             }
-            v0 = TaggedVal::from(local_2);
-            v1 = TaggedVal::from(0i32);
-            v2 = TaggedVal::from(local_1);
-            if ValType::from(v0) != ValType::from(v1) {
-                return None;
-            }
-            if v2.try_as_i32()? != 0 {
-                v0 = v0;
-            } else {
-                v0 = v1;
+            v0_i32 = local_2;
+            v1_i32 = 0i32;
+            v2 = local_1;
+            if v2 == 0 {
+                v0_i32 = v1_i32;
             }
-            return Some(v0.try_as_i32()?);
+            return Ok(v0_i32);
             break;
         }
-        v0 = TaggedVal::from(local_6);
-        v1 = TaggedVal::from(local_1);
-        v0 = TaggedVal::from((v0.try_as_i32()? as u32).checked_div(v1.try_as_i32()? as u32)?);
-        Some(v0.try_as_i32()?)
+        v0_i32 = local_6;
+        v1_i32 = local_1;
+        v0_i32 = ((v0_i32 as u32)
+            .checked_div(v1_i32 as u32)
+            .ok_or(Trap::IntegerDivideByZero)
+            .at(0x329)?) as i32;
+        Ok(v0_i32)
+    }
+
+    fn func_20(&mut self, arg_0: Handle, arg_1: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_20_inner(arg_0, arg_1)
+            .map_err(|fault| call.unwind(20, fault))
     }
 
     #[allow(
@@ -1807,43 +4569,42 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_20(&mut self, arg_0: Handle, arg_1: Handle) -> Option<i32> {
+    fn func_20_inner(&mut self, arg_0: Handle, arg_1: Handle) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: Handle = arg_1;
         let mut local_2: i32 = 0i32;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
-        let mut v5: TaggedVal;
-        let mut v6: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v0 = TaggedVal::from(self.func_32(v0.try_as_Handle()?)?);
-        local_2 = v0.try_as_i32()?;
-        v0 = TaggedVal::from(-1i32);
-        v1 = TaggedVal::from(0i32);
-        v2 = TaggedVal::from(local_2);
-        v3 = TaggedVal::from(local_0);
-        v4 = TaggedVal::from(1i32);
-        v5 = TaggedVal::from(local_2);
-        v6 = TaggedVal::from(local_1);
-        v3 = TaggedVal::from(self.func_19(
-            v3.try_as_Handle()?,
-            v4.try_as_i32()?,
-            v5.try_as_i32()?,
-            v6.try_as_Handle()?,
-        )?);
-        v2 = TaggedVal::from((v2.try_as_i32()? != v3.try_as_i32()?) as i32);
-        if ValType::from(v0) != ValType::from(v1) {
-            return None;
-        }
-        if v2.try_as_i32()? != 0 {
-            v0 = v0;
-        } else {
-            v0 = v1;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        let mut v2: i32;
+        let mut v3_i32: i32;
+        let mut v3_handle: Handle;
+        let mut v4: i32;
+        let mut v5: i32;
+        let mut v6: Handle;
+        self.checkpoint().at(0x32f)?;
+        v0_handle = local_0;
+        v0_i32 = self.func_32(v0_handle).at(0x331)?;
+        local_2 = v0_i32;
+        v0_i32 = -1i32;
+        v1 = 0i32;
+        v2 = local_2;
+        v3_handle = local_0;
+        v4 = 1i32;
+        v5 = local_2;
+        v6 = local_1;
+        v3_i32 = self.func_19(v3_handle, v4, v5, v6).at(0x347)?;
+        v2 = (v2 != v3_i32) as i32;
+        if v2 == 0 {
+            v0_i32 = v1;
         }
-        Some(v0.try_as_i32()?)
+        Ok(v0_i32)
+    }
+
+    fn func_21(&mut self, arg_0: Handle, arg_1: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_21_inner(arg_0, arg_1)
+            .map_err(|fault| call.unwind(21, fault))
     }
 
     #[allow(
@@ -1854,178 +4615,145 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_21(&mut self, arg_0: Handle, arg_1: i32) -> Option<i32> {
+    fn func_21_inner(&mut self, arg_0: Handle, arg_1: i32) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: i32 = arg_1;
         let mut local_2: Handle = Handle::NULL;
         let mut local_3: Handle = Handle::NULL;
         let mut local_4: i32 = 0i32;
         let mut local_5: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
-        v0 = self.globals[0];
-        v1 = TaggedVal::from(-16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        local_2 = v0.try_as_Handle()?;
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_2);
-        v1 = TaggedVal::from(local_1);
-        write_mem_u8(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(15)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(15)?.segment_offset()?) as usize,
-            v1.try_as_i32()? as u8,
-        )?;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        let mut v3_i32: i32;
+        let mut v3_handle: Handle;
+        let mut v4: i32;
+        self.checkpoint().at(0x359)?;
+        v0_handle = self.globals[0].try_as_handle().at(0x359)?;
+        v1_i32 = -16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x361)?;
+        local_2 = v0_handle;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_handle = local_2;
+        v1_i32 = local_1;
+        write!(write_mem_u8, self.segments, v0_handle.add(15), v1_i32 as u8, at 0x36e);
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(32i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_3 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(read!(
-                    get_handle,
-                    self.segments,
-                    v0.try_as_handle()?.add(0)?
-                ));
-                v1 = TaggedVal::from(Handle::NULL);
-                v0 = TaggedVal::from(v0.try_as_handle()?.is_eq(v1.try_as_handle()?) as u32);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? ^ v1.try_as_i32()?);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_0;
+                v1_i32 = 32i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x379)?;
+                local_3 = v0_handle;
+                v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x37c);
+                v1_handle = Handle::NULL;
+                v0_i32 = v0_handle.is_eq(v1_handle) as i32;
+                v1_i32 = 1i32;
+                v0_i32 = v0_i32 ^ v1_i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(-1i32);
-                local_4 = v0.try_as_i32()?;
-                v0 = TaggedVal::from(local_0);
-                v0 = TaggedVal::from(self.func_18(v0.try_as_Handle()?)?);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = -1i32;
+                local_4 = v0_i32;
+                v0_handle = local_0;
+                v0_i32 = self.func_18(v0_handle).at(0x38c)?;
+                if v0_i32 != 0 {
                     {}
                     break 'label_0;
                 }
                 break;
             }
             'label_2: loop {
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(40i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v0 = TaggedVal::from(read!(
-                    get_handle,
-                    self.segments,
-                    v0.try_as_handle()?.add(0)?
-                ));
-                local_5 = v0.try_as_Handle()?;
-                v1 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(read!(
-                    get_handle,
-                    self.segments,
-                    v1.try_as_handle()?.add(0)?
-                ));
-                v0 = TaggedVal::from(v0.try_as_handle()?.is_eq(v1.try_as_handle()?) as u32);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_0;
+                v1_i32 = 40i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x39b)?;
+                v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x39c);
+                local_5 = v0_handle;
+                v1_handle = local_3;
+                v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x3a3);
+                v0_i32 = v0_handle.is_eq(v1_handle) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_2;
                 }
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(120i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v0 = TaggedVal::from(read_mem_i32(
-                    &self
-                        .segments
-                        .get(v0.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(255i32);
-                v1 = TaggedVal::from(v1.try_as_i32()? & v2.try_as_i32()?);
-                local_4 = v1.try_as_i32()?;
-                v0 = TaggedVal::from((v0.try_as_i32()? == v1.try_as_i32()?) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_0;
+                v1_i32 = 120i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x3ae)?;
+                v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x3af);
+                v1_i32 = local_1;
+                v2 = 255i32;
+                v1_i32 = v1_i32 & v2;
+                local_4 = v1_i32;
+                v0_i32 = (v0_i32 == v1_i32) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_2;
                 }
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(40i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_5);
-                v2 = TaggedVal::from(1i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                write!(
-                    store_handle,
-                    self.segments,
-                    v0.try_as_handle()?.add(0)?,
-                    v1.try_as_handle()?
-                );
-                v0 = TaggedVal::from(local_5);
-                v1 = TaggedVal::from(local_1);
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
+                v0_handle = local_0;
+                v1_i32 = 40i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x3c1)?;
+                v1_handle = local_5;
+                v2 = 1i32;
+                v1_handle = v1_handle.add(v2).at(0x3c6)?;
+                write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x3c7);
+                v0_handle = local_5;
+                v1_i32 = local_1;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x3ce);
                 {}
                 break 'label_0;
                 break;
             }
-            v0 = TaggedVal::from(-1i32);
-            local_4 = v0.try_as_i32()?;
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(local_2);
-            v2 = TaggedVal::from(15i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            v2 = TaggedVal::from(1i32);
-            v3 = TaggedVal::from(local_0);
-            v4 = TaggedVal::from(64i32);
-            v3 = TaggedVal::from(v3.try_as_handle()?.add(v4.try_as_i32()?)?);
-            v3 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v3.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v3.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
+            v0_i32 = -1i32;
+            local_4 = v0_i32;
+            v0_handle = local_0;
+            v1_handle = local_2;
+            v2 = 15i32;
+            v1_handle = v1_handle.add(v2).at(0x3de)?;
+            v2 = 1i32;
+            v3_handle = local_0;
+            v4 = 64i32;
+            v3_handle = v3_handle.add(v4).at(0x3e6)?;
+            v3_i32 = read!(read_mem_i32, self.segments, v3_handle.add(0), at 0x3e7);
             {
-                let rets = self.indirect_call(v3.try_as_i32()? as usize, &[v0, v1, v2])?;
+                let rets = self
+                    .indirect_call(
+                        v3_i32 as usize,
+                        &[
+                            TaggedVal::from(v0_handle),
+                            TaggedVal::from(v1_handle),
+                            TaggedVal::from(v2),
+                        ],
+                    )
+                    .at(0x3ea)?;
                 if rets.len() != 1 {
-                    return None;
+                    return Err(Fault::new(Trap::IndirectCallSignatureMismatch, 0x3ea));
                 }
-                v0 = rets[0];
+                v0_i32 = rets[0].try_as_i32().at(0x3ea)?;
             }
-            v1 = TaggedVal::from(1i32);
-            v0 = TaggedVal::from((v0.try_as_i32()? != v1.try_as_i32()?) as i32);
-            if v0.try_as_i32()? != 0 {
+            v1_i32 = 1i32;
+            v0_i32 = (v0_i32 != v1_i32) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(local_2);
-            v0 = TaggedVal::from(
-                read_mem_u8(
-                    &self
-                        .segments
-                        .get(v0.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v0.try_as_Handle()?.add(15)?.segment_offset()?) as usize,
-                )
-                .and_then(|x| Some(x as i32))?,
-            );
-            local_4 = v0.try_as_i32()?;
+            v0_handle = local_2;
+            v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(15), at 0x3f8) as i32;
+            local_4 = v0_i32;
             break;
         }
-        v0 = TaggedVal::from(local_2);
-        v1 = TaggedVal::from(16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_4);
-        Some(v0.try_as_i32()?)
+        v0_handle = local_2;
+        v1_i32 = 16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x402)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_i32 = local_4;
+        Ok(v0_i32)
+    }
+
+    fn func_22(&mut self, arg_0: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_22_inner(arg_0)
+            .map_err(|fault| call.unwind(22, fault))
     }
 
     #[allow(
@@ -2036,108 +4764,91 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_22(&mut self, arg_0: Handle) -> Option<i32> {
+    fn func_22_inner(&mut self, arg_0: Handle) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        self.checkpoint().at(0x411)?;
         'label_0: loop {
-            v0 = TaggedVal::from(local_0);
-            v1 = self.globals[1];
-            v2 = TaggedVal::from(1040i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            v0 = TaggedVal::from(self.func_20(v0.try_as_Handle()?, v1.try_as_Handle()?)?);
-            v1 = TaggedVal::from(0i32);
-            v0 = TaggedVal::from((v0.try_as_i32()? >= v1.try_as_i32()?) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = local_0;
+            v1_handle = self.globals[1].try_as_handle().at(0x415)?;
+            v2 = 1040i32;
+            v1_handle = v1_handle.add(v2).at(0x421)?;
+            v0_i32 = self.func_20(v0_handle, v1_handle).at(0x422)?;
+            v1_i32 = 0i32;
+            v0_i32 = (v0_i32 >= v1_i32) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(-1i32);
-            return Some(v0.try_as_i32()?);
+            v0_i32 = -1i32;
+            return Ok(v0_i32);
             break;
         }
         'label_1: loop {
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v1 = TaggedVal::from(120i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v0 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v0.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
-            v1 = TaggedVal::from(10i32);
-            v0 = TaggedVal::from((v0.try_as_i32()? == v1.try_as_i32()?) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = self.globals[1].try_as_handle().at(0x433)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x43f)?;
+            v1_i32 = 120i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x443)?;
+            v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x444);
+            v1_i32 = 10i32;
+            v0_i32 = (v0_i32 == v1_i32) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_1;
             }
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            local_1 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(40i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v0 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?
-            ));
-            local_0 = v0.try_as_Handle()?;
-            v1 = TaggedVal::from(local_1);
-            v2 = TaggedVal::from(32i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            v1 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v1.try_as_handle()?.add(0)?
-            ));
-            v0 = TaggedVal::from(v0.try_as_handle()?.is_eq(v1.try_as_handle()?) as u32);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = self.globals[1].try_as_handle().at(0x44c)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x458)?;
+            local_1 = v0_handle;
+            v1_i32 = 40i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x45d)?;
+            v0_handle = read!(get_handle, self.segments, v0_handle.add(0), at 0x45e);
+            local_0 = v0_handle;
+            v1_handle = local_1;
+            v2 = 32i32;
+            v1_handle = v1_handle.add(v2).at(0x467)?;
+            v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x468);
+            v0_i32 = v0_handle.is_eq(v1_handle) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_1;
             }
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1040i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v1 = TaggedVal::from(40i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v1 = TaggedVal::from(local_0);
-            v2 = TaggedVal::from(1i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            write!(
-                store_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?,
-                v1.try_as_handle()?
-            );
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(10i32);
-            write_mem_u8(
-                &mut self
-                    .segments
-                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                    .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                v1.try_as_i32()? as u8,
-            )?;
-            v0 = TaggedVal::from(0i32);
-            return Some(v0.try_as_i32()?);
+            v0_handle = self.globals[1].try_as_handle().at(0x46e)?;
+            v1_i32 = 1040i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x47a)?;
+            v1_i32 = 40i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x47d)?;
+            v1_handle = local_0;
+            v2 = 1i32;
+            v1_handle = v1_handle.add(v2).at(0x482)?;
+            write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x483);
+            v0_handle = local_0;
+            v1_i32 = 10i32;
+            write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x48a);
+            v0_i32 = 0i32;
+            return Ok(v0_i32);
             break;
         }
-        v0 = self.globals[1];
-        v1 = TaggedVal::from(1040i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(10i32);
-        v0 = TaggedVal::from(self.func_21(v0.try_as_Handle()?, v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(31i32);
-        v0 = TaggedVal::from(v0.try_as_i32()? >> (v1.try_as_i32()? % 32));
-        Some(v0.try_as_i32()?)
+        v0_handle = self.globals[1].try_as_handle().at(0x491)?;
+        v1_i32 = 1040i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x49d)?;
+        v1_i32 = 10i32;
+        v0_i32 = self.func_21(v0_handle, v1_i32).at(0x4a0)?;
+        v1_i32 = 31i32;
+        v0_i32 = v0_i32 >> (v1_i32 % 32);
+        Ok(v0_i32)
+    }
+
+    fn func_23(&mut self, arg_0: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_23_inner(arg_0)
+            .map_err(|fault| call.unwind(23, fault))
     }
 
     #[allow(
@@ -2148,36 +4859,37 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_23(&mut self, arg_0: i32) -> Option<i32> {
+    fn func_23_inner(&mut self, arg_0: i32) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        self.checkpoint().at(0x4ac)?;
         'label_0: loop {
-            v0 = TaggedVal::from(local_0);
-            v0 = TaggedVal::from(self.func_10(v0.try_as_i32()?)?);
-            local_0 = v0.try_as_i32()?;
-            if v0.try_as_i32()? != 0 {
+            v0_i32 = local_0;
+            v0_i32 = self.func_10(v0_i32).at(0x4b0)?;
+            local_0 = v0_i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(0i32);
-            return Some(v0.try_as_i32()?);
+            v0_i32 = 0i32;
+            return Ok(v0_i32);
             break;
         }
-        v0 = self.globals[1];
-        v1 = TaggedVal::from(1232i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_0);
-        write_mem_i32(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i32()?,
-        )?;
-        v0 = TaggedVal::from(-1i32);
-        Some(v0.try_as_i32()?)
+        v0_handle = self.globals[1].try_as_handle().at(0x4be)?;
+        v1 = 1232i32;
+        v0_handle = v0_handle.add(v1).at(0x4ca)?;
+        v1 = local_0;
+        write!(write_mem_i32, self.segments, v0_handle.add(0), v1, at 0x4cd);
+        v0_i32 = -1i32;
+        Ok(v0_i32)
+    }
+
+    fn func_24(&mut self, arg_0: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_24_inner(arg_0)
+            .map_err(|fault| call.unwind(24, fault))
     }
 
     #[allow(
@@ -2188,22 +4900,24 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_24(&mut self, arg_0: Handle) -> Option<i32> {
+    fn func_24_inner(&mut self, arg_0: Handle) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(112i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v0 = TaggedVal::from(read_mem_i32(
-            &self
-                .segments
-                .get(v0.try_as_Handle()?.segment_index()?)?
-                .get_data()?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-        )?);
-        v0 = TaggedVal::from(self.func_23(v0.try_as_i32()?)?);
-        Some(v0.try_as_i32()?)
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        self.checkpoint().at(0x4d5)?;
+        v0_handle = local_0;
+        v1 = 112i32;
+        v0_handle = v0_handle.add(v1).at(0x4da)?;
+        v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x4db);
+        v0_i32 = self.func_23(v0_i32).at(0x4de)?;
+        Ok(v0_i32)
+    }
+
+    fn func_25(&mut self, arg_0: i32, arg_1: Handle, arg_2: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_25_inner(arg_0, arg_1, arg_2)
+            .map_err(|fault| call.unwind(25, fault))
     }
 
     #[allow(
@@ -2214,103 +4928,87 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_25(&mut self, arg_0: i32, arg_1: Handle, arg_2: i32) -> Option<i32> {
+    fn func_25_inner(&mut self, arg_0: i32, arg_1: Handle, arg_2: i32) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
         let mut local_1: Handle = arg_1;
         let mut local_2: i32 = arg_2;
         let mut local_3: Handle = Handle::NULL;
         let mut local_4: i32 = 0i32;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
-        v0 = self.globals[0];
-        v1 = TaggedVal::from(-16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        local_3 = v0.try_as_Handle()?;
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(-1i32);
-        local_4 = v0.try_as_i32()?;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        let mut v3: Handle;
+        let mut v4: i32;
+        self.checkpoint().at(0x4ec)?;
+        v0_handle = self.globals[0].try_as_handle().at(0x4ec)?;
+        v1_i32 = -16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x4f4)?;
+        local_3 = v0_handle;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_i32 = -1i32;
+        local_4 = v0_i32;
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_2);
-                v1 = TaggedVal::from(-1i32);
-                v0 = TaggedVal::from((v0.try_as_i32()? > v1.try_as_i32()?) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_2;
+                v1_i32 = -1i32;
+                v0_i32 = (v0_i32 > v1_i32) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = self.globals[1];
-                v1 = TaggedVal::from(1232i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(28i32);
-                write_mem_i32(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()?,
-                )?;
+                v0_handle = self.globals[1].try_as_handle().at(0x50c)?;
+                v1_i32 = 1232i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x518)?;
+                v1_i32 = 28i32;
+                write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x51b);
                 {}
                 break 'label_0;
                 break;
             }
             'label_2: loop {
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(local_2);
-                v3 = TaggedVal::from(local_3);
-                v4 = TaggedVal::from(12i32);
-                v3 = TaggedVal::from(v3.try_as_handle()?.add(v4.try_as_i32()?)?);
-                v0 = TaggedVal::from(self.func_13(
-                    v0.try_as_i32()?,
-                    v1.try_as_Handle()?,
-                    v2.try_as_i32()?,
-                    v3.try_as_Handle()?,
-                )?);
-                local_2 = v0.try_as_i32()?;
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_0;
+                v1_handle = local_1;
+                v2 = local_2;
+                v3 = local_3;
+                v4 = 12i32;
+                v3 = v3.add(v4).at(0x52d)?;
+                v0_i32 = self.func_13(v0_i32, v1_handle, v2, v3).at(0x52e)?;
+                local_2 = v0_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_2;
                 }
-                v0 = self.globals[1];
-                v1 = TaggedVal::from(1232i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_2);
-                write_mem_i32(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()?,
-                )?;
-                v0 = TaggedVal::from(-1i32);
-                local_4 = v0.try_as_i32()?;
+                v0_handle = self.globals[1].try_as_handle().at(0x539)?;
+                v1_i32 = 1232i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x545)?;
+                v1_i32 = local_2;
+                write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x548);
+                v0_i32 = -1i32;
+                local_4 = v0_i32;
                 {}
                 break 'label_0;
                 break;
             }
-            v0 = TaggedVal::from(local_3);
-            v0 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v0.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v0.try_as_Handle()?.add(12)?.segment_offset()?) as usize,
-            )?);
-            local_4 = v0.try_as_i32()?;
+            v0_handle = local_3;
+            v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(12), at 0x554);
+            local_4 = v0_i32;
             break;
         }
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_4);
-        Some(v0.try_as_i32()?)
+        v0_handle = local_3;
+        v1_i32 = 16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x55e)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_i32 = local_4;
+        Ok(v0_i32)
+    }
+
+    fn func_26(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_26_inner(arg_0, arg_1, arg_2)
+            .map_err(|fault| call.unwind(26, fault))
     }
 
     #[allow(
@@ -2321,7 +5019,7 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_26(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Option<i32> {
+    fn func_26_inner(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: Handle = arg_1;
         let mut local_2: i32 = arg_2;
@@ -2335,323 +5033,193 @@ impl WasmModule {
         let mut local_10: i32 = 0i32;
         let mut local_11: i32 = 0i32;
         let mut local_12: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
-        let mut v5: TaggedVal;
-        v0 = self.globals[0];
-        v1 = TaggedVal::from(-32i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        local_3 = v0.try_as_Handle()?;
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_1);
-        write!(
-            store_handle,
-            self.segments,
-            v0.try_as_handle()?.add(0)?,
-            v1.try_as_handle()?
-        );
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(24i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_2);
-        write_mem_i32(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i32()?,
-        )?;
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(8i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(local_0);
-        v2 = TaggedVal::from(40i32);
-        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-        local_4 = v1.try_as_Handle()?;
-        v1 = TaggedVal::from(read!(
-            get_handle,
-            self.segments,
-            v1.try_as_handle()?.add(0)?
-        ));
-        v1 = TaggedVal::from(v1.try_as_handle()?.segment_offset()? as u32);
-        v2 = TaggedVal::from(local_0);
-        v3 = TaggedVal::from(48i32);
-        v2 = TaggedVal::from(v2.try_as_handle()?.add(v3.try_as_i32()?)?);
-        local_5 = v2.try_as_Handle()?;
-        v2 = TaggedVal::from(read!(
-            get_handle,
-            self.segments,
-            v2.try_as_handle()?.add(0)?
-        ));
-        local_1 = v2.try_as_Handle()?;
-        v2 = TaggedVal::from(v2.try_as_handle()?.segment_offset()? as u32);
-        v1 = TaggedVal::from(v1.try_as_i32()?.wrapping_sub(v2.try_as_i32()?));
-        local_6 = v1.try_as_i32()?;
-        write_mem_i32(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i32()?,
-        )?;
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(local_1);
-        write!(
-            store_handle,
-            self.segments,
-            v0.try_as_handle()?.add(0)?,
-            v1.try_as_handle()?
-        );
-        v0 = TaggedVal::from(2i32);
-        local_7 = v0.try_as_i32()?;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_i64: i64;
+        let mut v1_handle: Handle;
+        let mut v2_i32: i32;
+        let mut v2_handle: Handle;
+        let mut v3_i32: i32;
+        let mut v3_handle: Handle;
+        let mut v4: i32;
+        let mut v5: i32;
+        self.checkpoint().at(0x575)?;
+        v0_handle = self.globals[0].try_as_handle().at(0x575)?;
+        v1_i32 = -32i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x57d)?;
+        local_3 = v0_handle;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_handle = local_3;
+        v1_i32 = 16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x58a)?;
+        v1_handle = local_1;
+        write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x58d);
+        v0_handle = local_3;
+        v1_i32 = 24i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x594)?;
+        v1_i32 = local_2;
+        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x597);
+        v0_handle = local_3;
+        v1_i32 = 8i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x59e)?;
+        v1_handle = local_0;
+        v2_i32 = 40i32;
+        v1_handle = v1_handle.add(v2_i32).at(0x5a3)?;
+        local_4 = v1_handle;
+        v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x5a6);
+        v1_i32 = v1_handle.segment_offset().at(0x5a9)? as i32;
+        v2_handle = local_0;
+        v3_i32 = 48i32;
+        v2_handle = v2_handle.add(v3_i32).at(0x5ae)?;
+        local_5 = v2_handle;
+        v2_handle = read!(get_handle, self.segments, v2_handle.add(0), at 0x5b1);
+        local_1 = v2_handle;
+        v2_i32 = v2_handle.segment_offset().at(0x5b6)? as i32;
+        v1_i32 = v1_i32.wrapping_sub(v2_i32);
+        local_6 = v1_i32;
+        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x5ba);
+        v0_handle = local_3;
+        v1_handle = local_1;
+        write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x5c1);
+        v0_i32 = 2i32;
+        local_7 = v0_i32;
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_6);
-                v1 = TaggedVal::from(local_2);
-                v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                local_8 = v0.try_as_i32()?;
-                v1 = TaggedVal::from(local_0);
-                v2 = TaggedVal::from(112i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                local_9 = v1.try_as_Handle()?;
-                v1 = TaggedVal::from(read_mem_i32(
-                    &self
-                        .segments
-                        .get(v1.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )?);
-                v2 = TaggedVal::from(local_3);
-                v3 = TaggedVal::from(2i32);
-                v1 = TaggedVal::from(self.func_25(
-                    v1.try_as_i32()?,
-                    v2.try_as_Handle()?,
-                    v3.try_as_i32()?,
-                )?);
-                local_6 = v1.try_as_i32()?;
-                v0 = TaggedVal::from((v0.try_as_i32()? == v1.try_as_i32()?) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_6;
+                v1_i32 = local_2;
+                v0_i32 = v0_i32.wrapping_add(v1_i32);
+                local_8 = v0_i32;
+                v1_handle = local_0;
+                v2_i32 = 112i32;
+                v1_handle = v1_handle.add(v2_i32).at(0x5d8)?;
+                local_9 = v1_handle;
+                v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x5db);
+                v2_handle = local_3;
+                v3_i32 = 2i32;
+                v1_i32 = self.func_25(v1_i32, v2_handle, v3_i32).at(0x5e2)?;
+                local_6 = v1_i32;
+                v0_i32 = (v0_i32 == v1_i32) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(local_3);
-                local_1 = v0.try_as_Handle()?;
+                v0_handle = local_3;
+                local_1 = v0_handle;
                 'label_2: loop {
+                    self.checkpoint().at(0x5f3)?;
                     'label_3: loop {
-                        v0 = TaggedVal::from(local_6);
-                        v1 = TaggedVal::from(-1i32);
-                        v0 = TaggedVal::from((v0.try_as_i32()? > v1.try_as_i32()?) as i32);
-                        if v0.try_as_i32()? != 0 {
+                        v0_i32 = local_6;
+                        v1_i32 = -1i32;
+                        v0_i32 = (v0_i32 > v1_i32) as i32;
+                        if v0_i32 != 0 {
                             {}
                             break 'label_3;
                         }
-                        v0 = TaggedVal::from(local_0);
-                        v1 = TaggedVal::from(48i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(0i64);
-                        write_mem_i64(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i64()?,
-                        )?;
-                        v0 = TaggedVal::from(local_0);
-                        v1 = TaggedVal::from(40i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(0i64);
-                        write_mem_i64(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i64()?,
-                        )?;
-                        v0 = TaggedVal::from(local_0);
-                        v1 = TaggedVal::from(32i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(0i64);
-                        write_mem_i64(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i64()?,
-                        )?;
-                        v0 = TaggedVal::from(local_0);
-                        v1 = TaggedVal::from(local_0);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        v2 = TaggedVal::from(32i32);
-                        v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(0i32);
-                        local_6 = v0.try_as_i32()?;
-                        v0 = TaggedVal::from(local_7);
-                        v1 = TaggedVal::from(2i32);
-                        v0 = TaggedVal::from((v0.try_as_i32()? == v1.try_as_i32()?) as i32);
-                        if v0.try_as_i32()? != 0 {
+                        v0_handle = local_0;
+                        v1_i32 = 48i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x600)?;
+                        v1_i64 = 0i64;
+                        write!(write_mem_i64, self.segments, v0_handle.add(0), v1_i64, at 0x603);
+                        v0_handle = local_0;
+                        v1_i32 = 40i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x60a)?;
+                        v1_i64 = 0i64;
+                        write!(write_mem_i64, self.segments, v0_handle.add(0), v1_i64, at 0x60d);
+                        v0_handle = local_0;
+                        v1_i32 = 32i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x614)?;
+                        v1_i64 = 0i64;
+                        write!(write_mem_i64, self.segments, v0_handle.add(0), v1_i64, at 0x617);
+                        v0_handle = local_0;
+                        v1_handle = local_0;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x61e);
+                        v2_i32 = 32i32;
+                        v1_i32 = v1_i32 | v2_i32;
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x624);
+                        v0_i32 = 0i32;
+                        local_6 = v0_i32;
+                        v0_i32 = local_7;
+                        v1_i32 = 2i32;
+                        v0_i32 = (v0_i32 == v1_i32) as i32;
+                        if v0_i32 != 0 {
                             {}
                             break 'label_0;
                         }
-                        v0 = TaggedVal::from(local_2);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(8i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_sub(v1.try_as_i32()?));
-                        local_6 = v0.try_as_i32()?;
+                        v0_i32 = local_2;
+                        v1_handle = local_1;
+                        v2_i32 = 8i32;
+                        v1_handle = v1_handle.add(v2_i32).at(0x638)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x639);
+                        v0_i32 = v0_i32.wrapping_sub(v1_i32);
+                        local_6 = v0_i32;
                         {}
                         break 'label_0;
                         break;
                     }
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(local_6);
-                    v2 = TaggedVal::from(local_1);
-                    v3 = TaggedVal::from(8i32);
-                    v2 = TaggedVal::from(v2.try_as_handle()?.add(v3.try_as_i32()?)?);
-                    v2 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v2.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v2.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    local_10 = v2.try_as_i32()?;
-                    v1 = TaggedVal::from(
-                        ((v1.try_as_i32()? as u32) > (v2.try_as_i32()? as u32)) as i32,
-                    );
-                    local_11 = v1.try_as_i32()?;
-                    v2 = TaggedVal::from(4i32);
-                    v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_12 = v0.try_as_Handle()?;
-                    v1 = TaggedVal::from(local_12);
-                    v1 = TaggedVal::from(read!(
-                        get_handle,
-                        self.segments,
-                        v1.try_as_handle()?.add(0)?
-                    ));
-                    v2 = TaggedVal::from(local_6);
-                    v3 = TaggedVal::from(local_10);
-                    v4 = TaggedVal::from(0i32);
-                    v5 = TaggedVal::from(local_11);
-                    if ValType::from(v3) != ValType::from(v4) {
-                        return None;
+                    v0_handle = local_1;
+                    v1_i32 = local_6;
+                    v2_handle = local_1;
+                    v3_i32 = 8i32;
+                    v2_handle = v2_handle.add(v3_i32).at(0x64a)?;
+                    v2_i32 = read!(read_mem_i32, self.segments, v2_handle.add(0), at 0x64b);
+                    local_10 = v2_i32;
+                    v1_i32 = ((v1_i32 as u32) > (v2_i32 as u32)) as i32;
+                    local_11 = v1_i32;
+                    v2_i32 = 4i32;
+                    v1_i32 = v1_i32 << (v2_i32 % 32);
+                    v0_handle = v0_handle.add(v1_i32).at(0x656)?;
+                    local_12 = v0_handle;
+                    v1_handle = local_12;
+                    v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x65b);
+                    v2_i32 = local_6;
+                    v3_i32 = local_10;
+                    v4 = 0i32;
+                    v5 = local_11;
+                    if v5 == 0 {
+                        v3_i32 = v4;
                     }
-                    if v5.try_as_i32()? != 0 {
-                        v3 = v3;
-                    } else {
-                        v3 = v4;
+                    v2_i32 = v2_i32.wrapping_sub(v3_i32);
+                    local_10 = v2_i32;
+                    v1_handle = v1_handle.add(v2_i32).at(0x66a)?;
+                    write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x66b);
+                    v0_handle = local_1;
+                    v1_i32 = 24i32;
+                    v2_i32 = 8i32;
+                    v3_i32 = local_11;
+                    if v3_i32 == 0 {
+                        v1_i32 = v2_i32;
                     }
-                    v2 = TaggedVal::from(v2.try_as_i32()?.wrapping_sub(v3.try_as_i32()?));
-                    local_10 = v2.try_as_i32()?;
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    write!(
-                        store_handle,
-                        self.segments,
-                        v0.try_as_handle()?.add(0)?,
-                        v1.try_as_handle()?
-                    );
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(24i32);
-                    v2 = TaggedVal::from(8i32);
-                    v3 = TaggedVal::from(local_11);
-                    if ValType::from(v1) != ValType::from(v2) {
-                        return None;
+                    v0_handle = v0_handle.add(v1_i32).at(0x677)?;
+                    local_12 = v0_handle;
+                    v1_handle = local_12;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x67c);
+                    v2_i32 = local_10;
+                    v1_i32 = v1_i32.wrapping_sub(v2_i32);
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x682);
+                    v0_i32 = local_8;
+                    v1_i32 = local_6;
+                    v0_i32 = v0_i32.wrapping_sub(v1_i32);
+                    local_8 = v0_i32;
+                    v1_handle = local_9;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x68e);
+                    v2_handle = local_1;
+                    v3_i32 = 16i32;
+                    v2_handle = v2_handle.add(v3_i32).at(0x695)?;
+                    v3_handle = local_1;
+                    v4 = local_11;
+                    if v4 == 0 {
+                        v2_handle = v3_handle;
                     }
-                    if v3.try_as_i32()? != 0 {
-                        v1 = v1;
-                    } else {
-                        v1 = v2;
-                    }
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_12 = v0.try_as_Handle()?;
-                    v1 = TaggedVal::from(local_12);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    v2 = TaggedVal::from(local_10);
-                    v1 = TaggedVal::from(v1.try_as_i32()?.wrapping_sub(v2.try_as_i32()?));
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_8);
-                    v1 = TaggedVal::from(local_6);
-                    v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_sub(v1.try_as_i32()?));
-                    local_8 = v0.try_as_i32()?;
-                    v1 = TaggedVal::from(local_9);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    v2 = TaggedVal::from(local_1);
-                    v3 = TaggedVal::from(16i32);
-                    v2 = TaggedVal::from(v2.try_as_handle()?.add(v3.try_as_i32()?)?);
-                    v3 = TaggedVal::from(local_1);
-                    v4 = TaggedVal::from(local_11);
-                    if ValType::from(v2) != ValType::from(v3) {
-                        return None;
-                    }
-                    if v4.try_as_i32()? != 0 {
-                        v2 = v2;
-                    } else {
-                        v2 = v3;
-                    }
-                    local_1 = v2.try_as_Handle()?;
-                    v3 = TaggedVal::from(local_7);
-                    v4 = TaggedVal::from(local_11);
-                    v3 = TaggedVal::from(v3.try_as_i32()?.wrapping_sub(v4.try_as_i32()?));
-                    local_7 = v3.try_as_i32()?;
-                    v1 = TaggedVal::from(self.func_25(
-                        v1.try_as_i32()?,
-                        v2.try_as_Handle()?,
-                        v3.try_as_i32()?,
-                    )?);
-                    local_6 = v1.try_as_i32()?;
-                    v0 = TaggedVal::from((v0.try_as_i32()? != v1.try_as_i32()?) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    local_1 = v2_handle;
+                    v3_i32 = local_7;
+                    v4 = local_11;
+                    v3_i32 = v3_i32.wrapping_sub(v4);
+                    local_7 = v3_i32;
+                    v1_i32 = self.func_25(v1_i32, v2_handle, v3_i32).at(0x6a4)?;
+                    local_6 = v1_i32;
+                    v0_i32 = (v0_i32 != v1_i32) as i32;
+                    if v0_i32 != 0 {
                         {}
                         continue 'label_2;
                     }
@@ -2659,61 +5227,42 @@ impl WasmModule {
                 }
                 break;
             }
-            v0 = TaggedVal::from(local_5);
-            v1 = TaggedVal::from(local_0);
-            v2 = TaggedVal::from(80i32);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            v1 = TaggedVal::from(read!(
-                get_handle,
-                self.segments,
-                v1.try_as_handle()?.add(0)?
-            ));
-            local_1 = v1.try_as_Handle()?;
-            write!(
-                store_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?,
-                v1.try_as_handle()?
-            );
-            v0 = TaggedVal::from(local_4);
-            v1 = TaggedVal::from(local_1);
-            write!(
-                store_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?,
-                v1.try_as_handle()?
-            );
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(32i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v1 = TaggedVal::from(local_1);
-            v2 = TaggedVal::from(local_0);
-            v3 = TaggedVal::from(88i32);
-            v2 = TaggedVal::from(v2.try_as_handle()?.add(v3.try_as_i32()?)?);
-            v2 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v2.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v2.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
-            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-            write!(
-                store_handle,
-                self.segments,
-                v0.try_as_handle()?.add(0)?,
-                v1.try_as_handle()?
-            );
-            v0 = TaggedVal::from(local_2);
-            local_6 = v0.try_as_i32()?;
+            v0_handle = local_5;
+            v1_handle = local_0;
+            v2_i32 = 80i32;
+            v1_handle = v1_handle.add(v2_i32).at(0x6b8)?;
+            v1_handle = read!(get_handle, self.segments, v1_handle.add(0), at 0x6b9);
+            local_1 = v1_handle;
+            write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x6be);
+            v0_handle = local_4;
+            v1_handle = local_1;
+            write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x6c5);
+            v0_handle = local_0;
+            v1_i32 = 32i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x6cc)?;
+            v1_handle = local_1;
+            v2_handle = local_0;
+            v3_i32 = 88i32;
+            v2_handle = v2_handle.add(v3_i32).at(0x6d4)?;
+            v2_i32 = read!(read_mem_i32, self.segments, v2_handle.add(0), at 0x6d5);
+            v1_handle = v1_handle.add(v2_i32).at(0x6d8)?;
+            write!(store_handle, self.segments, v0_handle.add(0), v1_handle, at 0x6d9);
+            v0_i32 = local_2;
+            local_6 = v0_i32;
             break;
         }
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(32i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_6);
-        Some(v0.try_as_i32()?)
+        v0_handle = local_3;
+        v1_i32 = 32i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x6e5)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_i32 = local_6;
+        Ok(v0_i32)
+    }
+
+    fn func_27(&mut self, arg_0: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_27_inner(arg_0)
+            .map_err(|fault| call.unwind(27, fault))
     }
 
     #[allow(
@@ -2724,95 +5273,79 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_27(&mut self, arg_0: i32) -> Option<i32> {
+    fn func_27_inner(&mut self, arg_0: i32) -> Result<i32, Fault> {
         let mut local_0: i32 = arg_0;
         let mut local_1: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        v0 = self.globals[0];
-        v1 = TaggedVal::from(-32i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        local_1 = v0.try_as_Handle()?;
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        self.checkpoint().at(0x6f3)?;
+        v0_handle = self.globals[0].try_as_handle().at(0x6f3)?;
+        v1_i32 = -32i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x6fb)?;
+        local_1 = v0_handle;
+        self.globals[0] = TaggedVal::from(v0_handle);
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(8i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v0 = TaggedVal::from(self.func_11(v0.try_as_i32()?, v1.try_as_Handle()?)?);
-                local_0 = v0.try_as_i32()?;
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_0;
+                v1_handle = local_1;
+                v2 = 8i32;
+                v1_handle = v1_handle.add(v2).at(0x70e)?;
+                v0_i32 = self.func_11(v0_i32, v1_handle).at(0x70f)?;
+                local_0 = v0_i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(59i32);
-                local_0 = v0.try_as_i32()?;
-                v0 = TaggedVal::from(local_1);
-                v0 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v0.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v0.try_as_Handle()?.add(8)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from((v0.try_as_i32()? != v1.try_as_i32()?) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = 59i32;
+                local_0 = v0_i32;
+                v0_handle = local_1;
+                v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(8), at 0x71f) as i32;
+                v1_i32 = 2i32;
+                v0_i32 = (v0_i32 != v1_i32) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(16i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v0 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v0.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                v1 = TaggedVal::from(36i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_1;
+                v1_i32 = 16i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x72b)?;
+                v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(0), at 0x72c) as i32;
+                v1_i32 = 36i32;
+                v0_i32 = v0_i32 & v1_i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(1i32);
-                local_0 = v0.try_as_i32()?;
+                v0_i32 = 1i32;
+                local_0 = v0_i32;
                 {}
                 break 'label_0;
                 break;
             }
-            v0 = self.globals[1];
-            v1 = TaggedVal::from(1232i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v1 = TaggedVal::from(local_0);
-            write_mem_i32(
-                &mut self
-                    .segments
-                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                    .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                v1.try_as_i32()?,
-            )?;
-            v0 = TaggedVal::from(0i32);
-            local_0 = v0.try_as_i32()?;
+            v0_handle = self.globals[1].try_as_handle().at(0x73b)?;
+            v1_i32 = 1232i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x747)?;
+            v1_i32 = local_0;
+            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x74a);
+            v0_i32 = 0i32;
+            local_0 = v0_i32;
             break;
         }
-        v0 = TaggedVal::from(local_1);
-        v1 = TaggedVal::from(32i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_0);
-        Some(v0.try_as_i32()?)
+        v0_handle = local_1;
+        v1_i32 = 32i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x756)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_i32 = local_0;
+        Ok(v0_i32)
+    }
+
+    fn func_28(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_28_inner(arg_0, arg_1, arg_2)
+            .map_err(|fault| call.unwind(28, fault))
     }
 
     #[allow(
@@ -2823,81 +5356,57 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_28(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Option<i32> {
+    fn func_28_inner(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: Handle = arg_1;
         let mut local_2: i32 = arg_2;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(64i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v1 = TaggedVal::from(1i32);
-        write_mem_i32(
-            &mut self
-                .segments
-                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            v1.try_as_i32()?,
-        )?;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        self.checkpoint().at(0x762)?;
+        v0_handle = local_0;
+        v1_i32 = 64i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x767)?;
+        v1_i32 = 1i32;
+        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x76e);
         'label_0: loop {
-            v0 = TaggedVal::from(local_0);
-            v0 = TaggedVal::from(
-                read_mem_u8(
-                    &self
-                        .segments
-                        .get(v0.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )
-                .and_then(|x| Some(x as i32))?,
-            );
-            v1 = TaggedVal::from(64i32);
-            v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = local_0;
+            v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(0), at 0x775) as i32;
+            v1_i32 = 64i32;
+            v0_i32 = v0_i32 & v1_i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(112i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v0 = TaggedVal::from(read_mem_i32(
-                &self
-                    .segments
-                    .get(v0.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-            )?);
-            v0 = TaggedVal::from(self.func_27(v0.try_as_i32()?)?);
-            if v0.try_as_i32()? != 0 {
+            v0_handle = local_0;
+            v1_i32 = 112i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x783)?;
+            v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x784);
+            v0_i32 = self.func_27(v0_i32).at(0x787)?;
+            if v0_i32 != 0 {
                 {}
                 break 'label_0;
             }
-            v0 = TaggedVal::from(local_0);
-            v1 = TaggedVal::from(120i32);
-            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-            v1 = TaggedVal::from(-1i32);
-            write_mem_i32(
-                &mut self
-                    .segments
-                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                    .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                v1.try_as_i32()?,
-            )?;
+            v0_handle = local_0;
+            v1_i32 = 120i32;
+            v0_handle = v0_handle.add(v1_i32).at(0x794)?;
+            v1_i32 = -1i32;
+            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x797);
             break;
         }
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(local_1);
-        v2 = TaggedVal::from(local_2);
-        v0 = TaggedVal::from(self.func_26(
-            v0.try_as_Handle()?,
-            v1.try_as_Handle()?,
-            v2.try_as_i32()?,
-        )?);
-        Some(v0.try_as_i32()?)
+        v0_handle = local_0;
+        v1_handle = local_1;
+        v2 = local_2;
+        v0_i32 = self.func_26(v0_handle, v1_handle, v2).at(0x7a1)?;
+        Ok(v0_i32)
+    }
+
+    fn func_29(&mut self, arg_0: i32, arg_1: i64, arg_2: i32) -> Result<i64, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_29_inner(arg_0, arg_1, arg_2)
+            .map_err(|fault| call.unwind(29, fault))
     }
 
     #[allow(
@@ -2908,90 +5417,78 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_29(&mut self, arg_0: i32, arg_1: i64, arg_2: i32) -> Option<i64> {
+    fn func_29_inner(&mut self, arg_0: i32, arg_1: i64, arg_2: i32) -> Result<i64, Fault> {
         let mut local_0: i32 = arg_0;
         let mut local_1: i64 = arg_1;
         let mut local_2: i32 = arg_2;
         let mut local_3: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
-        let mut v4: TaggedVal;
-        v0 = self.globals[0];
-        v1 = TaggedVal::from(-16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        local_3 = v0.try_as_Handle()?;
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
+        let mut v0_i32: i32;
+        let mut v0_i64: i64;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_i64: i64;
+        let mut v2: i32;
+        let mut v3_i32: i32;
+        let mut v3_handle: Handle;
+        let mut v4: i32;
+        self.checkpoint().at(0x7ac)?;
+        v0_handle = self.globals[0].try_as_handle().at(0x7ac)?;
+        v1_i32 = -16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x7b4)?;
+        local_3 = v0_handle;
+        self.globals[0] = TaggedVal::from(v0_handle);
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_0);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(local_2);
-                v3 = TaggedVal::from(255i32);
-                v2 = TaggedVal::from(v2.try_as_i32()? & v3.try_as_i32()?);
-                v3 = TaggedVal::from(local_3);
-                v4 = TaggedVal::from(8i32);
-                v3 = TaggedVal::from(v3.try_as_handle()?.add(v4.try_as_i32()?)?);
-                v0 = TaggedVal::from(self.func_12(
-                    v0.try_as_i32()?,
-                    v1.try_as_i64()?,
-                    v2.try_as_i32()?,
-                    v3.try_as_Handle()?,
-                )?);
-                local_0 = v0.try_as_i32()?;
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_0;
+                v1_i64 = local_1;
+                v2 = local_2;
+                v3_i32 = 255i32;
+                v2 = v2 & v3_i32;
+                v3_handle = local_3;
+                v4 = 8i32;
+                v3_handle = v3_handle.add(v4).at(0x7cf)?;
+                v0_i32 = self.func_12(v0_i32, v1_i64, v2, v3_handle).at(0x7d0)?;
+                local_0 = v0_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = self.globals[1];
-                v1 = TaggedVal::from(1232i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(70i32);
-                v2 = TaggedVal::from(local_0);
-                v3 = TaggedVal::from(local_0);
-                v4 = TaggedVal::from(76i32);
-                v3 = TaggedVal::from((v3.try_as_i32()? == v4.try_as_i32()?) as i32);
-                if ValType::from(v1) != ValType::from(v2) {
-                    return None;
+                v0_handle = self.globals[1].try_as_handle().at(0x7db)?;
+                v1_i32 = 1232i32;
+                v0_handle = v0_handle.add(v1_i32).at(0x7e7)?;
+                v1_i32 = 70i32;
+                v2 = local_0;
+                v3_i32 = local_0;
+                v4 = 76i32;
+                v3_i32 = (v3_i32 == v4) as i32;
+                if v3_i32 == 0 {
+                    v1_i32 = v2;
                 }
-                if v3.try_as_i32()? != 0 {
-                    v1 = v1;
-                } else {
-                    v1 = v2;
-                }
-                write_mem_i32(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()?,
-                )?;
-                v0 = TaggedVal::from(-1i64);
-                local_1 = v0.try_as_i64()?;
+                write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x7f4);
+                v0_i64 = -1i64;
+                local_1 = v0_i64;
                 {}
                 break 'label_0;
                 break;
             }
-            v0 = TaggedVal::from(local_3);
-            v0 = TaggedVal::from(read_mem_i64(
-                &self
-                    .segments
-                    .get(v0.try_as_Handle()?.segment_index()?)?
-                    .get_data()?,
-                (v0.try_as_Handle()?.add(8)?.segment_offset()?) as usize,
-            )?);
-            local_1 = v0.try_as_i64()?;
+            v0_handle = local_3;
+            v0_i64 = read!(read_mem_i64, self.segments, v0_handle.add(8), at 0x800);
+            local_1 = v0_i64;
             break;
         }
-        v0 = TaggedVal::from(local_3);
-        v1 = TaggedVal::from(16i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        self.globals[0] = TaggedVal::from(v0.try_as_Handle()?);
-        v0 = TaggedVal::from(local_1);
-        Some(v0.try_as_i64()?)
+        v0_handle = local_3;
+        v1_i32 = 16i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x80a)?;
+        self.globals[0] = TaggedVal::from(v0_handle);
+        v0_i64 = local_1;
+        Ok(v0_i64)
+    }
+
+    fn func_30(&mut self, arg_0: Handle, arg_1: i64, arg_2: i32) -> Result<i64, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_30_inner(arg_0, arg_1, arg_2)
+            .map_err(|fault| call.unwind(30, fault))
     }
 
     #[allow(
@@ -3002,27 +5499,31 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_30(&mut self, arg_0: Handle, arg_1: i64, arg_2: i32) -> Option<i64> {
+    fn func_30_inner(&mut self, arg_0: Handle, arg_1: i64, arg_2: i32) -> Result<i64, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: i64 = arg_1;
         let mut local_2: i32 = arg_2;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        v0 = TaggedVal::from(local_0);
-        v1 = TaggedVal::from(112i32);
-        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-        v0 = TaggedVal::from(read_mem_i32(
-            &self
-                .segments
-                .get(v0.try_as_Handle()?.segment_index()?)?
-                .get_data()?,
-            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-        )?);
-        v1 = TaggedVal::from(local_1);
-        v2 = TaggedVal::from(local_2);
-        v0 = TaggedVal::from(self.func_29(v0.try_as_i32()?, v1.try_as_i64()?, v2.try_as_i32()?)?);
-        Some(v0.try_as_i64()?)
+        let mut v0_i32: i32;
+        let mut v0_i64: i64;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_i64: i64;
+        let mut v2: i32;
+        self.checkpoint().at(0x816)?;
+        v0_handle = local_0;
+        v1_i32 = 112i32;
+        v0_handle = v0_handle.add(v1_i32).at(0x81b)?;
+        v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0x81c);
+        v1_i64 = local_1;
+        v2 = local_2;
+        v0_i64 = self.func_29(v0_i32, v1_i64, v2).at(0x823)?;
+        Ok(v0_i64)
+    }
+
+    fn func_31(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Result<Handle, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_31_inner(arg_0, arg_1, arg_2)
+            .map_err(|fault| call.unwind(31, fault))
     }
 
     #[allow(
@@ -3033,83 +5534,71 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_31(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Option<Handle> {
+    fn func_31_inner(&mut self, arg_0: Handle, arg_1: Handle, arg_2: i32) -> Result<Handle, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: Handle = arg_1;
         let mut local_2: i32 = arg_2;
         let mut local_3: Handle = Handle::NULL;
         let mut local_4: i32 = 0i32;
         let mut local_5: i32 = 0i32;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
-        let mut v3: TaggedVal;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1_i32: i32;
+        let mut v1_handle: Handle;
+        let mut v2: i32;
+        let mut v3: i32;
+        self.checkpoint().at(0x831)?;
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_2);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_2;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(local_1);
-                v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-                v1 = TaggedVal::from(3i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_1;
+                v0_i32 = v0_handle.segment_offset().at(0x83c)? as i32;
+                v1_i32 = 3i32;
+                v0_i32 = v0_i32 & v1_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(local_0);
-                local_3 = v0.try_as_Handle()?;
+                v0_handle = local_0;
+                local_3 = v0_handle;
                 'label_2: loop {
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    write_mem_u8(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()? as u8,
-                    )?;
-                    v0 = TaggedVal::from(local_2);
-                    v1 = TaggedVal::from(-1i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                    local_4 = v0.try_as_i32()?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_1 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_2);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from((v0.try_as_i32()? == v1.try_as_i32()?) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    self.checkpoint().at(0x849)?;
+                    v0_handle = local_3;
+                    v1_handle = local_1;
+                    v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0x84d) as i32;
+                    write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x850);
+                    v0_i32 = local_2;
+                    v1_i32 = -1i32;
+                    v0_i32 = v0_i32.wrapping_add(v1_i32);
+                    local_4 = v0_i32;
+                    v0_handle = local_3;
+                    v1_i32 = 1i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x85e)?;
+                    local_3 = v0_handle;
+                    v0_handle = local_1;
+                    v1_i32 = 1i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x865)?;
+                    local_1 = v0_handle;
+                    v0_i32 = local_2;
+                    v1_i32 = 1i32;
+                    v0_i32 = (v0_i32 == v1_i32) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_0;
                     }
-                    v0 = TaggedVal::from(local_4);
-                    local_2 = v0.try_as_i32()?;
-                    v0 = TaggedVal::from(local_1);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-                    v1 = TaggedVal::from(3i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                    if v0.try_as_i32()? != 0 {
+                    v0_i32 = local_4;
+                    local_2 = v0_i32;
+                    v0_handle = local_1;
+                    v0_i32 = v0_handle.segment_offset().at(0x875)? as i32;
+                    v1_i32 = 3i32;
+                    v0_i32 = v0_i32 & v1_i32;
+                    if v0_i32 != 0 {
                         {}
                         continue 'label_2;
                     }
@@ -3119,131 +5608,76 @@ impl WasmModule {
                 }
                 break;
             }
-            v0 = TaggedVal::from(local_2);
-            local_4 = v0.try_as_i32()?;
-            v0 = TaggedVal::from(local_0);
-            local_3 = v0.try_as_Handle()?;
+            v0_i32 = local_2;
+            local_4 = v0_i32;
+            v0_handle = local_0;
+            local_3 = v0_handle;
             break;
         }
         'label_3: loop {
             'label_4: loop {
-                v0 = TaggedVal::from(local_3);
-                v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-                v1 = TaggedVal::from(3i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                local_2 = v0.try_as_i32()?;
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_3;
+                v0_i32 = v0_handle.segment_offset().at(0x88e)? as i32;
+                v1_i32 = 3i32;
+                v0_i32 = v0_i32 & v1_i32;
+                local_2 = v0_i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_4;
                 }
                 'label_5: loop {
-                    v0 = TaggedVal::from(local_4);
-                    v1 = TaggedVal::from(16i32);
-                    v0 = TaggedVal::from(
-                        ((v0.try_as_i32()? as u32) < (v1.try_as_i32()? as u32)) as i32,
-                    );
-                    if v0.try_as_i32()? != 0 {
+                    v0_i32 = local_4;
+                    v1_i32 = 16i32;
+                    v0_i32 = ((v0_i32 as u32) < (v1_i32 as u32)) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_5;
                     }
                     'label_6: loop {
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(local_1);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(4i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(4i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(8i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(8i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(12i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(12i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(16i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        local_3 = v0.try_as_Handle()?;
-                        v0 = TaggedVal::from(local_1);
-                        v1 = TaggedVal::from(16i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        local_1 = v0.try_as_Handle()?;
-                        v0 = TaggedVal::from(local_4);
-                        v1 = TaggedVal::from(-16i32);
-                        v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                        local_4 = v0.try_as_i32()?;
-                        v1 = TaggedVal::from(15i32);
-                        v0 = TaggedVal::from(
-                            ((v0.try_as_i32()? as u32) > (v1.try_as_i32()? as u32)) as i32,
-                        );
-                        if v0.try_as_i32()? != 0 {
+                        self.checkpoint().at(0x8a1)?;
+                        v0_handle = local_3;
+                        v1_handle = local_1;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x8a5);
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x8a8);
+                        v0_handle = local_3;
+                        v1_i32 = 4i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x8af)?;
+                        v1_handle = local_1;
+                        v2 = 4i32;
+                        v1_handle = v1_handle.add(v2).at(0x8b4)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x8b5);
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x8b8);
+                        v0_handle = local_3;
+                        v1_i32 = 8i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x8bf)?;
+                        v1_handle = local_1;
+                        v2 = 8i32;
+                        v1_handle = v1_handle.add(v2).at(0x8c4)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x8c5);
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x8c8);
+                        v0_handle = local_3;
+                        v1_i32 = 12i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x8cf)?;
+                        v1_handle = local_1;
+                        v2 = 12i32;
+                        v1_handle = v1_handle.add(v2).at(0x8d4)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x8d5);
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x8d8);
+                        v0_handle = local_3;
+                        v1_i32 = 16i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x8df)?;
+                        local_3 = v0_handle;
+                        v0_handle = local_1;
+                        v1_i32 = 16i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x8e6)?;
+                        local_1 = v0_handle;
+                        v0_i32 = local_4;
+                        v1_i32 = -16i32;
+                        v0_i32 = v0_i32.wrapping_add(v1_i32);
+                        local_4 = v0_i32;
+                        v1_i32 = 15i32;
+                        v0_i32 = ((v0_i32 as u32) > (v1_i32 as u32)) as i32;
+                        if v0_i32 != 0 {
                             {}
                             continue 'label_6;
                         }
@@ -3252,209 +5686,121 @@ impl WasmModule {
                     break;
                 }
                 'label_7: loop {
-                    v0 = TaggedVal::from(local_4);
-                    v1 = TaggedVal::from(8i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                    v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    v0_i32 = local_4;
+                    v1_i32 = 8i32;
+                    v0_i32 = v0_i32 & v1_i32;
+                    v0_i32 = (v0_i32 == 0) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_7;
                     }
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(4i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(4i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(8i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_1 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(8i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
+                    v0_handle = local_3;
+                    v1_handle = local_1;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x905);
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x908);
+                    v0_handle = local_3;
+                    v1_i32 = 4i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x90f)?;
+                    v1_handle = local_1;
+                    v2 = 4i32;
+                    v1_handle = v1_handle.add(v2).at(0x914)?;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x915);
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x918);
+                    v0_handle = local_1;
+                    v1_i32 = 8i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x91f)?;
+                    local_1 = v0_handle;
+                    v0_handle = local_3;
+                    v1_i32 = 8i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x926)?;
+                    local_3 = v0_handle;
                     break;
                 }
                 'label_8: loop {
-                    v0 = TaggedVal::from(local_4);
-                    v1 = TaggedVal::from(4i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                    v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    v0_i32 = local_4;
+                    v1_i32 = 4i32;
+                    v0_i32 = v0_i32 & v1_i32;
+                    v0_i32 = (v0_i32 == 0) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_8;
                     }
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(4i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_1 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(4i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
+                    v0_handle = local_3;
+                    v1_handle = local_1;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x938);
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0x93b);
+                    v0_handle = local_1;
+                    v1_i32 = 4i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x942)?;
+                    local_1 = v0_handle;
+                    v0_handle = local_3;
+                    v1_i32 = 4i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x949)?;
+                    local_3 = v0_handle;
                     break;
                 }
                 'label_9: loop {
-                    v0 = TaggedVal::from(local_4);
-                    v1 = TaggedVal::from(2i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                    v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    v0_i32 = local_4;
+                    v1_i32 = 2i32;
+                    v0_i32 = v0_i32 & v1_i32;
+                    v0_i32 = (v0_i32 == 0) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_9;
                     }
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    write_mem_u8(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()? as u8,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(1i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    write_mem_u8(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()? as u8,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(2i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(2i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_1 = v0.try_as_Handle()?;
+                    v0_handle = local_3;
+                    v1_handle = local_1;
+                    v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0x95b) as i32;
+                    write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x95e);
+                    v0_handle = local_3;
+                    v1_i32 = 1i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x965)?;
+                    v1_handle = local_1;
+                    v2 = 1i32;
+                    v1_handle = v1_handle.add(v2).at(0x96a)?;
+                    v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0x96b) as i32;
+                    write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x96e);
+                    v0_handle = local_3;
+                    v1_i32 = 2i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x975)?;
+                    local_3 = v0_handle;
+                    v0_handle = local_1;
+                    v1_i32 = 2i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0x97c)?;
+                    local_1 = v0_handle;
                     break;
                 }
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_4;
+                v1_i32 = 1i32;
+                v0_i32 = v0_i32 & v1_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_3;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_0);
-                return Some(v0.try_as_Handle()?);
+                v0_handle = local_3;
+                v1_handle = local_1;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0x98c) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x98f);
+                v0_handle = local_0;
+                return Ok(v0_handle);
                 break;
             }
             'label_10: loop {
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(32i32);
-                v0 =
-                    TaggedVal::from(((v0.try_as_i32()? as u32) < (v1.try_as_i32()? as u32)) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_4;
+                v1_i32 = 32i32;
+                v0_i32 = ((v0_i32 as u32) < (v1_i32 as u32)) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_10;
                 }
                 'label_11: loop {
                     'label_12: loop {
                         'label_13: loop {
-                            v0 = TaggedVal::from(local_2);
-                            v1 = TaggedVal::from(-1i32);
-                            v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                            match v0.try_as_i32()? {
+                            v0_i32 = local_2;
+                            v1_i32 = -1i32;
+                            v0_i32 = v0_i32.wrapping_add(v1_i32);
+                            match v0_i32 {
                                 0 => {
                                     {}
                                     break 'label_13;
@@ -3474,228 +5820,116 @@ impl WasmModule {
                             }
                             break;
                         }
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(1i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(1i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(
-                            read_mem_u8(
-                                &self
-                                    .segments
-                                    .get(v1.try_as_Handle()?.segment_index()?)?
-                                    .get_data()?,
-                                (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            )
-                            .and_then(|x| Some(x as i32))?,
-                        );
-                        write_mem_u8(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()? as u8,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(local_1);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        local_2 = v1.try_as_i32()?;
-                        write_mem_u8(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()? as u8,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(2i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(2i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(
-                            read_mem_u8(
-                                &self
-                                    .segments
-                                    .get(v1.try_as_Handle()?.segment_index()?)?
-                                    .get_data()?,
-                                (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            )
-                            .and_then(|x| Some(x as i32))?,
-                        );
-                        write_mem_u8(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()? as u8,
-                        )?;
-                        v0 = TaggedVal::from(local_4);
-                        v1 = TaggedVal::from(-3i32);
-                        v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                        local_4 = v0.try_as_i32()?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(3i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        local_3 = v0.try_as_Handle()?;
-                        v0 = TaggedVal::from(local_1);
-                        v1 = TaggedVal::from(3i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        local_1 = v0.try_as_Handle()?;
+                        v0_handle = local_3;
+                        v1_i32 = 1i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x9b5)?;
+                        v1_handle = local_1;
+                        v2 = 1i32;
+                        v1_handle = v1_handle.add(v2).at(0x9ba)?;
+                        v1_i32 =
+                            read!(read_mem_u8, self.segments, v1_handle.add(0), at 0x9bb) as i32;
+                        write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x9be);
+                        v0_handle = local_3;
+                        v1_handle = local_1;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x9c5);
+                        local_2 = v1_i32;
+                        write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x9ca);
+                        v0_handle = local_3;
+                        v1_i32 = 2i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x9d1)?;
+                        v1_handle = local_1;
+                        v2 = 2i32;
+                        v1_handle = v1_handle.add(v2).at(0x9d6)?;
+                        v1_i32 =
+                            read!(read_mem_u8, self.segments, v1_handle.add(0), at 0x9d7) as i32;
+                        write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0x9da);
+                        v0_i32 = local_4;
+                        v1_i32 = -3i32;
+                        v0_i32 = v0_i32.wrapping_add(v1_i32);
+                        local_4 = v0_i32;
+                        v0_handle = local_3;
+                        v1_i32 = 3i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x9e8)?;
+                        local_3 = v0_handle;
+                        v0_handle = local_1;
+                        v1_i32 = 3i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0x9ef)?;
+                        local_1 = v0_handle;
                         'label_14: loop {
-                            v0 = TaggedVal::from(local_3);
-                            v1 = TaggedVal::from(local_1);
-                            v2 = TaggedVal::from(1i32);
-                            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                            v1 = TaggedVal::from(read_mem_i32(
-                                &self
-                                    .segments
-                                    .get(v1.try_as_Handle()?.segment_index()?)?
-                                    .get_data()?,
-                                (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            )?);
-                            local_5 = v1.try_as_i32()?;
-                            v2 = TaggedVal::from(8i32);
-                            v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                            v2 = TaggedVal::from(local_2);
-                            v3 = TaggedVal::from(24i32);
-                            v2 = TaggedVal::from(
-                                (v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32),
-                            );
-                            v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                            write_mem_i32(
-                                &mut self
-                                    .segments
-                                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                    .get_mut_data(
-                                        (v0.try_as_Handle()?.add(0)?.segment_offset()?),
-                                    )?,
-                                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                                v1.try_as_i32()?,
-                            )?;
-                            v0 = TaggedVal::from(local_3);
-                            v1 = TaggedVal::from(4i32);
-                            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                            v1 = TaggedVal::from(local_1);
-                            v2 = TaggedVal::from(5i32);
-                            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                            v1 = TaggedVal::from(read_mem_i32(
-                                &self
-                                    .segments
-                                    .get(v1.try_as_Handle()?.segment_index()?)?
-                                    .get_data()?,
-                                (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            )?);
-                            local_2 = v1.try_as_i32()?;
-                            v2 = TaggedVal::from(8i32);
-                            v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                            v2 = TaggedVal::from(local_5);
-                            v3 = TaggedVal::from(24i32);
-                            v2 = TaggedVal::from(
-                                (v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32),
-                            );
-                            v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                            write_mem_i32(
-                                &mut self
-                                    .segments
-                                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                    .get_mut_data(
-                                        (v0.try_as_Handle()?.add(0)?.segment_offset()?),
-                                    )?,
-                                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                                v1.try_as_i32()?,
-                            )?;
-                            v0 = TaggedVal::from(local_3);
-                            v1 = TaggedVal::from(8i32);
-                            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                            v1 = TaggedVal::from(local_1);
-                            v2 = TaggedVal::from(9i32);
-                            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                            v1 = TaggedVal::from(read_mem_i32(
-                                &self
-                                    .segments
-                                    .get(v1.try_as_Handle()?.segment_index()?)?
-                                    .get_data()?,
-                                (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            )?);
-                            local_5 = v1.try_as_i32()?;
-                            v2 = TaggedVal::from(8i32);
-                            v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                            v2 = TaggedVal::from(local_2);
-                            v3 = TaggedVal::from(24i32);
-                            v2 = TaggedVal::from(
-                                (v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32),
-                            );
-                            v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                            write_mem_i32(
-                                &mut self
-                                    .segments
-                                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                    .get_mut_data(
-                                        (v0.try_as_Handle()?.add(0)?.segment_offset()?),
-                                    )?,
-                                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                                v1.try_as_i32()?,
-                            )?;
-                            v0 = TaggedVal::from(local_3);
-                            v1 = TaggedVal::from(12i32);
-                            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                            v1 = TaggedVal::from(local_1);
-                            v2 = TaggedVal::from(13i32);
-                            v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                            v1 = TaggedVal::from(read_mem_i32(
-                                &self
-                                    .segments
-                                    .get(v1.try_as_Handle()?.segment_index()?)?
-                                    .get_data()?,
-                                (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            )?);
-                            local_2 = v1.try_as_i32()?;
-                            v2 = TaggedVal::from(8i32);
-                            v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                            v2 = TaggedVal::from(local_5);
-                            v3 = TaggedVal::from(24i32);
-                            v2 = TaggedVal::from(
-                                (v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32),
-                            );
-                            v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                            write_mem_i32(
-                                &mut self
-                                    .segments
-                                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                    .get_mut_data(
-                                        (v0.try_as_Handle()?.add(0)?.segment_offset()?),
-                                    )?,
-                                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                                v1.try_as_i32()?,
-                            )?;
-                            v0 = TaggedVal::from(local_3);
-                            v1 = TaggedVal::from(16i32);
-                            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                            local_3 = v0.try_as_Handle()?;
-                            v0 = TaggedVal::from(local_1);
-                            v1 = TaggedVal::from(16i32);
-                            v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                            local_1 = v0.try_as_Handle()?;
-                            v0 = TaggedVal::from(local_4);
-                            v1 = TaggedVal::from(-16i32);
-                            v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                            local_4 = v0.try_as_i32()?;
-                            v1 = TaggedVal::from(16i32);
-                            v0 = TaggedVal::from(
-                                ((v0.try_as_i32()? as u32) > (v1.try_as_i32()? as u32)) as i32,
-                            );
-                            if v0.try_as_i32()? != 0 {
+                            self.checkpoint().at(0x9f4)?;
+                            v0_handle = local_3;
+                            v1_handle = local_1;
+                            v2 = 1i32;
+                            v1_handle = v1_handle.add(v2).at(0x9fa)?;
+                            v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0x9fb);
+                            local_5 = v1_i32;
+                            v2 = 8i32;
+                            v1_i32 = v1_i32 << (v2 % 32);
+                            v2 = local_2;
+                            v3 = 24i32;
+                            v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                            v1_i32 = v1_i32 | v2;
+                            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xa09);
+                            v0_handle = local_3;
+                            v1_i32 = 4i32;
+                            v0_handle = v0_handle.add(v1_i32).at(0xa10)?;
+                            v1_handle = local_1;
+                            v2 = 5i32;
+                            v1_handle = v1_handle.add(v2).at(0xa15)?;
+                            v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xa16);
+                            local_2 = v1_i32;
+                            v2 = 8i32;
+                            v1_i32 = v1_i32 << (v2 % 32);
+                            v2 = local_5;
+                            v3 = 24i32;
+                            v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                            v1_i32 = v1_i32 | v2;
+                            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xa24);
+                            v0_handle = local_3;
+                            v1_i32 = 8i32;
+                            v0_handle = v0_handle.add(v1_i32).at(0xa2b)?;
+                            v1_handle = local_1;
+                            v2 = 9i32;
+                            v1_handle = v1_handle.add(v2).at(0xa30)?;
+                            v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xa31);
+                            local_5 = v1_i32;
+                            v2 = 8i32;
+                            v1_i32 = v1_i32 << (v2 % 32);
+                            v2 = local_2;
+                            v3 = 24i32;
+                            v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                            v1_i32 = v1_i32 | v2;
+                            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xa3f);
+                            v0_handle = local_3;
+                            v1_i32 = 12i32;
+                            v0_handle = v0_handle.add(v1_i32).at(0xa46)?;
+                            v1_handle = local_1;
+                            v2 = 13i32;
+                            v1_handle = v1_handle.add(v2).at(0xa4b)?;
+                            v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xa4c);
+                            local_2 = v1_i32;
+                            v2 = 8i32;
+                            v1_i32 = v1_i32 << (v2 % 32);
+                            v2 = local_5;
+                            v3 = 24i32;
+                            v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                            v1_i32 = v1_i32 | v2;
+                            write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xa5a);
+                            v0_handle = local_3;
+                            v1_i32 = 16i32;
+                            v0_handle = v0_handle.add(v1_i32).at(0xa61)?;
+                            local_3 = v0_handle;
+                            v0_handle = local_1;
+                            v1_i32 = 16i32;
+                            v0_handle = v0_handle.add(v1_i32).at(0xa68)?;
+                            local_1 = v0_handle;
+                            v0_i32 = local_4;
+                            v1_i32 = -16i32;
+                            v0_i32 = v0_i32.wrapping_add(v1_i32);
+                            local_4 = v0_i32;
+                            v1_i32 = 16i32;
+                            v0_i32 = ((v0_i32 as u32) > (v1_i32 as u32)) as i32;
+                            if v0_i32 != 0 {
                                 {}
                                 continue 'label_14;
                             }
@@ -3705,188 +5939,106 @@ impl WasmModule {
                         }
                         break;
                     }
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    local_2 = v1.try_as_i32()?;
-                    write_mem_u8(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()? as u8,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(1i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    write_mem_u8(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()? as u8,
-                    )?;
-                    v0 = TaggedVal::from(local_4);
-                    v1 = TaggedVal::from(-2i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                    local_4 = v0.try_as_i32()?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(2i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(2i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_1 = v0.try_as_Handle()?;
+                    v0_handle = local_3;
+                    v1_handle = local_1;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xa7f);
+                    local_2 = v1_i32;
+                    write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xa84);
+                    v0_handle = local_3;
+                    v1_i32 = 1i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xa8b)?;
+                    v1_handle = local_1;
+                    v2 = 1i32;
+                    v1_handle = v1_handle.add(v2).at(0xa90)?;
+                    v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xa91) as i32;
+                    write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xa94);
+                    v0_i32 = local_4;
+                    v1_i32 = -2i32;
+                    v0_i32 = v0_i32.wrapping_add(v1_i32);
+                    local_4 = v0_i32;
+                    v0_handle = local_3;
+                    v1_i32 = 2i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xaa2)?;
+                    local_3 = v0_handle;
+                    v0_handle = local_1;
+                    v1_i32 = 2i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xaa9)?;
+                    local_1 = v0_handle;
                     'label_15: loop {
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(2i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        local_5 = v1.try_as_i32()?;
-                        v2 = TaggedVal::from(16i32);
-                        v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                        v2 = TaggedVal::from(local_2);
-                        v3 = TaggedVal::from(16i32);
-                        v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                        v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(4i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(6i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        local_2 = v1.try_as_i32()?;
-                        v2 = TaggedVal::from(16i32);
-                        v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                        v2 = TaggedVal::from(local_5);
-                        v3 = TaggedVal::from(16i32);
-                        v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                        v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(8i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(10i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        local_5 = v1.try_as_i32()?;
-                        v2 = TaggedVal::from(16i32);
-                        v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                        v2 = TaggedVal::from(local_2);
-                        v3 = TaggedVal::from(16i32);
-                        v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                        v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(12i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        v1 = TaggedVal::from(local_1);
-                        v2 = TaggedVal::from(14i32);
-                        v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                        v1 = TaggedVal::from(read_mem_i32(
-                            &self
-                                .segments
-                                .get(v1.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )?);
-                        local_2 = v1.try_as_i32()?;
-                        v2 = TaggedVal::from(16i32);
-                        v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                        v2 = TaggedVal::from(local_5);
-                        v3 = TaggedVal::from(16i32);
-                        v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                        v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                        write_mem_i32(
-                            &mut self
-                                .segments
-                                .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                                .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                            v1.try_as_i32()?,
-                        )?;
-                        v0 = TaggedVal::from(local_3);
-                        v1 = TaggedVal::from(16i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        local_3 = v0.try_as_Handle()?;
-                        v0 = TaggedVal::from(local_1);
-                        v1 = TaggedVal::from(16i32);
-                        v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                        local_1 = v0.try_as_Handle()?;
-                        v0 = TaggedVal::from(local_4);
-                        v1 = TaggedVal::from(-16i32);
-                        v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                        local_4 = v0.try_as_i32()?;
-                        v1 = TaggedVal::from(17i32);
-                        v0 = TaggedVal::from(
-                            ((v0.try_as_i32()? as u32) > (v1.try_as_i32()? as u32)) as i32,
-                        );
-                        if v0.try_as_i32()? != 0 {
+                        self.checkpoint().at(0xaae)?;
+                        v0_handle = local_3;
+                        v1_handle = local_1;
+                        v2 = 2i32;
+                        v1_handle = v1_handle.add(v2).at(0xab4)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xab5);
+                        local_5 = v1_i32;
+                        v2 = 16i32;
+                        v1_i32 = v1_i32 << (v2 % 32);
+                        v2 = local_2;
+                        v3 = 16i32;
+                        v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                        v1_i32 = v1_i32 | v2;
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xac3);
+                        v0_handle = local_3;
+                        v1_i32 = 4i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0xaca)?;
+                        v1_handle = local_1;
+                        v2 = 6i32;
+                        v1_handle = v1_handle.add(v2).at(0xacf)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xad0);
+                        local_2 = v1_i32;
+                        v2 = 16i32;
+                        v1_i32 = v1_i32 << (v2 % 32);
+                        v2 = local_5;
+                        v3 = 16i32;
+                        v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                        v1_i32 = v1_i32 | v2;
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xade);
+                        v0_handle = local_3;
+                        v1_i32 = 8i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0xae5)?;
+                        v1_handle = local_1;
+                        v2 = 10i32;
+                        v1_handle = v1_handle.add(v2).at(0xaea)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xaeb);
+                        local_5 = v1_i32;
+                        v2 = 16i32;
+                        v1_i32 = v1_i32 << (v2 % 32);
+                        v2 = local_2;
+                        v3 = 16i32;
+                        v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                        v1_i32 = v1_i32 | v2;
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xaf9);
+                        v0_handle = local_3;
+                        v1_i32 = 12i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0xb00)?;
+                        v1_handle = local_1;
+                        v2 = 14i32;
+                        v1_handle = v1_handle.add(v2).at(0xb05)?;
+                        v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xb06);
+                        local_2 = v1_i32;
+                        v2 = 16i32;
+                        v1_i32 = v1_i32 << (v2 % 32);
+                        v2 = local_5;
+                        v3 = 16i32;
+                        v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                        v1_i32 = v1_i32 | v2;
+                        write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xb14);
+                        v0_handle = local_3;
+                        v1_i32 = 16i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0xb1b)?;
+                        local_3 = v0_handle;
+                        v0_handle = local_1;
+                        v1_i32 = 16i32;
+                        v0_handle = v0_handle.add(v1_i32).at(0xb22)?;
+                        local_1 = v0_handle;
+                        v0_i32 = local_4;
+                        v1_i32 = -16i32;
+                        v0_i32 = v0_i32.wrapping_add(v1_i32);
+                        local_4 = v0_i32;
+                        v1_i32 = 17i32;
+                        v0_i32 = ((v0_i32 as u32) > (v1_i32 as u32)) as i32;
+                        if v0_i32 != 0 {
                             {}
                             continue 'label_15;
                         }
@@ -3896,164 +6048,98 @@ impl WasmModule {
                     }
                     break;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(read_mem_i32(
-                    &self
-                        .segments
-                        .get(v1.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )?);
-                local_2 = v1.try_as_i32()?;
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(-1i32);
-                v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                local_4 = v0.try_as_i32()?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_3 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_1 = v0.try_as_Handle()?;
+                v0_handle = local_3;
+                v1_handle = local_1;
+                v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xb39);
+                local_2 = v1_i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xb3e);
+                v0_i32 = local_4;
+                v1_i32 = -1i32;
+                v0_i32 = v0_i32.wrapping_add(v1_i32);
+                local_4 = v0_i32;
+                v0_handle = local_3;
+                v1_i32 = 1i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xb4c)?;
+                local_3 = v0_handle;
+                v0_handle = local_1;
+                v1_i32 = 1i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xb53)?;
+                local_1 = v0_handle;
                 'label_16: loop {
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(3i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    local_5 = v1.try_as_i32()?;
-                    v2 = TaggedVal::from(24i32);
-                    v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                    v2 = TaggedVal::from(local_2);
-                    v3 = TaggedVal::from(8i32);
-                    v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                    v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(4i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(7i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    local_2 = v1.try_as_i32()?;
-                    v2 = TaggedVal::from(24i32);
-                    v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                    v2 = TaggedVal::from(local_5);
-                    v3 = TaggedVal::from(8i32);
-                    v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                    v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(8i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(11i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    local_5 = v1.try_as_i32()?;
-                    v2 = TaggedVal::from(24i32);
-                    v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                    v2 = TaggedVal::from(local_2);
-                    v3 = TaggedVal::from(8i32);
-                    v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                    v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(12i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    v1 = TaggedVal::from(local_1);
-                    v2 = TaggedVal::from(15i32);
-                    v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                    v1 = TaggedVal::from(read_mem_i32(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )?);
-                    local_2 = v1.try_as_i32()?;
-                    v2 = TaggedVal::from(24i32);
-                    v1 = TaggedVal::from(v1.try_as_i32()? << (v2.try_as_i32()? % 32));
-                    v2 = TaggedVal::from(local_5);
-                    v3 = TaggedVal::from(8i32);
-                    v2 = TaggedVal::from((v2.try_as_i32()? as u32) >> (v3.try_as_i32()? % 32));
-                    v1 = TaggedVal::from(v1.try_as_i32()? | v2.try_as_i32()?);
-                    write_mem_i32(
-                        &mut self
-                            .segments
-                            .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                            .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        v1.try_as_i32()?,
-                    )?;
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(16i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_1);
-                    v1 = TaggedVal::from(16i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_1 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(local_4);
-                    v1 = TaggedVal::from(-16i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_add(v1.try_as_i32()?));
-                    local_4 = v0.try_as_i32()?;
-                    v1 = TaggedVal::from(18i32);
-                    v0 = TaggedVal::from(
-                        ((v0.try_as_i32()? as u32) > (v1.try_as_i32()? as u32)) as i32,
-                    );
-                    if v0.try_as_i32()? != 0 {
+                    self.checkpoint().at(0xb58)?;
+                    v0_handle = local_3;
+                    v1_handle = local_1;
+                    v2 = 3i32;
+                    v1_handle = v1_handle.add(v2).at(0xb5e)?;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xb5f);
+                    local_5 = v1_i32;
+                    v2 = 24i32;
+                    v1_i32 = v1_i32 << (v2 % 32);
+                    v2 = local_2;
+                    v3 = 8i32;
+                    v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                    v1_i32 = v1_i32 | v2;
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xb6d);
+                    v0_handle = local_3;
+                    v1_i32 = 4i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xb74)?;
+                    v1_handle = local_1;
+                    v2 = 7i32;
+                    v1_handle = v1_handle.add(v2).at(0xb79)?;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xb7a);
+                    local_2 = v1_i32;
+                    v2 = 24i32;
+                    v1_i32 = v1_i32 << (v2 % 32);
+                    v2 = local_5;
+                    v3 = 8i32;
+                    v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                    v1_i32 = v1_i32 | v2;
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xb88);
+                    v0_handle = local_3;
+                    v1_i32 = 8i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xb8f)?;
+                    v1_handle = local_1;
+                    v2 = 11i32;
+                    v1_handle = v1_handle.add(v2).at(0xb94)?;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xb95);
+                    local_5 = v1_i32;
+                    v2 = 24i32;
+                    v1_i32 = v1_i32 << (v2 % 32);
+                    v2 = local_2;
+                    v3 = 8i32;
+                    v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                    v1_i32 = v1_i32 | v2;
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xba3);
+                    v0_handle = local_3;
+                    v1_i32 = 12i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xbaa)?;
+                    v1_handle = local_1;
+                    v2 = 15i32;
+                    v1_handle = v1_handle.add(v2).at(0xbaf)?;
+                    v1_i32 = read!(read_mem_i32, self.segments, v1_handle.add(0), at 0xbb0);
+                    local_2 = v1_i32;
+                    v2 = 24i32;
+                    v1_i32 = v1_i32 << (v2 % 32);
+                    v2 = local_5;
+                    v3 = 8i32;
+                    v2 = ((v2 as u32) >> (v3 % 32)) as i32;
+                    v1_i32 = v1_i32 | v2;
+                    write!(write_mem_i32, self.segments, v0_handle.add(0), v1_i32, at 0xbbe);
+                    v0_handle = local_3;
+                    v1_i32 = 16i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xbc5)?;
+                    local_3 = v0_handle;
+                    v0_handle = local_1;
+                    v1_i32 = 16i32;
+                    v0_handle = v0_handle.add(v1_i32).at(0xbcc)?;
+                    local_1 = v0_handle;
+                    v0_i32 = local_4;
+                    v1_i32 = -16i32;
+                    v0_i32 = v0_i32.wrapping_add(v1_i32);
+                    local_4 = v0_i32;
+                    v1_i32 = 18i32;
+                    v0_i32 = ((v0_i32 as u32) > (v1_i32 as u32)) as i32;
+                    if v0_i32 != 0 {
                         {}
                         continue 'label_16;
                     }
@@ -4062,817 +6148,327 @@ impl WasmModule {
                 break;
             }
             'label_17: loop {
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(16i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_4;
+                v1_i32 = 16i32;
+                v0_i32 = v0_i32 & v1_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_17;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(1i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(2i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(3i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(3i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(4i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(4i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(5i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(5i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(6i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(6i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(7i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(7i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(8i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(8i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(9i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(9i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(10i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(10i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(11i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(11i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(12i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(12i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(13i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(13i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(14i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(14i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(15i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(15i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(16i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_3 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(16i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_1 = v0.try_as_Handle()?;
+                v0_handle = local_3;
+                v1_i32 = 1i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xbeb)?;
+                v1_handle = local_1;
+                v2 = 1i32;
+                v1_handle = v1_handle.add(v2).at(0xbf0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xbf1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xbf4);
+                v0_handle = local_3;
+                v1_i32 = 2i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xbfb)?;
+                v1_handle = local_1;
+                v2 = 2i32;
+                v1_handle = v1_handle.add(v2).at(0xc00)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc01) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc04);
+                v0_handle = local_3;
+                v1_i32 = 3i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc0b)?;
+                v1_handle = local_1;
+                v2 = 3i32;
+                v1_handle = v1_handle.add(v2).at(0xc10)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc11) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc14);
+                v0_handle = local_3;
+                v1_i32 = 4i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc1b)?;
+                v1_handle = local_1;
+                v2 = 4i32;
+                v1_handle = v1_handle.add(v2).at(0xc20)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc21) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc24);
+                v0_handle = local_3;
+                v1_i32 = 5i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc2b)?;
+                v1_handle = local_1;
+                v2 = 5i32;
+                v1_handle = v1_handle.add(v2).at(0xc30)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc31) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc34);
+                v0_handle = local_3;
+                v1_i32 = 6i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc3b)?;
+                v1_handle = local_1;
+                v2 = 6i32;
+                v1_handle = v1_handle.add(v2).at(0xc40)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc41) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc44);
+                v0_handle = local_3;
+                v1_i32 = 7i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc4b)?;
+                v1_handle = local_1;
+                v2 = 7i32;
+                v1_handle = v1_handle.add(v2).at(0xc50)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc51) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc54);
+                v0_handle = local_3;
+                v1_i32 = 8i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc5b)?;
+                v1_handle = local_1;
+                v2 = 8i32;
+                v1_handle = v1_handle.add(v2).at(0xc60)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc61) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc64);
+                v0_handle = local_3;
+                v1_i32 = 9i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc6b)?;
+                v1_handle = local_1;
+                v2 = 9i32;
+                v1_handle = v1_handle.add(v2).at(0xc70)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc71) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc74);
+                v0_handle = local_3;
+                v1_i32 = 10i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc7b)?;
+                v1_handle = local_1;
+                v2 = 10i32;
+                v1_handle = v1_handle.add(v2).at(0xc80)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc81) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc84);
+                v0_handle = local_3;
+                v1_i32 = 11i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc8b)?;
+                v1_handle = local_1;
+                v2 = 11i32;
+                v1_handle = v1_handle.add(v2).at(0xc90)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xc91) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xc94);
+                v0_handle = local_3;
+                v1_i32 = 12i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xc9b)?;
+                v1_handle = local_1;
+                v2 = 12i32;
+                v1_handle = v1_handle.add(v2).at(0xca0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xca1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xca4);
+                v0_handle = local_3;
+                v1_i32 = 13i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xcab)?;
+                v1_handle = local_1;
+                v2 = 13i32;
+                v1_handle = v1_handle.add(v2).at(0xcb0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xcb1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xcb4);
+                v0_handle = local_3;
+                v1_i32 = 14i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xcbb)?;
+                v1_handle = local_1;
+                v2 = 14i32;
+                v1_handle = v1_handle.add(v2).at(0xcc0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xcc1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xcc4);
+                v0_handle = local_3;
+                v1_handle = local_1;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xccb) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xcce);
+                v0_handle = local_3;
+                v1_i32 = 15i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xcd5)?;
+                v1_handle = local_1;
+                v2 = 15i32;
+                v1_handle = v1_handle.add(v2).at(0xcda)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xcdb) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xcde);
+                v0_handle = local_3;
+                v1_i32 = 16i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xce5)?;
+                local_3 = v0_handle;
+                v0_handle = local_1;
+                v1_i32 = 16i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xcec)?;
+                local_1 = v0_handle;
                 break;
             }
             'label_18: loop {
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(8i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_4;
+                v1_i32 = 8i32;
+                v0_i32 = v0_i32 & v1_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_18;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(1i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(2i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(3i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(3i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(4i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(4i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(5i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(5i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(6i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(6i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(7i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(7i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(8i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_3 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(8i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_1 = v0.try_as_Handle()?;
+                v0_handle = local_3;
+                v1_handle = local_1;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xcfe) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd01);
+                v0_handle = local_3;
+                v1_i32 = 1i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd08)?;
+                v1_handle = local_1;
+                v2 = 1i32;
+                v1_handle = v1_handle.add(v2).at(0xd0d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd0e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd11);
+                v0_handle = local_3;
+                v1_i32 = 2i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd18)?;
+                v1_handle = local_1;
+                v2 = 2i32;
+                v1_handle = v1_handle.add(v2).at(0xd1d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd1e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd21);
+                v0_handle = local_3;
+                v1_i32 = 3i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd28)?;
+                v1_handle = local_1;
+                v2 = 3i32;
+                v1_handle = v1_handle.add(v2).at(0xd2d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd2e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd31);
+                v0_handle = local_3;
+                v1_i32 = 4i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd38)?;
+                v1_handle = local_1;
+                v2 = 4i32;
+                v1_handle = v1_handle.add(v2).at(0xd3d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd3e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd41);
+                v0_handle = local_3;
+                v1_i32 = 5i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd48)?;
+                v1_handle = local_1;
+                v2 = 5i32;
+                v1_handle = v1_handle.add(v2).at(0xd4d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd4e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd51);
+                v0_handle = local_3;
+                v1_i32 = 6i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd58)?;
+                v1_handle = local_1;
+                v2 = 6i32;
+                v1_handle = v1_handle.add(v2).at(0xd5d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd5e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd61);
+                v0_handle = local_3;
+                v1_i32 = 7i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd68)?;
+                v1_handle = local_1;
+                v2 = 7i32;
+                v1_handle = v1_handle.add(v2).at(0xd6d)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd6e) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd71);
+                v0_handle = local_3;
+                v1_i32 = 8i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd78)?;
+                local_3 = v0_handle;
+                v0_handle = local_1;
+                v1_i32 = 8i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd7f)?;
+                local_1 = v0_handle;
                 break;
             }
             'label_19: loop {
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(4i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_4;
+                v1_i32 = 4i32;
+                v0_i32 = v0_i32 & v1_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_19;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(1i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(2i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(3i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(3i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(4i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_3 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(4i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_1 = v0.try_as_Handle()?;
+                v0_handle = local_3;
+                v1_handle = local_1;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xd91) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xd94);
+                v0_handle = local_3;
+                v1_i32 = 1i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xd9b)?;
+                v1_handle = local_1;
+                v2 = 1i32;
+                v1_handle = v1_handle.add(v2).at(0xda0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xda1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xda4);
+                v0_handle = local_3;
+                v1_i32 = 2i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xdab)?;
+                v1_handle = local_1;
+                v2 = 2i32;
+                v1_handle = v1_handle.add(v2).at(0xdb0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xdb1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xdb4);
+                v0_handle = local_3;
+                v1_i32 = 3i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xdbb)?;
+                v1_handle = local_1;
+                v2 = 3i32;
+                v1_handle = v1_handle.add(v2).at(0xdc0)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xdc1) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xdc4);
+                v0_handle = local_3;
+                v1_i32 = 4i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xdcb)?;
+                local_3 = v0_handle;
+                v0_handle = local_1;
+                v1_i32 = 4i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xdd2)?;
+                local_1 = v0_handle;
                 break;
             }
             'label_20: loop {
-                v0 = TaggedVal::from(local_4);
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_4;
+                v1_i32 = 2i32;
+                v0_i32 = v0_i32 & v1_i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_20;
                 }
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(1i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                v1 = TaggedVal::from(local_1);
-                v2 = TaggedVal::from(1i32);
-                v1 = TaggedVal::from(v1.try_as_handle()?.add(v2.try_as_i32()?)?);
-                v1 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v1.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                write_mem_u8(
-                    &mut self
-                        .segments
-                        .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                        .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    v1.try_as_i32()? as u8,
-                )?;
-                v0 = TaggedVal::from(local_3);
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_3 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_1);
-                v1 = TaggedVal::from(2i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_1 = v0.try_as_Handle()?;
+                v0_handle = local_3;
+                v1_handle = local_1;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xde4) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xde7);
+                v0_handle = local_3;
+                v1_i32 = 1i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xdee)?;
+                v1_handle = local_1;
+                v2 = 1i32;
+                v1_handle = v1_handle.add(v2).at(0xdf3)?;
+                v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xdf4) as i32;
+                write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xdf7);
+                v0_handle = local_3;
+                v1_i32 = 2i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xdfe)?;
+                local_3 = v0_handle;
+                v0_handle = local_1;
+                v1_i32 = 2i32;
+                v0_handle = v0_handle.add(v1_i32).at(0xe05)?;
+                local_1 = v0_handle;
                 break;
             }
-            v0 = TaggedVal::from(local_4);
-            v1 = TaggedVal::from(1i32);
-            v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-            v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-            if v0.try_as_i32()? != 0 {
+            v0_i32 = local_4;
+            v1_i32 = 1i32;
+            v0_i32 = v0_i32 & v1_i32;
+            v0_i32 = (v0_i32 == 0) as i32;
+            if v0_i32 != 0 {
                 {}
                 break 'label_3;
             }
-            v0 = TaggedVal::from(local_3);
-            v1 = TaggedVal::from(local_1);
-            v1 = TaggedVal::from(
-                read_mem_u8(
-                    &self
-                        .segments
-                        .get(v1.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v1.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )
-                .and_then(|x| Some(x as i32))?,
-            );
-            write_mem_u8(
-                &mut self
-                    .segments
-                    .get_mut(v0.try_as_Handle()?.segment_index()?)?
-                    .get_mut_data((v0.try_as_Handle()?.add(0)?.segment_offset()?))?,
-                (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                v1.try_as_i32()? as u8,
-            )?;
+            v0_handle = local_3;
+            v1_handle = local_1;
+            v1_i32 = read!(read_mem_u8, self.segments, v1_handle.add(0), at 0xe15) as i32;
+            write!(write_mem_u8, self.segments, v0_handle.add(0), v1_i32 as u8, at 0xe18);
             break;
         }
-        v0 = TaggedVal::from(local_0);
-        Some(v0.try_as_Handle()?)
+        v0_handle = local_0;
+        Ok(v0_handle)
+    }
+
+    fn func_32(&mut self, arg_0: Handle) -> Result<i32, Trap> {
+        let mut call = self.enter_call()?;
+        call.func_32_inner(arg_0)
+            .map_err(|fault| call.unwind(32, fault))
     }
 
     #[allow(
@@ -4883,71 +6479,56 @@ impl WasmModule {
         unreachable_code,
         unused_labels
     )]
-    fn func_32(&mut self, arg_0: Handle) -> Option<i32> {
+    fn func_32_inner(&mut self, arg_0: Handle) -> Result<i32, Fault> {
         let mut local_0: Handle = arg_0;
         let mut local_1: i32 = 0i32;
         let mut local_2: i32 = 0i32;
         let mut local_3: Handle = Handle::NULL;
-        let mut v0: TaggedVal;
-        let mut v1: TaggedVal;
-        let mut v2: TaggedVal;
+        let mut v0_i32: i32;
+        let mut v0_handle: Handle;
+        let mut v1: i32;
+        let mut v2: i32;
+        self.checkpoint().at(0xe26)?;
         'label_0: loop {
             'label_1: loop {
-                v0 = TaggedVal::from(local_0);
-                v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-                local_1 = v0.try_as_i32()?;
-                v1 = TaggedVal::from(3i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_handle = local_0;
+                v0_i32 = v0_handle.segment_offset().at(0xe2c)? as i32;
+                local_1 = v0_i32;
+                v1 = 3i32;
+                v0_i32 = v0_i32 & v1;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_1;
                 }
-                v0 = TaggedVal::from(local_1);
-                local_2 = v0.try_as_i32()?;
-                v0 = TaggedVal::from(local_0);
-                v0 = TaggedVal::from(
-                    read_mem_u8(
-                        &self
-                            .segments
-                            .get(v0.try_as_Handle()?.segment_index()?)?
-                            .get_data()?,
-                        (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                    )
-                    .and_then(|x| Some(x as i32))?,
-                );
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_1;
+                local_2 = v0_i32;
+                v0_handle = local_0;
+                v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(0), at 0xe3b) as i32;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_0;
                 }
                 'label_2: loop {
-                    v0 = TaggedVal::from(local_0);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_0 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-                    local_2 = v0.try_as_i32()?;
-                    v1 = TaggedVal::from(3i32);
-                    v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                    v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    self.checkpoint().at(0xe43)?;
+                    v0_handle = local_0;
+                    v1 = 1i32;
+                    v0_handle = v0_handle.add(v1).at(0xe47)?;
+                    local_0 = v0_handle;
+                    v0_i32 = v0_handle.segment_offset().at(0xe4a)? as i32;
+                    local_2 = v0_i32;
+                    v1 = 3i32;
+                    v0_i32 = v0_i32 & v1;
+                    v0_i32 = (v0_i32 == 0) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_1;
                     }
-                    v0 = TaggedVal::from(local_0);
-                    v0 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v0.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                    if v0.try_as_i32()? != 0 {
+                    v0_handle = local_0;
+                    v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(0), at 0xe55) as i32;
+                    v0_i32 = (v0_i32 == 0) as i32;
+                    if v0_i32 != 0 {
                         {}
                         break 'label_0;
                     }
@@ -4958,60 +6539,47 @@ impl WasmModule {
                 break;
             }
             'label_3: loop {
-                v0 = TaggedVal::from(local_0);
-                local_3 = v0.try_as_Handle()?;
-                v1 = TaggedVal::from(4i32);
-                v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                local_0 = v0.try_as_Handle()?;
-                v0 = TaggedVal::from(local_3);
-                v0 = TaggedVal::from(read_mem_i32(
-                    &self
-                        .segments
-                        .get(v0.try_as_Handle()?.segment_index()?)?
-                        .get_data()?,
-                    (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                )?);
-                local_2 = v0.try_as_i32()?;
-                v1 = TaggedVal::from(-1i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? ^ v1.try_as_i32()?);
-                v1 = TaggedVal::from(local_2);
-                v2 = TaggedVal::from(-16843009i32);
-                v1 = TaggedVal::from(v1.try_as_i32()?.wrapping_add(v2.try_as_i32()?));
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v1 = TaggedVal::from(-2139062144i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                self.checkpoint().at(0xe61)?;
+                v0_handle = local_0;
+                local_3 = v0_handle;
+                v1 = 4i32;
+                v0_handle = v0_handle.add(v1).at(0xe67)?;
+                local_0 = v0_handle;
+                v0_handle = local_3;
+                v0_i32 = read!(read_mem_i32, self.segments, v0_handle.add(0), at 0xe6c);
+                local_2 = v0_i32;
+                v1 = -1i32;
+                v0_i32 = v0_i32 ^ v1;
+                v1 = local_2;
+                v2 = -16843009i32;
+                v1 = v1.wrapping_add(v2);
+                v0_i32 = v0_i32 & v1;
+                v1 = -2139062144i32;
+                v0_i32 = v0_i32 & v1;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     continue 'label_3;
                 }
                 break;
             }
             'label_4: loop {
-                v0 = TaggedVal::from(local_2);
-                v1 = TaggedVal::from(255i32);
-                v0 = TaggedVal::from(v0.try_as_i32()? & v1.try_as_i32()?);
-                v0 = TaggedVal::from((v0.try_as_i32()? == 0) as i32);
-                if v0.try_as_i32()? != 0 {
+                v0_i32 = local_2;
+                v1 = 255i32;
+                v0_i32 = v0_i32 & v1;
+                v0_i32 = (v0_i32 == 0) as i32;
+                if v0_i32 != 0 {
                     {}
                     break 'label_4;
                 }
                 'label_5: loop {
-                    v0 = TaggedVal::from(local_3);
-                    v1 = TaggedVal::from(1i32);
-                    v0 = TaggedVal::from(v0.try_as_handle()?.add(v1.try_as_i32()?)?);
-                    local_3 = v0.try_as_Handle()?;
-                    v0 = TaggedVal::from(
-                        read_mem_u8(
-                            &self
-                                .segments
-                                .get(v0.try_as_Handle()?.segment_index()?)?
-                                .get_data()?,
-                            (v0.try_as_Handle()?.add(0)?.segment_offset()?) as usize,
-                        )
-                        .and_then(|x| Some(x as i32))?,
-                    );
-                    if v0.try_as_i32()? != 0 {
+                    self.checkpoint().at(0xe95)?;
+                    v0_handle = local_3;
+                    v1 = 1i32;
+                    v0_handle = v0_handle.add(v1).at(0xe99)?;
+                    local_3 = v0_handle;
+                    v0_i32 = read!(read_mem_u8, self.segments, v0_handle.add(0), at 0xe9c) as i32;
+                    if v0_i32 != 0 {
                         {}
                         continue 'label_5;
                     }
@@ -5019,336 +6587,402 @@ impl WasmModule {
                 }
                 break;
             }
-            v0 = TaggedVal::from(local_3);
-            v0 = TaggedVal::from(v0.try_as_handle()?.segment_offset()? as u32);
-            local_2 = v0.try_as_i32()?;
+            v0_handle = local_3;
+            v0_i32 = v0_handle.segment_offset().at(0xea5)? as i32;
+            local_2 = v0_i32;
             break;
         }
-        v0 = TaggedVal::from(local_2);
-        v1 = TaggedVal::from(local_1);
-        v0 = TaggedVal::from(v0.try_as_i32()?.wrapping_sub(v1.try_as_i32()?));
-        Some(v0.try_as_i32()?)
+        v0_i32 = local_2;
+        v1 = local_1;
+        v0_i32 = v0_i32.wrapping_sub(v1);
+        Ok(v0_i32)
     }
 }
 
 impl WasmModule {
     #[allow(dead_code)]
-    fn indirect_call(&mut self, idx: usize, args: &[TaggedVal]) -> Option<Vec<TaggedVal>> {
-        let call_target = (*self.indirect_call_table.get(idx)?)?;
+    fn indirect_call(&mut self, idx: usize, args: &[TaggedVal]) -> Result<Vec<TaggedVal>, Trap> {
+        let call_target = (*self
+            .indirect_call_table
+            .get(idx)
+            .ok_or(Trap::UndefinedTableElement)?)
+        .ok_or(Trap::UndefinedTableElement)?;
         match call_target {
             0 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let rets = self.func_0(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             1 => {
                 if args.len() != 2 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_Handle()?;
                 let rets = self.func_1(a0, a1)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             2 => {
                 if args.len() != 4 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_i64()?;
                 let a2 = args[2].try_as_i32()?;
                 let a3 = args[3].try_as_Handle()?;
                 let rets = self.func_2(a0, a1, a2, a3)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             3 => {
                 if args.len() != 4 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_Handle()?;
                 let a2 = args[2].try_as_i32()?;
                 let a3 = args[3].try_as_Handle()?;
                 let rets = self.func_3(a0, a1, a2, a3)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             4 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 self.func_4(a0)?;
-                Some(vec![])
+                Ok(vec![])
             }
             5 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_5()?;
-                Some(vec![])
+                Ok(vec![])
             }
             6 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_6()?;
-                Some(vec![])
+                Ok(vec![])
             }
             7 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_7()?;
-                Some(vec![])
+                Ok(vec![])
             }
             8 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 let rets = self.func_8()?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             9 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 let rets = self.func_9()?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             10 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let rets = self.func_10(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             11 => {
                 if args.len() != 2 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_Handle()?;
                 let rets = self.func_11(a0, a1)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             12 => {
                 if args.len() != 4 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_i64()?;
                 let a2 = args[2].try_as_i32()?;
                 let a3 = args[3].try_as_Handle()?;
                 let rets = self.func_12(a0, a1, a2, a3)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             13 => {
                 if args.len() != 4 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_Handle()?;
                 let a2 = args[2].try_as_i32()?;
                 let a3 = args[3].try_as_Handle()?;
                 let rets = self.func_13(a0, a1, a2, a3)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             14 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 self.func_14(a0)?;
-                Some(vec![])
+                Ok(vec![])
             }
             15 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_15()?;
-                Some(vec![])
+                Ok(vec![])
             }
             16 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_16()?;
-                Some(vec![])
+                Ok(vec![])
             }
             17 => {
                 if args.len() != 0 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
 
                 self.func_17()?;
-                Some(vec![])
+                Ok(vec![])
             }
             18 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let rets = self.func_18(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             19 => {
                 if args.len() != 4 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_i32()?;
                 let a2 = args[2].try_as_i32()?;
                 let a3 = args[3].try_as_Handle()?;
                 let rets = self.func_19(a0, a1, a2, a3)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             20 => {
                 if args.len() != 2 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_Handle()?;
                 let rets = self.func_20(a0, a1)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             21 => {
                 if args.len() != 2 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_i32()?;
                 let rets = self.func_21(a0, a1)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             22 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let rets = self.func_22(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             23 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let rets = self.func_23(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             24 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let rets = self.func_24(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             25 => {
                 if args.len() != 3 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_Handle()?;
                 let a2 = args[2].try_as_i32()?;
                 let rets = self.func_25(a0, a1, a2)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             26 => {
                 if args.len() != 3 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_Handle()?;
                 let a2 = args[2].try_as_i32()?;
                 let rets = self.func_26(a0, a1, a2)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             27 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let rets = self.func_27(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             28 => {
                 if args.len() != 3 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_Handle()?;
                 let a2 = args[2].try_as_i32()?;
                 let rets = self.func_28(a0, a1, a2)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             29 => {
                 if args.len() != 3 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_i32()?;
                 let a1 = args[1].try_as_i64()?;
                 let a2 = args[2].try_as_i32()?;
                 let rets = self.func_29(a0, a1, a2)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             30 => {
                 if args.len() != 3 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_i64()?;
                 let a2 = args[2].try_as_i32()?;
                 let rets = self.func_30(a0, a1, a2)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             31 => {
                 if args.len() != 3 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let a1 = args[1].try_as_Handle()?;
                 let a2 = args[2].try_as_i32()?;
                 let rets = self.func_31(a0, a1, a2)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
             32 => {
                 if args.len() != 1 {
-                    return None;
+                    return Err(Trap::IndirectCallSignatureMismatch);
                 }
                 let a0 = args[0].try_as_Handle()?;
                 let rets = self.func_32(a0)?;
-                Some(vec![TaggedVal::from(rets)])
+                Ok(vec![TaggedVal::from(rets)])
             }
-            _ => None,
+            _ => Err(Trap::UndefinedTableElement),
         }
     }
 }
 
+// Names from the `name` section, by function index
+const FUNC_NAMES: [&str; 33] = [
+    "__imported_wasi_snapshot_preview1_fd_close",
+    "__imported_wasi_snapshot_preview1_fd_fdstat_get",
+    "__imported_wasi_snapshot_preview1_fd_seek",
+    "__imported_wasi_snapshot_preview1_fd_write",
+    "__imported_wasi_snapshot_preview1_proc_exit",
+    "__wasm_call_ctors",
+    "__mswasm_init_stack",
+    "_start",
+    "main",
+    "__original_main",
+    "__wasi_fd_close",
+    "__wasi_fd_fdstat_get",
+    "__wasi_fd_seek",
+    "__wasi_fd_write",
+    "__wasi_proc_exit",
+    "dummy",
+    "__wasm_call_dtors",
+    "__stdio_exit",
+    "__towrite",
+    "fwrite",
+    "fputs",
+    "__overflow",
+    "puts",
+    "close",
+    "__stdio_close",
+    "writev",
+    "__stdio_write",
+    "__isatty",
+    "__stdout_write",
+    "__lseek",
+    "__stdio_seek",
+    "memcpy",
+    "strlen",
+];
+
 impl WasmModule {
-    #[allow(dead_code)]
-    pub fn get_memory(&mut self) -> *mut u8 {
-        panic!("Memory export currently unimplemented for MS Wasm")
+    pub fn _start(&mut self) -> Result<(), Trap> {
+        self.trap_backtrace.frames.clear();
+        self.func_7()
     }
 }
 
+// Imports resolved through the `Linker`, indexed by `call_host`
+const HOST_IMPORTS: [Import; 0] = [];
+
+// Signatures of the exports above, in the order `call_export` dispatches on
+const EXPORTS: [Export; 1] = [Export {
+    name: "_start",
+    params: &[],
+    results: &[],
+}];
+
 impl WasmModule {
-    pub fn _start(&mut self) -> Option<()> {
-        self.func_7()
+    #[allow(unused_variables)]
+    fn call_export(&mut self, index: usize, args: &[TaggedVal]) -> Result<Vec<TaggedVal>, Trap> {
+        match index {
+            0 => {
+                self._start()?;
+                Ok(vec![])
+            }
+            _ => unreachable!(),
+        }
     }
 }
 fn main() {
     let mut wasm_module = WasmModule::new();
-    wasm_module._start().unwrap();
+    match wasm_module._start() {
+        Ok(()) => {}
+        Err(Trap::Exit(code)) => std::process::exit(code),
+        Err(trap) => {
+            eprintln!("wasm trap: {}", trap);
+            eprint!("{}", wasm_module.trap_backtrace());
+            std::process::exit(1);
+        }
+    }
 }
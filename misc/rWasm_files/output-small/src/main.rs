@@ -1,6 +1,14 @@
-use sandboxed_small::WasmModule;
+use sandboxed_small::{Trap, WasmModule};
 
 fn main() {
     let mut wasm_module = WasmModule::new();
-    wasm_module._start().unwrap();
-}
\ No newline at end of file
+    match wasm_module._start() {
+        Ok(()) => {}
+        Err(Trap::Exit(code)) => std::process::exit(code),
+        Err(trap) => {
+            eprintln!("wasm trap: {}", trap);
+            eprint!("{}", wasm_module.trap_backtrace());
+            std::process::exit(1);
+        }
+    }
+}
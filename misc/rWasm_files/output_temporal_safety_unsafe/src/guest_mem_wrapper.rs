@@ -33,26 +33,34 @@ unsafe impl<'a> wiggle::GuestMemory for GuestMemWrapper<'a> {
         (base.as_mut_ptr(), base.len() as u32)
     }
     fn has_outstanding_borrows(&self) -> bool {
-        self.mem.try_borrow_mut().is_err()
+        !self.borrowed_regions.borrow().is_empty()
     }
+    // Whether any byte of `r` is borrowed, as all borrows are exclusive
     fn is_borrowed(&self, r: wiggle::Region) -> bool {
+        // An empty region covers no byte, so it cannot overlap anything
+        // (and `Region::overlaps` does not expect one)
+        if r.len == 0 {
+            return false;
+        }
         self.borrowed_regions
             .borrow()
-            .iter()
-            .any(|(_, b)| b.overlaps(r))
+            .values()
+            .any(|&b| b.len != 0 && b.overlaps(r))
     }
     fn borrow(
         &self,
         r: wiggle::Region,
     ) -> std::result::Result<wiggle::BorrowHandle, wiggle::GuestError> {
         if self.is_borrowed(r) {
-            todo!()
-        } else {
-            let handle: usize = self.borrowed_regions_handle_freshness.get();
-            self.borrowed_regions_handle_freshness.set(handle + 1);
-            self.borrowed_regions.borrow_mut().insert(handle, r);
-            Ok(wiggle::BorrowHandle(handle))
+            return Err(wiggle::GuestError::PtrBorrowed(r));
         }
+        let handle: usize = self.borrowed_regions_handle_freshness.get();
+        let next = handle
+            .checked_add(1)
+            .ok_or(wiggle::GuestError::BorrowCheckerOutOfHandles)?;
+        self.borrowed_regions_handle_freshness.set(next);
+        self.borrowed_regions.borrow_mut().insert(handle, r);
+        Ok(wiggle::BorrowHandle(handle))
     }
     fn unborrow(&self, handle: wiggle::BorrowHandle) {
         let _: wiggle::Region = self